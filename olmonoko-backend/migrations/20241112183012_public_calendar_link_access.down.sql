@@ -0,0 +1,4 @@
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS fetch_count;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS last_accessed_at;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS expires_at;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS name;
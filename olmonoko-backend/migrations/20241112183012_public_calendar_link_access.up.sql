@@ -0,0 +1,4 @@
+ALTER TABLE public_calendar_links ADD COLUMN name TEXT;
+ALTER TABLE public_calendar_links ADD COLUMN expires_at BIGINT;
+ALTER TABLE public_calendar_links ADD COLUMN last_accessed_at BIGINT;
+ALTER TABLE public_calendar_links ADD COLUMN fetch_count BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS export_categories;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS export_source;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS export_attendance;
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS alarm_priority;
@@ -0,0 +1,5 @@
+ALTER TABLE public_calendar_links ADD COLUMN export_categories BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE public_calendar_links ADD COLUMN export_source BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE public_calendar_links ADD COLUMN export_attendance BOOLEAN NOT NULL DEFAULT FALSE;
+-- events with a priority at least this high (numerically lower or equal) get reminders
+ALTER TABLE public_calendar_links ADD COLUMN alarm_priority INTEGER;
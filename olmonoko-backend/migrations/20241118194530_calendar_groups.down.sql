@@ -0,0 +1,3 @@
+ALTER TABLE public_calendar_links DROP COLUMN IF EXISTS group_id;
+DROP TABLE IF EXISTS calendar_group_members;
+DROP TABLE IF EXISTS calendar_groups;
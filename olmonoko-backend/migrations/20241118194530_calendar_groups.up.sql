@@ -0,0 +1,21 @@
+CREATE TABLE calendar_groups (
+    id          SERIAL  PRIMARY KEY,
+    owner_id    INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    name        TEXT    NOT NULL,
+    created_at  BIGINT  NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW())
+);
+
+CREATE TABLE calendar_group_members (
+    group_id        INTEGER NOT NULL    REFERENCES calendar_groups(id) ON DELETE CASCADE,
+    user_id         INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    colour          TEXT,
+    -- members have to opt in to sharing their events with the group
+    share_events    BOOLEAN NOT NULL    DEFAULT FALSE,
+    min_priority    INTEGER,
+    max_priority    INTEGER,
+    created_at      BIGINT  NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW()),
+    PRIMARY KEY (group_id, user_id)
+);
+
+-- links with a group export the merged calendar of the group instead of the user's own
+ALTER TABLE public_calendar_links ADD COLUMN group_id INTEGER REFERENCES calendar_groups(id) ON DELETE CASCADE;
@@ -0,0 +1,16 @@
+-- grants a single user access to a private source or to the owner's local calendar
+CREATE TABLE calendar_shares (
+    id              SERIAL  PRIMARY KEY,
+    owner_id        INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    grantee_id      INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    -- NULL shares the owner's local calendar
+    ics_source_id   INTEGER             REFERENCES ics_sources(id) ON DELETE CASCADE,
+    permission      TEXT    NOT NULL,
+    created_at      BIGINT  NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW()),
+    CHECK (permission IN ('read', 'write')),
+    -- remote sources are read only, the grantee can only subscribe to them
+    CHECK (ics_source_id IS NULL OR permission = 'read'),
+    CHECK (owner_id <> grantee_id),
+    UNIQUE NULLS NOT DISTINCT (owner_id, grantee_id, ics_source_id)
+);
+CREATE INDEX calendar_shares_grantee_id ON calendar_shares(grantee_id);
@@ -0,0 +1,6 @@
+DROP INDEX bills_user_id;
+DROP INDEX bills_remote_event_id;
+DROP INDEX bills_local_event_id;
+ALTER TABLE bills ADD CONSTRAINT bills_local_event_id_remote_event_id_key UNIQUE (local_event_id, remote_event_id);
+ALTER TABLE bills DROP COLUMN paid_at;
+ALTER TABLE bills DROP COLUMN user_id;
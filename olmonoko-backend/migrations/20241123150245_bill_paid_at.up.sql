@@ -0,0 +1,20 @@
+-- bills on remote events need an owner of their own, local ones get it from the event
+ALTER TABLE bills ADD COLUMN user_id INTEGER REFERENCES users(id) ON DELETE CASCADE;
+-- in seconds, like the starts_at of the event the bill is due on
+ALTER TABLE bills ADD COLUMN paid_at BIGINT;
+
+-- paid bills used to be recorded as a duration (paid_at - due) on their event
+UPDATE bills
+SET user_id = event.user_id,
+    paid_at = event.starts_at + event.duration
+FROM local_events AS event
+WHERE event.id = bills.local_event_id;
+DELETE FROM bills WHERE user_id IS NULL;
+ALTER TABLE bills ALTER COLUMN user_id SET NOT NULL;
+
+-- the old UNIQUE (local_event_id, remote_event_id) never matched, as one of them is always NULL
+ALTER TABLE bills DROP CONSTRAINT bills_local_event_id_remote_event_id_key;
+CREATE UNIQUE INDEX bills_local_event_id ON bills(local_event_id) WHERE local_event_id IS NOT NULL;
+-- remote events are shared, so every user can have their own bill on one
+CREATE UNIQUE INDEX bills_remote_event_id ON bills(user_id, remote_event_id) WHERE remote_event_id IS NOT NULL;
+CREATE INDEX bills_user_id ON bills(user_id);
@@ -0,0 +1,2 @@
+-- EPC QR codes and e-invoices name the payee's bank, barcodes only work with Finnish IBANs
+ALTER TABLE bills ADD COLUMN payee_bic TEXT;
@@ -0,0 +1,2 @@
+-- ISO 4217 code, the amount is in the minor unit of the currency
+ALTER TABLE bills ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
@@ -0,0 +1,2 @@
+DROP TABLE bill_reminders;
+DROP TABLE bill_reminder_settings;
@@ -0,0 +1,24 @@
+-- users without a row get the defaults
+CREATE TABLE bill_reminder_settings (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- opt-in, or every existing bill past its due date would be reminded of at once
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- how many days before the due date to remind
+    lead_days INTEGER NOT NULL DEFAULT 3 CHECK (lead_days >= 0),
+    -- email or webhook
+    channel TEXT NOT NULL DEFAULT 'email',
+    webhook_url TEXT,
+    -- raise the priority of the events of overdue bills
+    escalate_overdue BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at BIGINT NOT NULL,
+    CHECK (channel <> 'webhook' OR webhook_url IS NOT NULL)
+);
+
+-- reminders already sent, so every kind is sent once per bill
+CREATE TABLE bill_reminders (
+    bill_id INTEGER NOT NULL REFERENCES bills(id) ON DELETE CASCADE,
+    -- upcoming or overdue
+    kind TEXT NOT NULL,
+    sent_at BIGINT NOT NULL,
+    PRIMARY KEY (bill_id, kind)
+);
@@ -0,0 +1 @@
+ALTER TABLE local_events DROP COLUMN timer_template_id;
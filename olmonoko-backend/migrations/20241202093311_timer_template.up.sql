@@ -0,0 +1,2 @@
+-- the template of events created by stopping a timer, for time reports
+ALTER TABLE local_events ADD COLUMN timer_template_id INTEGER REFERENCES local_events(id) ON DELETE SET NULL;
@@ -0,0 +1,7 @@
+DROP TABLE timer_segments;
+ALTER TABLE timers DROP COLUMN name;
+-- only the oldest timer of every user can be kept
+DELETE FROM timers WHERE id NOT IN (
+    SELECT DISTINCT ON (user_id) id FROM timers ORDER BY user_id, created_at
+);
+ALTER TABLE timers ADD CONSTRAINT timers_user_id_key UNIQUE (user_id);
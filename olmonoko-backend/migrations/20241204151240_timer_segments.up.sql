@@ -0,0 +1,18 @@
+-- users can run several timers at once
+ALTER TABLE timers DROP CONSTRAINT timers_user_id_key;
+-- tells concurrent timers apart
+ALTER TABLE timers ADD COLUMN name TEXT;
+
+-- the timer runs during its segments, pausing ends the open one and resuming opens a new one
+CREATE TABLE timer_segments (
+    id          SERIAL  PRIMARY KEY,
+    timer_id    UUID    NOT NULL REFERENCES timers(id) ON DELETE CASCADE,
+    started_at  BIGINT  NOT NULL,
+    -- NULL while the timer is running
+    ended_at    BIGINT,
+    CHECK (ended_at IS NULL OR ended_at >= started_at)
+);
+CREATE UNIQUE INDEX timer_segments_one_open ON timer_segments (timer_id) WHERE ended_at IS NULL;
+
+-- timers running during the migration keep running
+INSERT INTO timer_segments (timer_id, started_at) SELECT id, created_at FROM timers;
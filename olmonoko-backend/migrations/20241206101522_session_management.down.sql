@@ -0,0 +1,10 @@
+DROP INDEX IF EXISTS sessions_expires_at_idx;
+DROP INDEX IF EXISTS sessions_user_id_idx;
+ALTER TABLE sessions
+    DROP COLUMN remember,
+    DROP COLUMN ip_address,
+    DROP COLUMN user_agent,
+    DROP COLUMN last_seen_at,
+    DROP COLUMN handle,
+    ALTER COLUMN created_at SET DEFAULT EXTRACT(EPOCH FROM NOW())*1000;
+UPDATE sessions SET created_at = created_at * 1000;
@@ -0,0 +1,12 @@
+-- created_at was in milliseconds unlike expires_at
+UPDATE sessions SET created_at = created_at / 1000;
+ALTER TABLE sessions
+    ALTER COLUMN created_at SET DEFAULT EXTRACT(EPOCH FROM NOW()),
+    -- identifies the session to the user without revealing the secret id
+    ADD COLUMN handle       UUID    NOT NULL    DEFAULT gen_random_uuid() UNIQUE,
+    ADD COLUMN last_seen_at BIGINT  NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW()),
+    ADD COLUMN user_agent   TEXT,
+    ADD COLUMN ip_address   TEXT,
+    ADD COLUMN remember     BOOLEAN NOT NULL    DEFAULT FALSE;
+CREATE INDEX sessions_user_id_idx ON sessions(user_id);
+CREATE INDEX sessions_expires_at_idx ON sessions(expires_at);
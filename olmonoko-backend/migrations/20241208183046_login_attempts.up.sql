@@ -0,0 +1,12 @@
+CREATE TABLE login_attempts (
+    id          BIGSERIAL   PRIMARY KEY,
+    -- as entered, the account may not exist
+    email       TEXT        NOT NULL,
+    user_id     INTEGER     NULL        REFERENCES users(id) ON DELETE SET NULL,
+    ip_address  TEXT,
+    user_agent  TEXT,
+    success     BOOLEAN     NOT NULL,
+    created_at  BIGINT      NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW())
+);
+CREATE INDEX login_attempts_email_idx ON login_attempts(email, created_at);
+CREATE INDEX login_attempts_ip_address_idx ON login_attempts(ip_address, created_at);
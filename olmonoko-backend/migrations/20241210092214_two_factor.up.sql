@@ -0,0 +1,26 @@
+CREATE TABLE user_totp (
+    user_id         INTEGER NOT NULL    PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- base32
+    secret          TEXT    NOT NULL,
+    -- false until the user has confirmed a code from the new secret
+    enabled         BOOLEAN NOT NULL    DEFAULT FALSE,
+    -- codes of this or earlier time steps can't be reused
+    last_used_step  BIGINT,
+    created_at      BIGINT  NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW())
+);
+
+CREATE TABLE user_recovery_codes (
+    id          SERIAL  PRIMARY KEY,
+    user_id     INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    code_hash   TEXT    NOT NULL,
+    used_at     BIGINT
+);
+CREATE INDEX user_recovery_codes_user_id_idx ON user_recovery_codes(user_id);
+
+-- logins waiting for the second factor
+CREATE TABLE login_challenges (
+    id          TEXT    NOT NULL    PRIMARY KEY,
+    user_id     INTEGER NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    remember    BOOLEAN NOT NULL,
+    expires_at  BIGINT  NOT NULL
+);
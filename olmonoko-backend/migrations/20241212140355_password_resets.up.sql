@@ -0,0 +1,7 @@
+CREATE TABLE password_resets (
+    secret      VARCHAR(255)    NOT NULL    PRIMARY KEY,
+    user_id     INTEGER         NOT NULL    REFERENCES users(id) ON DELETE CASCADE,
+    expires_at  BIGINT          NOT NULL,
+    created_at  BIGINT          NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW())
+);
+CREATE INDEX password_resets_user_id_idx ON password_resets(user_id);
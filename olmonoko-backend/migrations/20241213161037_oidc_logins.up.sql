@@ -0,0 +1,9 @@
+-- logins waiting for the identity provider to redirect back
+CREATE TABLE oidc_logins (
+    -- the CSRF state sent to the provider
+    state           TEXT    NOT NULL    PRIMARY KEY,
+    pkce_verifier   TEXT    NOT NULL,
+    nonce           TEXT    NOT NULL,
+    remember        BOOLEAN NOT NULL,
+    expires_at      BIGINT  NOT NULL
+);
@@ -0,0 +1,9 @@
+-- only used to throttle the reset emails, so unknown emails are recorded too
+CREATE TABLE password_reset_requests (
+    id          BIGSERIAL   PRIMARY KEY,
+    email       TEXT        NOT NULL,
+    ip_address  TEXT,
+    created_at  BIGINT      NOT NULL    DEFAULT EXTRACT(EPOCH FROM NOW())
+);
+CREATE INDEX password_reset_requests_email_idx ON password_reset_requests(email, created_at);
+CREATE INDEX password_reset_requests_ip_address_idx ON password_reset_requests(ip_address, created_at);
@@ -0,0 +1,3 @@
+-- the rows stay in seconds, as the code has always written them
+ALTER TABLE bills ALTER COLUMN created_at SET DEFAULT EXTRACT(EPOCH FROM NOW())*1000;
+ALTER TABLE bills ALTER COLUMN updated_at SET DEFAULT EXTRACT(EPOCH FROM NOW())*1000;
@@ -0,0 +1,6 @@
+-- bills are written and read in seconds, only the defaults were in milliseconds
+ALTER TABLE bills ALTER COLUMN created_at SET DEFAULT EXTRACT(EPOCH FROM NOW());
+ALTER TABLE bills ALTER COLUMN updated_at SET DEFAULT EXTRACT(EPOCH FROM NOW());
+-- anything past the year 5000 in seconds was written in milliseconds
+UPDATE bills SET created_at = created_at / 1000 WHERE created_at > 100000000000;
+UPDATE bills SET updated_at = updated_at / 1000 WHERE updated_at > 100000000000;
@@ -1,16 +1,32 @@
 pub mod email;
+pub mod oidc;
+pub mod two_factor;
 
+use std::sync::LazyLock;
+
+use actix_web::cookie::{time::Duration, Cookie};
+use actix_web::HttpRequest;
 use olmonoko_common::{
-    models::user::{NewUser, RawUser, User},
+    models::{
+        session::{NewSession, SessionRaw, SESSION_MAX_AGE},
+        two_factor::LOGIN_CHALLENGE_LIFETIME,
+        user::{
+            NewUser, RawUser, User, UserId, PASSWORD_RESETS_PER_EMAIL, PASSWORD_RESETS_PER_IP,
+            PASSWORD_RESET_LIFETIME, PASSWORD_RESET_WINDOW,
+        },
+    },
+    utils::time::timestamp,
     AppState,
 };
 
+use crate::db::request::{EnhancedRequest, LOGIN_CHALLENGE_COOKIE_NAME, SESSION_COOKIE_NAME};
+
 #[derive(Debug, thiserror::Error)]
 pub enum UnverifiedUserCreationError {
     #[error("Database error: {0}")]
     DbErr(#[from] sqlx::Error),
     #[error("Failed to send verification email")]
-    EmailErr(#[from] email::Error),
+    EmailErr(#[from] crate::email::EmailError),
 }
 
 pub async fn create_unverified_user(
@@ -63,3 +79,251 @@ pub async fn verify_user(data: &AppState, secret: &str) -> Result<User, sqlx::Er
     .map(User::from)?;
     Ok(user)
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordResetRequestError {
+    #[error("Database error: {0}")]
+    DbErr(#[from] sqlx::Error),
+    #[error("Failed to send password reset email")]
+    EmailErr(#[from] crate::email::EmailError),
+}
+
+/// Records the request unless there have been too many for the email or from the address.
+/// Checked and recorded in one go, so that concurrent requests can't all get through.
+async fn allow_password_reset_request(
+    conn: &sqlx::PgPool,
+    email: &str,
+    ip_address: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // always in this order so that concurrent requests can't deadlock
+    sqlx::query!(
+        "SELECT 1 AS locked FROM pg_advisory_xact_lock(3, hashtext($1))",
+        email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(ip_address) = ip_address {
+        sqlx::query!(
+            "SELECT 1 AS locked FROM pg_advisory_xact_lock(4, hashtext($1))",
+            ip_address
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+    }
+    let now = timestamp();
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM password_reset_requests WHERE email = $1 AND created_at >= $3) AS "email!",
+            (SELECT COUNT(*) FROM password_reset_requests WHERE ip_address = $2 AND created_at >= $3) AS "ip_address!"
+        "#,
+        email,
+        ip_address,
+        now - PASSWORD_RESET_WINDOW
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if counts.email >= PASSWORD_RESETS_PER_EMAIL || counts.ip_address >= PASSWORD_RESETS_PER_IP {
+        return Ok(false);
+    }
+    sqlx::query!(
+        "INSERT INTO password_reset_requests (email, ip_address, created_at) VALUES ($1, $2, $3)",
+        email,
+        ip_address,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Emails a reset link if there's an account with the email, and tells nobody whether there was.
+/// Only a few links are sent to an email or to anyone asking from the same address.
+pub async fn request_password_reset(
+    data: &AppState,
+    email: &str,
+    ip_address: Option<&str>,
+) -> Result<(), PasswordResetRequestError> {
+    if !allow_password_reset_request(&data.conn, email, ip_address).await? {
+        tracing::warn!("Throttled password reset request for {email} from {ip_address:?}");
+        return Ok(());
+    }
+    let Some(user_id) = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", email)
+        .fetch_optional(&data.conn)
+        .await?
+    else {
+        tracing::info!("Password reset requested for unknown email {email}");
+        return Ok(());
+    };
+    let secret = uuid::Uuid::new_v4().to_string();
+    let now = timestamp();
+    sqlx::query!(
+        "INSERT INTO password_resets (secret, user_id, expires_at, created_at) VALUES ($1, $2, $3, $4)",
+        secret,
+        user_id,
+        now + PASSWORD_RESET_LIFETIME,
+        now
+    )
+    .execute(&data.conn)
+    .await?;
+    email::send_password_reset_email(data, email, &secret).await?;
+    Ok(())
+}
+
+/// Sets the password of the user the unexpired secret was sent to and logs them out everywhere.
+/// Returns None if the secret isn't valid.
+pub async fn reset_password(
+    data: &AppState,
+    secret: &str,
+    password_hash: &str,
+) -> Result<Option<UserId>, sqlx::Error> {
+    let mut tx = data.conn.begin().await?;
+    let Some(user_id) = sqlx::query_scalar!(
+        "DELETE FROM password_resets WHERE secret = $1 AND expires_at >= $2 RETURNING user_id",
+        secret,
+        timestamp()
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE id = $2",
+        password_hash,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    // the other links sent to the user and any sessions of whoever knew the old password
+    sqlx::query!("DELETE FROM password_resets WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
+
+pub async fn purge_expired_password_resets(conn: &sqlx::PgPool) -> Result<u64, sqlx::Error> {
+    let now = timestamp();
+    let resets = sqlx::query!("DELETE FROM password_resets WHERE expires_at < $1", now)
+        .execute(conn)
+        .await?;
+    let requests = sqlx::query!(
+        "DELETE FROM password_reset_requests WHERE created_at < $1",
+        now - PASSWORD_RESET_WINDOW
+    )
+    .execute(conn)
+    .await?;
+    Ok(resets.rows_affected() + requests.rows_affected())
+}
+
+/// Compared against when the account doesn't exist, so that it takes as long as a wrong password
+pub(crate) static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    bcrypt::hash(uuid::Uuid::new_v4().to_string(), bcrypt::DEFAULT_COST)
+        .expect("Failed to hash the dummy password")
+});
+
+/// Takes the same time whether or not there is a hash to compare against
+pub fn verify_password(password: &str, hash: Option<&str>) -> bool {
+    match hash {
+        Some(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+        None => {
+            let _ = bcrypt::verify(password, &DUMMY_PASSWORD_HASH);
+            false
+        }
+    }
+}
+
+/// Starts a session for the user and builds the cookie holding it.
+/// Unless the user asked to be remembered, the cookie is dropped when the browser is closed.
+pub async fn create_session(
+    data: &AppState,
+    req: &HttpRequest,
+    user_id: UserId,
+    remember: bool,
+) -> Result<(SessionRaw, Cookie<'static>), sqlx::Error> {
+    let now = timestamp();
+    let new_session = NewSession {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id,
+        expires_at: SessionRaw::expiry(now, remember, now),
+        created_at: now,
+        user_agent: req.get_user_agent(),
+        ip_address: req.get_client_ip(),
+        remember,
+    };
+    let created = sqlx::query_as!(
+        SessionRaw,
+        r#"
+        INSERT INTO sessions (id, user_id, expires_at, created_at, last_seen_at, user_agent, ip_address, remember)
+        VALUES ($1, $2, $3, $4, $4, $5, $6, $7)
+        RETURNING *
+        "#,
+        new_session.id,
+        new_session.user_id,
+        new_session.expires_at,
+        new_session.created_at,
+        new_session.user_agent,
+        new_session.ip_address,
+        new_session.remember
+    )
+    .fetch_one(&data.conn)
+    .await?;
+    let mut cookie = Cookie::build(SESSION_COOKIE_NAME, created.id.clone())
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .finish();
+    if remember {
+        cookie.set_max_age(Duration::seconds(SESSION_MAX_AGE));
+    } else {
+        cookie.set_expires(None);
+    }
+    Ok((created, cookie))
+}
+
+/// Holds the login challenge of a user who still has to enter their second factor
+pub fn login_challenge_cookie(challenge: String) -> Cookie<'static> {
+    Cookie::build(LOGIN_CHALLENGE_COOKIE_NAME, challenge)
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .max_age(Duration::seconds(LOGIN_CHALLENGE_LIFETIME))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn password_reset_requests_are_throttled(conn: sqlx::PgPool) {
+        let allowed = |email: &'static str, ip_address: &'static str| {
+            let conn = conn.clone();
+            async move {
+                allow_password_reset_request(&conn, email, Some(ip_address))
+                    .await
+                    .unwrap()
+            }
+        };
+        let mut per_email = 0;
+        for _ in 0..5 {
+            per_email += allowed("user@example.com", "192.0.2.1").await as i64;
+        }
+        assert_eq!(per_email, PASSWORD_RESETS_PER_EMAIL);
+        let mut per_ip = per_email;
+        for email in ["a@example.com", "b@example.com", "c@example.com"] {
+            for _ in 0..PASSWORD_RESETS_PER_EMAIL {
+                per_ip += allowed(email, "192.0.2.1").await as i64;
+            }
+        }
+        assert_eq!(per_ip, PASSWORD_RESETS_PER_IP);
+        // others can still ask for a link
+        assert!(allowed("d@example.com", "192.0.2.2").await);
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::LazyLock;
+
+use olmonoko_common::{get_site_url, utils::time::timestamp};
+use openidconnect::core::{
+    CoreAuthenticationFlow, CoreClient, CoreErrorResponseType, CoreProviderMetadata,
+};
+use openidconnect::{
+    reqwest, AuthorizationCode, ClaimsVerificationError, ClientId, ClientSecret,
+    ConfigurationError, CsrfToken, DiscoveryError, EndpointMaybeSet, EndpointNotSet, EndpointSet,
+    HttpClientError, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl,
+    RequestTokenError, Scope, StandardErrorResponse, TokenResponse,
+};
+use tokio::sync::RwLock;
+
+/// The discovery document, and the signing keys in it, are fetched again after this many seconds
+const DISCOVERY_TTL: i64 = 60 * 60;
+const DEFAULT_PROVIDER_NAME: &str = "SSO";
+
+type DiscoveredClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("Failed to build the HTTP client: {0}")]
+    HttpClient(#[from] reqwest::Error),
+    #[error("Invalid URL: {0}")]
+    Url(#[from] openidconnect::url::ParseError),
+    #[error("Failed to discover the identity provider: {0}")]
+    Discovery(#[from] DiscoveryError<HttpClientError<reqwest::Error>>),
+    #[error("The identity provider is missing an endpoint: {0}")]
+    Configuration(#[from] ConfigurationError),
+    #[error("Failed to exchange the code for tokens: {0}")]
+    TokenRequest(
+        #[from]
+        RequestTokenError<
+            HttpClientError<reqwest::Error>,
+            StandardErrorResponse<CoreErrorResponseType>,
+        >,
+    ),
+    #[error("The identity provider didn't return an ID token")]
+    MissingIdToken,
+    #[error("Invalid ID token: {0}")]
+    Claims(#[from] ClaimsVerificationError),
+    #[error("The ID token has no email claim")]
+    MissingEmail,
+}
+
+/// Secrets of a login sent to the identity provider, needed once it redirects back
+#[derive(Debug, Clone)]
+pub struct OidcAuthorization {
+    pub url: String,
+    pub state: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+}
+
+/// Who the identity provider says logged in
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub email: String,
+    /// Only true when the provider vouches for the email, providers may leave the claim out
+    pub email_verified: bool,
+}
+
+pub struct OidcProvider {
+    /// Shown on the login button
+    pub name: String,
+    /// Create an account on the first login of an email that doesn't have one
+    pub auto_provision: bool,
+    issuer_url: IssuerUrl,
+    client_id: ClientId,
+    client_secret: Option<ClientSecret>,
+    redirect_url: RedirectUrl,
+    http_client: reqwest::Client,
+    metadata: RwLock<Option<(i64, CoreProviderMetadata)>>,
+}
+
+/// Configured with `OIDC_ISSUER_URL` and `OIDC_CLIENT_ID`, and optionally `OIDC_CLIENT_SECRET`,
+/// `OIDC_PROVIDER_NAME` and `OIDC_AUTO_PROVISION`. None when single sign-on isn't configured.
+pub static OIDC_PROVIDER: LazyLock<Option<OidcProvider>> = LazyLock::new(|| {
+    let issuer_url = std::env::var("OIDC_ISSUER_URL").ok()?;
+    let client_id = std::env::var("OIDC_CLIENT_ID").ok()?;
+    match OidcProvider::new(issuer_url, client_id) {
+        Ok(provider) => Some(provider),
+        Err(e) => {
+            tracing::error!("Single sign-on disabled, invalid OIDC configuration: {e}");
+            None
+        }
+    }
+});
+
+impl OidcProvider {
+    fn new(issuer_url: String, client_id: String) -> Result<Self, OidcError> {
+        let http_client = reqwest::ClientBuilder::new()
+            // following redirects would open the client up to SSRF
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            name: std::env::var("OIDC_PROVIDER_NAME")
+                .unwrap_or_else(|_| DEFAULT_PROVIDER_NAME.to_string()),
+            auto_provision: std::env::var("OIDC_AUTO_PROVISION")
+                .is_ok_and(|value| value == "true" || value == "1"),
+            issuer_url: IssuerUrl::new(issuer_url)?,
+            client_id: ClientId::new(client_id),
+            client_secret: std::env::var("OIDC_CLIENT_SECRET")
+                .ok()
+                .map(ClientSecret::new),
+            redirect_url: RedirectUrl::new(format!("{}/api/oidc/callback", get_site_url()))?,
+            http_client,
+            metadata: RwLock::new(None),
+        })
+    }
+
+    async fn client(&self) -> Result<DiscoveredClient, OidcError> {
+        let now = timestamp();
+        let cached = self.metadata.read().await.clone();
+        let metadata = match cached {
+            Some((fetched_at, metadata)) if now - fetched_at < DISCOVERY_TTL => metadata,
+            _ => {
+                let metadata = CoreProviderMetadata::discover_async(
+                    self.issuer_url.clone(),
+                    &self.http_client,
+                )
+                .await?;
+                *self.metadata.write().await = Some((now, metadata.clone()));
+                metadata
+            }
+        };
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            self.client_id.clone(),
+            self.client_secret.clone(),
+        )
+        .set_redirect_uri(self.redirect_url.clone()))
+    }
+
+    /// Where to send the user to log in, with the secrets to check the response against
+    pub async fn authorize(&self) -> Result<OidcAuthorization, OidcError> {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = self
+            .client()
+            .await?
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        Ok(OidcAuthorization {
+            url: url.to_string(),
+            state: state.into_secret(),
+            pkce_verifier: pkce_verifier.into_secret(),
+            nonce: nonce.secret().clone(),
+        })
+    }
+
+    /// Exchanges the code from the callback for an ID token and returns the identity in it
+    pub async fn identify(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<OidcIdentity, OidcError> {
+        let client = self.client().await?;
+        let tokens = client
+            .exchange_code(AuthorizationCode::new(code))?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(&self.http_client)
+            .await?;
+        let id_token = tokens.id_token().ok_or(OidcError::MissingIdToken)?;
+        let claims = id_token.claims(&client.id_token_verifier(), &Nonce::new(nonce))?;
+        let email = claims.email().ok_or(OidcError::MissingEmail)?;
+        Ok(OidcIdentity {
+            email: email.to_string(),
+            email_verified: claims.email_verified() == Some(true),
+        })
+    }
+}
@@ -0,0 +1,145 @@
+use olmonoko_common::models::two_factor::{
+    normalize_recovery_code, TotpProvisioning, RECOVERY_CODE_COUNT,
+};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "OLMONOKO";
+const DIGITS: usize = 6;
+/// Seconds per code
+const STEP: i64 = 30;
+/// Codes of the steps just before and after are accepted for clock drift
+const SKEW: i64 = 1;
+/// Without easily confused characters
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TwoFactorError {
+    #[error("Invalid TOTP parameters: {0}")]
+    Totp(#[from] totp_rs::TotpUrlError),
+    #[error("Invalid TOTP secret: {0}")]
+    Secret(#[from] totp_rs::SecretParseError),
+    #[error("Failed to render the QR code: {0}")]
+    Qr(#[from] qrcode::types::QrError),
+}
+
+fn totp(secret: &str, email: &str) -> Result<TOTP, TwoFactorError> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes()?;
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP as u64,
+        secret,
+        Some(ISSUER.to_string()),
+        email.to_string(),
+    )?)
+}
+
+/// A new base32 secret
+pub fn new_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+pub fn provisioning(secret: &str, email: &str) -> Result<TotpProvisioning, TwoFactorError> {
+    let url = totp(secret, email)?.get_url();
+    let qr_svg = qrcode::QrCode::new(&url)?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok(TotpProvisioning {
+        secret: secret.to_string(),
+        url,
+        qr_svg: Some(qr_svg),
+    })
+}
+
+/// The time step of the code if it's valid at `now` and newer than `last_used_step`,
+/// which keeps a code from being used twice.
+pub fn verify_code(
+    secret: &str,
+    email: &str,
+    code: &str,
+    last_used_step: Option<i64>,
+    now: i64,
+) -> Result<Option<i64>, TwoFactorError> {
+    let totp = totp(secret, email)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = now / STEP;
+    Ok(((current - SKEW)..=(current + SKEW))
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| totp.check(&code, (step * STEP) as u64)))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let random = uuid::Uuid::new_v4().into_bytes();
+            // the version and variant are in the 7th and 9th byte
+            let code: String = random
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != 6 && *index != 8)
+                .map(|(_, byte)| byte)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(|byte| {
+                    let index = *byte as usize % RECOVERY_CODE_ALPHABET.len();
+                    RECOVERY_CODE_ALPHABET[index] as char
+                })
+                .collect();
+            let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+            format!("{first}-{second}")
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough not to need a slow hash
+pub fn hash_recovery_code(code: &str) -> String {
+    let digest = Sha256::digest(normalize_recovery_code(code).as_bytes());
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_checked_once_within_the_skew() {
+        let secret = new_secret();
+        let email = "user@example.com";
+        let now = 1_733_000_000;
+        let code = totp(&secret, email).unwrap().generate(now as u64);
+        let step = now / STEP;
+        assert_eq!(
+            verify_code(&secret, email, &code, None, now).unwrap(),
+            Some(step)
+        );
+        assert_eq!(
+            verify_code(&secret, email, &code, None, now + STEP).unwrap(),
+            Some(step)
+        );
+        assert_eq!(
+            verify_code(&secret, email, &code, None, now + 2 * STEP).unwrap(),
+            None
+        );
+        assert_eq!(
+            verify_code(&secret, email, &code, Some(step), now).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == RECOVERY_CODE_LENGTH + 1));
+        let code = &codes[0];
+        assert_eq!(
+            hash_recovery_code(code),
+            hash_recovery_code(&code.to_uppercase().replace('-', " "))
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use olmonoko_common::{
+    models::bills::{
+        reminder::{BillReminder, NotificationChannelKind, ReminderKind, ReminderSettings},
+        Bill, RawBill,
+    },
+    utils::time::{from_timestamp, timestamp},
+};
+
+use crate::notifications::NotificationChannel;
+
+/// The highest priority, given to the events of overdue bills
+const OVERDUE_PRIORITY: i32 = 1;
+
+/// Sends the reminders due for unpaid bills of every user and escalates the overdue ones.
+/// Every kind of reminder is sent once per bill, failed ones are retried on the next run.
+pub async fn remind_all() -> Result<(), anyhow::Error> {
+    let conn = crate::get_conn().await?;
+    let now = timestamp();
+    let defaults = ReminderSettings::default();
+    let due_bills = sqlx::query!(
+        r#"
+            SELECT bill.*, event.summary AS "summary!", event.due_at AS "due_at!",
+                usr.email,
+                COALESCE(settings.lead_days, $2) AS "lead_days!",
+                COALESCE(settings.channel, $3) AS "channel!",
+                settings.webhook_url AS "webhook_url?",
+                COALESCE(settings.escalate_overdue, $4) AS "escalate_overdue!",
+                ARRAY(SELECT kind FROM bill_reminders AS sent WHERE sent.bill_id = bill.id) AS "sent!"
+            FROM bills AS bill
+            INNER JOIN users AS usr ON usr.id = bill.user_id
+            LEFT JOIN bill_reminder_settings AS settings ON settings.user_id = bill.user_id
+            INNER JOIN LATERAL (
+                SELECT event.summary, event.starts_at AS due_at
+                FROM local_events AS event
+                WHERE event.id = bill.local_event_id
+                UNION ALL
+                SELECT event.summary, (
+                    SELECT MIN(o.starts_at) FROM event_occurrences AS o WHERE o.event_id = event.id
+                ) AS due_at
+                FROM events AS event
+                WHERE event.id = bill.remote_event_id
+            ) AS event ON TRUE
+            WHERE bill.paid_at IS NULL
+                AND event.due_at IS NOT NULL
+                AND COALESCE(settings.enabled, $5)
+                AND event.due_at < $1 + COALESCE(settings.lead_days, $2)::bigint * 24 * 60 * 60
+            ORDER BY event.due_at, bill.id
+        "#,
+        now,
+        defaults.lead_days,
+        defaults.channel.to_string(),
+        defaults.escalate_overdue,
+        defaults.enabled,
+    )
+    .fetch_all(&conn)
+    .await?;
+
+    let mut escalated_users = vec![];
+    for row in due_bills {
+        let sent: Vec<ReminderKind> = row
+            .sent
+            .iter()
+            .filter_map(|kind| ReminderKind::try_from(kind.as_str()).ok())
+            .collect();
+        let Some(kind) = BillReminder::due_kind(row.due_at, now, row.lead_days, &sent) else {
+            continue;
+        };
+        let Ok(channel) = NotificationChannelKind::try_from(row.channel.as_str()) else {
+            // skipped so that the reminders of everyone else still go out
+            tracing::error!(
+                bill_id = row.id,
+                user_id = row.user_id,
+                "Invalid notification channel in database: {}",
+                row.channel
+            );
+            continue;
+        };
+        let settings = ReminderSettings {
+            enabled: true,
+            lead_days: row.lead_days,
+            channel,
+            webhook_url: row.webhook_url,
+            escalate_overdue: row.escalate_overdue,
+        };
+        let bill = Bill::from(RawBill {
+            id: row.id,
+            user_id: row.user_id,
+            local_event_id: row.local_event_id,
+            remote_event_id: row.remote_event_id,
+            payee_account_number: row.payee_account_number,
+            amount: row.amount,
+            currency: row.currency,
+            reference: row.reference,
+            payee_name: row.payee_name,
+            payee_email: row.payee_email,
+            payee_address: row.payee_address,
+            payee_phone: row.payee_phone,
+            payee_bic: row.payee_bic,
+            paid_at: row.paid_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        });
+        let reminder = BillReminder {
+            kind,
+            bill,
+            summary: row.summary,
+            due_at: from_timestamp(row.due_at),
+        };
+
+        let sent = match NotificationChannel::from_settings(&settings, &row.email) {
+            Ok(channel) => channel.send(&reminder).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            tracing::error!(
+                bill_id = row.id,
+                user_id = row.user_id,
+                "Failed to send bill reminder: {e}"
+            );
+            continue;
+        }
+        sqlx::query!(
+            "INSERT INTO bill_reminders (bill_id, kind, sent_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            row.id,
+            kind.to_string(),
+            now
+        )
+        .execute(&conn)
+        .await?;
+
+        // remote events belong to their source, so only local ones can be escalated
+        if kind == ReminderKind::Overdue && settings.escalate_overdue {
+            if let Some(local_event_id) = row.local_event_id {
+                let updated = sqlx::query!(
+                    // the export etags follow updated_at
+                    "UPDATE local_events SET priority = $2, updated_at = EXTRACT(EPOCH FROM NOW())*1000 WHERE id = $1 AND (priority IS NULL OR priority > $2)",
+                    local_event_id,
+                    OVERDUE_PRIORITY
+                )
+                .execute(&conn)
+                .await?;
+                if updated.rows_affected() > 0 && !escalated_users.contains(&row.user_id) {
+                    escalated_users.push(row.user_id);
+                }
+            }
+        }
+    }
+    for user_id in escalated_users {
+        super::export_cache::invalidate_user(user_id);
+    }
+    Ok(())
+}
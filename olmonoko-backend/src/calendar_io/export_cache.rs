@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use moka::future::{Cache, CacheBuilder};
+use olmonoko_common::models::user::UserId;
+use once_cell::sync::Lazy;
+
+/// A rendered export, along with the fingerprint of the data it was rendered from.
+#[derive(Debug, Clone)]
+pub struct CachedExport {
+    /// Users whose events are included in the export
+    pub user_ids: Vec<UserId>,
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+    pub body: Arc<String>,
+}
+
+type ExportCache = Cache<String, CachedExport>;
+fn build_cache() -> ExportCache {
+    CacheBuilder::new(1000)
+        .time_to_live(std::time::Duration::from_secs(60 * 60))
+        .support_invalidation_closures()
+        .build()
+}
+static CACHE: Lazy<ExportCache> = Lazy::new(build_cache);
+
+/// Returns the cached export for the given link, if it is still up to date with `etag`.
+pub async fn get(link_id: &str, etag: &str) -> Option<CachedExport> {
+    CACHE
+        .get(link_id)
+        .await
+        .filter(|cached| cached.etag == etag)
+}
+
+pub async fn insert(link_id: &str, export: CachedExport) {
+    CACHE.insert(link_id.to_owned(), export).await;
+}
+
+/// Drops all cached exports belonging to a user, e.g. after their local events changed.
+pub fn invalidate_user(user_id: UserId) {
+    if let Err(e) = CACHE.invalidate_entries_if(move |_, cached| cached.user_ids.contains(&user_id))
+    {
+        tracing::error!("Failed to invalidate export cache for user {user_id}: {e}");
+    }
+}
+
+/// Drops every cached export, e.g. after a (possibly shared) source has been synced.
+pub fn invalidate_all() {
+    CACHE.invalidate_all();
+}
@@ -1,16 +1,59 @@
-use icalendar::{Component, EventLike};
+use std::collections::HashMap;
+
+use icalendar::{Alarm, Component, EventLike, EventStatus, Trigger};
 
 use itertools::Itertools;
 use olmonoko_common::{
-    models::event::{EventOccurrence, EventOccurrenceHuman},
+    models::{
+        event::{
+            remote::RemoteSourceId, EventOccurrence, EventOccurrenceHuman, EventSource, Priority,
+        },
+        public_link::PublicLink,
+    },
     utils::time::{from_timestamp, get_current_time},
 };
 
+pub mod bill_reminders;
+pub mod export_cache;
 pub mod scheduler;
 pub mod source_processing;
 
+/// Optional properties to include in a composed ics file.
+/// The default only includes the core event data.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IcsOptions {
+    /// Emit CATEGORIES from event tags
+    pub categories: bool,
+    /// Emit X-OLMONOKO-SOURCE, using these names for remote sources
+    pub source_names: Option<HashMap<RemoteSourceId, String>>,
+    /// Emit STATUS and TRANSP based on planned attendance
+    pub attendance: bool,
+    /// Events with at least this priority get a VALARM
+    pub alarm_priority: Option<Priority>,
+}
+impl IcsOptions {
+    pub fn from_link(link: &PublicLink, source_names: HashMap<RemoteSourceId, String>) -> Self {
+        Self {
+            categories: link.export_categories,
+            source_names: link.export_source.then_some(source_names),
+            attendance: link.export_attendance,
+            alarm_priority: crate::db::events::parse_priority(link.alarm_priority),
+        }
+    }
+}
+
+/// How long before an event a reminder should fire, more important events are reminded of earlier.
+fn alarm_offset(priority: Priority) -> chrono::Duration {
+    match priority {
+        ..=1 => chrono::Duration::days(1),
+        2..=3 => chrono::Duration::hours(1),
+        _ => chrono::Duration::minutes(15),
+    }
+}
+
 pub(crate) async fn compose_ics(
     events: Vec<EventOccurrence>,
+    options: &IcsOptions,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let dt_stamp = get_current_time();
     let mut calendar = icalendar::Calendar::new();
@@ -64,6 +107,47 @@ pub(crate) async fn compose_ics(
         if let Some(location) = &event.location {
             ical_event.location(location);
         }
+        if options.categories {
+            for tag in &event.tags {
+                ical_event.add_multi_property("CATEGORIES", tag);
+            }
+        }
+        if let Some(source_names) = &options.source_names {
+            let source_name = match event.source {
+                EventSource::Local(_) => Some("local"),
+                EventSource::Remote(source) => {
+                    source_names.get(&source.source_id).map(String::as_str)
+                }
+                EventSource::Group(source) => match source.source_id {
+                    Some(source_id) => source_names.get(&source_id).map(String::as_str),
+                    None => Some("local"),
+                },
+            };
+            if let Some(source_name) = source_name {
+                // x-properties aren't escaped as text, so a stray newline would break the file
+                ical_event
+                    .add_property("X-OLMONOKO-SOURCE", source_name.replace(['\r', '\n'], " "));
+            }
+        }
+        if options.attendance {
+            if let Some(attendance) = &event.attendance {
+                if attendance.planned {
+                    ical_event.status(EventStatus::Confirmed);
+                    ical_event.add_property("TRANSP", "OPAQUE");
+                } else {
+                    ical_event.status(EventStatus::Tentative);
+                    ical_event.add_property("TRANSP", "TRANSPARENT");
+                }
+            }
+        }
+        if let Some(alarm_priority) = options.alarm_priority {
+            if event.priority > 0 && event.priority <= alarm_priority {
+                ical_event.alarm(Alarm::display(
+                    &event.summary,
+                    Trigger::before_start(alarm_offset(event.priority)),
+                ));
+            }
+        }
         // if let Some(dt_stamp) = event.dt_stamp {
         //     let ts = dt_stamp.parse::<i64>().expect("Failed to parse timestamp");
         //     ical_event.timestamp(chrono::NaiveDateTime::from_timestamp(ts, 0));
@@ -122,3 +206,213 @@ pub(crate) fn compose_rss(
 
     Ok(channel.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use olmonoko_common::models::{
+        attendance::Attendance,
+        event::{SourceLocal, SourceRemote},
+    };
+
+    fn event(summary: &str, priority: Priority, source: EventSource) -> EventOccurrence {
+        EventOccurrence {
+            id: 1,
+            source,
+            priority,
+            tags: vec!["work".to_string(), "travel".to_string()],
+            attendance: None,
+            starts_at: from_timestamp(1_700_000_000),
+            all_day: false,
+            duration: Some(3600),
+            rrule: None,
+            from_rrule: false,
+            summary: summary.to_string(),
+            description: None,
+            location: None,
+            uid: summary.to_string(),
+        }
+    }
+
+    fn local(summary: &str, priority: Priority) -> EventOccurrence {
+        event(
+            summary,
+            priority,
+            EventSource::Local(SourceLocal { user_id: 1 }),
+        )
+    }
+
+    fn attendance(planned: bool) -> Option<Attendance> {
+        Some(Attendance {
+            planned,
+            actual: false,
+            created_at: from_timestamp(0),
+            updated_at: from_timestamp(0),
+        })
+    }
+
+    /// The unfolded property lines of each event in the ics
+    fn events_of(ics: &str) -> Vec<Vec<String>> {
+        ics.replace("\r\n ", "")
+            .split("BEGIN:VEVENT")
+            .skip(1)
+            .map(|event| event.lines().map(str::to_string).collect())
+            .collect()
+    }
+
+    fn has(event: &[String], prefix: &str) -> bool {
+        event.iter().any(|line| line.starts_with(prefix))
+    }
+
+    #[tokio::test]
+    async fn defaults_only_include_core_event_data() {
+        let mut planned = local("planned", 1);
+        planned.attendance = attendance(true);
+        let ics = compose_ics(vec![planned], &IcsOptions::default())
+            .await
+            .unwrap();
+        let events = events_of(&ics);
+        assert_eq!(events.len(), 1);
+        for prefix in [
+            "CATEGORIES",
+            "X-OLMONOKO-SOURCE",
+            "STATUS",
+            "TRANSP",
+            "BEGIN:VALARM",
+        ] {
+            assert!(!has(&events[0], prefix), "{prefix} exported by default");
+        }
+        assert!(has(&events[0], "SUMMARY:planned"));
+        assert!(has(&events[0], "PRIORITY:1"));
+    }
+
+    #[tokio::test]
+    async fn categories_come_from_tags() {
+        let options = IcsOptions {
+            categories: true,
+            ..Default::default()
+        };
+        let ics = compose_ics(vec![local("tagged", 5)], &options)
+            .await
+            .unwrap();
+        let categories = events_of(&ics)[0]
+            .iter()
+            .filter(|line| line.starts_with("CATEGORIES"))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(categories, ["CATEGORIES:work", "CATEGORIES:travel"]);
+    }
+
+    #[tokio::test]
+    async fn sources_are_named() {
+        let options = IcsOptions {
+            source_names: Some(HashMap::from([(7, "Work\r\nshifts".to_string())])),
+            ..Default::default()
+        };
+        let events = vec![
+            local("local", 5),
+            event(
+                "named",
+                5,
+                EventSource::Remote(SourceRemote { source_id: 7 }),
+            ),
+            event(
+                "unnamed",
+                5,
+                EventSource::Remote(SourceRemote { source_id: 8 }),
+            ),
+        ];
+        let ics = compose_ics(events, &options).await.unwrap();
+        let sources = events_of(&ics)
+            .iter()
+            .map(|event| {
+                event
+                    .iter()
+                    .find_map(|line| line.strip_prefix("X-OLMONOKO-SOURCE:"))
+                    .map(str::to_string)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                Some("local".to_string()),
+                Some("Work  shifts".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn attendance_sets_status_and_transparency() {
+        let options = IcsOptions {
+            attendance: true,
+            ..Default::default()
+        };
+        let mut planned = local("planned", 5);
+        planned.attendance = attendance(true);
+        let mut skipped = local("skipped", 5);
+        skipped.attendance = attendance(false);
+        let undecided = local("undecided", 5);
+        let ics = compose_ics(vec![planned, skipped, undecided], &options)
+            .await
+            .unwrap();
+        let events = events_of(&ics);
+        assert!(has(&events[0], "STATUS:CONFIRMED"));
+        assert!(has(&events[0], "TRANSP:OPAQUE"));
+        assert!(has(&events[1], "STATUS:TENTATIVE"));
+        assert!(has(&events[1], "TRANSP:TRANSPARENT"));
+        assert!(!has(&events[2], "STATUS"));
+        assert!(!has(&events[2], "TRANSP"));
+    }
+
+    #[tokio::test]
+    async fn alarms_are_added_up_to_the_priority() {
+        let options = IcsOptions {
+            alarm_priority: Some(5),
+            ..Default::default()
+        };
+        let events = vec![
+            local("urgent", 1),
+            local("important", 3),
+            local("normal", 5),
+            local("minor", 6),
+            local("unprioritized", 0),
+        ];
+        let ics = compose_ics(events, &options).await.unwrap();
+        let triggers = events_of(&ics)
+            .iter()
+            .map(|event| {
+                event
+                    .iter()
+                    .find_map(|line| line.strip_prefix("TRIGGER"))
+                    .map(str::to_string)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            triggers,
+            [
+                Some(";RELATED=START:-PT86400S".to_string()),
+                Some(";RELATED=START:-PT3600S".to_string()),
+                Some(";RELATED=START:-PT900S".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn out_of_range_priorities_are_left_out() {
+        let events = vec![local("zero", 0), local("nine", 9), local("ten", 10)];
+        let ics = compose_ics(events, &IcsOptions::default()).await.unwrap();
+        let priorities = events_of(&ics)
+            .iter()
+            .map(|event| {
+                event
+                    .iter()
+                    .find_map(|line| line.strip_prefix("PRIORITY:"))
+                    .map(str::to_string)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(priorities, [None, Some("9".to_string()), None]);
+    }
+}
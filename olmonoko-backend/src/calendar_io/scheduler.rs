@@ -1,4 +1,8 @@
-use super::source_processing;
+use std::sync::Arc;
+
+use super::{bill_reminders, source_processing};
+use crate::snapshots::{prune_snapshots, take_snapshot, SnapshotConfig, SNAPSHOTS};
+use olmonoko_common::AppState;
 use tokio_cron_scheduler::{Job, JobScheduler, JobSchedulerError};
 
 async fn job_sync_all_sources(job_uuid: String, oneoff: bool) {
@@ -13,6 +17,92 @@ async fn job_sync_all_sources(job_uuid: String, oneoff: bool) {
     tracing::info!(job_uuid, "Sync complete!");
 }
 
+async fn job_remind_bills(job_uuid: String) {
+    tracing::info!(job_uuid, "Sending bill reminders!");
+    if let Err(e) = bill_reminders::remind_all().await {
+        tracing::error!(job_uuid, "Failed to send bill reminders: {e:?}");
+    } else {
+        tracing::info!(job_uuid, "Bill reminders sent!");
+    }
+}
+
+async fn job_purge_auth_records(job_uuid: String) {
+    let conn = match crate::get_conn().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::error!(job_uuid, "Failed to purge auth records: {e:?}");
+            return;
+        }
+    };
+    match crate::db::sessions::purge_expired_sessions(&conn).await {
+        Ok(count) => tracing::info!(job_uuid, "Purged {count} expired sessions!"),
+        Err(e) => tracing::error!(job_uuid, "Failed to purge expired sessions: {e:?}"),
+    }
+    match crate::db::login_attempts::purge_old_login_attempts(&conn).await {
+        Ok(count) => tracing::info!(job_uuid, "Purged {count} old login attempts!"),
+        Err(e) => tracing::error!(job_uuid, "Failed to purge old login attempts: {e:?}"),
+    }
+    match crate::db::two_factor::purge_expired_login_challenges(&conn).await {
+        Ok(count) => tracing::info!(job_uuid, "Purged {count} expired login challenges!"),
+        Err(e) => tracing::error!(job_uuid, "Failed to purge expired login challenges: {e:?}"),
+    }
+    match crate::auth::purge_expired_password_resets(&conn).await {
+        Ok(count) => tracing::info!(job_uuid, "Purged {count} expired password resets!"),
+        Err(e) => tracing::error!(job_uuid, "Failed to purge expired password resets: {e:?}"),
+    }
+    match crate::db::oidc::purge_expired_oidc_logins(&conn).await {
+        Ok(count) => tracing::info!(job_uuid, "Purged {count} expired single sign-on logins!"),
+        Err(e) => tracing::error!(
+            job_uuid,
+            "Failed to purge expired single sign-on logins: {e:?}"
+        ),
+    }
+}
+
+async fn job_take_snapshot(job_uuid: String, data: &AppState, config: &SnapshotConfig) {
+    tracing::info!(job_uuid, "Backing up the instance!");
+    match take_snapshot(data, config).await {
+        Ok(snapshot) => tracing::info!(job_uuid, snapshot.name, snapshot.size, "Backup saved!"),
+        Err(e) => {
+            tracing::error!(job_uuid, "Failed to back up the instance: {e:?}");
+            // the older ones are kept until there's a new one to replace them
+            return;
+        }
+    }
+    match prune_snapshots(config).await {
+        Ok(count) => tracing::info!(job_uuid, "Removed {count} old backups!"),
+        Err(e) => tracing::error!(job_uuid, "Failed to remove old backups: {e:?}"),
+    }
+}
+
+/// Backs up the instance on the schedule of [`SNAPSHOTS`], if it's configured.
+/// Separate from [`init`] because the backups need the app state.
+pub async fn schedule_snapshots(data: &AppState) -> Result<(), JobSchedulerError> {
+    let Some(config) = SNAPSHOTS.as_ref() else {
+        return Ok(());
+    };
+    let state = Arc::new(data.clone());
+    data.scheduler
+        .add(Job::new_async(
+            config.schedule.as_str(),
+            move |job_uuid, _| {
+                let state = state.clone();
+                Box::pin(async move {
+                    job_take_snapshot(job_uuid.to_string(), &state, config).await;
+                })
+            },
+        )?)
+        .await?;
+    tracing::info!(
+        dir = %config.dir.display(),
+        config.schedule,
+        config.keep,
+        config.max_age_days,
+        "Scheduled backups enabled"
+    );
+    Ok(())
+}
+
 pub async fn schedule_sync_oneoff(scheduler: &JobScheduler) -> Result<(), JobSchedulerError> {
     scheduler
         .add(Job::new_one_shot_async(
@@ -37,6 +127,22 @@ pub async fn init() -> Result<JobScheduler, JobSchedulerError> {
             })
         })?)
         .await?;
+    // check for due and overdue bills every hour
+    scheduler
+        .add(Job::new_async("0 0 * * * *", |job_uuid, _| {
+            Box::pin(async move {
+                job_remind_bills(job_uuid.to_string()).await;
+            })
+        })?)
+        .await?;
+    // remove expired sessions and old login attempts every hour
+    scheduler
+        .add(Job::new_async("0 30 * * * *", |job_uuid, _| {
+            Box::pin(async move {
+                job_purge_auth_records(job_uuid.to_string()).await;
+            })
+        })?)
+        .await?;
 
     scheduler.shutdown_on_ctrl_c();
     scheduler.start().await.unwrap();
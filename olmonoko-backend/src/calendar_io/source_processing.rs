@@ -291,6 +291,7 @@ where
     )
     .execute(&mut *conn)
     .await?;
+    super::export_cache::invalidate_all();
 
     Ok(true)
 }
@@ -0,0 +1,1114 @@
+use std::collections::BTreeMap;
+
+use futures_util::{stream::BoxStream, TryStreamExt};
+use olmonoko_common::models::{
+    api_key::RawApiKey,
+    attendance::RawAttendance,
+    backup::{
+        BackupHeader, BackupImportReport, BackupRecord, BackupTrailer, BackupV1, RawBillReminder,
+        RawEventTag, RawRecoveryCode, RawSourcePriority, BACKUP_FORMAT, BACKUP_FORMAT_VERSION,
+    },
+    bills::{reminder::RawReminderSettings, RawBill},
+    event::{
+        local::{LocalEventId, RawLocalEvent},
+        remote::{RawRemoteEvent, RawRemoteEventOccurrence},
+    },
+    group::{RawGroup, RawGroupMember},
+    ics_source::RawIcsSource,
+    login_attempt::RawLoginAttempt,
+    public_link::RawPublicLink,
+    session::SessionRaw,
+    share::RawShare,
+    timer::{RawTimer, RawTimerSegment},
+    two_factor::RawUserTotp,
+    user::{RawUser, UnverifiedUser},
+};
+use olmonoko_common::{utils::time::timestamp, AppState};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufWriter, Lines};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The backup is empty")]
+    Empty,
+    #[error("Not an OLMONOKO backup")]
+    UnknownFormat,
+    #[error("Backup format version {0} isn't supported by this version of OLMONOKO")]
+    UnsupportedVersion(u32),
+}
+impl BackupError {
+    /// Whether the backup itself is at fault rather than the instance
+    pub fn is_invalid_backup(&self) -> bool {
+        matches!(
+            self,
+            Self::Json(_) | Self::Empty | Self::UnknownFormat | Self::UnsupportedVersion(_)
+        )
+    }
+}
+
+pub fn backup_header(data: &AppState) -> BackupHeader {
+    BackupHeader {
+        format: BACKUP_FORMAT.to_string(),
+        format_version: BACKUP_FORMAT_VERSION,
+        created_at: timestamp(),
+        site_url: data.site_url.clone(),
+        version: data.version.clone(),
+        build_info: data.build_info.clone(),
+    }
+}
+
+async fn write_line<W: AsyncWrite + Unpin, T: serde::Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> Result<(), BackupError> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+async fn write_rows<W: AsyncWrite + Unpin, T>(
+    writer: &mut W,
+    mut rows: BoxStream<'_, Result<T, sqlx::Error>>,
+    record: fn(T) -> BackupRecord,
+) -> Result<usize, BackupError> {
+    let mut count = 0;
+    while let Some(row) = rows.try_next().await? {
+        write_line(writer, &record(row)).await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Streams the whole instance to `writer` one row at a time, from a single snapshot of the
+/// database. Returns the number of rows written.
+pub async fn write_backup<W: AsyncWrite + Unpin>(
+    conn: &PgPool,
+    header: &BackupHeader,
+    writer: W,
+) -> Result<usize, BackupError> {
+    let mut writer = BufWriter::new(writer);
+    let mut tx = conn.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    write_line(&mut writer, header).await?;
+    let mut records = 0;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawUser, "SELECT * FROM users ORDER BY id").fetch(&mut *tx),
+        BackupRecord::Users,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            UnverifiedUser,
+            "SELECT id, email, password_hash, created_at, admin, secret FROM unverified_users ORDER BY id"
+        )
+        .fetch(&mut *tx),
+        BackupRecord::UnverifiedUsers,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(SessionRaw, "SELECT * FROM sessions").fetch(&mut *tx),
+        BackupRecord::Sessions,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawApiKey, "SELECT * FROM api_keys").fetch(&mut *tx),
+        BackupRecord::ApiKeys,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawUserTotp, "SELECT * FROM user_totp").fetch(&mut *tx),
+        BackupRecord::UserTotp,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawRecoveryCode,
+            "SELECT * FROM user_recovery_codes ORDER BY id"
+        )
+        .fetch(&mut *tx),
+        BackupRecord::UserRecoveryCodes,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawLoginAttempt, "SELECT * FROM login_attempts ORDER BY id")
+            .fetch(&mut *tx),
+        BackupRecord::LoginAttempts,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawGroup, "SELECT * FROM calendar_groups ORDER BY id").fetch(&mut *tx),
+        BackupRecord::CalendarGroups,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawGroupMember, "SELECT * FROM calendar_group_members").fetch(&mut *tx),
+        BackupRecord::CalendarGroupMembers,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawIcsSource, "SELECT * FROM ics_sources ORDER BY id").fetch(&mut *tx),
+        BackupRecord::IcsSources,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawSourcePriority, "SELECT * FROM ics_source_priorities").fetch(&mut *tx),
+        BackupRecord::IcsSourcePriorities,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawShare, "SELECT * FROM calendar_shares ORDER BY id").fetch(&mut *tx),
+        BackupRecord::CalendarShares,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawPublicLink, "SELECT * FROM public_calendar_links").fetch(&mut *tx),
+        BackupRecord::PublicCalendarLinks,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawLocalEvent, "SELECT * FROM local_events ORDER BY id").fetch(&mut *tx),
+        BackupRecord::LocalEvents,
+    )
+    .await?;
+    // the events of other sources are fetched again after restoring
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawRemoteEvent,
+            "
+                SELECT events.*
+                FROM events
+                INNER JOIN ics_sources ON events.event_source_id = ics_sources.id AND ics_sources.persist_events = true
+                ORDER BY events.id
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::Events,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawRemoteEventOccurrence,
+            "
+                SELECT event_occurrences.*
+                FROM event_occurrences
+                INNER JOIN events ON events.id = event_occurrences.event_id
+                INNER JOIN ics_sources ON events.event_source_id = ics_sources.id AND ics_sources.persist_events = true
+                ORDER BY event_occurrences.id
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::EventOccurrences,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawEventTag,
+            "
+                SELECT event_tags.*
+                FROM event_tags
+                LEFT JOIN events ON events.id = event_tags.remote_event_id
+                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+                WHERE ics_sources.persist_events = true OR event_tags.local_event_id IS NOT NULL
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::EventTags,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawAttendance,
+            "
+                SELECT attendance.*
+                FROM attendance
+                LEFT JOIN events ON events.id = attendance.remote_event_id
+                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+                WHERE ics_sources.persist_events = true OR attendance.local_event_id IS NOT NULL
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::Attendance,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawBill,
+            "
+                SELECT bills.*
+                FROM bills
+                LEFT JOIN events ON events.id = bills.remote_event_id
+                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+                WHERE ics_sources.persist_events = true OR bills.local_event_id IS NOT NULL
+                ORDER BY bills.id
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::Bills,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(
+            RawBillReminder,
+            "
+                SELECT bill_reminders.*
+                FROM bill_reminders
+                INNER JOIN bills ON bills.id = bill_reminders.bill_id
+                LEFT JOIN events ON events.id = bills.remote_event_id
+                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+                WHERE ics_sources.persist_events = true OR bills.local_event_id IS NOT NULL
+            "
+        )
+        .fetch(&mut *tx),
+        BackupRecord::BillReminders,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawReminderSettings, "SELECT * FROM bill_reminder_settings")
+            .fetch(&mut *tx),
+        BackupRecord::BillReminderSettings,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawTimer, "SELECT * FROM timers").fetch(&mut *tx),
+        BackupRecord::Timers,
+    )
+    .await?;
+    records += write_rows(
+        &mut writer,
+        sqlx::query_as!(RawTimerSegment, "SELECT * FROM timer_segments ORDER BY id")
+            .fetch(&mut *tx),
+        BackupRecord::TimerSegments,
+    )
+    .await?;
+    write_line(&mut writer, &BackupRecord::End(BackupTrailer { records })).await?;
+    writer.shutdown().await?;
+    tx.commit().await?;
+    Ok(records)
+}
+
+/// The header of a backup, and for version 1 backups, which are a single line, the rest of it
+fn parse_first_line(line: &str) -> Result<(BackupHeader, Option<Vec<BackupRecord>>), BackupError> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    if value.get("format").is_some() {
+        let header: BackupHeader = serde_json::from_value(value)?;
+        if header.format != BACKUP_FORMAT {
+            return Err(BackupError::UnknownFormat);
+        }
+        if header.format_version != BACKUP_FORMAT_VERSION {
+            return Err(BackupError::UnsupportedVersion(header.format_version));
+        }
+        return Ok((header, None));
+    }
+    if value.get("users").is_some() {
+        let (header, records) = serde_json::from_value::<BackupV1>(value)?.migrate();
+        return Ok((header, Some(records)));
+    }
+    Err(BackupError::UnknownFormat)
+}
+
+/// Parses a record of the version in the header. When the records change, bump
+/// [`BACKUP_FORMAT_VERSION`] and upgrade the rows of older versions here before parsing.
+fn parse_record(line: &str) -> Result<BackupRecord, serde_json::Error> {
+    serde_json::from_str(line)
+}
+
+/// The records after the header, numbered by line
+struct RecordReader<R> {
+    lines: Lines<R>,
+    line: usize,
+    /// Version 1 backups are migrated as a whole, and numbered by record instead
+    migrated: Option<std::vec::IntoIter<BackupRecord>>,
+}
+impl<R: AsyncBufRead + Unpin> RecordReader<R> {
+    async fn next(
+        &mut self,
+    ) -> Result<Option<(usize, Result<BackupRecord, serde_json::Error>)>, BackupError> {
+        loop {
+            self.line += 1;
+            if let Some(migrated) = &mut self.migrated {
+                return Ok(migrated.next().map(|record| (self.line, Ok(record))));
+            }
+            match self.lines.next_line().await? {
+                None => return Ok(None),
+                Some(text) if text.trim().is_empty() => continue,
+                Some(text) => return Ok(Some((self.line, parse_record(&text)))),
+            }
+        }
+    }
+}
+
+/// Replaces everything on the instance with the backup read from `reader`, in one transaction.
+///
+/// Stops at the first row that can't be restored, unless it's a dry run, which checks every row
+/// and rolls back at the end. Either way the problems are in the report.
+/// Dry runs restore into empty copies of the tables, so the instance keeps working meanwhile.
+pub async fn import_backup<R: AsyncBufRead + Unpin>(
+    conn: &PgPool,
+    reader: R,
+    dry_run: bool,
+) -> Result<BackupImportReport, BackupError> {
+    let mut lines = reader.lines();
+    let first = loop {
+        match lines.next_line().await? {
+            None => return Err(BackupError::Empty),
+            Some(text) if text.trim().is_empty() => continue,
+            Some(text) => break text,
+        }
+    };
+    let (header, migrated) = parse_first_line(&first)?;
+    tracing::info!(
+        header.created_at,
+        header.site_url,
+        header.version,
+        header.format_version,
+        dry_run,
+        "Restoring backup"
+    );
+    let mut report = BackupImportReport {
+        format_version: if migrated.is_some() {
+            1
+        } else {
+            header.format_version
+        },
+        created_at: header.created_at,
+        site_url: header.site_url,
+        dry_run,
+        applied: false,
+        replaced_users: 0,
+        tables: BTreeMap::new(),
+        problems: Vec::new(),
+        problem_count: 0,
+    };
+    let mut records = RecordReader {
+        lines,
+        line: if migrated.is_some() { 0 } else { 1 },
+        migrated: migrated.map(Vec::into_iter),
+    };
+
+    let mut tx = conn.begin().await?;
+    if dry_run {
+        report.replaced_users = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM users"#)
+            .fetch_one(&mut *tx)
+            .await? as u64;
+        shadow_tables(&mut tx).await?;
+    } else {
+        report.replaced_users = clear_instance(&mut tx).await?;
+    }
+    let mut timer_templates = Vec::new();
+    let mut seen = 0;
+    let mut trailer = None;
+    while let Some((line, record)) = records.next().await? {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                seen += 1;
+                report.problem(line, None, format!("Invalid row: {e}"));
+                if dry_run {
+                    continue;
+                }
+                break;
+            }
+        };
+        if let BackupRecord::End(end) = record {
+            trailer = Some(end);
+            continue;
+        }
+        seen += 1;
+        let table = record.table();
+        if trailer.is_some() {
+            report.problem(line, Some(table), "Row after the end".to_string());
+            if dry_run {
+                continue;
+            }
+            break;
+        }
+        let result = if dry_run {
+            sqlx::query("SAVEPOINT backup_record")
+                .execute(&mut *tx)
+                .await?;
+            let result = insert_record(&mut tx, record, &mut timer_templates).await;
+            let statement = if result.is_ok() {
+                "RELEASE SAVEPOINT backup_record"
+            } else {
+                "ROLLBACK TO SAVEPOINT backup_record"
+            };
+            sqlx::query(statement).execute(&mut *tx).await?;
+            result
+        } else {
+            insert_record(&mut tx, record, &mut timer_templates).await
+        };
+        match result {
+            Ok(()) => *report.tables.entry(table.to_string()).or_default() += 1,
+            Err(e) => {
+                report.problem(line, Some(table), e.to_string());
+                if !dry_run {
+                    break;
+                }
+            }
+        }
+    }
+
+    if dry_run || report.problem_count == 0 {
+        match trailer {
+            None => report.problem(
+                records.line,
+                None,
+                "The backup ends without its last line, it was probably cut short".to_string(),
+            ),
+            Some(end) if end.records != seen => report.problem(
+                records.line,
+                None,
+                format!("The backup should have {} rows but has {seen}", end.records),
+            ),
+            Some(_) => {}
+        }
+    }
+    if dry_run || report.problem_count == 0 {
+        // templates may come after the events created from them
+        for (event_id, template_id) in timer_templates {
+            sqlx::query!(
+                "UPDATE local_events SET timer_template_id = $2 WHERE id = $1 AND EXISTS (SELECT 1 FROM local_events WHERE id = $2)",
+                event_id,
+                template_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        // setting a sequence isn't undone by rolling back
+        if !dry_run {
+            resync_sequences(&mut tx).await?;
+        }
+    }
+
+    if dry_run || report.problem_count > 0 {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+        report.applied = true;
+    }
+    Ok(report)
+}
+
+/// Creates empty copies of the tables, along with their constraints and triggers, in a schema
+/// that shadows the real tables for the rest of the transaction
+async fn shadow_tables(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    let schema = format!("backup_dry_run_{}", Uuid::new_v4().simple());
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT quote_ident(tablename) FROM pg_tables WHERE schemaname = 'public' AND tablename <> '_sqlx_migrations'",
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    // printed relative to the search path, so they apply to the copies once it's changed
+    let foreign_keys: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+            SELECT quote_ident(class.relname), quote_ident(conname), pg_get_constraintdef(con.oid)
+            FROM pg_constraint AS con
+            INNER JOIN pg_class AS class ON class.oid = con.conrelid
+            WHERE con.contype = 'f' AND con.connamespace = 'public'::regnamespace
+        "#,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let triggers: Vec<String> = sqlx::query_scalar(
+        r#"
+            SELECT replace(pg_get_triggerdef(trigger.oid), ' ON public.', ' ON ')
+            FROM pg_trigger AS trigger
+            INNER JOIN pg_class AS class ON class.oid = trigger.tgrelid
+            WHERE NOT trigger.tgisinternal AND class.relnamespace = 'public'::regnamespace
+        "#,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    sqlx::query(&format!("CREATE SCHEMA {schema}"))
+        .execute(&mut **tx)
+        .await?;
+    for table in &tables {
+        sqlx::query(&format!(
+            "CREATE TABLE {schema}.{table} (LIKE public.{table} INCLUDING ALL)"
+        ))
+        .execute(&mut **tx)
+        .await?;
+    }
+    // functions are still found in public
+    sqlx::query(&format!("SET LOCAL search_path TO {schema}, public"))
+        .execute(&mut **tx)
+        .await?;
+    for (table, name, definition) in foreign_keys {
+        sqlx::query(&format!(
+            "ALTER TABLE {table} ADD CONSTRAINT {name} {definition}"
+        ))
+        .execute(&mut **tx)
+        .await?;
+    }
+    for trigger in triggers {
+        sqlx::query(&trigger).execute(&mut **tx).await?;
+    }
+    Ok(())
+}
+
+/// Returns the number of users removed
+async fn clear_instance(tx: &mut Transaction<'_, Postgres>) -> Result<u64, sqlx::Error> {
+    sqlx::query!("DELETE FROM login_attempts")
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query!("DELETE FROM unverified_users")
+        .execute(&mut **tx)
+        .await?;
+    // everything else belongs to a user
+    let result = sqlx::query!("DELETE FROM users").execute(&mut **tx).await?;
+    Ok(result.rows_affected())
+}
+
+async fn insert_record(
+    tx: &mut Transaction<'_, Postgres>,
+    record: BackupRecord,
+    timer_templates: &mut Vec<(LocalEventId, LocalEventId)>,
+) -> Result<(), sqlx::Error> {
+    match record {
+        BackupRecord::Users(user) => {
+            sqlx::query!(
+                "INSERT INTO users (id, email, password_hash, admin, created_at, interface_timezone) VALUES ($1, $2, $3, $4, $5, $6)",
+                user.id,
+                user.email,
+                user.password_hash,
+                user.admin,
+                user.created_at,
+                user.interface_timezone,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::UnverifiedUsers(user) => {
+            sqlx::query!(
+                "INSERT INTO unverified_users (id, email, password_hash, admin, secret, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+                user.id,
+                user.email,
+                user.password_hash,
+                user.admin,
+                user.secret,
+                user.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::Sessions(session) => {
+            sqlx::query!(
+                "INSERT INTO sessions (id, user_id, expires_at, created_at, handle, last_seen_at, user_agent, ip_address, remember) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                session.id,
+                session.user_id,
+                session.expires_at,
+                session.created_at,
+                session.handle,
+                session.last_seen_at,
+                session.user_agent,
+                session.ip_address,
+                session.remember,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::ApiKeys(key) => {
+            sqlx::query!(
+                "INSERT INTO api_keys (id, user_id, description, scopes, revoked, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                key.id,
+                key.user_id,
+                key.description,
+                &key.scopes,
+                key.revoked,
+                key.created_at,
+                key.updated_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::UserTotp(totp) => {
+            sqlx::query!(
+                "INSERT INTO user_totp (user_id, secret, enabled, last_used_step, created_at) VALUES ($1, $2, $3, $4, $5)",
+                totp.user_id,
+                totp.secret,
+                totp.enabled,
+                totp.last_used_step,
+                totp.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::UserRecoveryCodes(code) => {
+            sqlx::query!(
+                "INSERT INTO user_recovery_codes (id, user_id, code_hash, used_at) VALUES ($1, $2, $3, $4)",
+                code.id,
+                code.user_id,
+                code.code_hash,
+                code.used_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::LoginAttempts(attempt) => {
+            sqlx::query!(
+                "INSERT INTO login_attempts (id, email, user_id, ip_address, user_agent, success, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                attempt.id,
+                attempt.email,
+                attempt.user_id,
+                attempt.ip_address,
+                attempt.user_agent,
+                attempt.success,
+                attempt.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::CalendarGroups(group) => {
+            sqlx::query!(
+                "INSERT INTO calendar_groups (id, owner_id, name, created_at) VALUES ($1, $2, $3, $4)",
+                group.id,
+                group.owner_id,
+                group.name,
+                group.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::CalendarGroupMembers(member) => {
+            sqlx::query!(
+                "INSERT INTO calendar_group_members (group_id, user_id, colour, share_events, min_priority, max_priority, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                member.group_id,
+                member.user_id,
+                member.colour,
+                member.share_events,
+                member.min_priority,
+                member.max_priority,
+                member.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::IcsSources(source) => {
+            // Restoring file or object hashes would block updates to the source until the file changes
+            sqlx::query!(
+                "INSERT INTO ics_sources (id, user_id, is_public, name, url, created_at, last_fetched_at, updated_at, persist_events, all_as_allday, import_template) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                source.id,
+                source.user_id,
+                source.is_public,
+                source.name,
+                source.url,
+                source.created_at,
+                source.last_fetched_at,
+                source.updated_at,
+                source.persist_events,
+                source.all_as_allday,
+                source.import_template,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::IcsSourcePriorities(priority) => {
+            sqlx::query!(
+                "INSERT INTO ics_source_priorities (user_id, ics_source_id, priority) VALUES ($1, $2, $3)",
+                priority.user_id,
+                priority.ics_source_id,
+                priority.priority,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::CalendarShares(share) => {
+            sqlx::query!(
+                "INSERT INTO calendar_shares (id, owner_id, grantee_id, ics_source_id, permission, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+                share.id,
+                share.owner_id,
+                share.grantee_id,
+                share.ics_source_id,
+                share.permission,
+                share.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::PublicCalendarLinks(link) => {
+            sqlx::query!(
+                "INSERT INTO public_calendar_links (id, user_id, created_at, min_priority, max_priority, name, expires_at, last_accessed_at, fetch_count, export_categories, export_source, export_attendance, alarm_priority, group_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+                link.id,
+                link.user_id,
+                link.created_at,
+                link.min_priority,
+                link.max_priority,
+                link.name,
+                link.expires_at,
+                link.last_accessed_at,
+                link.fetch_count,
+                link.export_categories,
+                link.export_source,
+                link.export_attendance,
+                link.alarm_priority,
+                link.group_id,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::LocalEvents(event) => {
+            sqlx::query!(
+                "INSERT INTO local_events (id, user_id, created_at, updated_at, starts_at, duration, summary, description, location, uid, all_day, priority, rrule) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+                event.id,
+                event.user_id,
+                event.created_at,
+                event.updated_at,
+                event.starts_at,
+                event.duration,
+                event.summary,
+                event.description,
+                event.location,
+                event.uid,
+                event.all_day,
+                event.priority,
+                event.rrule,
+            )
+            .execute(&mut **tx)
+            .await?;
+            if let Some(template_id) = event.timer_template_id {
+                timer_templates.push((event.id, template_id));
+            }
+        }
+        BackupRecord::Events(event) => {
+            sqlx::query!(
+                "INSERT INTO events (id, event_source_id, priority_override, rrule, dt_stamp, all_day, duration, summary, description, location, uid) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+                event.id,
+                event.event_source_id,
+                event.priority_override,
+                event.rrule,
+                event.dt_stamp,
+                event.all_day,
+                event.duration,
+                event.summary,
+                event.description,
+                event.location,
+                event.uid,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::EventOccurrences(occurrence) => {
+            sqlx::query!(
+                "INSERT INTO event_occurrences (id, event_id, starts_at, from_rrule) VALUES ($1, $2, $3, $4)",
+                occurrence.id,
+                occurrence.event_id,
+                occurrence.starts_at,
+                occurrence.from_rrule,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::EventTags(tag) => {
+            sqlx::query!(
+                "INSERT INTO event_tags (created_at, local_event_id, remote_event_id, tag) VALUES ($1, $2, $3, $4)",
+                tag.created_at,
+                tag.local_event_id,
+                tag.remote_event_id,
+                tag.tag,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::Attendance(attendance) => {
+            sqlx::query!(
+                "INSERT INTO attendance (user_id, local_event_id, remote_event_id, planned, actual, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                attendance.user_id,
+                attendance.local_event_id,
+                attendance.remote_event_id,
+                attendance.planned,
+                attendance.actual,
+                attendance.created_at,
+                attendance.updated_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::Bills(bill) => {
+            sqlx::query!(
+                "INSERT INTO bills (id, local_event_id, remote_event_id, payee_account_number, amount, reference, payee_name, payee_email, payee_address, payee_phone, created_at, updated_at, user_id, paid_at, payee_bic, currency) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+                bill.id,
+                bill.local_event_id,
+                bill.remote_event_id,
+                bill.payee_account_number,
+                bill.amount,
+                bill.reference,
+                bill.payee_name,
+                bill.payee_email,
+                bill.payee_address,
+                bill.payee_phone,
+                bill.created_at,
+                bill.updated_at,
+                bill.user_id,
+                bill.paid_at,
+                bill.payee_bic,
+                bill.currency,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::BillReminders(reminder) => {
+            sqlx::query!(
+                "INSERT INTO bill_reminders (bill_id, kind, sent_at) VALUES ($1, $2, $3)",
+                reminder.bill_id,
+                reminder.kind,
+                reminder.sent_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::BillReminderSettings(settings) => {
+            sqlx::query!(
+                "INSERT INTO bill_reminder_settings (user_id, enabled, lead_days, channel, webhook_url, escalate_overdue, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                settings.user_id,
+                settings.enabled,
+                settings.lead_days,
+                settings.channel,
+                settings.webhook_url,
+                settings.escalate_overdue,
+                settings.updated_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::Timers(timer) => {
+            sqlx::query!(
+                "INSERT INTO timers (id, user_id, template, name, summary, details, location, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                timer.id,
+                timer.user_id,
+                timer.template,
+                timer.name,
+                timer.summary,
+                timer.details,
+                timer.location,
+                timer.created_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::TimerSegments(segment) => {
+            sqlx::query!(
+                "INSERT INTO timer_segments (id, timer_id, started_at, ended_at) VALUES ($1, $2, $3, $4)",
+                segment.id,
+                segment.timer_id,
+                segment.started_at,
+                segment.ended_at,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        BackupRecord::End(_) => {}
+    }
+    Ok(())
+}
+
+/// Points the id sequences past the restored rows
+async fn resync_sequences(tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    // NOTE See https://wiki.postgresql.org/wiki/Fixing_Sequences
+    let statements: Vec<String> = sqlx::query_scalar(r#"SELECT
+    'SELECT SETVAL(' ||
+       quote_literal(quote_ident(sequence_namespace.nspname) || '.' || quote_ident(class_sequence.relname)) ||
+       ', COALESCE(MAX(' ||quote_ident(pg_attribute.attname)|| '), 1) ) FROM ' ||
+       quote_ident(table_namespace.nspname)|| '.'||quote_ident(class_table.relname)|| ';'
+FROM pg_depend
+    INNER JOIN pg_class AS class_sequence
+        ON class_sequence.oid = pg_depend.objid
+            AND class_sequence.relkind = 'S'
+    INNER JOIN pg_class AS class_table
+        ON class_table.oid = pg_depend.refobjid
+    INNER JOIN pg_attribute
+        ON pg_attribute.attrelid = class_table.oid
+            AND pg_depend.refobjsubid = pg_attribute.attnum
+    INNER JOIN pg_namespace as table_namespace
+        ON table_namespace.oid = class_table.relnamespace
+    INNER JOIN pg_namespace AS sequence_namespace
+        ON sequence_namespace.oid = class_sequence.relnamespace
+ORDER BY sequence_namespace.nspname, class_sequence.relname;"#)
+    .fetch_all(&mut **tx)
+    .await?;
+    for statement in statements {
+        sqlx::query(&statement).execute(&mut **tx).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{app_state, insert_local_event, insert_user};
+
+    const V1_BACKUP: &str = r#"{"created_at":1700000000,"site_url":"https://old.example.com","version":"v1","build_info":{"package_version":"0.1.0","commit":null,"commit_short":null,"build_time":"2024-01-01T00:00:00Z"},"users":[{"id":1,"email":"a@example.com","password_hash":"$2b$04$x","admin":true,"created_at":1,"interface_timezone":"UTC"}],"public_links":[],"local_events":[],"sources":[],"source_priorities":[[1,2,3]],"attendance":[],"bills":[],"persisted_remote_events":[],"persisted_remote_event_occurrences":[],"tags":[[5,7,null,"work"]]}"#;
+
+    #[test]
+    fn version_1_is_migrated() {
+        let (header, records) = parse_first_line(V1_BACKUP).unwrap();
+        assert_eq!(header.format_version, BACKUP_FORMAT_VERSION);
+        assert_eq!(header.site_url, "https://old.example.com");
+        let records = records.unwrap();
+        let tables: Vec<_> = records.iter().map(BackupRecord::table).collect();
+        assert_eq!(
+            tables,
+            ["users", "ics_source_priorities", "event_tags", "end"]
+        );
+        match &records[2] {
+            BackupRecord::EventTags(tag) => {
+                assert_eq!((tag.local_event_id, tag.tag.as_str()), (Some(7), "work"))
+            }
+            other => panic!("expected a tag, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn records_are_tagged_with_their_table() {
+        let record = BackupRecord::IcsSourcePriorities(RawSourcePriority {
+            user_id: 1,
+            ics_source_id: 2,
+            priority: 3,
+        });
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            line,
+            r#"{"table":"ics_source_priorities","row":{"user_id":1,"ics_source_id":2,"priority":3}}"#
+        );
+        assert_eq!(parse_record(&line).unwrap().table(), record.table());
+        assert!(parse_record(r#"{"table":"nope","row":{}}"#).is_err());
+    }
+
+    #[test]
+    fn headers_are_checked() {
+        let header = |format: &str, version: u32| {
+            format!(
+                r#"{{"format":"{format}","format_version":{version},"created_at":0,"site_url":"","version":"","build_info":{{"package_version":"","commit":null,"commit_short":null,"build_time":"2024-01-01T00:00:00Z"}}}}"#
+            )
+        };
+        assert!(
+            parse_first_line(&header(BACKUP_FORMAT, BACKUP_FORMAT_VERSION))
+                .unwrap()
+                .1
+                .is_none()
+        );
+        assert!(matches!(
+            parse_first_line(&header(BACKUP_FORMAT, BACKUP_FORMAT_VERSION + 1)),
+            Err(BackupError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            parse_first_line(&header("something-else", BACKUP_FORMAT_VERSION)),
+            Err(BackupError::UnknownFormat)
+        ));
+        assert!(matches!(
+            parse_first_line(r#"{"hello":"world"}"#),
+            Err(BackupError::UnknownFormat)
+        ));
+    }
+
+    #[sqlx::test]
+    async fn dry_runs_leave_the_instance_usable(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let user_id = insert_user(&conn, "user@example.com").await;
+        insert_local_event(&conn, user_id, "event").await;
+        let mut backup = Vec::new();
+        write_backup(&conn, &backup_header(&data), &mut backup)
+            .await
+            .unwrap();
+        let backup = String::from_utf8(backup).unwrap();
+        let (rows, end) = backup.trim_end().rsplit_once('\n').unwrap();
+
+        // a small buffer, so the rows have been restored by the time they're all written
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let import = tokio::spawn({
+            let conn = conn.clone();
+            async move { import_backup(&conn, tokio::io::BufReader::new(reader), true).await }
+        });
+        writer
+            .write_all(format!("{rows}\n").as_bytes())
+            .await
+            .unwrap();
+        let updated = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            sqlx::query!(
+                "UPDATE users SET email = 'changed@example.com' WHERE id = $1",
+                user_id
+            )
+            .execute(&conn),
+        )
+        .await
+        .expect("the dry run locked the users")
+        .unwrap();
+        assert_eq!(updated.rows_affected(), 1);
+        writer
+            .write_all(format!("{end}\n").as_bytes())
+            .await
+            .unwrap();
+        drop(writer);
+
+        let report = import.await.unwrap().unwrap();
+        assert_eq!(report.problem_count, 0, "{:?}", report.problems);
+        assert!(!report.applied);
+        assert_eq!(report.replaced_users, 1);
+        assert_eq!(report.tables.get("users"), Some(&1));
+        assert_eq!(report.tables.get("local_events"), Some(&1));
+        let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", user_id)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        assert_eq!(email, "changed@example.com");
+    }
+
+    #[sqlx::test]
+    async fn dry_runs_check_the_constraints(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let user_id = insert_user(&conn, "user@example.com").await;
+        insert_local_event(&conn, user_id, "event").await;
+        let mut backup = Vec::new();
+        write_backup(&conn, &backup_header(&data), &mut backup)
+            .await
+            .unwrap();
+        let without_users = String::from_utf8(backup)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with(r#"{"table":"users""#))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let report = import_backup(&conn, without_users.as_bytes(), true)
+            .await
+            .unwrap();
+        assert!(report
+            .problems
+            .iter()
+            .any(|problem| problem.table.as_deref() == Some("local_events")
+                && problem.message.contains("foreign key")));
+    }
+}
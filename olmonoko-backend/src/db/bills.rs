@@ -0,0 +1,347 @@
+use actix_web::web;
+
+use olmonoko_common::{
+    models::{
+        bills::{
+            reminder::{RawReminderSettings, ReminderSettings},
+            statement::{reconcile, Reconciliation, Transaction},
+            Bill, BillFilter, BillWithEvent, NewBillWithEvent, RawBill,
+        },
+        event::local::{LocalEvent, RawLocalEvent},
+        user::UserId,
+    },
+    utils::time::{from_date_form, timestamp},
+    AppState,
+};
+
+use super::request::{InternalServerError, OrInternalServerError};
+
+/// Bills of the user along with the events they're due on, ordered by due date.
+/// `due_after` and `due_before` are the already parsed bounds of the filter's dates.
+pub async fn get_user_bills(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    filter: &BillFilter,
+    due_after: Option<i64>,
+    due_before: Option<i64>,
+) -> Result<Vec<BillWithEvent>, InternalServerError<sqlx::Error>> {
+    let payee_like = filter.payee.as_ref().map(|payee| format!("%{payee}%"));
+    let bills = sqlx::query!(
+        r#"
+            SELECT bill.*, summary AS "summary!", due_at
+            FROM bills AS bill
+            INNER JOIN LATERAL (
+                SELECT event.summary, event.starts_at AS due_at
+                FROM local_events AS event
+                WHERE event.id = bill.local_event_id
+                UNION ALL
+                SELECT event.summary, (
+                    SELECT MIN(o.starts_at) FROM event_occurrences AS o WHERE o.event_id = event.id
+                ) AS due_at
+                FROM events AS event
+                WHERE event.id = bill.remote_event_id
+            ) AS event ON TRUE
+            WHERE bill.user_id = $1
+                AND ($2::bigint IS NULL OR due_at >= $2)
+                AND ($3::bigint IS NULL OR due_at < $3)
+                AND ($4::text IS NULL OR bill.payee_name ILIKE $4 OR bill.payee_account_number ILIKE $4)
+            ORDER BY due_at, bill.id
+        "#,
+        user_id,
+        due_after,
+        due_before,
+        payee_like,
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query bills from db")?
+    .into_iter()
+    .map(|bill| {
+        BillWithEvent::from((
+            RawBill {
+                id: bill.id,
+                user_id: bill.user_id,
+                local_event_id: bill.local_event_id,
+                remote_event_id: bill.remote_event_id,
+                payee_account_number: bill.payee_account_number,
+                amount: bill.amount,
+                currency: bill.currency,
+                reference: bill.reference,
+                payee_name: bill.payee_name,
+                payee_email: bill.payee_email,
+                payee_address: bill.payee_address,
+                payee_phone: bill.payee_phone,
+                payee_bic: bill.payee_bic,
+                paid_at: bill.paid_at,
+                created_at: bill.created_at,
+                updated_at: bill.updated_at,
+            },
+            bill.summary,
+            bill.due_at,
+        ))
+    })
+    .filter(|bill| filter.status.is_none_or(|status| bill.status == status))
+    .collect();
+    Ok(bills)
+}
+
+/// Inserts a bill decoded from a barcode, QR code or e-invoice along with the event it's due on
+pub async fn insert_bill_with_event(
+    data: &web::Data<AppState>,
+    (new_event, new_bill): NewBillWithEvent,
+) -> Result<(LocalEvent, RawBill), InternalServerError<sqlx::Error>> {
+    let mut txn = data
+        .conn
+        .begin()
+        .await
+        .or_internal_server_error("Failed to begin transaction")?;
+    let inserted_event = sqlx::query_as!(
+        RawLocalEvent,
+        r#"
+            INSERT INTO local_events (user_id, priority, starts_at, all_day, duration, summary, description, location, uid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING *
+        "#,
+        new_event.user_id,
+        new_event.priority,
+        new_event.starts_at,
+        new_event.all_day,
+        new_event.duration,
+        new_event.summary,
+        new_event.description,
+        new_event.location,
+        new_event.uid
+    )
+    .fetch_one(&mut *txn)
+    .await
+    .map(LocalEvent::from)
+    .or_internal_server_error("Failed to insert new local event")?;
+
+    for tag in new_event.tags {
+        sqlx::query!(
+            "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, $2)",
+            inserted_event.id,
+            tag
+        )
+        .execute(&mut *txn)
+        .await
+        .or_internal_server_error("Failed to insert tag")?;
+    }
+
+    let inserted_bill = sqlx::query_as!(
+        RawBill,
+        r#"
+            INSERT INTO bills (user_id, local_event_id, payee_account_number, amount, currency, reference, payee_name, payee_email, payee_address, payee_phone, payee_bic, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12)
+            RETURNING *
+        "#,
+        new_event.user_id,
+        inserted_event.id,
+        new_bill.payee_account_number,
+        new_bill.amount,
+        new_bill.currency,
+        new_bill.reference,
+        new_bill.payee_name,
+        new_bill.payee_email,
+        new_bill.payee_address,
+        new_bill.payee_phone,
+        new_bill.payee_bic,
+        timestamp()
+    )
+    .fetch_one(&mut *txn)
+    .await
+    .or_internal_server_error("Failed to insert new bill")?;
+
+    txn.commit()
+        .await
+        .or_internal_server_error("Failed to commit transaction")?;
+    crate::calendar_io::export_cache::invalidate_user(new_event.user_id);
+    Ok((inserted_event, inserted_bill))
+}
+
+/// Marks the unpaid bills paid by the transactions of a statement as paid on their booking date,
+/// optionally creating events for the rest of the transactions
+pub async fn reconcile_statement(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    tz_offset: i8,
+    transactions: &[Transaction],
+    create_events: bool,
+) -> Result<Reconciliation, InternalServerError<sqlx::Error>> {
+    let mut txn = data
+        .conn
+        .begin()
+        .await
+        .or_internal_server_error("Failed to begin transaction")?;
+    let bills: Vec<Bill> = sqlx::query_as!(
+        RawBill,
+        "SELECT * FROM bills WHERE user_id = $1 ORDER BY id",
+        user_id
+    )
+    .fetch_all(&mut *txn)
+    .await
+    .or_internal_server_error("Failed to query bills")?
+    .into_iter()
+    .map(Bill::from)
+    .collect();
+
+    let matches = reconcile(transactions, &bills);
+    let now = timestamp();
+    for (bill_id, transaction) in &matches {
+        let paid_at = from_date_form(&transaction.booked_on.to_string(), tz_offset)
+            .map(|paid_at| paid_at.timestamp());
+        sqlx::query!(
+            "UPDATE bills SET paid_at = $2, updated_at = $3 WHERE id = $1",
+            bill_id,
+            paid_at,
+            now
+        )
+        .execute(&mut *txn)
+        .await
+        .or_internal_server_error("Failed to mark bill as paid")?;
+    }
+
+    // payments of bills paid before, e.g. by importing the same statement again, aren't unmatched
+    let unmatched: Vec<&Transaction> = transactions
+        .iter()
+        .filter(|transaction| {
+            !matches
+                .iter()
+                .any(|(_, matched)| std::ptr::eq(*matched, *transaction))
+                && !bills
+                    .iter()
+                    .any(|bill| bill.paid_at.is_some() && transaction.pays(bill))
+        })
+        .collect();
+    let mut events_created = 0;
+    if create_events {
+        for transaction in &unmatched {
+            let uid = transaction.uid();
+            let exists = sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM local_events WHERE user_id = $1 AND uid = $2) AS "exists!""#,
+                user_id,
+                uid
+            )
+            .fetch_one(&mut *txn)
+            .await
+            .or_internal_server_error("Failed to query transaction events")?;
+            if exists {
+                continue;
+            }
+            let starts_at = transaction
+                .booked_on
+                .and_time(chrono::NaiveTime::MIN)
+                .and_utc()
+                .timestamp();
+            let event_id = sqlx::query_scalar!(
+                r#"
+                    INSERT INTO local_events (user_id, starts_at, all_day, summary, description, uid)
+                    VALUES ($1, $2, true, $3, $4, $5)
+                    RETURNING id
+                "#,
+                user_id,
+                starts_at,
+                transaction.summary(),
+                transaction.description(),
+                uid
+            )
+            .fetch_one(&mut *txn)
+            .await
+            .or_internal_server_error("Failed to insert transaction event")?;
+            sqlx::query!(
+                "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, 'olmonoko::transaction')",
+                event_id
+            )
+            .execute(&mut *txn)
+            .await
+            .or_internal_server_error("Failed to insert tag")?;
+            events_created += 1;
+        }
+    }
+
+    txn.commit()
+        .await
+        .or_internal_server_error("Failed to commit transaction")?;
+    if !matches.is_empty() || events_created > 0 {
+        crate::calendar_io::export_cache::invalidate_user(user_id);
+    }
+    Ok(Reconciliation {
+        paid: matches.iter().map(|(bill_id, _)| *bill_id).collect(),
+        unmatched: unmatched.len(),
+        events_created,
+    })
+}
+
+/// The bill reminder settings of the user, or the defaults if they haven't saved any
+pub async fn get_reminder_settings(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+) -> Result<ReminderSettings, InternalServerError<sqlx::Error>> {
+    let settings = sqlx::query_as!(
+        RawReminderSettings,
+        "SELECT * FROM bill_reminder_settings WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query reminder settings")?
+    .map(ReminderSettings::from)
+    .unwrap_or_default();
+    Ok(settings)
+}
+
+pub async fn set_reminder_settings(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    settings: &ReminderSettings,
+) -> Result<(), InternalServerError<sqlx::Error>> {
+    sqlx::query!(
+        r#"
+            INSERT INTO bill_reminder_settings (user_id, enabled, lead_days, channel, webhook_url, escalate_overdue, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (user_id) DO UPDATE SET
+                enabled = EXCLUDED.enabled,
+                lead_days = EXCLUDED.lead_days,
+                channel = EXCLUDED.channel,
+                webhook_url = EXCLUDED.webhook_url,
+                escalate_overdue = EXCLUDED.escalate_overdue,
+                updated_at = EXCLUDED.updated_at
+        "#,
+        user_id,
+        settings.enabled,
+        settings.lead_days,
+        settings.channel.to_string(),
+        settings.webhook_url,
+        settings.escalate_overdue,
+        timestamp()
+    )
+    .execute(&data.conn)
+    .await
+    .or_internal_server_error("Failed to save reminder settings")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{insert_local_event, insert_user};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn bill_defaults_are_in_seconds(conn: PgPool) {
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let event_id = insert_local_event(&conn, user_id, "bill").await;
+        let bill = sqlx::query_as!(
+            RawBill,
+            "INSERT INTO bills (user_id, local_event_id, payee_account_number, amount, reference) VALUES ($1, $2, 'FI2112345600000785', 100, '13') RETURNING *",
+            user_id,
+            event_id
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        let now = timestamp();
+        assert!((bill.created_at - now).abs() < 60);
+        assert!((bill.updated_at - now).abs() < 60);
+    }
+}
@@ -7,7 +7,7 @@ use olmonoko_common::{
         bills::RawBill,
         event::{
             local::{LocalEvent, RawLocalEvent},
-            remote::{RawRemoteEvent, RemoteEvent},
+            remote::{RawRemoteEvent, RemoteEvent, RemoteEventId},
             Event, EventOccurrence, Priority, DEFAULT_PRIORITY,
         },
         user::UserId,
@@ -17,11 +17,46 @@ use olmonoko_common::{
 
 use olmonoko_common::utils::event_filters::EventFilter;
 
+use super::request::{InternalServerError, OrInternalServerError};
+
+/// Whose events are visible to a user besides their own local events and sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventScope {
+    /// Nothing else, for what members share with their groups
+    Owned,
+    /// The public sources the user follows, for their export links
+    Followed,
+    /// The public sources the user follows and the calendars and sources other users have
+    /// shared with them. Only for the user themselves, shares must not be passed on.
+    Shared,
+}
+
+/// Local events the user owns
 pub async fn get_user_local_events(
     data: &web::Data<AppState>,
     user_id: UserId,
     autodescription: bool,
     filter: &EventFilter,
+) -> Vec<LocalEvent> {
+    query_local_events(data, user_id, false, autodescription, filter).await
+}
+
+/// Local events of the calendars other users have shared with the user
+pub async fn get_shared_local_events(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    autodescription: bool,
+    filter: &EventFilter,
+) -> Vec<LocalEvent> {
+    query_local_events(data, user_id, true, autodescription, filter).await
+}
+
+async fn query_local_events(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    shared: bool,
+    autodescription: bool,
+    filter: &EventFilter,
 ) -> Vec<LocalEvent> {
     let min_priority = parse_priority(filter.min_priority);
     let max_priority = parse_priority(filter.max_priority);
@@ -32,12 +67,16 @@ pub async fn get_user_local_events(
             bill.payee_account_number as "payee_account_number?", 
             bill.reference as "reference?", 
             bill.amount as "amount?",
+            bill.currency as "currency?",
             bill.created_at as "bill_created_at?", 
             bill.updated_at as "bill_updated_at?",
             bill.payee_name as "payee_name?",
             bill.payee_email as "payee_email?",
             bill.payee_address as "payee_address?",
             bill.payee_phone as "payee_phone?",
+            bill.payee_bic as "payee_bic?",
+            bill.user_id as "bill_user_id?",
+            bill.paid_at as "paid_at?",
             STRING_AGG(tag.tag, ',') AS tags,
             attendance.id as "attendance_id?",
             attendance.planned as "planned?",
@@ -49,9 +88,14 @@ pub async fn get_user_local_events(
             ON bill.local_event_id = event.id 
         LEFT JOIN attendance
             ON attendance.local_event_id = event.id
+            AND attendance.user_id = $1
         LEFT JOIN event_tags AS tag 
             ON tag.local_event_id = event.id
-        WHERE event.user_id = $1 
+        WHERE ((NOT $12 AND event.user_id = $1) OR ($12 AND event.user_id IN (
+                SELECT share.owner_id
+                FROM calendar_shares AS share
+                WHERE share.grantee_id = $1 AND share.ics_source_id IS NULL
+            )))
             AND ($2::bigint IS NULL OR event.starts_at + COALESCE(event.duration, 0) > $2)
             AND ($3::bigint IS NULL OR event.starts_at < $3) 
             AND (COALESCE(NULLIF(event.priority, 0), $6) >= $4 OR $4 IS NULL)
@@ -79,7 +123,8 @@ pub async fn get_user_local_events(
         filter.tags.as_deref(),
         filter.exclude_tags.as_deref(),
         filter.attendance_planned,
-        filter.attendance_actual
+        filter.attendance_actual,
+        shared
     )
     .fetch_all(&data.conn)
     .await
@@ -88,7 +133,7 @@ pub async fn get_user_local_events(
     .map(|event| {
         let raw_event = RawLocalEvent {
             id: event.id,
-            user_id,
+            user_id: event.user_id,
             created_at: event.created_at,
             updated_at: event.updated_at,
             priority: event.priority,
@@ -100,20 +145,25 @@ pub async fn get_user_local_events(
             duration: event.duration,
             location: event.location,
             description: event.description,
+            timer_template_id: event.timer_template_id,
         };
         let raw_bill = event.bill_id.map(|bill_id| RawBill {
             id: bill_id,
+            user_id: event.bill_user_id.unwrap(),
             local_event_id: Some(event.id),
             remote_event_id: None,
             payee_account_number: event.payee_account_number.unwrap(),
             reference: event.reference.unwrap(),
             amount: event.amount.unwrap(),
+            currency: event.currency.unwrap(),
             created_at: event.bill_created_at.unwrap(),
             updated_at: event.bill_updated_at.unwrap(),
             payee_name: event.payee_name,
             payee_email: event.payee_email,
             payee_address: event.payee_address,
             payee_phone: event.payee_phone,
+            payee_bic: event.payee_bic,
+            paid_at: event.paid_at,
         });
         let attendance = event
             .attendance_id
@@ -123,7 +173,7 @@ pub async fn get_user_local_events(
                 updated_at: event.attendance_updated_at.unwrap(),
                 planned: event.planned.unwrap(),
                 actual: event.actual.unwrap(),
-                user_id: event.user_id,
+                user_id,
                 local_event_id: Some(event.id),
                 remote_event_id: None,
             })
@@ -155,6 +205,7 @@ pub fn parse_priority(priority: Option<i32>) -> Option<Priority> {
 async fn get_visible_remote_events(
     data: &web::Data<AppState>,
     user_id: Option<UserId>,
+    scope: EventScope,
     filter: &EventFilter,
 ) -> Vec<(RemoteEvent, i64, bool)> {
     let min_priority = parse_priority(filter.min_priority);
@@ -177,7 +228,11 @@ async fn get_visible_remote_events(
         INNER JOIN 
             ics_sources AS s 
             ON e.event_source_id = s.id 
-            AND (s.user_id = $1 OR s.is_public)
+            AND (s.user_id = $1 OR ($12 AND s.is_public) OR ($13 AND EXISTS (
+                SELECT 1
+                FROM calendar_shares AS share
+                WHERE share.ics_source_id = s.id AND share.grantee_id = $1
+            )))
         INNER JOIN 
             event_occurrences AS o 
             ON o.event_id = e.id 
@@ -193,6 +248,7 @@ async fn get_visible_remote_events(
             ON tag.remote_event_id = e.id
         LEFT JOIN attendance
             ON attendance.remote_event_id = e.id
+            AND attendance.user_id = $1
         WHERE 
             ($2::bigint IS NULL OR o.starts_at + COALESCE(e.duration, 0) > $2::bigint) 
             AND ($3::bigint IS NULL OR o.starts_at < $3) 
@@ -220,6 +276,8 @@ async fn get_visible_remote_events(
         filter.exclude_tags.as_deref(),
         filter.attendance_planned,
         filter.attendance_actual,
+        scope != EventScope::Owned,
+        scope == EventScope::Shared,
     )
     .fetch_all(&data.conn)
     .await
@@ -264,11 +322,12 @@ async fn get_visible_remote_events(
 pub async fn get_visible_events(
     data: &web::Data<AppState>,
     user_id: Option<UserId>,
+    scope: EventScope,
     autodescription: bool,
     filter: &EventFilter,
 ) -> Vec<Event> {
     // remote
-    let remote_events = get_visible_remote_events(data, user_id, filter).await;
+    let remote_events = get_visible_remote_events(data, user_id, scope, filter).await;
     // NOTE: Add documentation, what does this do?
     // Does it just form Events from RemoteEvents?
     let mut events: Vec<Event> = remote_events
@@ -299,6 +358,13 @@ pub async fn get_visible_events(
                 .map(Event::from)
                 .collect();
         events.extend(local_events);
+        if scope == EventScope::Shared {
+            let shared_events = get_shared_local_events(data, user_id, autodescription, filter)
+                .await
+                .into_iter()
+                .map(Event::from);
+            events.extend(shared_events);
+        }
     }
     events.sort_by_key(|event| {
         event
@@ -312,13 +378,130 @@ pub async fn get_visible_events(
 pub async fn get_visible_event_occurrences(
     data: &web::Data<AppState>,
     user_id: Option<UserId>,
+    scope: EventScope,
     autodescription: bool,
     filter: &EventFilter,
 ) -> Vec<EventOccurrence> {
-    let events = get_visible_events(data, user_id, autodescription, filter).await;
+    let events = get_visible_events(data, user_id, scope, autodescription, filter).await;
     events
         .into_iter()
         .flat_map(Vec::<EventOccurrence>::from)
         .sorted_by_key(|event| event.starts_at.timestamp())
         .collect()
 }
+
+/// Whether a remote event comes from a source the user can see
+pub async fn is_remote_event_visible(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    event_id: RemoteEventId,
+) -> Result<bool, InternalServerError<sqlx::Error>> {
+    sqlx::query_scalar!(
+        r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM events AS e
+                INNER JOIN ics_sources AS s ON s.id = e.event_source_id
+                WHERE e.id = $2 AND (s.user_id = $1 OR s.is_public OR s.id IN (
+                    SELECT ics_source_id FROM calendar_shares WHERE grantee_id = $1
+                ))
+            ) AS "visible!"
+        "#,
+        user_id,
+        event_id
+    )
+    .fetch_one(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query remote event visibility from db")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{
+        add_source_to_calendar, app_state, insert_local_event, insert_source, insert_user, share,
+    };
+    use sqlx::PgPool;
+
+    async fn summaries(
+        data: &web::Data<AppState>,
+        user_id: UserId,
+        scope: EventScope,
+    ) -> Vec<String> {
+        get_visible_events(data, Some(user_id), scope, false, &EventFilter::default())
+            .await
+            .into_iter()
+            .map(|event| event.summary)
+            .sorted()
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn shared_calendars_are_only_visible_to_the_grantee(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let owner = insert_user(&conn, "owner@example.com").await;
+        let grantee = insert_user(&conn, "grantee@example.com").await;
+        let other = insert_user(&conn, "other@example.com").await;
+        insert_local_event(&conn, owner, "owner local").await;
+        insert_local_event(&conn, grantee, "grantee local").await;
+        let private_source = insert_source(&conn, owner, false, "owner private").await;
+        let public_source = insert_source(&conn, other, true, "other public").await;
+        add_source_to_calendar(&conn, grantee, public_source).await;
+        add_source_to_calendar(&conn, grantee, private_source).await;
+        share(&conn, owner, grantee, None, "read").await;
+        share(&conn, owner, grantee, Some(private_source), "read").await;
+
+        assert_eq!(
+            summaries(&data, grantee, EventScope::Shared).await,
+            [
+                "grantee local",
+                "other public",
+                "owner local",
+                "owner private"
+            ]
+        );
+        // what ends up in the grantee's export links
+        assert_eq!(
+            summaries(&data, grantee, EventScope::Followed).await,
+            ["grantee local", "other public"]
+        );
+        assert_eq!(
+            summaries(&data, grantee, EventScope::Owned).await,
+            ["grantee local"]
+        );
+        let own_events =
+            get_user_local_events(&data, grantee, false, &EventFilter::default()).await;
+        assert_eq!(own_events.len(), 1);
+        // sharing doesn't go both ways
+        assert_eq!(
+            summaries(&data, owner, EventScope::Shared).await,
+            ["owner local", "owner private"]
+        );
+        assert_eq!(
+            summaries(&data, other, EventScope::Shared).await,
+            ["other public"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn unshared_private_sources_stay_hidden(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let owner = insert_user(&conn, "owner@example.com").await;
+        let user = insert_user(&conn, "user@example.com").await;
+        let source = insert_source(&conn, owner, false, "owner private").await;
+        let event = sqlx::query_scalar!("SELECT id FROM events WHERE event_source_id = $1", source)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        // even with the source in their calendar
+        add_source_to_calendar(&conn, user, source).await;
+        assert!(summaries(&data, user, EventScope::Shared).await.is_empty());
+        assert!(!is_remote_event_visible(&data, user, event).await.unwrap());
+        share(&conn, owner, user, Some(source), "read").await;
+        assert!(is_remote_event_visible(&data, user, event).await.unwrap());
+        assert_eq!(
+            summaries(&data, user, EventScope::Shared).await,
+            ["owner private"]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use actix_web::web;
+use itertools::Itertools;
+
+use olmonoko_common::{
+    models::{
+        event::{EventOccurrence, Priority},
+        group::{Group, GroupId, GroupMember, RawGroup, RawGroupMember},
+        public_link::{PublicLink, RawPublicLink},
+        user::UserId,
+    },
+    utils::event_filters::EventFilter,
+    AppState,
+};
+
+use super::{
+    events::{get_visible_events, parse_priority, EventScope},
+    request::{InternalServerError, OrInternalServerError},
+};
+
+/// Groups the user is a member of
+pub async fn get_user_groups(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+) -> Result<Vec<Group>, InternalServerError<sqlx::Error>> {
+    sqlx::query_as!(
+        RawGroup,
+        r#"
+            SELECT g.*
+            FROM calendar_groups AS g
+            INNER JOIN calendar_group_members AS m ON m.group_id = g.id
+            WHERE m.user_id = $1
+            ORDER BY g.name
+        "#,
+        user_id
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query user groups from db")
+    .map(|groups| groups.into_iter().map(Group::from).collect())
+}
+
+/// Fetches a group, if the user is a member of it
+pub async fn get_group_as_member(
+    data: &web::Data<AppState>,
+    group_id: GroupId,
+    user_id: UserId,
+) -> Result<Option<Group>, InternalServerError<sqlx::Error>> {
+    sqlx::query_as!(
+        RawGroup,
+        r#"
+            SELECT g.*
+            FROM calendar_groups AS g
+            INNER JOIN calendar_group_members AS m ON m.group_id = g.id
+            WHERE g.id = $1 AND m.user_id = $2
+        "#,
+        group_id,
+        user_id
+    )
+    .fetch_optional(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query group from db")
+    .map(|group| group.map(Group::from))
+}
+
+pub async fn get_group_members(
+    data: &web::Data<AppState>,
+    group_id: GroupId,
+) -> Result<Vec<GroupMember>, InternalServerError<sqlx::Error>> {
+    sqlx::query!(
+        r#"
+            SELECT m.*, u.email
+            FROM calendar_group_members AS m
+            INNER JOIN users AS u ON u.id = m.user_id
+            WHERE m.group_id = $1
+            ORDER BY m.created_at
+        "#,
+        group_id
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query group members from db")
+    .map(|members| {
+        members
+            .into_iter()
+            .map(|m| {
+                GroupMember::from((
+                    RawGroupMember {
+                        group_id: m.group_id,
+                        user_id: m.user_id,
+                        colour: m.colour,
+                        share_events: m.share_events,
+                        min_priority: m.min_priority,
+                        max_priority: m.max_priority,
+                        created_at: m.created_at,
+                    },
+                    m.email,
+                ))
+            })
+            .collect()
+    })
+}
+
+pub async fn get_group_export_links(
+    data: &web::Data<AppState>,
+    group_id: GroupId,
+) -> Result<Vec<PublicLink>, InternalServerError<sqlx::Error>> {
+    sqlx::query_as!(
+        RawPublicLink,
+        "SELECT * FROM public_calendar_links WHERE group_id = $1",
+        group_id
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query group export links from db")
+    .map(|links| links.into_iter().map(PublicLink::from).collect())
+}
+
+/// Picks the stricter of two priority bounds, `pick` being `max` for lower bounds and `min` for upper ones
+fn narrow(
+    a: Option<Priority>,
+    b: Option<Priority>,
+    pick: fn(Priority, Priority) -> Priority,
+) -> Option<Priority> {
+    match (parse_priority(a), parse_priority(b)) {
+        (Some(a), Some(b)) => Some(pick(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Merges the events every opted-in member shares with the group: their own local events and
+/// the events of the sources they own. Calendars shared with a member aren't theirs to pass on.
+pub async fn get_group_event_occurrences(
+    data: &web::Data<AppState>,
+    members: &[GroupMember],
+    autodescription: bool,
+    filter: &EventFilter,
+) -> Vec<EventOccurrence> {
+    let mut events = vec![];
+    for member in members.iter().filter(|m| m.share_events) {
+        let member_filter = EventFilter {
+            min_priority: narrow(filter.min_priority, member.min_priority, Priority::max),
+            max_priority: narrow(filter.max_priority, member.max_priority, Priority::min),
+            ..filter.clone()
+        };
+        let member_events = get_visible_events(
+            data,
+            Some(member.user_id),
+            EventScope::Owned,
+            autodescription,
+            &member_filter,
+        )
+        .await;
+        for mut event in member_events {
+            event.source = event.source.into_group(member.group_id, member.user_id);
+            events.push(event);
+        }
+    }
+    events
+        .into_iter()
+        .flat_map(Vec::<EventOccurrence>::from)
+        .sorted_by_key(|event| event.starts_at.timestamp())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::insert_user;
+    use olmonoko_common::utils::time::timestamp;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn group_defaults_are_in_seconds(conn: PgPool) {
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let group = sqlx::query_as!(
+            RawGroup,
+            "INSERT INTO calendar_groups (owner_id, name) VALUES ($1, 'group') RETURNING *",
+            user_id
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        let member_created_at = sqlx::query_scalar!(
+            "INSERT INTO calendar_group_members (group_id, user_id) VALUES ($1, $2) RETURNING created_at",
+            group.id,
+            user_id
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        let now = timestamp();
+        assert!((group.created_at - now).abs() < 60);
+        assert!((member_created_at - now).abs() < 60);
+    }
+}
@@ -0,0 +1,212 @@
+use olmonoko_common::{
+    models::{
+        login_attempt::{
+            LoginAttempt, RawLoginAttempt, ACCOUNT_THROTTLE, FAILURE_WINDOW, IP_THROTTLE,
+            LOGIN_ATTEMPT_RETENTION,
+        },
+        user::UserId,
+    },
+    utils::time::timestamp,
+};
+use sqlx::{PgConnection, PgPool};
+
+/// Whether logging in may be tried right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginPermit {
+    /// The attempt is recorded as failed until [`finish_login_attempt`] says otherwise
+    Granted(i64),
+    /// The seconds to wait before trying again
+    Throttled(i64),
+}
+
+/// The seconds to wait before trying to log in to the account or from the address.
+/// A successful login resets the count of the account but not of the address,
+/// so that one's own account can't be used to guess others.
+async fn login_wait(
+    conn: &mut PgConnection,
+    email: &str,
+    ip_address: Option<&str>,
+    now: i64,
+) -> Result<i64, sqlx::Error> {
+    let since = now - FAILURE_WINDOW;
+    let account_failures = sqlx::query_scalar!(
+        r#"
+        SELECT created_at FROM login_attempts
+        WHERE email = $1 AND NOT success AND created_at >= $2
+        AND created_at > COALESCE(
+            (SELECT MAX(created_at) FROM login_attempts WHERE email = $1 AND success), 0
+        )
+        ORDER BY created_at
+        "#,
+        email,
+        since
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut wait = ACCOUNT_THROTTLE.wait(&account_failures, now);
+    if let Some(ip_address) = ip_address {
+        let ip_failures = sqlx::query_scalar!(
+            r#"
+            SELECT created_at FROM login_attempts
+            WHERE ip_address = $1 AND NOT success AND created_at >= $2
+            ORDER BY created_at
+            "#,
+            ip_address,
+            since
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        wait = wait.max(IP_THROTTLE.wait(&ip_failures, now));
+    }
+    Ok(wait)
+}
+
+/// Checks the throttling and records the attempt in one go, so that concurrent attempts
+/// can't all get past the check before any of them is counted.
+pub async fn begin_login_attempt(
+    conn: &PgPool,
+    email: &str,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<LoginPermit, sqlx::Error> {
+    let mut txn = conn.begin().await?;
+    // attempts for the same account or from the same address wait for each other,
+    // always in this order so that they can't deadlock
+    sqlx::query!(
+        "SELECT 1 AS locked FROM pg_advisory_xact_lock(1, hashtext($1))",
+        email
+    )
+    .fetch_one(&mut *txn)
+    .await?;
+    if let Some(ip_address) = ip_address {
+        sqlx::query!(
+            "SELECT 1 AS locked FROM pg_advisory_xact_lock(2, hashtext($1))",
+            ip_address
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+    }
+    let now = timestamp();
+    let wait = login_wait(&mut txn, email, ip_address, now).await?;
+    if wait > 0 {
+        return Ok(LoginPermit::Throttled(wait));
+    }
+    let id = sqlx::query_scalar!(
+        "INSERT INTO login_attempts (email, ip_address, user_agent, success, created_at) VALUES ($1, $2, $3, false, $4) RETURNING id",
+        email,
+        ip_address,
+        user_agent,
+        now
+    )
+    .fetch_one(&mut *txn)
+    .await?;
+    txn.commit().await?;
+    Ok(LoginPermit::Granted(id))
+}
+
+/// Records the outcome of an attempt started with [`begin_login_attempt`]
+pub async fn finish_login_attempt(
+    conn: &PgPool,
+    id: i64,
+    user_id: Option<UserId>,
+    success: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE login_attempts SET user_id = $2, success = $3 WHERE id = $1",
+        id,
+        user_id,
+        success
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Forgets an attempt started with [`begin_login_attempt`] that moved on to the second factor,
+/// which is then recorded as an attempt of its own
+pub async fn cancel_login_attempt(conn: &PgPool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM login_attempts WHERE id = $1", id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Records an attempt that isn't throttled, like a single sign-on
+pub async fn record_login_attempt(
+    conn: &PgPool,
+    email: &str,
+    user_id: Option<UserId>,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+    success: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO login_attempts (email, user_id, ip_address, user_agent, success, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        email,
+        user_id,
+        ip_address,
+        user_agent,
+        success,
+        timestamp()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// The newest attempts first
+pub async fn get_recent_login_attempts(
+    conn: &PgPool,
+    limit: i64,
+) -> Result<Vec<LoginAttempt>, sqlx::Error> {
+    Ok(sqlx::query_as!(
+        RawLoginAttempt,
+        "SELECT * FROM login_attempts ORDER BY created_at DESC, id DESC LIMIT $1",
+        limit
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(LoginAttempt::from)
+    .collect())
+}
+
+pub async fn purge_old_login_attempts(conn: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM login_attempts WHERE created_at < $1",
+        timestamp() - LOGIN_ATTEMPT_RETENTION
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn concurrent_attempts_are_all_counted(conn: PgPool) {
+        let started_at = timestamp();
+        let attempts = (0..20).map(|_| {
+            let conn = conn.clone();
+            tokio::spawn(async move {
+                begin_login_attempt(&conn, "user@example.com", Some("127.0.0.1"), None)
+                    .await
+                    .unwrap()
+            })
+        });
+        let granted = futures_util::future::join_all(attempts)
+            .await
+            .into_iter()
+            .filter(|permit| matches!(permit, Ok(LoginPermit::Granted(_))))
+            .count();
+        // the first throttled attempt only waits a second, so a slow run may let another through
+        let elapsed = usize::try_from(timestamp() - started_at).unwrap();
+        assert!(granted > ACCOUNT_THROTTLE.free_failures);
+        assert!(
+            granted <= ACCOUNT_THROTTLE.free_failures + 1 + elapsed,
+            "{granted} attempts granted in {elapsed} seconds"
+        );
+    }
+}
@@ -1,8 +1,21 @@
 pub mod attendance;
+pub mod backup;
+pub mod bills;
 pub mod errors;
 pub mod events;
+pub mod groups;
 pub mod ical;
+pub mod login_attempts;
+pub mod oidc;
+pub mod reports;
 pub mod request;
+pub mod sessions;
+pub mod shares;
 pub mod sources;
+#[cfg(test)]
+pub mod testing;
 pub mod timeline;
+pub mod timers;
+pub mod two_factor;
 pub mod user;
+pub mod user_backup;
@@ -0,0 +1,144 @@
+use olmonoko_common::{
+    models::{
+        oidc::{RawOidcLogin, OIDC_LOGIN_LIFETIME},
+        user::UserId,
+    },
+    utils::time::timestamp,
+};
+use sqlx::PgPool;
+
+use crate::auth::oidc::{OidcAuthorization, OidcIdentity};
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcUserError {
+    #[error("Database error: {0}")]
+    DbErr(#[from] sqlx::Error),
+    #[error("Failed to hash the placeholder password: {0}")]
+    HashErr(#[from] bcrypt::BcryptError),
+    #[error("The identity provider hasn't verified {0}")]
+    UnverifiedEmail(String),
+}
+
+pub async fn create_oidc_login(
+    conn: &PgPool,
+    authorization: &OidcAuthorization,
+    remember: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO oidc_logins (state, pkce_verifier, nonce, remember, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        authorization.state,
+        authorization.pkce_verifier,
+        authorization.nonce,
+        remember,
+        timestamp() + OIDC_LOGIN_LIFETIME
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Removes the login so that the state can't be used twice, None if it's unknown or expired
+pub async fn take_oidc_login(
+    conn: &PgPool,
+    state: &str,
+) -> Result<Option<RawOidcLogin>, sqlx::Error> {
+    sqlx::query_as!(
+        RawOidcLogin,
+        "DELETE FROM oidc_logins WHERE state = $1 AND expires_at >= $2 RETURNING *",
+        state,
+        timestamp()
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+pub async fn purge_expired_oidc_logins(conn: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM oidc_logins WHERE expires_at < $1", timestamp())
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// The user with the email, ignoring case. If there's none and `auto_provision` is set,
+/// creates one with a random password, which can be replaced with a password reset.
+/// Refuses emails the provider hasn't verified, which could otherwise take over an account
+/// or claim an email before its owner signs up.
+pub async fn get_or_provision_oidc_user(
+    conn: &PgPool,
+    identity: &OidcIdentity,
+    auto_provision: bool,
+) -> Result<Option<UserId>, OidcUserError> {
+    let email = &identity.email;
+    if !identity.email_verified {
+        return Err(OidcUserError::UnverifiedEmail(email.clone()));
+    }
+    let existing = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE LOWER(email) = LOWER($1) ORDER BY id LIMIT 1",
+        email
+    )
+    .fetch_optional(conn)
+    .await?;
+    if existing.is_some() || !auto_provision {
+        return Ok(existing);
+    }
+    let password_hash = bcrypt::hash(uuid::Uuid::new_v4().to_string(), bcrypt::DEFAULT_COST)?;
+    // the first user is the admin, as with registering
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (email, password_hash, admin)
+        VALUES ($1, $2, NOT EXISTS (SELECT 1 FROM users))
+        RETURNING id
+        "#,
+        email,
+        password_hash
+    )
+    .fetch_one(conn)
+    .await?;
+    tracing::info!(
+        user = user_id,
+        "Provisioned user {email} from single sign-on"
+    );
+    Ok(Some(user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::insert_user;
+
+    fn identity(email: &str, email_verified: bool) -> OidcIdentity {
+        OidcIdentity {
+            email: email.to_string(),
+            email_verified,
+        }
+    }
+
+    #[sqlx::test]
+    async fn only_verified_emails_are_accepted(conn: PgPool) {
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let linked = get_or_provision_oidc_user(&conn, &identity("User@example.com", true), false)
+            .await
+            .unwrap();
+        assert_eq!(linked, Some(user_id));
+        let unverified =
+            get_or_provision_oidc_user(&conn, &identity("user@example.com", false), true).await;
+        assert!(matches!(unverified, Err(OidcUserError::UnverifiedEmail(_))));
+        // nor can an unverified email be claimed before its owner signs up
+        let unprovisioned =
+            get_or_provision_oidc_user(&conn, &identity("new@example.com", false), true).await;
+        assert!(matches!(
+            unprovisioned,
+            Err(OidcUserError::UnverifiedEmail(_))
+        ));
+        let users = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM users"#)
+            .fetch_one(&conn)
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+        let provisioned =
+            get_or_provision_oidc_user(&conn, &identity("new@example.com", true), true)
+                .await
+                .unwrap();
+        assert!(provisioned.is_some_and(|id| id != user_id));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use actix_web::web;
+use chrono_tz::Tz;
+
+use olmonoko_common::{
+    models::{
+        bills::{BillFilter, BillId},
+        event::local::LocalEvent,
+        report::{bill_report, time_report, BillReportRow, ReportQuery, TimeReportRow},
+        timesheet::{timesheet, TimesheetQuery, TimesheetRow},
+        user::UserId,
+    },
+    utils::event_filters::EventFilter,
+    AppState,
+};
+
+use super::{
+    bills::get_user_bills,
+    events::get_user_local_events,
+    request::{InternalServerError, OrInternalServerError},
+};
+
+/// The user's own local events between the timestamps
+async fn get_own_events(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    (after, before): (Option<i64>, Option<i64>),
+) -> Vec<LocalEvent> {
+    // filtering by tag would leave the other tags out of the events
+    let filter = EventFilter {
+        after,
+        before,
+        ..Default::default()
+    };
+    get_user_local_events(data, user_id, false, &filter).await
+}
+
+/// Totals of the user's bills, on both local and remote events, and tracked time of the user's
+/// own local events within the range parsed from the query with [`ReportQuery::range`]
+pub async fn get_reports(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    tz: &Tz,
+    (after, before): (Option<i64>, Option<i64>),
+    query: &ReportQuery,
+) -> Result<(Vec<BillReportRow>, Vec<TimeReportRow>), InternalServerError<sqlx::Error>> {
+    let events = get_own_events(data, user_id, (after, before)).await;
+
+    let bills = get_user_bills(data, user_id, &BillFilter::default(), after, before).await?;
+    let bill_ids: Vec<BillId> = bills.iter().map(|bill| bill.bill.id).collect();
+    let mut bill_tags: HashMap<BillId, Vec<String>> = HashMap::new();
+    for row in sqlx::query!(
+        r#"
+            SELECT bill.id, tag.tag
+            FROM bills AS bill
+            INNER JOIN event_tags AS tag
+                ON tag.local_event_id = bill.local_event_id OR tag.remote_event_id = bill.remote_event_id
+            WHERE bill.user_id = $1 AND bill.id = ANY($2)
+            ORDER BY tag.created_at, tag.tag
+        "#,
+        user_id,
+        &bill_ids
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query bill tags")?
+    {
+        bill_tags.entry(row.id).or_default().push(row.tag);
+    }
+
+    let template_ids: Vec<i32> = events
+        .iter()
+        .filter_map(|event| event.timer_template_id)
+        .collect();
+    let template_names: HashMap<i32, String> = sqlx::query!(
+        "SELECT id, summary FROM local_events WHERE user_id = $1 AND id = ANY($2)",
+        user_id,
+        &template_ids
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query timer templates")?
+    .into_iter()
+    .map(|template| (template.id, template.summary))
+    .collect();
+
+    Ok((
+        bill_report(&bills, query.bills_by, tz, &bill_tags),
+        time_report(&events, query.time_by, tz, &template_names),
+    ))
+}
+
+/// The entries of the user's stopped timers within the range parsed from the query
+/// with [`TimesheetQuery::range`]
+pub async fn get_timesheet(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    tz: &Tz,
+    range: (Option<i64>, Option<i64>),
+    query: &TimesheetQuery,
+) -> Vec<TimesheetRow> {
+    let events = get_own_events(data, user_id, range).await;
+    timesheet(&events, query, tz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{app_state, insert_local_event, insert_source, insert_user};
+    use olmonoko_common::models::report::BillGrouping;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn bills_on_remote_events_are_reported(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let local_event_id = insert_local_event(&conn, user_id, "local").await;
+        let source_id = insert_source(&conn, user_id, false, "remote").await;
+        let remote_event_id = sqlx::query_scalar!(
+            "SELECT id FROM events WHERE event_source_id = $1",
+            source_id
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO event_tags (remote_event_id, tag) VALUES ($1, 'rent')",
+            remote_event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO bills (user_id, local_event_id, payee_account_number, amount, reference) VALUES ($1, $2, 'FI2112345600000785', 100, '13')",
+            user_id,
+            local_event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO bills (user_id, remote_event_id, payee_account_number, amount, reference) VALUES ($1, $2, 'FI2112345600000785', 250, '13')",
+            user_id,
+            remote_event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+
+        let query = ReportQuery {
+            bills_by: BillGrouping::Tag,
+            ..Default::default()
+        };
+        let (rows, _) = get_reports(&data, user_id, &Tz::UTC, (None, None), &query)
+            .await
+            .unwrap();
+        let totals: Vec<_> = rows
+            .iter()
+            .map(|row| (row.key.as_str(), row.bills, row.unpaid))
+            .collect();
+        assert_eq!(totals, [("(untagged)", 1, 100), ("rent", 1, 250)]);
+    }
+}
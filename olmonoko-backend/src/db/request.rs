@@ -1,8 +1,14 @@
+use std::net::IpAddr;
+use std::sync::LazyLock;
+
 use actix_web::{
     body::BoxBody, web, HttpRequest, HttpResponse, HttpResponseBuilder, ResponseError,
 };
 use olmonoko_common::{
-    models::{api_key::{ApiKey, RawApiKey}, timer::{RawTimer, Timer}},
+    models::{
+        api_key::{ApiKey, RawApiKey},
+        timer::Timer,
+    },
     utils::{
         flash::{FlashMessage, FLASH_COOKIE_NAME},
         time::timestamp,
@@ -19,14 +25,55 @@ use olmonoko_common::{
 };
 use uuid::Uuid;
 
+use crate::auth::oidc::OIDC_PROVIDER;
+
+use super::sessions::renew_session;
+use super::timers::get_user_timers;
+
 pub const SESSION_COOKIE_NAME: &str = "session_id";
+/// Holds a login waiting for the second factor
+pub const LOGIN_CHALLENGE_COOKIE_NAME: &str = "login_challenge";
 pub const API_KEY_HEADER_NAME: &str = "X-OLMONOKO-API-KEY";
 pub const RESPONSE_TYPE_HEADER: &str = "HX-Request";
 
+/// The reverse proxies whose `X-Forwarded-For` is trusted, from the comma separated addresses
+/// in `TRUSTED_PROXIES`. Without any, the address of the connection is used as is.
+static TRUSTED_PROXIES: LazyLock<Vec<IpAddr>> = LazyLock::new(|| {
+    std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .filter_map(|proxy| match proxy.parse() {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                tracing::error!("Ignoring invalid trusted proxy {proxy}: {e}");
+                None
+            }
+        })
+        .collect()
+});
+
+/// The address of the client. Each proxy appends the address it got the request from, so the
+/// header is read from the end, and only as long as the address is one of the trusted proxies.
+fn client_ip(peer: IpAddr, forwarded_for: &str, trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => client = hop,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
 pub async fn get_user_from_request(
     data: &web::Data<AppState>,
     req: &HttpRequest,
-) -> Option<(User, Option<ApiKey>, Option<Timer>)> {
+) -> Option<(User, Option<ApiKey>, Vec<Timer>)> {
     let session_cookie = req.cookie(SESSION_COOKIE_NAME);
     match session_cookie {
         None => {
@@ -76,7 +123,7 @@ pub async fn get_user_from_request(
                     };
                     let user = User::from(raw_user);
 
-                    return Some((user, Some(api_key), None));
+                    return Some((user, Some(api_key), vec![]));
                 }
             }
 
@@ -91,29 +138,27 @@ pub async fn get_user_from_request(
                     users.password_hash AS user_password_hash,
                     users.admin AS user_admin,
                     users.created_at AS user_created_at,
-                    users.interface_timezone AS user_interface_timezone,
-                    timers.id AS "timer_id?",
-                    timers.template AS "timer_template?",
-                    timers.summary AS "timer_summary?",
-                    timers.details AS "timer_details?",
-                    timers.location AS "timer_location?",
-                    timers.created_at AS "timer_created_at?"
+                    users.interface_timezone AS user_interface_timezone
                 FROM sessions
                     INNER JOIN users 
                         ON users.id = sessions.user_id 
-                    LEFT JOIN timers 
-                        ON timers.user_id = users.id 
                 WHERE sessions.id = $1"#r,
                 session_id
             )
             .fetch_optional(&data.conn)
             .await
-            .unwrap().map(|row| {
+            .unwrap()
+            .map(|row| {
                 let session = SessionRaw {
                     id: row.id,
                     user_id: row.user_id,
                     created_at: row.created_at,
-                    expires_at: row.expires_at
+                    expires_at: row.expires_at,
+                    handle: row.handle,
+                    last_seen_at: row.last_seen_at,
+                    user_agent: row.user_agent,
+                    ip_address: row.ip_address,
+                    remember: row.remember,
                 };
                 let user = User::from(RawUser {
                     id: row.user_id,
@@ -121,34 +166,49 @@ pub async fn get_user_from_request(
                     password_hash: row.user_password_hash,
                     admin: row.user_admin,
                     created_at: row.user_created_at,
-                    interface_timezone: row.user_interface_timezone
+                    interface_timezone: row.user_interface_timezone,
                 });
-                let timer = row.timer_id.map(|timer_id| {
-                    RawTimer {
-                        id: timer_id,
-                        user_id: user.id,
-                        created_at: row.timer_created_at.expect("Timer id is set, so should timer created_at"),
-                        summary: row.timer_summary,
-                        details: row.timer_details,
-                        location: row.timer_location,
-                        template: row.timer_template.expect("Timer id is set, so should timer template")
-                    }
-                }).map(Timer::from);
-                (session, user, timer)
+                (session, user)
             });
-            if let Some((session, user, timer)) = result {
-                if session.expires_at < timestamp() {
+            if let Some((session, user)) = result {
+                let now = timestamp();
+                if session.expires_at < now {
                     return None;
                 }
+                if session.needs_renewal(now) {
+                    // the session is still valid until it expires, so it's renewed on a later request
+                    if let Err(e) = renew_session(
+                        &data.conn,
+                        &session,
+                        req.get_user_agent().as_deref(),
+                        req.get_client_ip().as_deref(),
+                    )
+                    .await
+                    {
+                        tracing::error!("Failed to renew session of user {}: {e}", user.id);
+                    }
+                }
+                // the page can still be shown without the timers
+                let timers = get_user_timers(&data.conn, user.id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Failed to fetch timers of user {}: {e}", user.id);
+                        Vec::new()
+                    });
                 // No api key attached to this request
-                return Some((user, None, timer));
+                return Some((user, None, timers));
             }
             None
         }
     }
 }
 
-pub(crate) type SessionContext = (tera::Context, Option<UserPublic>, Option<ApiKey>, Option<Timer>);
+pub(crate) type SessionContext = (
+    tera::Context,
+    Option<UserPublic>,
+    Option<ApiKey>,
+    Vec<Timer>,
+);
 pub(crate) async fn get_session_context(
     data: &web::Data<AppState>,
     request: &HttpRequest,
@@ -159,9 +219,9 @@ pub(crate) async fn get_session_context(
     let user_with_key = get_user_from_request(data, request)
         .await
         .map(|(u, k, t)| (UserPublic::from(u), k, t));
-    let api_key = user_with_key.clone().map(|(_, k, _)| k).flatten();
+    let api_key = user_with_key.clone().and_then(|(_, k, _)| k);
     let user = user_with_key.clone().map(|(u, _, _)| u);
-    let timer = user_with_key.map(|(_, _, t)| t).flatten();
+    let timers = user_with_key.map(|(_, _, t)| t).unwrap_or_default();
     let path = request.path();
     let root_path = request.path().split('/').nth(1).unwrap_or("");
     let mut context = tera::Context::new();
@@ -171,8 +231,12 @@ pub(crate) async fn get_session_context(
     context.insert("version", &data.version);
     context.insert("flash", &flash_message);
     context.insert("user", &user);
-    context.insert("timer_active", &timer);
+    context.insert("timers", &timers);
     context.insert("event_priority_options", &PRIORITY_OPTIONS);
+    context.insert(
+        "oidc_provider",
+        &OIDC_PROVIDER.as_ref().map(|provider| &provider.name),
+    );
     let mut nav_entries = vec![];
     if let Some(user) = user.clone() {
         if user.admin {
@@ -193,13 +257,15 @@ pub(crate) async fn get_session_context(
     }
     nav_entries.sort_by_key(|e| e.position);
     context.insert("nav_entries", &nav_entries);
-    (context, user, api_key, timer)
+    (context, user, api_key, timers)
 }
 
 #[allow(async_fn_in_trait)]
 pub trait EnhancedRequest {
     fn get_referer(&self) -> Option<&str>;
     fn get_session_id(&self) -> Option<String>;
+    fn get_user_agent(&self) -> Option<String>;
+    fn get_client_ip(&self) -> Option<String>;
     async fn get_session_user(&self, data: &web::Data<AppState>) -> Option<User>;
     async fn get_session_context(&self, data: &web::Data<AppState>) -> SessionContext;
     fn is_frontend_request(&self) -> bool;
@@ -214,6 +280,21 @@ impl EnhancedRequest for HttpRequest {
         let session_cookie = self.cookie(SESSION_COOKIE_NAME)?;
         Some(session_cookie.value().to_string())
     }
+    fn get_user_agent(&self) -> Option<String> {
+        let header = self.headers().get(actix_web::http::header::USER_AGENT)?;
+        header.to_str().ok().map(|agent| agent.to_string())
+    }
+    /// Only trusts the forwarding headers set by [`TRUSTED_PROXIES`]
+    fn get_client_ip(&self) -> Option<String> {
+        let peer = self.peer_addr()?.ip();
+        let forwarded_for = self
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|header| header.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        Some(client_ip(peer, &forwarded_for, &TRUSTED_PROXIES).to_string())
+    }
     async fn get_session_user(&self, data: &web::Data<AppState>) -> Option<User> {
         get_user_from_request(data, self).await.map(|(u, _k, _)| u)
     }
@@ -230,14 +311,10 @@ impl EnhancedRequest for HttpRequest {
     }
 }
 
-pub fn deauth(req: &HttpRequest) -> HttpResponse {
+pub fn deauth(_req: &HttpRequest) -> HttpResponse {
     let mut removal_cookie = actix_web::cookie::Cookie::build(SESSION_COOKIE_NAME, "").finish();
     removal_cookie.make_removal();
-    if req.is_frontend_request() {
-        HttpResponse::Unauthorized().cookie(removal_cookie).finish()
-    } else {
-        HttpResponse::Unauthorized().cookie(removal_cookie).finish()
-    }
+    HttpResponse::Unauthorized().cookie(removal_cookie).finish()
 }
 pub fn redirect(location: &str) -> HttpResponseBuilder {
     tracing::info!("Redirecting to: {location}");
@@ -248,10 +325,7 @@ pub fn redirect(location: &str) -> HttpResponseBuilder {
 pub fn reload(req: &HttpRequest, persist_queryparams: bool) -> HttpResponseBuilder {
     let mut location = req.get_referer().unwrap_or("/");
     if !persist_queryparams {
-        location = location
-            .split('?')
-            .next()
-            .unwrap_or(location);
+        location = location.split('?').next().unwrap_or(location);
     }
     redirect(location)
 }
@@ -349,3 +423,30 @@ impl ResponseError for AnyInternalServerError {
         HttpResponse::InternalServerError().body("Internal Server Error")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_for_is_only_trusted_from_proxies() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let proxy = ip("10.0.0.1");
+        let client = ip("203.0.113.7");
+        // anyone could send the header
+        assert_eq!(client_ip(client, "198.51.100.1", &[proxy]), client);
+        assert_eq!(client_ip(proxy, "203.0.113.7", &[]), proxy);
+        assert_eq!(client_ip(proxy, "203.0.113.7", &[proxy]), client);
+        // the client can put anything in front of what the proxy appends
+        assert_eq!(
+            client_ip(proxy, "198.51.100.1, 203.0.113.7", &[proxy]),
+            client
+        );
+        assert_eq!(
+            client_ip(proxy, "203.0.113.7, 10.0.0.2", &[proxy, ip("10.0.0.2")]),
+            client
+        );
+        assert_eq!(client_ip(proxy, "", &[proxy]), proxy);
+        assert_eq!(client_ip(proxy, "garbage", &[proxy]), proxy);
+    }
+}
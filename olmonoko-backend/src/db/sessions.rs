@@ -0,0 +1,87 @@
+use olmonoko_common::{
+    models::{
+        session::{Session, SessionHandle, SessionRaw},
+        user::UserId,
+    },
+    utils::time::timestamp,
+};
+use sqlx::PgPool;
+
+/// The unexpired sessions of the user, most recently used first
+pub async fn get_user_sessions(
+    conn: &PgPool,
+    user_id: UserId,
+    current_id: &str,
+) -> Result<Vec<Session>, sqlx::Error> {
+    Ok(sqlx::query_as!(
+        SessionRaw,
+        "SELECT * FROM sessions WHERE user_id = $1 AND expires_at >= $2 ORDER BY last_seen_at DESC",
+        user_id,
+        timestamp()
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|raw| Session::from((raw, current_id)))
+    .collect())
+}
+
+/// Returns whether the session existed
+pub async fn revoke_session(
+    conn: &PgPool,
+    user_id: UserId,
+    handle: SessionHandle,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = $1 AND handle = $2",
+        user_id,
+        handle
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Signs the user out everywhere else
+pub async fn revoke_other_sessions(
+    conn: &PgPool,
+    user_id: UserId,
+    current_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM sessions WHERE user_id = $1 AND id != $2",
+        user_id,
+        current_id
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Extends the session and records where it was used from
+pub async fn renew_session(
+    conn: &PgPool,
+    session: &SessionRaw,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let now = timestamp();
+    sqlx::query!(
+        "UPDATE sessions SET last_seen_at = $2, expires_at = $3, user_agent = COALESCE($4, user_agent), ip_address = COALESCE($5, ip_address) WHERE id = $1",
+        session.id,
+        now,
+        SessionRaw::expiry(session.created_at, session.remember, now),
+        user_agent,
+        ip_address
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn purge_expired_sessions(conn: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM sessions WHERE expires_at < $1", timestamp())
+        .execute(conn)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,192 @@
+use actix_web::web;
+
+use olmonoko_common::{
+    models::{
+        event::local::LocalEventId,
+        share::{RawShare, Share, SharePermission},
+        user::UserId,
+    },
+    AppState,
+};
+
+use super::request::{InternalServerError, OrInternalServerError};
+
+/// Shares the user has granted (`owner = true`) or received (`owner = false`)
+pub async fn get_user_shares(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    owner: bool,
+) -> Result<Vec<Share>, InternalServerError<sqlx::Error>> {
+    sqlx::query!(
+        r#"
+            SELECT sh.*, o.email AS owner_email, g.email AS grantee_email, s.name AS "ics_source_name?"
+            FROM calendar_shares AS sh
+            INNER JOIN users AS o ON o.id = sh.owner_id
+            INNER JOIN users AS g ON g.id = sh.grantee_id
+            LEFT JOIN ics_sources AS s ON s.id = sh.ics_source_id
+            WHERE (sh.owner_id = $1 AND $2) OR (sh.grantee_id = $1 AND NOT $2)
+            ORDER BY sh.created_at
+        "#,
+        user_id,
+        owner
+    )
+    .fetch_all(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query shares from db")
+    .map(|shares| {
+        shares
+            .into_iter()
+            .map(|sh| {
+                Share::try_from((
+                    RawShare {
+                        id: sh.id,
+                        owner_id: sh.owner_id,
+                        grantee_id: sh.grantee_id,
+                        ics_source_id: sh.ics_source_id,
+                        permission: sh.permission,
+                        created_at: sh.created_at,
+                    },
+                    sh.owner_email,
+                    sh.grantee_email,
+                    sh.ics_source_name,
+                ))
+                .expect("invalid share permission returned from db")
+            })
+            .collect()
+    })
+}
+
+/// What the user may do with `owner_id`'s local calendar. Owners can always write to their own.
+pub async fn get_local_calendar_permission(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    owner_id: UserId,
+) -> Result<Option<SharePermission>, InternalServerError<sqlx::Error>> {
+    if user_id == owner_id {
+        return Ok(Some(SharePermission::Write));
+    }
+    sqlx::query_scalar!(
+        "SELECT permission FROM calendar_shares WHERE owner_id = $1 AND grantee_id = $2 AND ics_source_id IS NULL",
+        owner_id,
+        user_id
+    )
+    .fetch_optional(&data.conn)
+    .await
+    .or_internal_server_error("Failed to query local calendar permission from db")
+    .map(|permission| {
+        permission.map(|p| {
+            SharePermission::try_from(p.as_str())
+                .expect("invalid share permission returned from db")
+        })
+    })
+}
+
+/// The owner of a local event along with what the user may do with it, if they can see it at all
+pub async fn get_local_event_permission(
+    data: &web::Data<AppState>,
+    user_id: UserId,
+    event_id: LocalEventId,
+) -> Result<Option<(UserId, SharePermission)>, InternalServerError<sqlx::Error>> {
+    let owner_id = sqlx::query_scalar!("SELECT user_id FROM local_events WHERE id = $1", event_id)
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to query local event owner from db")?;
+    let Some(owner_id) = owner_id else {
+        return Ok(None);
+    };
+    Ok(get_local_calendar_permission(data, user_id, owner_id)
+        .await?
+        .map(|permission| (owner_id, permission)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{app_state, insert_local_event, insert_user, share};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn local_calendar_permissions(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let owner = insert_user(&conn, "owner@example.com").await;
+        let reader = insert_user(&conn, "reader@example.com").await;
+        let writer = insert_user(&conn, "writer@example.com").await;
+        let stranger = insert_user(&conn, "stranger@example.com").await;
+        share(&conn, owner, reader, None, "read").await;
+        share(&conn, owner, writer, None, "write").await;
+        let event = insert_local_event(&conn, owner, "event").await;
+
+        let permission = |user_id| get_local_calendar_permission(&data, user_id, owner);
+        assert_eq!(
+            permission(owner).await.unwrap(),
+            Some(SharePermission::Write)
+        );
+        assert_eq!(
+            permission(reader).await.unwrap(),
+            Some(SharePermission::Read)
+        );
+        assert_eq!(
+            permission(writer).await.unwrap(),
+            Some(SharePermission::Write)
+        );
+        assert_eq!(permission(stranger).await.unwrap(), None);
+        // shares aren't mutual
+        assert_eq!(
+            get_local_calendar_permission(&data, owner, reader)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let event_permission = |user_id| get_local_event_permission(&data, user_id, event);
+        assert_eq!(
+            event_permission(reader).await.unwrap(),
+            Some((owner, SharePermission::Read))
+        );
+        assert_eq!(
+            event_permission(writer).await.unwrap(),
+            Some((owner, SharePermission::Write))
+        );
+        assert_eq!(event_permission(stranger).await.unwrap(), None);
+        assert_eq!(
+            get_local_event_permission(&data, reader, event + 1)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[sqlx::test]
+    async fn shares_are_listed_for_both_sides(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let owner = insert_user(&conn, "owner@example.com").await;
+        let grantee = insert_user(&conn, "grantee@example.com").await;
+        share(&conn, owner, grantee, None, "read").await;
+
+        let granted = get_user_shares(&data, owner, true).await.unwrap();
+        assert_eq!(granted.len(), 1);
+        assert_eq!(granted[0].grantee_id, grantee);
+        assert!(get_user_shares(&data, owner, false)
+            .await
+            .unwrap()
+            .is_empty());
+        let received = get_user_shares(&data, grantee, false).await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].owner_id, owner);
+    }
+
+    #[sqlx::test]
+    async fn share_defaults_are_in_seconds(conn: PgPool) {
+        let owner = insert_user(&conn, "owner@example.com").await;
+        let grantee = insert_user(&conn, "grantee@example.com").await;
+        share(&conn, owner, grantee, None, "read").await;
+        let created_at = sqlx::query_scalar!(
+            "SELECT created_at FROM calendar_shares WHERE owner_id = $1",
+            owner
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        assert!((created_at - olmonoko_common::utils::time::timestamp()).abs() < 60);
+    }
+}
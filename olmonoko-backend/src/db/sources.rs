@@ -14,7 +14,7 @@ pub async fn get_visible_sources(
     user_id: Option<UserId>,
 ) -> Vec<IcsSource> {
     sqlx::query!(
-        "SELECT s.*, p.priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 WHERE s.is_public = true OR s.user_id = $1",
+        "SELECT s.*, p.priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 WHERE s.is_public = true OR s.user_id = $1 OR s.id IN (SELECT ics_source_id FROM calendar_shares WHERE grantee_id = $1)",
         user_id
     )
     .fetch_all(&data.conn)
@@ -30,7 +30,7 @@ pub async fn get_visible_sources(
             last_fetched_at: source.last_fetched_at,
             is_public: source.is_public,
             created_at: source.created_at,
-            updated_at: source.updated_at.map(i64::from),
+            updated_at: source.updated_at,
             persist_events: source.persist_events,
             all_as_allday: source.all_as_allday,
             import_template: source.import_template,
@@ -47,7 +47,7 @@ pub async fn get_visible_sources_with_event_count(
 ) -> Vec<(IcsSource, i64, i64)> {
     // TODO: Check if p.priority has to be considered in grouping
     sqlx::query!(
-        "SELECT COUNT(DISTINCT e.id) AS event_count, COUNT(o.id) AS occurrence_count, s.*, MAX(CASE WHEN p.priority IS NOT NULL THEN p.priority END) AS priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 LEFT JOIN events AS e ON e.event_source_id = s.id LEFT JOIN event_occurrences AS o ON o.event_id = e.id WHERE s.is_public = true OR s.user_id = $1 GROUP BY s.id, p.priority",
+        "SELECT COUNT(DISTINCT e.id) AS event_count, COUNT(o.id) AS occurrence_count, s.*, MAX(CASE WHEN p.priority IS NOT NULL THEN p.priority END) AS priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 LEFT JOIN events AS e ON e.event_source_id = s.id LEFT JOIN event_occurrences AS o ON o.event_id = e.id WHERE s.is_public = true OR s.user_id = $1 OR s.id IN (SELECT ics_source_id FROM calendar_shares WHERE grantee_id = $1) GROUP BY s.id, p.priority",
         user_id
     )
     .fetch_all(&data.conn)
@@ -63,7 +63,7 @@ pub async fn get_visible_sources_with_event_count(
             last_fetched_at: source.last_fetched_at,
             is_public: source.is_public,
             created_at: source.created_at,
-            updated_at: source.updated_at.map(i64::from),
+            updated_at: source.updated_at,
             persist_events: source.persist_events,
             all_as_allday: source.all_as_allday,
             import_template: source.import_template,
@@ -81,7 +81,7 @@ pub async fn get_source_as_user(
     id: RemoteSourceId,
 ) -> Option<IcsSource> {
     sqlx::query!(
-        r#"SELECT s.*, p.priority AS "priority?" FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 WHERE (s.is_public = true OR s.user_id = $1) AND s.id = $2"#,
+        r#"SELECT s.*, p.priority AS "priority?" FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 WHERE (s.is_public = true OR s.user_id = $1 OR s.id IN (SELECT ics_source_id FROM calendar_shares WHERE grantee_id = $1)) AND s.id = $2"#,
         user_id,
         id
     )
@@ -113,7 +113,7 @@ pub async fn get_source_as_user_with_event_count(
     id: RemoteSourceId,
 ) -> (IcsSource, i64, i64) {
     let r = sqlx::query!(
-        "SELECT COUNT(DISTINCT e.id) AS event_count, COUNT(o.id) AS occurrence_count, s.*, MAX(CASE WHEN p.priority IS NOT NULL THEN p.priority END) AS priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 LEFT JOIN events AS e ON e.event_source_id = s.id LEFT JOIN event_occurrences AS o ON o.event_id = e.id WHERE (s.is_public = true OR s.user_id = $1) AND s.id = $2 GROUP BY s.id",
+        "SELECT COUNT(DISTINCT e.id) AS event_count, COUNT(o.id) AS occurrence_count, s.*, MAX(CASE WHEN p.priority IS NOT NULL THEN p.priority END) AS priority FROM ics_sources AS s LEFT JOIN ics_source_priorities AS p ON p.ics_source_id = s.id AND p.user_id = $1 LEFT JOIN events AS e ON e.event_source_id = s.id LEFT JOIN event_occurrences AS o ON o.event_id = e.id WHERE (s.is_public = true OR s.user_id = $1 OR s.id IN (SELECT ics_source_id FROM calendar_shares WHERE grantee_id = $1)) AND s.id = $2 GROUP BY s.id",
         user_id,
         id
     )
@@ -0,0 +1,122 @@
+//! Fixtures for tests that run against a database created by `#[sqlx::test]`
+
+use actix_web::web;
+use olmonoko_common::{
+    models::{event::local::LocalEventId, ics_source::IcsSourceId, user::UserId},
+    AppState, BuildInformation,
+};
+use sqlx::PgPool;
+
+const SITE_URL: &str = "http://localhost:8080";
+
+pub async fn app_state(conn: PgPool) -> web::Data<AppState> {
+    // export links are built from the environment
+    if std::env::var("SITE_URL").is_err() {
+        std::env::set_var("SITE_URL", SITE_URL);
+    }
+    web::Data::new(AppState {
+        site_url: SITE_URL.to_string(),
+        version: "test".to_string(),
+        build_info: BuildInformation {
+            package_version: "test".to_string(),
+            commit: None,
+            commit_short: None,
+            build_time: chrono::Utc::now(),
+        },
+        conn,
+        scheduler: tokio_cron_scheduler::JobScheduler::new()
+            .await
+            .expect("Failed to create scheduler"),
+        templates: tera::Tera::default(),
+    })
+}
+
+pub async fn insert_user(conn: &PgPool, email: &str) -> UserId {
+    sqlx::query_scalar!(
+        "INSERT INTO users (email, password_hash, admin) VALUES ($1, 'x', false) RETURNING id",
+        email
+    )
+    .fetch_one(conn)
+    .await
+    .expect("Failed to insert user")
+}
+
+pub async fn insert_local_event(conn: &PgPool, user_id: UserId, summary: &str) -> LocalEventId {
+    sqlx::query_scalar!(
+        "INSERT INTO local_events (user_id, starts_at, summary, uid) VALUES ($1, $2, $3, $3) RETURNING id",
+        user_id,
+        chrono::Utc::now().timestamp() + 60 * 60,
+        summary
+    )
+    .fetch_one(conn)
+    .await
+    .expect("Failed to insert local event")
+}
+
+/// A source of `user_id` with a single upcoming event, already in the owner's calendar
+pub async fn insert_source(
+    conn: &PgPool,
+    user_id: UserId,
+    is_public: bool,
+    summary: &str,
+) -> IcsSourceId {
+    let source_id = sqlx::query_scalar!(
+        "INSERT INTO ics_sources (user_id, is_public, name, url, persist_events) VALUES ($1, $2, $3, $4, false) RETURNING id",
+        user_id,
+        is_public,
+        summary,
+        format!("http://localhost/{summary}.ics")
+    )
+    .fetch_one(conn)
+    .await
+    .expect("Failed to insert source");
+    let event_id = sqlx::query_scalar!(
+        "INSERT INTO events (event_source_id, summary, uid, all_day) VALUES ($1, $2, $2, false) RETURNING id",
+        source_id,
+        summary
+    )
+    .fetch_one(conn)
+    .await
+    .expect("Failed to insert remote event");
+    sqlx::query!(
+        "INSERT INTO event_occurrences (event_id, starts_at, from_rrule) VALUES ($1, $2, false)",
+        event_id,
+        chrono::Utc::now().timestamp() + 60 * 60
+    )
+    .execute(conn)
+    .await
+    .expect("Failed to insert event occurrence");
+    add_source_to_calendar(conn, user_id, source_id).await;
+    source_id
+}
+
+pub async fn add_source_to_calendar(conn: &PgPool, user_id: UserId, source_id: IcsSourceId) {
+    sqlx::query!(
+        "INSERT INTO ics_source_priorities (user_id, ics_source_id, priority) VALUES ($1, $2, 0)",
+        user_id,
+        source_id
+    )
+    .execute(conn)
+    .await
+    .expect("Failed to add source to calendar");
+}
+
+/// Shares the owner's local calendar, or the source, with the grantee
+pub async fn share(
+    conn: &PgPool,
+    owner_id: UserId,
+    grantee_id: UserId,
+    ics_source_id: Option<IcsSourceId>,
+    permission: &str,
+) {
+    sqlx::query!(
+        "INSERT INTO calendar_shares (owner_id, grantee_id, ics_source_id, permission) VALUES ($1, $2, $3, $4)",
+        owner_id,
+        grantee_id,
+        ics_source_id,
+        permission
+    )
+    .execute(conn)
+    .await
+    .expect("Failed to share calendar");
+}
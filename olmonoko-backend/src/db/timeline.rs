@@ -4,7 +4,7 @@ use actix_web::web;
 
 use olmonoko_common::{models::user::UserId, utils::event_filters::EventFilter, AppState};
 
-use crate::db::events::get_visible_event_occurrences;
+use crate::db::events::{get_visible_event_occurrences, EventScope};
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
 pub struct Timeline {
@@ -44,7 +44,8 @@ pub async fn compile_timeline(
     // )
     // .fetch_all(&data.conn)
     // .await?;
-    let event_occurrences = get_visible_event_occurrences(data, Some(user_id), false, filter).await;
+    let event_occurrences =
+        get_visible_event_occurrences(data, Some(user_id), EventScope::Shared, false, filter).await;
 
     let data = event_occurrences
         .into_iter()
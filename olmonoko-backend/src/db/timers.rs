@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use olmonoko_common::models::{
+    timer::{RawTimer, RawTimerSegment, Timer, TimerId},
+    user::UserId,
+};
+use sqlx::PgPool;
+
+async fn with_segments(conn: &PgPool, timers: Vec<RawTimer>) -> Result<Vec<Timer>, sqlx::Error> {
+    let ids: Vec<TimerId> = timers.iter().map(|timer| timer.id).collect();
+    let mut segments: HashMap<TimerId, Vec<RawTimerSegment>> = HashMap::new();
+    for segment in sqlx::query_as!(
+        RawTimerSegment,
+        "SELECT * FROM timer_segments WHERE timer_id = ANY($1) ORDER BY started_at, id",
+        &ids
+    )
+    .fetch_all(conn)
+    .await?
+    {
+        segments.entry(segment.timer_id).or_default().push(segment);
+    }
+    Ok(timers
+        .into_iter()
+        .map(|timer| {
+            let segments = segments.remove(&timer.id).unwrap_or_default();
+            Timer::from((timer, segments))
+        })
+        .collect())
+}
+
+/// The timers of the user, oldest first
+pub async fn get_user_timers(conn: &PgPool, user_id: UserId) -> Result<Vec<Timer>, sqlx::Error> {
+    let timers = sqlx::query_as!(
+        RawTimer,
+        "SELECT * FROM timers WHERE user_id = $1 ORDER BY created_at, id",
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+    with_segments(conn, timers).await
+}
+
+pub async fn get_user_timer(
+    conn: &PgPool,
+    user_id: UserId,
+    timer_id: TimerId,
+) -> Result<Option<Timer>, sqlx::Error> {
+    let timer = sqlx::query_as!(
+        RawTimer,
+        "SELECT * FROM timers WHERE user_id = $1 AND id = $2",
+        user_id,
+        timer_id
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(with_segments(conn, timer.into_iter().collect())
+        .await?
+        .pop())
+}
@@ -0,0 +1,217 @@
+use olmonoko_common::{
+    models::{
+        two_factor::{RawUserTotp, TwoFactorStatus, LOGIN_CHALLENGE_LIFETIME},
+        user::UserId,
+    },
+    utils::time::timestamp,
+};
+use sqlx::PgPool;
+
+use crate::auth::two_factor::{hash_recovery_code, verify_code, TwoFactorError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecondFactorError {
+    #[error("Database error: {0}")]
+    DbErr(#[from] sqlx::Error),
+    #[error(transparent)]
+    TwoFactorErr(#[from] TwoFactorError),
+}
+
+pub async fn get_user_totp(
+    conn: &PgPool,
+    user_id: UserId,
+) -> Result<Option<RawUserTotp>, sqlx::Error> {
+    sqlx::query_as!(
+        RawUserTotp,
+        "SELECT * FROM user_totp WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+pub async fn get_two_factor_status(
+    conn: &PgPool,
+    user_id: UserId,
+) -> Result<TwoFactorStatus, sqlx::Error> {
+    let enabled = get_user_totp(conn, user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled);
+    let recovery_codes_left = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM user_recovery_codes WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(TwoFactorStatus {
+        enabled,
+        recovery_codes_left,
+    })
+}
+
+/// Replaces any earlier secret that wasn't confirmed
+pub async fn set_pending_totp(
+    conn: &PgPool,
+    user_id: UserId,
+    secret: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_totp (user_id, secret, created_at) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, enabled = FALSE, last_used_step = NULL, created_at = EXCLUDED.created_at
+        "#,
+        user_id,
+        secret,
+        timestamp()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Enables the secret, which also records the step of the code confirming it as used
+pub async fn enable_totp(conn: &PgPool, user_id: UserId, step: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE user_totp SET enabled = TRUE, last_used_step = $2 WHERE user_id = $1",
+        user_id,
+        step
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Removes the secret and the recovery codes
+pub async fn disable_totp(conn: &PgPool, user_id: UserId) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+pub async fn replace_recovery_codes(
+    conn: &PgPool,
+    user_id: UserId,
+    codes: &[String],
+) -> Result<(), sqlx::Error> {
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    let mut tx = conn.begin().await?;
+    sqlx::query!(
+        "DELETE FROM user_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO user_recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::TEXT[])",
+        user_id,
+        &hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Checks a code from the authenticator app or an unused recovery code, and uses it up.
+/// Always false if the user hasn't enabled two-factor authentication.
+pub async fn verify_second_factor(
+    conn: &PgPool,
+    user_id: UserId,
+    email: &str,
+    code: &str,
+    is_totp: bool,
+) -> Result<bool, SecondFactorError> {
+    let Some(totp) = get_user_totp(conn, user_id).await? else {
+        return Ok(false);
+    };
+    if !totp.enabled {
+        return Ok(false);
+    }
+    if is_totp {
+        let now = timestamp();
+        let Some(step) = verify_code(&totp.secret, email, code, totp.last_used_step, now)? else {
+            return Ok(false);
+        };
+        // another request may have used the same code meanwhile
+        let result = sqlx::query!(
+            "UPDATE user_totp SET last_used_step = $2 WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+            user_id,
+            step
+        )
+        .execute(conn)
+        .await?;
+        return Ok(result.rows_affected() > 0);
+    }
+    let result = sqlx::query!(
+        "UPDATE user_recovery_codes SET used_at = $3 WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        user_id,
+        hash_recovery_code(code),
+        timestamp()
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the secret id of the challenge
+pub async fn create_login_challenge(
+    conn: &PgPool,
+    user_id: UserId,
+    remember: bool,
+) -> Result<String, sqlx::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query!(
+        "INSERT INTO login_challenges (id, user_id, remember, expires_at) VALUES ($1, $2, $3, $4)",
+        id,
+        user_id,
+        remember,
+        timestamp() + LOGIN_CHALLENGE_LIFETIME
+    )
+    .execute(conn)
+    .await?;
+    Ok(id)
+}
+
+/// The user and email of an unexpired challenge, and whether to remember the session
+pub async fn get_login_challenge(
+    conn: &PgPool,
+    id: &str,
+) -> Result<Option<(UserId, String, bool)>, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"
+        SELECT challenge.user_id, usr.email, challenge.remember
+        FROM login_challenges AS challenge
+        INNER JOIN users AS usr ON usr.id = challenge.user_id
+        WHERE challenge.id = $1 AND challenge.expires_at >= $2
+        "#,
+        id,
+        timestamp()
+    )
+    .fetch_optional(conn)
+    .await?
+    .map(|row| (row.user_id, row.email, row.remember)))
+}
+
+pub async fn delete_login_challenge(conn: &PgPool, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM login_challenges WHERE id = $1", id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn purge_expired_login_challenges(conn: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM login_challenges WHERE expires_at < $1",
+        timestamp()
+    )
+    .execute(conn)
+    .await?;
+    Ok(result.rows_affected())
+}
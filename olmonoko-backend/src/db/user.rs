@@ -16,7 +16,7 @@ pub async fn get_user_export_links(
 ) -> Result<Vec<PublicLink>, InternalServerError<sqlx::Error>> {
     sqlx::query_as!(
         RawPublicLink,
-        "SELECT * FROM public_calendar_links WHERE user_id = $1",
+        "SELECT * FROM public_calendar_links WHERE user_id = $1 AND group_id IS NULL",
         user_id
     )
     .fetch_all(&data.conn)
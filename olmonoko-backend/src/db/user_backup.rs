@@ -0,0 +1,783 @@
+use std::collections::HashMap;
+
+use olmonoko_common::{
+    models::{
+        api_key::AuthScope,
+        attendance::RawAttendance,
+        backup::{ApiKeyMetadata, UserBackup, UserBackupImportMode, UserBackupImportSummary},
+        bills::{reminder::RawReminderSettings, RawBill},
+        event::{
+            local::{LocalEventId, RawLocalEvent},
+            remote::{RawRemoteEvent, RawRemoteEventOccurrence, RemoteEventId},
+        },
+        ics_source::{IcsSourceId, RawIcsSource},
+        public_link::RawPublicLink,
+        timer::{RawTimer, RawTimerSegment, TimerId},
+        user::{User, UserId},
+    },
+    utils::time::timestamp,
+    AppState,
+};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserBackupError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Unknown API key scope {0}")]
+    InvalidScope(String),
+    #[error("Unknown timezone {0}")]
+    InvalidTimezone(String),
+}
+
+/// Events of sources that aren't persisted get new ids on every sync, so only the tags,
+/// attendance and bills of persisted ones can be carried over
+pub async fn export_user_backup(data: &AppState, user: &User) -> Result<UserBackup, sqlx::Error> {
+    let local_events = sqlx::query_as!(
+        RawLocalEvent,
+        "SELECT * FROM local_events WHERE user_id = $1",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let sources = sqlx::query_as!(
+        RawIcsSource,
+        "SELECT * FROM ics_sources WHERE user_id = $1",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let source_priorities = sqlx::query!(
+        "
+            SELECT ics_source_priorities.ics_source_id, ics_source_priorities.priority
+            FROM ics_source_priorities
+            INNER JOIN ics_sources ON ics_sources.id = ics_source_priorities.ics_source_id
+            WHERE ics_source_priorities.user_id = $1 AND ics_sources.user_id = $1
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?
+    .into_iter()
+    .map(|p| (p.ics_source_id, p.priority))
+    .collect();
+    let persisted_remote_events = sqlx::query_as!(
+        RawRemoteEvent,
+        "
+            SELECT events.*
+            FROM events
+            INNER JOIN ics_sources ON events.event_source_id = ics_sources.id
+            WHERE ics_sources.user_id = $1 AND ics_sources.persist_events = true
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let persisted_remote_event_occurrences = sqlx::query_as!(
+        RawRemoteEventOccurrence,
+        "
+            SELECT event_occurrences.*
+            FROM event_occurrences
+            INNER JOIN events ON events.id = event_occurrences.event_id
+            INNER JOIN ics_sources ON events.event_source_id = ics_sources.id
+            WHERE ics_sources.user_id = $1 AND ics_sources.persist_events = true
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let tags = sqlx::query!(
+        "
+            SELECT event_tags.*
+            FROM event_tags
+            LEFT JOIN local_events ON local_events.id = event_tags.local_event_id
+            LEFT JOIN events ON events.id = event_tags.remote_event_id
+            LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+            WHERE local_events.user_id = $1 OR (ics_sources.user_id = $1 AND ics_sources.persist_events = true)
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?
+    .into_iter()
+    .map(|t| (t.created_at, t.local_event_id, t.remote_event_id, t.tag))
+    .collect();
+    let attendance = sqlx::query_as!(
+        RawAttendance,
+        "
+            SELECT attendance.*
+            FROM attendance
+            LEFT JOIN local_events ON local_events.id = attendance.local_event_id
+            LEFT JOIN events ON events.id = attendance.remote_event_id
+            LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+            WHERE attendance.user_id = $1
+                AND (local_events.user_id = $1 OR (ics_sources.user_id = $1 AND ics_sources.persist_events = true))
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let bills = sqlx::query_as!(
+        RawBill,
+        "
+            SELECT bills.*
+            FROM bills
+            LEFT JOIN local_events ON local_events.id = bills.local_event_id
+            LEFT JOIN events ON events.id = bills.remote_event_id
+            LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
+            WHERE bills.user_id = $1
+                AND (local_events.user_id = $1 OR (ics_sources.user_id = $1 AND ics_sources.persist_events = true))
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let bill_reminder_settings = sqlx::query_as!(
+        RawReminderSettings,
+        "SELECT * FROM bill_reminder_settings WHERE user_id = $1",
+        user.id
+    )
+    .fetch_optional(&data.conn)
+    .await?;
+    let timers = sqlx::query_as!(RawTimer, "SELECT * FROM timers WHERE user_id = $1", user.id)
+        .fetch_all(&data.conn)
+        .await?;
+    let timer_segments = sqlx::query_as!(
+        RawTimerSegment,
+        "
+            SELECT timer_segments.*
+            FROM timer_segments
+            INNER JOIN timers ON timers.id = timer_segments.timer_id
+            WHERE timers.user_id = $1
+        ",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    // links of groups go with the group
+    let public_links = sqlx::query_as!(
+        RawPublicLink,
+        "SELECT * FROM public_calendar_links WHERE user_id = $1 AND group_id IS NULL",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    let api_keys = sqlx::query_as!(
+        ApiKeyMetadata,
+        "SELECT description, scopes, revoked, created_at, updated_at FROM api_keys WHERE user_id = $1",
+        user.id
+    )
+    .fetch_all(&data.conn)
+    .await?;
+    Ok(UserBackup {
+        created_at: timestamp(),
+        site_url: data.site_url.clone(),
+        version: data.version.clone(),
+        email: user.email.clone(),
+        interface_timezone: user.interface_timezone.clone(),
+        local_events,
+        sources,
+        source_priorities,
+        persisted_remote_events,
+        persisted_remote_event_occurrences,
+        tags,
+        attendance,
+        bills,
+        bill_reminder_settings,
+        timers,
+        timer_segments,
+        public_links,
+        api_keys,
+    })
+}
+
+/// Imports a backup made with [`export_user_backup`] into the account of `user_id`, possibly on
+/// another instance. Rows get new ids, and the references between them are remapped.
+///
+/// Duplicates are recognized by the uid of events, the URL of sources, and the id or creation
+/// time of timers and links, so importing the same backup twice doesn't change anything.
+pub async fn import_user_backup(
+    conn: &PgPool,
+    user_id: UserId,
+    backup: &UserBackup,
+    mode: UserBackupImportMode,
+) -> Result<UserBackupImportSummary, UserBackupError> {
+    if backup.interface_timezone.parse::<chrono_tz::Tz>().is_err() {
+        return Err(UserBackupError::InvalidTimezone(
+            backup.interface_timezone.clone(),
+        ));
+    }
+    if let Some(scope) = backup
+        .api_keys
+        .iter()
+        .flat_map(|key| &key.scopes)
+        .find(|scope| AuthScope::try_from(scope.as_str()).is_err())
+    {
+        return Err(UserBackupError::InvalidScope(scope.clone()));
+    }
+
+    let mut txn = conn.begin().await?;
+    if mode == UserBackupImportMode::Replace {
+        clear_user_data(&mut txn, user_id).await?;
+        sqlx::query!(
+            "UPDATE users SET interface_timezone = $2 WHERE id = $1",
+            user_id,
+            backup.interface_timezone
+        )
+        .execute(&mut *txn)
+        .await?;
+    }
+    let mut summary = UserBackupImportSummary::default();
+
+    let mut local_event_ids: HashMap<LocalEventId, LocalEventId> = HashMap::new();
+    let mut new_local_events = Vec::new();
+    for event in &backup.local_events {
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM local_events WHERE user_id = $1 AND uid = $2",
+            user_id,
+            event.uid
+        )
+        .fetch_optional(&mut *txn)
+        .await?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let id = sqlx::query_scalar!(
+                    "INSERT INTO local_events (user_id, created_at, updated_at, starts_at, duration, summary, description, location, uid, all_day, priority, rrule) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id",
+                    user_id,
+                    event.created_at,
+                    event.updated_at,
+                    event.starts_at,
+                    event.duration,
+                    event.summary,
+                    event.description,
+                    event.location,
+                    event.uid,
+                    event.all_day,
+                    event.priority,
+                    event.rrule,
+                )
+                .fetch_one(&mut *txn)
+                .await?;
+                new_local_events.push(event);
+                id
+            }
+        };
+        summary.count(existing.is_none());
+        local_event_ids.insert(event.id, id);
+    }
+    // templates may come after the events created from them
+    for event in new_local_events {
+        let template_id = event
+            .timer_template_id
+            .and_then(|template_id| local_event_ids.get(&template_id));
+        if let Some(template_id) = template_id {
+            sqlx::query!(
+                "UPDATE local_events SET timer_template_id = $2 WHERE id = $1",
+                local_event_ids[&event.id],
+                template_id
+            )
+            .execute(&mut *txn)
+            .await?;
+        }
+    }
+
+    let mut source_ids: HashMap<IcsSourceId, IcsSourceId> = HashMap::new();
+    for source in &backup.sources {
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM ics_sources WHERE user_id = $1 AND url = $2",
+            user_id,
+            source.url
+        )
+        .fetch_optional(&mut *txn)
+        .await?;
+        let id = match existing {
+            Some(id) => id,
+            // without the hashes the source is fetched again with the next sync
+            None => {
+                sqlx::query_scalar!(
+                    "INSERT INTO ics_sources (user_id, is_public, name, url, created_at, last_fetched_at, updated_at, persist_events, all_as_allday, import_template) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+                    user_id,
+                    source.is_public,
+                    source.name,
+                    source.url,
+                    source.created_at,
+                    source.last_fetched_at,
+                    source.updated_at,
+                    source.persist_events,
+                    source.all_as_allday,
+                    source.import_template,
+                )
+                .fetch_one(&mut *txn)
+                .await?
+            }
+        };
+        summary.count(existing.is_none());
+        source_ids.insert(source.id, id);
+    }
+
+    for (source_id, priority) in &backup.source_priorities {
+        let Some(source_id) = source_ids.get(source_id) else {
+            summary.count(false);
+            continue;
+        };
+        let result = sqlx::query!(
+            "INSERT INTO ics_source_priorities (user_id, ics_source_id, priority) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            user_id,
+            source_id,
+            priority,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    let mut remote_event_ids: HashMap<RemoteEventId, RemoteEventId> = HashMap::new();
+    let mut new_remote_events = HashMap::new();
+    for event in &backup.persisted_remote_events {
+        let Some(source_id) = source_ids.get(&event.event_source_id) else {
+            summary.count(false);
+            continue;
+        };
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM events WHERE event_source_id = $1 AND uid = $2 AND COALESCE(rrule, '') = COALESCE($3, '')",
+            source_id,
+            event.uid,
+            event.rrule,
+        )
+        .fetch_optional(&mut *txn)
+        .await?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let id = sqlx::query_scalar!(
+                    "INSERT INTO events (event_source_id, priority_override, rrule, dt_stamp, all_day, duration, summary, description, location, uid) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+                    source_id,
+                    event.priority_override,
+                    event.rrule,
+                    event.dt_stamp,
+                    event.all_day,
+                    event.duration,
+                    event.summary,
+                    event.description,
+                    event.location,
+                    event.uid,
+                )
+                .fetch_one(&mut *txn)
+                .await?;
+                new_remote_events.insert(event.id, id);
+                id
+            }
+        };
+        summary.count(existing.is_none());
+        remote_event_ids.insert(event.id, id);
+    }
+
+    for occurrence in &backup.persisted_remote_event_occurrences {
+        let Some(event_id) = new_remote_events.get(&occurrence.event_id) else {
+            summary.count(false);
+            continue;
+        };
+        let result = sqlx::query!(
+            "INSERT INTO event_occurrences (event_id, starts_at, from_rrule) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            event_id,
+            occurrence.starts_at,
+            occurrence.from_rrule,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    let map_event = |local_event_id: Option<LocalEventId>,
+                     remote_event_id: Option<RemoteEventId>| {
+        match (local_event_id, remote_event_id) {
+            (Some(id), None) => local_event_ids.get(&id).map(|id| (Some(*id), None)),
+            (None, Some(id)) => remote_event_ids.get(&id).map(|id| (None, Some(*id))),
+            _ => None,
+        }
+    };
+
+    for (created_at, local_event_id, remote_event_id, tag) in &backup.tags {
+        let Some((local_event_id, remote_event_id)) = map_event(*local_event_id, *remote_event_id)
+        else {
+            summary.count(false);
+            continue;
+        };
+        // the unique constraint doesn't apply as one of the ids is always null
+        let result = sqlx::query!(
+            "
+                INSERT INTO event_tags (created_at, local_event_id, remote_event_id, tag)
+                SELECT $1, $2, $3, $4
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM event_tags
+                    WHERE local_event_id IS NOT DISTINCT FROM $2 AND remote_event_id IS NOT DISTINCT FROM $3 AND tag = $4
+                )
+            ",
+            created_at,
+            local_event_id,
+            remote_event_id,
+            tag,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    for attendance in &backup.attendance {
+        let Some((local_event_id, remote_event_id)) =
+            map_event(attendance.local_event_id, attendance.remote_event_id)
+        else {
+            summary.count(false);
+            continue;
+        };
+        let result = sqlx::query!(
+            "INSERT INTO attendance (user_id, local_event_id, remote_event_id, planned, actual, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+            user_id,
+            local_event_id,
+            remote_event_id,
+            attendance.planned,
+            attendance.actual,
+            attendance.created_at,
+            attendance.updated_at,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    for bill in &backup.bills {
+        let Some((local_event_id, remote_event_id)) =
+            map_event(bill.local_event_id, bill.remote_event_id)
+        else {
+            summary.count(false);
+            continue;
+        };
+        let result = sqlx::query!(
+            "INSERT INTO bills (local_event_id, remote_event_id, payee_account_number, amount, reference, payee_name, payee_email, payee_address, payee_phone, created_at, updated_at, user_id, paid_at, payee_bic, currency) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) ON CONFLICT DO NOTHING",
+            local_event_id,
+            remote_event_id,
+            bill.payee_account_number,
+            bill.amount,
+            bill.reference,
+            bill.payee_name,
+            bill.payee_email,
+            bill.payee_address,
+            bill.payee_phone,
+            bill.created_at,
+            bill.updated_at,
+            user_id,
+            bill.paid_at,
+            bill.payee_bic,
+            bill.currency,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    if let Some(settings) = &backup.bill_reminder_settings {
+        let result = sqlx::query!(
+            "INSERT INTO bill_reminder_settings (user_id, enabled, lead_days, channel, webhook_url, escalate_overdue, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+            user_id,
+            settings.enabled,
+            settings.lead_days,
+            settings.channel,
+            settings.webhook_url,
+            settings.escalate_overdue,
+            settings.updated_at,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    let mut timer_ids: HashMap<TimerId, TimerId> = HashMap::new();
+    for timer in &backup.timers {
+        let Some(template) = local_event_ids.get(&timer.template) else {
+            summary.count(false);
+            continue;
+        };
+        let duplicate = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM timers WHERE user_id = $1 AND (id = $2 OR (template = $3 AND created_at = $4))) AS "exists!""#,
+            user_id,
+            timer.id,
+            template,
+            timer.created_at,
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+        if duplicate {
+            summary.count(false);
+            continue;
+        }
+        let taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM timers WHERE id = $1) AS "exists!""#,
+            timer.id
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+        // the id is kept unless another account on this instance has it
+        let id = if taken { Uuid::new_v4() } else { timer.id };
+        sqlx::query!(
+            "INSERT INTO timers (id, user_id, template, name, summary, details, location, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            id,
+            user_id,
+            template,
+            timer.name,
+            timer.summary,
+            timer.details,
+            timer.location,
+            timer.created_at,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(true);
+        timer_ids.insert(timer.id, id);
+    }
+
+    for segment in &backup.timer_segments {
+        let Some(timer_id) = timer_ids.get(&segment.timer_id) else {
+            summary.count(false);
+            continue;
+        };
+        sqlx::query!(
+            "INSERT INTO timer_segments (timer_id, started_at, ended_at) VALUES ($1, $2, $3)",
+            timer_id,
+            segment.started_at,
+            segment.ended_at,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(true);
+    }
+
+    for link in &backup.public_links {
+        let duplicate = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM public_calendar_links WHERE user_id = $1 AND (id = $2 OR (created_at = $3 AND name IS NOT DISTINCT FROM $4))) AS "exists!""#,
+            user_id,
+            link.id,
+            link.created_at,
+            link.name,
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+        if duplicate {
+            summary.count(false);
+            continue;
+        }
+        let taken = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM public_calendar_links WHERE id = $1) AS "exists!""#,
+            link.id
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+        // keeping the id keeps subscriptions working after moving to another instance
+        let id = if taken {
+            Uuid::new_v4().to_string()
+        } else {
+            link.id.clone()
+        };
+        sqlx::query!(
+            "INSERT INTO public_calendar_links (id, user_id, created_at, min_priority, max_priority, name, expires_at, last_accessed_at, fetch_count, export_categories, export_source, export_attendance, alarm_priority) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+            id,
+            user_id,
+            link.created_at,
+            link.min_priority,
+            link.max_priority,
+            link.name,
+            link.expires_at,
+            link.last_accessed_at,
+            link.fetch_count,
+            link.export_categories,
+            link.export_source,
+            link.export_attendance,
+            link.alarm_priority,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(true);
+    }
+
+    // keys get new ids, and a backup shouldn't be able to hand out working ones, so they're
+    // only kept as a record of what to create again
+    for key in &backup.api_keys {
+        let result = sqlx::query!(
+            "
+                INSERT INTO api_keys (user_id, description, scopes, revoked, created_at, updated_at)
+                SELECT $1, $2, $3, TRUE, $4, $5
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM api_keys WHERE user_id = $1 AND description = $2 AND scopes = $3
+                )
+            ",
+            user_id,
+            key.description,
+            &key.scopes,
+            key.created_at,
+            key.updated_at,
+        )
+        .execute(&mut *txn)
+        .await?;
+        summary.count(result.rows_affected() > 0);
+    }
+
+    txn.commit().await?;
+    Ok(summary)
+}
+
+/// Removes what [`export_user_backup`] covers. Deleting the sources also removes their shares.
+async fn clear_user_data(
+    txn: &mut Transaction<'_, Postgres>,
+    user_id: UserId,
+) -> Result<(), sqlx::Error> {
+    // the events take their tags, attendance, bills and timers with them
+    sqlx::query!("DELETE FROM local_events WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    sqlx::query!("DELETE FROM ics_sources WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    sqlx::query!("DELETE FROM attendance WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    sqlx::query!("DELETE FROM bills WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM bill_reminder_settings WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut **txn)
+    .await?;
+    sqlx::query!("DELETE FROM timers WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM public_calendar_links WHERE user_id = $1 AND group_id IS NULL",
+        user_id
+    )
+    .execute(&mut **txn)
+    .await?;
+    sqlx::query!("DELETE FROM api_keys WHERE user_id = $1", user_id)
+        .execute(&mut **txn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{app_state, insert_local_event, insert_source, insert_user};
+    use olmonoko_common::models::user::RawUser;
+
+    async fn user(conn: &PgPool, user_id: UserId) -> User {
+        sqlx::query_as!(RawUser, "SELECT * FROM users WHERE id = $1", user_id)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+            .into()
+    }
+
+    /// The summaries of the user's events, with the summary of the template of each
+    async fn local_events(conn: &PgPool, user_id: UserId) -> Vec<(String, Option<String>)> {
+        sqlx::query!(
+            r#"
+                SELECT event.summary AS "summary!", template.summary AS "template?"
+                FROM local_events event
+                LEFT JOIN local_events template ON template.id = event.timer_template_id
+                WHERE event.user_id = $1 AND (template.id IS NULL OR template.user_id = $1)
+                ORDER BY event.summary
+            "#,
+            user_id
+        )
+        .fetch_all(conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|event| (event.summary, event.template))
+        .collect()
+    }
+
+    #[sqlx::test]
+    async fn backups_round_trip_into_another_account(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let owner_id = insert_user(&conn, "owner@example.com").await;
+        let template_id = insert_local_event(&conn, owner_id, "template").await;
+        let event_id = insert_local_event(&conn, owner_id, "from template").await;
+        sqlx::query!(
+            "UPDATE local_events SET timer_template_id = $2 WHERE id = $1",
+            event_id,
+            template_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, 'tagged')",
+            event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        insert_source(&conn, owner_id, false, "source").await;
+        sqlx::query!(
+            "INSERT INTO api_keys (user_id, description, scopes) VALUES ($1, 'key', ARRAY['upcoming_events:r'])",
+            owner_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let backup = export_user_backup(&data, &user(&conn, owner_id).await)
+            .await
+            .unwrap();
+
+        let user_id = insert_user(&conn, "user@example.com").await;
+        insert_local_event(&conn, user_id, "existing").await;
+        let summary = import_user_backup(&conn, user_id, &backup, UserBackupImportMode::Merge)
+            .await
+            .unwrap();
+        assert_eq!(summary.skipped, 0);
+        let expected = [
+            ("existing".to_string(), None),
+            ("from template".to_string(), Some("template".to_string())),
+            ("template".to_string(), None),
+        ];
+        // the template refers to the imported copy, not to the event of the owner
+        assert_eq!(local_events(&conn, user_id).await, expected);
+        let tagged = sqlx::query_scalar!(
+            r#"SELECT local_events.summary AS "summary!" FROM event_tags INNER JOIN local_events ON local_events.id = event_tags.local_event_id WHERE local_events.user_id = $1"#,
+            user_id
+        )
+        .fetch_all(&conn)
+        .await
+        .unwrap();
+        assert_eq!(tagged, ["from template"]);
+        let priorities = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM ics_source_priorities INNER JOIN ics_sources ON ics_sources.id = ics_source_priorities.ics_source_id WHERE ics_source_priorities.user_id = $1 AND ics_sources.user_id = $1",
+            user_id
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        assert_eq!(priorities, Some(1));
+        let keys = sqlx::query_scalar!("SELECT revoked FROM api_keys WHERE user_id = $1", user_id)
+            .fetch_all(&conn)
+            .await
+            .unwrap();
+        assert_eq!(keys, [true]);
+
+        let again = import_user_backup(&conn, user_id, &backup, UserBackupImportMode::Merge)
+            .await
+            .unwrap();
+        assert_eq!(again.imported, 0);
+        assert_eq!(local_events(&conn, user_id).await, expected);
+
+        import_user_backup(&conn, user_id, &backup, UserBackupImportMode::Replace)
+            .await
+            .unwrap();
+        assert_eq!(local_events(&conn, user_id).await, expected[1..]);
+        // the owner's data is untouched
+        assert_eq!(local_events(&conn, owner_id).await, expected[1..]);
+    }
+}
@@ -0,0 +1,107 @@
+mod transport;
+
+use std::sync::LazyLock;
+
+use lettre::message::Mailbox;
+
+use transport::EmailTransport;
+
+const DEFAULT_FROM: &str = "OLMONOKO <onboarding@olmonoko.ruta.fi>";
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailError {
+    #[error("Invalid email configuration: {0}")]
+    Config(String),
+    #[error("Failed to render email: {0}")]
+    Template(#[from] tera::Error),
+    #[error("Invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build email: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Failed to write email: {0}")]
+    File(#[from] lettre::transport::file::Error),
+    #[error("Resend error: {0}")]
+    Resend(#[from] resend_rs::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Where emails are sent through and who they're from, see [`EmailTransport::from_env`]
+/// and `EMAIL_FROM`
+pub struct Mailer {
+    transport: EmailTransport,
+    from: Mailbox,
+}
+
+pub static MAILER: LazyLock<Mailer> = LazyLock::new(|| {
+    let transport = EmailTransport::from_env().expect("Invalid email configuration");
+    let from = std::env::var("EMAIL_FROM")
+        .unwrap_or_else(|_| DEFAULT_FROM.to_string())
+        .parse()
+        .expect("EMAIL_FROM must be a valid address");
+    tracing::info!("Sending emails with the {} transport", transport.name());
+    Mailer { transport, from }
+});
+
+/// Separate from the page templates so that jobs without the app state can send emails too
+static TEMPLATES: LazyLock<tera::Tera> = LazyLock::new(|| {
+    tera::Tera::new("templates/emails/**/*").expect("Failed to load email templates")
+});
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+impl Mail {
+    /// Renders `templates/emails/{template}.txt` and `.html` with the same context
+    pub fn render(
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &tera::Context,
+    ) -> Result<Self, EmailError> {
+        Ok(Self {
+            to: to.to_string(),
+            subject: subject.to_string(),
+            text: TEMPLATES.render(&format!("{template}.txt"), context)?,
+            html: TEMPLATES.render(&format!("{template}.html"), context)?,
+        })
+    }
+
+    pub async fn send(&self) -> Result<(), EmailError> {
+        MAILER.transport.send(&MAILER.from, self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transport::SmtpTls;
+    use super::*;
+
+    #[test]
+    fn templates_render() {
+        let mut context = tera::Context::new();
+        context.insert("link", "https://example.com/?a=1&b=2");
+        context.insert("subject", "Bill due");
+        context.insert("text", "Pay <this>\nsoon");
+        let mail = Mail::render("a@example.com", "Hi", "verification", &context).unwrap();
+        assert!(mail.text.contains("https://example.com/?a=1&b=2"));
+        assert!(mail.html.contains("a=1&amp;b=2"));
+        let mail = Mail::render("a@example.com", "Hi", "password_reset", &context).unwrap();
+        assert!(mail.text.contains("https://example.com/?a=1&b=2"));
+        let mail = Mail::render("a@example.com", "Hi", "notification", &context).unwrap();
+        assert!(mail.text.contains("Pay <this>\nsoon"));
+        assert!(mail.html.contains("Pay &lt;this&gt;<br"));
+    }
+
+    #[test]
+    fn smtp_tls_modes() {
+        assert_eq!(SmtpTls::try_from("tls").unwrap(), SmtpTls::Implicit);
+        assert!(SmtpTls::try_from("ssl").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use resend_rs::types::CreateEmailBaseOptions;
+use resend_rs::Resend;
+
+use super::{EmailError, Mail};
+
+const DEFAULT_FILE_DIR: &str = "emails";
+
+/// How an SMTP server wants the connection encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Upgraded from plain text, usually on port 587
+    Starttls,
+    /// TLS from the start, usually on port 465
+    Implicit,
+    /// Only for servers on the same machine or network
+    None,
+}
+impl TryFrom<&str> for SmtpTls {
+    type Error = EmailError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "starttls" => Ok(Self::Starttls),
+            "tls" => Ok(Self::Implicit),
+            "none" => Ok(Self::None),
+            _ => Err(EmailError::Config(format!(
+                "SMTP_TLS must be starttls, tls or none, not {value}"
+            ))),
+        }
+    }
+}
+
+pub enum EmailTransport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    Resend(Resend),
+    /// Writes each email to a `.eml` file in the directory
+    File(AsyncFileTransport<Tokio1Executor>),
+    /// Only logs the emails
+    Log,
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+impl EmailTransport {
+    /// Picks the transport with `EMAIL_TRANSPORT` (`smtp`, `resend`, `file` or `log`).
+    /// Without it, emails go through Resend if `RESEND_API_KEY` is set and are logged otherwise.
+    pub fn from_env() -> Result<Self, EmailError> {
+        let kind = env("EMAIL_TRANSPORT").unwrap_or_else(|| {
+            if env("RESEND_API_KEY").is_some() {
+                "resend".to_string()
+            } else {
+                "log".to_string()
+            }
+        });
+        match kind.as_str() {
+            "smtp" => Self::smtp_from_env(),
+            "resend" => {
+                let key = env("RESEND_API_KEY").ok_or_else(|| {
+                    EmailError::Config("RESEND_API_KEY must be set for Resend".to_string())
+                })?;
+                Ok(Self::Resend(Resend::new(&key)))
+            }
+            "file" => {
+                let dir = PathBuf::from(
+                    env("EMAIL_FILE_DIR").unwrap_or_else(|| DEFAULT_FILE_DIR.to_string()),
+                );
+                std::fs::create_dir_all(&dir)?;
+                Ok(Self::File(AsyncFileTransport::new(dir)))
+            }
+            "log" => Ok(Self::Log),
+            _ => Err(EmailError::Config(format!(
+                "EMAIL_TRANSPORT must be smtp, resend, file or log, not {kind}"
+            ))),
+        }
+    }
+
+    /// Configured with `SMTP_HOST`, and optionally `SMTP_PORT`, `SMTP_TLS` (`starttls` by default),
+    /// `SMTP_USERNAME` and `SMTP_PASSWORD`
+    fn smtp_from_env() -> Result<Self, EmailError> {
+        let host = env("SMTP_HOST")
+            .ok_or_else(|| EmailError::Config("SMTP_HOST must be set for SMTP".to_string()))?;
+        let tls = SmtpTls::try_from(env("SMTP_TLS").as_deref().unwrap_or("starttls"))?;
+        let mut builder = match tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)?,
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+        };
+        if let Some(port) = env("SMTP_PORT") {
+            let port = port
+                .parse()
+                .map_err(|_| EmailError::Config(format!("Invalid SMTP_PORT {port}")))?;
+            builder = builder.port(port);
+        }
+        if let Some(username) = env("SMTP_USERNAME") {
+            let password = env("SMTP_PASSWORD").unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self::Smtp(builder.build()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Smtp(_) => "SMTP",
+            Self::Resend(_) => "Resend",
+            Self::File(_) => "file",
+            Self::Log => "log",
+        }
+    }
+
+    pub async fn send(&self, from: &Mailbox, mail: &Mail) -> Result<(), EmailError> {
+        match self {
+            Self::Smtp(transport) => {
+                transport.send(message(from, mail)?).await?;
+            }
+            Self::File(transport) => {
+                transport.send(message(from, mail)?).await?;
+            }
+            Self::Resend(resend) => {
+                let options =
+                    CreateEmailBaseOptions::new(from.to_string(), [&mail.to], &mail.subject)
+                        .with_text(&mail.text)
+                        .with_html(&mail.html);
+                resend.emails.send(options).await?;
+            }
+            Self::Log => {
+                tracing::info!(
+                    "Email to {} with subject {:?} and content: {}",
+                    mail.to,
+                    mail.subject,
+                    mail.text
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn message(from: &Mailbox, mail: &Mail) -> Result<Message, EmailError> {
+    Ok(Message::builder()
+        .from(from.clone())
+        .to(mail.to.parse()?)
+        .subject(&mail.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            mail.text.clone(),
+            mail.html.clone(),
+        ))?)
+}
@@ -0,0 +1,410 @@
+//! Declares users with their sources, API keys and export links in a TOML file, which is applied
+//! at startup so that an instance can be reproduced without the UI. Everything in the file is
+//! created or updated to match it, what isn't in it is left alone.
+
+use std::path::Path;
+
+use olmonoko_common::models::{
+    api_key::{ApiKeyId, AuthScope},
+    event::{Priority, PRIORITY_OPTIONS},
+    user::UserId,
+};
+use olmonoko_common::utils::time::timestamp;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::calendar_io::source_processing::test_import_template;
+
+/// The path of the config file
+pub const CONFIG_ENV: &str = "OLMONOKO_CONFIG";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeadlessError {
+    #[error("Failed to read the config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid config for {0}: {1}")]
+    Invalid(String, String),
+    #[error("Database error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("Failed to hash a placeholder password: {0}")]
+    Hash(#[from] bcrypt::BcryptError),
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeadlessConfig {
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+}
+
+/// Matched by email
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub email: String,
+    /// A bcrypt hash. Without one, new users can only log in after a password reset or with
+    /// single sign-on, and existing users keep their password.
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub admin: bool,
+    pub interface_timezone: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+    #[serde(default)]
+    pub links: Vec<LinkConfig>,
+}
+
+/// The id is the key itself
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub id: ApiKeyId,
+    pub description: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// Matched by name among the sources of the user
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub persist_events: bool,
+    #[serde(default)]
+    pub all_as_allday: bool,
+    pub import_template: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+/// The id is part of the export url, so keeping it keeps the url
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkConfig {
+    pub id: uuid::Uuid,
+    pub name: Option<String>,
+    pub min_priority: Option<Priority>,
+    pub max_priority: Option<Priority>,
+    #[serde(default)]
+    pub export_categories: bool,
+    #[serde(default)]
+    pub export_source: bool,
+    #[serde(default)]
+    pub export_attendance: bool,
+    pub alarm_priority: Option<Priority>,
+}
+
+fn check_priority(what: &str, priority: Option<Priority>) -> Result<(), HeadlessError> {
+    match priority {
+        Some(priority) if !PRIORITY_OPTIONS.contains(&priority) => Err(HeadlessError::Invalid(
+            what.to_string(),
+            format!("priority {priority} is not between 1 and 9"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl HeadlessConfig {
+    pub fn parse(content: &str) -> Result<Self, HeadlessError> {
+        let config: Self = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Catches what the database wouldn't, before anything is changed
+    fn validate(&self) -> Result<(), HeadlessError> {
+        for user in &self.users {
+            let invalid = |message: String| HeadlessError::Invalid(user.email.clone(), message);
+            if let Some(hash) = &user.password_hash {
+                if !hash.starts_with("$2") {
+                    return Err(invalid("password_hash must be a bcrypt hash".to_string()));
+                }
+            }
+            if let Some(timezone) = &user.interface_timezone {
+                if timezone.parse::<chrono_tz::Tz>().is_err() {
+                    return Err(invalid(format!("unknown timezone {timezone}")));
+                }
+            }
+            for key in &user.api_keys {
+                for scope in &key.scopes {
+                    AuthScope::try_from(scope.as_str())
+                        .map_err(|_| invalid(format!("unknown API key scope {scope}")))?;
+                }
+            }
+            for source in &user.sources {
+                let what = format!("{}, source {}", user.email, source.name);
+                check_priority(&what, source.priority)?;
+                if let Some(template) = &source.import_template {
+                    test_import_template(template)
+                        .map_err(|e| HeadlessError::Invalid(what, e.to_string()))?;
+                }
+            }
+            for link in &user.links {
+                let what = format!("{}, link {}", user.email, link.id);
+                check_priority(&what, link.min_priority)?;
+                check_priority(&what, link.max_priority)?;
+                check_priority(&what, link.alarm_priority)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconciles the database with the config in one transaction
+    pub async fn apply(&self, conn: &PgPool) -> Result<(), HeadlessError> {
+        let mut tx = conn.begin().await?;
+        for user in &self.users {
+            let user_id = apply_user(&mut tx, user).await?;
+            for key in &user.api_keys {
+                apply_api_key(&mut tx, user_id, key).await?;
+            }
+            for source in &user.sources {
+                apply_source(&mut tx, user_id, source).await?;
+            }
+            for link in &user.links {
+                apply_link(&mut tx, user_id, link).await?;
+            }
+            tracing::info!(user = user_id, "Applied config of {}", user.email);
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Applies the file at [`CONFIG_ENV`] if it's set
+pub async fn apply_from_env(conn: &PgPool) -> Result<(), HeadlessError> {
+    let Ok(path) = std::env::var(CONFIG_ENV) else {
+        return Ok(());
+    };
+    tracing::info!("Applying config file {path}");
+    let content = tokio::fs::read_to_string(Path::new(&path)).await?;
+    HeadlessConfig::parse(&content)?.apply(conn).await
+}
+
+async fn apply_user(
+    tx: &mut Transaction<'_, Postgres>,
+    user: &UserConfig,
+) -> Result<UserId, HeadlessError> {
+    let existing = sqlx::query_scalar!("SELECT id FROM users WHERE email = $1", user.email)
+        .fetch_optional(&mut **tx)
+        .await?;
+    let user_id = match existing {
+        Some(user_id) => {
+            sqlx::query!(
+                "UPDATE users SET admin = $2, password_hash = COALESCE($3, password_hash), interface_timezone = COALESCE($4, interface_timezone) WHERE id = $1",
+                user_id,
+                user.admin,
+                user.password_hash,
+                user.interface_timezone
+            )
+            .execute(&mut **tx)
+            .await?;
+            user_id
+        }
+        None => {
+            let password_hash = match &user.password_hash {
+                Some(hash) => hash.clone(),
+                None => bcrypt::hash(uuid::Uuid::new_v4().to_string(), bcrypt::DEFAULT_COST)?,
+            };
+            sqlx::query_scalar!(
+                "INSERT INTO users (email, password_hash, admin, interface_timezone) VALUES ($1, $2, $3, COALESCE($4, 'UTC')) RETURNING id",
+                user.email,
+                password_hash,
+                user.admin,
+                user.interface_timezone
+            )
+            .fetch_one(&mut **tx)
+            .await?
+        }
+    };
+    Ok(user_id)
+}
+
+async fn apply_api_key(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: UserId,
+    key: &ApiKeyConfig,
+) -> Result<(), HeadlessError> {
+    let now = timestamp();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (id, user_id, description, scopes, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $5)
+        ON CONFLICT (id) DO UPDATE SET description = EXCLUDED.description, scopes = EXCLUDED.scopes, revoked = FALSE, updated_at = EXCLUDED.updated_at
+        WHERE api_keys.user_id = EXCLUDED.user_id
+        "#,
+        key.id,
+        user_id,
+        key.description,
+        &key.scopes,
+        now
+    )
+    .execute(&mut **tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(HeadlessError::Invalid(
+            format!("API key {}", key.id),
+            "it belongs to another user".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn apply_source(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: UserId,
+    source: &SourceConfig,
+) -> Result<(), HeadlessError> {
+    let existing = sqlx::query_scalar!(
+        "SELECT id FROM ics_sources WHERE user_id = $1 AND name = $2 ORDER BY id LIMIT 1",
+        user_id,
+        source.name
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    let source_id = match existing {
+        Some(source_id) => {
+            sqlx::query!(
+                "UPDATE ics_sources SET url = $2, is_public = $3, persist_events = $4, all_as_allday = $5, import_template = $6 WHERE id = $1",
+                source_id,
+                source.url,
+                source.public,
+                source.persist_events,
+                source.all_as_allday,
+                source.import_template
+            )
+            .execute(&mut **tx)
+            .await?;
+            source_id
+        }
+        // fetched with the next sync of all sources
+        None => {
+            sqlx::query_scalar!(
+                "INSERT INTO ics_sources (user_id, name, url, is_public, persist_events, all_as_allday, import_template) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+                user_id,
+                source.name,
+                source.url,
+                source.public,
+                source.persist_events,
+                source.all_as_allday,
+                source.import_template
+            )
+            .fetch_one(&mut **tx)
+            .await?
+        }
+    };
+    match source.priority {
+        Some(priority) => {
+            sqlx::query!(
+                "INSERT INTO ics_source_priorities (user_id, ics_source_id, priority) VALUES ($1, $2, $3) ON CONFLICT (user_id, ics_source_id) DO UPDATE SET priority = $3",
+                user_id,
+                source_id,
+                priority
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        None => {
+            sqlx::query!(
+                "DELETE FROM ics_source_priorities WHERE user_id = $1 AND ics_source_id = $2",
+                user_id,
+                source_id
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn apply_link(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: UserId,
+    link: &LinkConfig,
+) -> Result<(), HeadlessError> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO public_calendar_links (id, user_id, name, min_priority, max_priority, export_categories, export_source, export_attendance, alarm_priority)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, min_priority = EXCLUDED.min_priority, max_priority = EXCLUDED.max_priority,
+            export_categories = EXCLUDED.export_categories, export_source = EXCLUDED.export_source,
+            export_attendance = EXCLUDED.export_attendance, alarm_priority = EXCLUDED.alarm_priority
+        WHERE public_calendar_links.user_id = EXCLUDED.user_id
+        "#,
+        link.id.to_string(),
+        user_id,
+        link.name,
+        link.min_priority,
+        link.max_priority,
+        link.export_categories,
+        link.export_source,
+        link.export_attendance,
+        link.alarm_priority
+    )
+    .execute(&mut **tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(HeadlessError::Invalid(
+            format!("link {}", link.id),
+            "it belongs to another user".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        [[users]]
+        email = "admin@example.com"
+        password_hash = "$2b$12$abcdefghijklmnopqrstuuxLhhC3yO1Sp4KgVGTMl8hQ8QjvDLXk."
+        admin = true
+        interface_timezone = "Europe/Helsinki"
+
+        [[users.api_keys]]
+        id = "3f0b8a4e-2c1d-4a7b-9e55-6a1c0f2d9b31"
+        description = "Dashboard"
+        scopes = ["upcoming_events:r"]
+
+        [[users.sources]]
+        name = "Holidays"
+        url = "https://example.com/holidays.ics"
+        priority = 2
+
+        [[users.links]]
+        id = "0c9d7b4f-8a61-4e2b-b3f5-1d2e3f4a5b6c"
+        name = "Phone"
+        min_priority = 1
+        max_priority = 5
+    "#;
+
+    #[test]
+    fn example_parses() {
+        let config = HeadlessConfig::parse(EXAMPLE).unwrap();
+        let user = &config.users[0];
+        assert!(user.admin);
+        assert_eq!(user.sources[0].priority, Some(2));
+        assert!(!user.sources[0].public);
+        assert_eq!(user.links[0].name.as_deref(), Some("Phone"));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let invalid = [
+            EXAMPLE.replace("priority = 2", "priority = 12"),
+            EXAMPLE.replace("upcoming_events:r", "everything"),
+            EXAMPLE.replace("Europe/Helsinki", "Mars/Olympus"),
+            EXAMPLE.replace("$2b$12$", "plain"),
+            EXAMPLE.replace("admin = true", "superuser = true"),
+        ];
+        for content in invalid {
+            assert!(HeadlessConfig::parse(&content).is_err(), "{content}");
+        }
+    }
+}
@@ -1,8 +1,12 @@
 mod auth;
 mod calendar_io;
 mod db;
+mod email;
+mod headless;
 mod middleware;
+mod notifications;
 mod routes;
+mod snapshots;
 
 use chrono::Datelike;
 use dotenvy::dotenv;
@@ -42,6 +46,14 @@ async fn main() -> Result<(), AppError> {
         .await
         .expect("Failed to run migrations");
     tracing::info!("Migrations complete");
+    headless::apply_from_env(&pool)
+        .await
+        .expect("Failed to apply the config file");
+    // hashed up front so that the first login with an unknown email isn't slower than the rest
+    std::sync::LazyLock::force(&auth::DUMMY_PASSWORD_HASH);
+    // fails on a bad email or backup configuration now rather than when it is first used
+    std::sync::LazyLock::force(&email::MAILER);
+    std::sync::LazyLock::force(&snapshots::SNAPSHOTS);
 
     tracing::info!("Starting scheduler");
     let scheduler = calendar_io::scheduler::init()
@@ -102,5 +114,5 @@ pub fn get_version() -> String {
             .clone()
             .unwrap_or_else(|| "eeeeeee".to_string())
     );
-    return version;
+    version
 }
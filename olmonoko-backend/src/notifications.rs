@@ -0,0 +1,226 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use olmonoko_common::models::bills::reminder::{NotificationChannelKind, ReminderSettings};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::email::{EmailError, Mail};
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotificationError {
+    #[error("Failed to send email: {0}")]
+    Email(#[from] EmailError),
+    #[error("Failed to call webhook: {0}")]
+    Webhook(#[from] reqwest::Error),
+    #[error("Failed to serialize notification: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("No webhook URL configured")]
+    MissingWebhookUrl,
+    #[error("Webhooks can only be sent to public http(s) URLs, not {0}")]
+    ForbiddenWebhookUrl(String),
+}
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the address is on the internet, rather than the server itself or its network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // unique local
+                || first & 0xfe00 == 0xfc00
+                // link-local
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+/// Only resolves hosts to public addresses. Checked when connecting rather than when the URL
+/// is saved, so that the host can't be pointed at an internal address later.
+struct PublicResolver;
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Shared by all webhooks. Redirects aren't followed, they could lead anywhere.
+static WEBHOOK_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        // a proxy would resolve the host itself
+        .no_proxy()
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Failed to build the webhook client")
+});
+
+/// The URL, if it's http(s) and doesn't point at a non-public address directly.
+/// Hosts are checked by [`PublicResolver`].
+fn webhook_url(url: &str) -> Result<reqwest::Url, NotificationError> {
+    let forbidden = || NotificationError::ForbiddenWebhookUrl(url.to_string());
+    let parsed = reqwest::Url::parse(url).map_err(|_| forbidden())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(forbidden());
+    }
+    let Some(host) = parsed.host_str() else {
+        return Err(forbidden());
+    };
+    // IPv6 addresses are in brackets
+    let ip = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>();
+    if ip.is_ok_and(|ip| !is_public(ip)) {
+        return Err(forbidden());
+    }
+    Ok(parsed)
+}
+
+/// Something the user should be told about outside of the calendar
+pub(crate) trait Notification: serde::Serialize {
+    fn subject(&self) -> String;
+    fn text(&self) -> String;
+}
+
+impl Notification for olmonoko_common::models::bills::reminder::BillReminder {
+    fn subject(&self) -> String {
+        self.subject()
+    }
+    fn text(&self) -> String {
+        self.text()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum NotificationChannel {
+    Email { to: String },
+    Webhook { url: String },
+}
+impl NotificationChannel {
+    pub fn from_settings(
+        settings: &ReminderSettings,
+        email: &str,
+    ) -> Result<Self, NotificationError> {
+        match settings.channel {
+            NotificationChannelKind::Email => Ok(Self::Email {
+                to: email.to_string(),
+            }),
+            NotificationChannelKind::Webhook => Ok(Self::Webhook {
+                url: settings
+                    .webhook_url
+                    .clone()
+                    .ok_or(NotificationError::MissingWebhookUrl)?,
+            }),
+        }
+    }
+
+    pub async fn send(&self, notification: &impl Notification) -> Result<(), NotificationError> {
+        match self {
+            Self::Email { to } => {
+                let subject = notification.subject();
+                let mut context = tera::Context::new();
+                context.insert("subject", &subject);
+                context.insert("text", &notification.text());
+                Mail::render(to, &subject, "notification", &context)?
+                    .send()
+                    .await?;
+            }
+            Self::Webhook { url } => {
+                let body = serde_json::to_string(notification)?;
+                WEBHOOK_CLIENT
+                    .post(webhook_url(url)?)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn webhook_urls_are_checked() {
+        assert!(webhook_url("https://example.com/hook").is_ok());
+        for url in [
+            "ftp://example.com/hook",
+            "http://127.0.0.1:8080/api",
+            "http://[::1]/api",
+            "http://169.254.169.254/latest/meta-data",
+            "not a url",
+        ] {
+            assert!(
+                matches!(
+                    webhook_url(url),
+                    Err(NotificationError::ForbiddenWebhookUrl(_))
+                ),
+                "{url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hosts_resolving_to_internal_addresses_are_refused() {
+        let addrs = PublicResolver.resolve("localhost".parse().unwrap()).await;
+        assert!(addrs.is_err());
+    }
+}
@@ -1,149 +1,196 @@
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Scope};
+use actix_web::{get, post, routes, web, HttpRequest, HttpResponse, Responder, Scope};
+use futures_util::TryStreamExt;
+use tokio::io::AsyncBufRead;
+use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::{
-    db::request::{
-        deauth, AnyInternalServerError, EnhancedRequest, IntoInternalServerError,
-        OrInternalServerError, SESSION_COOKIE_NAME,
+    db::{
+        backup::{backup_header, import_backup, write_backup},
+        request::{
+            deauth, get_user_from_request, reload, AnyInternalServerError, EnhancedRequest,
+            IntoInternalServerError, OrInternalServerError, SESSION_COOKIE_NAME,
+        },
+        user_backup::{export_user_backup, import_user_backup, UserBackupError},
     },
     middleware::autocacher::CACHE_RECURSION_PREVENTION_HEADER,
+    snapshots::{open_snapshot, prune_snapshots, take_snapshot, SnapshotError, SNAPSHOTS},
 };
 use olmonoko_common::{
     models::{
-        attendance::RawAttendance,
-        bills::RawBill,
-        event::{
-            local::{LocalEventId, RawLocalEvent},
-            remote::{RawRemoteEvent, RawRemoteEventOccurrence, RemoteEventId},
-            Priority,
+        backup::{
+            BackupImportQuery, UserBackup, UserBackupImportForm, UserBackupImportMode,
+            UserBackupImportQuery,
         },
-        ics_source::{IcsSourceId, RawIcsSource},
-        public_link::RawPublicLink,
-        user::{RawUser, User, UserId},
+        user::User,
     },
-    utils::time::timestamp,
-    AppState, BuildInformation,
+    utils::flash::{FlashMessage, WithFlashMessage},
+    AppState,
 };
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct Backup {
-    pub created_at: i64,
-    pub site_url: String,
-    pub version: String,
-    pub build_info: BuildInformation,
-    pub users: Vec<RawUser>,
-    pub public_links: Vec<RawPublicLink>,
-    pub local_events: Vec<RawLocalEvent>,
-    pub sources: Vec<RawIcsSource>,
-    pub source_priorities: Vec<(UserId, IcsSourceId, Priority)>, // user_id, ics_source_id, priority
-    pub attendance: Vec<RawAttendance>,
-    pub bills: Vec<RawBill>,
-    pub persisted_remote_events: Vec<RawRemoteEvent>,
-    pub persisted_remote_event_occurrences: Vec<RawRemoteEventOccurrence>,
-    pub tags: Vec<(i64, Option<LocalEventId>, Option<RemoteEventId>, String)>, // created_at, local_event_id, remote_event_id, tag
+/// Backups of the instance, scheduled backups and user backups can only be taken and restored
+/// with a session, not with an API key
+async fn session_user(data: &web::Data<AppState>, req: &HttpRequest) -> Option<User> {
+    match get_user_from_request(data, req).await {
+        Some((user, None, _timers)) => Some(user),
+        _ => None,
+    }
 }
 
+/// Streams a backup of the whole instance, see [`olmonoko_common::models::backup::BackupHeader`].
+/// Also served at the path of the old single JSON dump, for instances cloning from there.
+#[routes]
+#[get("/dump.ndjson")]
 #[get("/dump.json")]
 async fn export(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    if let Some(user) = req.get_session_user(&data).await {
-        tracing::info!(user.id, user.email, user.admin, "User requested a backup");
-        if !user.admin {
-            return deauth(&req);
+    let Some(user) = session_user(&data, &req).await else {
+        return deauth(&req);
+    };
+    tracing::info!(user.id, user.email, user.admin, "User requested a backup");
+    if !user.admin {
+        return deauth(&req);
+    }
+    let header = backup_header(&data);
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let conn = data.conn.clone();
+    tokio::spawn(async move {
+        // the response then ends without the last line, which marks the backup as incomplete
+        if let Err(e) = write_backup(&conn, &header, writer).await {
+            tracing::error!("Failed to write backup: {e}");
         }
-        let users: Vec<RawUser> = sqlx::query_as!(RawUser, "SELECT * FROM users")
-            .fetch_all(&data.conn)
-            .await
-            .expect("Failed to fetch users");
-        let sources: Vec<RawIcsSource> = sqlx::query_as!(RawIcsSource, "SELECT * FROM ics_sources")
-            .fetch_all(&data.conn)
-            .await
-            .expect("Failed to fetch sources");
-        let source_priorities: Vec<_> = sqlx::query!("SELECT * FROM ics_source_priorities")
-            .fetch_all(&data.conn)
+    });
+    let date = chrono::Utc::now().format("%Y-%m-%d");
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"olmonoko-instance-{date}.ndjson\""),
+        ))
+        .streaming(ReaderStream::new(reader))
+}
+
+/// Replaces the instance with the backup, or only checks it with `dry_run`
+async fn restore_from<R: AsyncBufRead + Unpin>(
+    data: &AppState,
+    reader: R,
+    dry_run: bool,
+) -> Result<HttpResponse, AnyInternalServerError> {
+    let report = match import_backup(&data.conn, reader, dry_run).await {
+        Ok(report) => report,
+        Err(e) if e.is_invalid_backup() => {
+            return Ok(HttpResponse::BadRequest().body(format!("Invalid backup: {e}")))
+        }
+        Err(e) => return Err(e.internal_server_error_any("Failed to restore backup")),
+    };
+    if report.applied {
+        tracing::info!(report.replaced_users, "Restore complete");
+        crate::calendar_io::scheduler::schedule_sync_oneoff(&data.scheduler)
             .await
-            .expect("Failed to fetch source priorities")
-            .into_iter()
-            .map(|p| (p.user_id, p.ics_source_id, p.priority))
-            .collect();
-        let persisted_remote_events: Vec<RawRemoteEvent> =
-            sqlx::query_as!(RawRemoteEvent, "SELECT events.* FROM events INNER JOIN ics_sources ON events.event_source_id = ics_sources.id AND ics_sources.persist_events = true")
-                .fetch_all(&data.conn)
-                .await
-                .expect("Failed to fetch remote events");
-        let persisted_remote_event_occurrences: Vec<RawRemoteEventOccurrence> =
-            sqlx::query_as!(RawRemoteEventOccurrence, "SELECT event_occurrences.* FROM event_occurrences INNER JOIN events ON events.id = event_occurrences.event_id INNER JOIN ics_sources ON events.event_source_id = ics_sources.id AND ics_sources.persist_events = true")
-                .fetch_all(&data.conn)
-                .await
-                .expect("Failed to fetch remote event occurrences");
-        let local_events: Vec<RawLocalEvent> =
-            sqlx::query_as!(RawLocalEvent, "SELECT * FROM local_events")
-                .fetch_all(&data.conn)
-                .await
-                .expect("Failed to fetch local events");
-        let tags: Vec<_> = sqlx::query!(
-            "
-                SELECT event_tags.*
-                FROM event_tags
-                LEFT JOIN events ON events.id = event_tags.remote_event_id
-                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
-                WHERE (ics_sources.persist_events = true OR event_tags.local_event_id IS NOT NULL);
-            "
-        )
-        .fetch_all(&data.conn)
-        .await
-        .expect("Failed to fetch event tags")
-        .into_iter()
-        .map(|t| (t.created_at, t.local_event_id, t.remote_event_id, t.tag))
-        .collect();
-        let attendance: Vec<RawAttendance> = sqlx::query_as!(
-            RawAttendance,
-            "
-                SELECT attendance.*
-                FROM attendance
-                LEFT JOIN events ON events.id = attendance.remote_event_id
-                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
-                WHERE (ics_sources.persist_events = true OR attendance.local_event_id IS NOT NULL);
-            "
-        )
-        .fetch_all(&data.conn)
-        .await
-        .expect("Failed to fetch local event attendance");
-        let bills: Vec<RawBill> = sqlx::query_as!(
-            RawBill,
-            "
-                SELECT bills.*
-                FROM bills
-                LEFT JOIN events ON events.id = bills.remote_event_id
-                LEFT JOIN ics_sources ON events.event_source_id = ics_sources.id
-                WHERE (ics_sources.persist_events = true OR bills.local_event_id IS NOT NULL);
-            "
-        )
-        .fetch_all(&data.conn)
-        .await
-        .expect("Failed to fetch bills");
-        let public_links: Vec<RawPublicLink> =
-            sqlx::query_as!(RawPublicLink, "SELECT * FROM public_calendar_links")
-                .fetch_all(&data.conn)
-                .await
-                .expect("Failed to fetch public links");
-        let backup = Backup {
-            created_at: timestamp(),
-            site_url: data.site_url.clone(),
-            version: data.version.clone(),
-            build_info: data.build_info.clone(),
-            users,
-            sources,
-            source_priorities,
-            persisted_remote_events,
-            local_events,
-            tags,
-            attendance,
-            bills,
-            public_links,
-            persisted_remote_event_occurrences,
-        };
-        return HttpResponse::Ok().json(backup);
+            .or_any_internal_server_error("Failed to schedule post-restore sync")?;
+        return Ok(HttpResponse::Ok().json(report));
     }
-    deauth(&req)
+    if report.dry_run {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+    tracing::warn!(
+        report.problem_count,
+        first_problem = ?report.problems.first(),
+        "Restore failed"
+    );
+    Ok(HttpResponse::UnprocessableEntity().json(report))
+}
+
+#[post("/restore")]
+async fn restore(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<BackupImportQuery>,
+    body: web::Payload,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    tracing::info!(user.id, user.email, user.admin, "User requested a restore");
+    if !user.admin {
+        return Ok(deauth(&req));
+    }
+    let reader = StreamReader::new(body.map_err(std::io::Error::other));
+    restore_from(&data, reader, query.dry_run).await
+}
+
+/// Backs up the instance into the backup directory right away, see [`SNAPSHOTS`]
+#[post("/snapshots")]
+async fn snapshot_now(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    if !user.admin {
+        return Ok(deauth(&req));
+    }
+    let Some(config) = SNAPSHOTS.as_ref() else {
+        return Ok(HttpResponse::NotFound().body("Scheduled backups aren't configured"));
+    };
+    tracing::info!(user.id, user.email, "User requested a scheduled backup now");
+    let snapshot = match take_snapshot(&data, config).await {
+        Err(e @ SnapshotError::AlreadyExists(_)) => {
+            if req.is_frontend_request() {
+                return Ok(reload(&req, false)
+                    .with_flash_message(FlashMessage::error(&e.to_string()))
+                    .finish());
+            }
+            return Ok(HttpResponse::Conflict().body(e.to_string()));
+        }
+        taken => taken.or_any_internal_server_error("Failed to back up the instance")?,
+    };
+    if let Err(e) = prune_snapshots(config).await {
+        tracing::error!("Failed to remove old backups: {e:?}");
+    }
+    if req.is_frontend_request() {
+        return Ok(reload(&req, false)
+            .with_flash_message(FlashMessage::info(&format!("Saved {}", snapshot.name)))
+            .finish());
+    }
+    Ok(HttpResponse::Ok().json(snapshot))
+}
+
+/// Like [`restore`] but from a snapshot in the backup directory, after checking its checksum
+#[post("/snapshots/{name}/restore")]
+async fn restore_snapshot(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<BackupImportQuery>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    if !user.admin {
+        return Ok(deauth(&req));
+    }
+    let Some(config) = SNAPSHOTS.as_ref() else {
+        return Ok(HttpResponse::NotFound().body("Scheduled backups aren't configured"));
+    };
+    let name = path.into_inner();
+    tracing::info!(
+        user.id,
+        user.email,
+        name,
+        query.dry_run,
+        "User requested a restore from a scheduled backup"
+    );
+    let reader = match open_snapshot(config, &name).await {
+        Ok(reader) => reader,
+        Err(e @ SnapshotError::NotFound(_)) => {
+            return Ok(HttpResponse::NotFound().body(e.to_string()))
+        }
+        Err(e @ (SnapshotError::MissingChecksum(_) | SnapshotError::ChecksumMismatch(_))) => {
+            return Ok(HttpResponse::BadRequest().body(e.to_string()))
+        }
+        Err(e) => return Err(e.internal_server_error_any("Failed to open backup")),
+    };
+    restore_from(&data, reader, query.dry_run).await
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -152,330 +199,270 @@ struct InstanceCloneForm {
     session_id: String,
 }
 
+/// Instances from before streamed backups only have the single JSON file, which restores too
+const LEGACY_DUMP_PATH: &str = "/api/backup/dump.json";
+
+/// A failed clone is the fault of the primary instance, so it's a bad gateway.
+/// The page is refreshed to show the error, as htmx doesn't swap in error responses.
+fn clone_failed(req: &HttpRequest, message: &str) -> HttpResponse {
+    tracing::error!("Clone failed: {message}");
+    if req.is_frontend_request() {
+        let mut builder = HttpResponse::BadGateway();
+        builder.insert_header(("HX-Refresh", "true"));
+        return builder
+            .with_flash_message(FlashMessage::error(&format!("Clone failed: {message}")))
+            .finish();
+    }
+    HttpResponse::BadGateway().body(format!("Clone failed: {message}"))
+}
+
 #[post("/clone")]
 async fn clone_instance(
     data: web::Data<AppState>,
     req: HttpRequest,
     form: web::Form<InstanceCloneForm>,
 ) -> Result<impl Responder, AnyInternalServerError> {
-    if let Some(user) = req.get_session_user(&data).await {
+    if let Some(user) = session_user(&data, &req).await {
         if !user.admin {
             return Ok(deauth(&req));
         }
         let session_id = form.session_id.clone();
-        let instance_endpoint = format!("{}/api/backup/dump.json", form.instance_url);
-        if let Ok(instance_url) = reqwest::Url::parse(&form.instance_url) {
-            let cookies = reqwest::cookie::Jar::default();
-            cookies.add_cookie_str(
-                &format!("{}={}", SESSION_COOKIE_NAME, session_id),
-                &instance_url,
-            );
-            let client = reqwest::Client::builder()
-                .cookie_provider(std::sync::Arc::new(cookies))
-                .build()
-                .or_any_internal_server_error("failed to build request client")?;
-            let response = client
-                .get(&instance_endpoint)
+        let Ok(instance_url) = reqwest::Url::parse(&form.instance_url) else {
+            return Ok(HttpResponse::BadRequest().body("Invalid instance_url"));
+        };
+        let cookies = reqwest::cookie::Jar::default();
+        cookies.add_cookie_str(
+            &format!("{}={}", SESSION_COOKIE_NAME, session_id),
+            &instance_url,
+        );
+        let client = reqwest::Client::builder()
+            .cookie_provider(std::sync::Arc::new(cookies))
+            .build()
+            .or_any_internal_server_error("failed to build request client")?;
+        let mut response = None;
+        for path in ["/api/backup/dump.ndjson", LEGACY_DUMP_PATH] {
+            let endpoint = format!("{}{path}", form.instance_url.trim_end_matches('/'));
+            let sent = client
+                .get(&endpoint)
                 .header(CACHE_RECURSION_PREVENTION_HEADER, "true")
                 .send()
-                .await
-                .or_any_internal_server_error("failed to request backup")?;
-            if !response.status().is_success() {
-                return Err(response.internal_server_error_any(
-                    "Request for instance backup returned non-ok status code",
-                ));
-            }
-            let body: Backup = response.json().await.unwrap();
-            if !restore(data, &user, body).await.unwrap() {
-                return Ok(deauth(&req));
+                .await;
+            match sent {
+                Ok(sent) if sent.status() == reqwest::StatusCode::NOT_FOUND => continue,
+                Ok(sent) => {
+                    response = Some(sent);
+                    break;
+                }
+                Err(e) => {
+                    return Ok(clone_failed(
+                        &req,
+                        &format!("Failed to request backup: {e}"),
+                    ))
+                }
             }
-            return Ok(HttpResponse::Ok().body("Clone complete!"));
         }
-        return Ok(HttpResponse::BadRequest().body("Invalid instance_url"));
+        let Some(response) = response else {
+            return Ok(clone_failed(&req, "The instance has no backup endpoint"));
+        };
+        if !response.status().is_success() {
+            return Ok(clone_failed(
+                &req,
+                &format!("The instance answered {}", response.status()),
+            ));
+        }
+        tracing::info!(user.id, user.email, "Cloning {}", form.instance_url);
+        let reader = StreamReader::new(response.bytes_stream().map_err(std::io::Error::other));
+        let restored = restore_from(&data, reader, false).await?;
+        if !restored.status().is_success() {
+            return Ok(clone_failed(
+                &req,
+                &format!("The backup couldn't be restored ({})", restored.status()),
+            ));
+        }
+        return Ok(HttpResponse::Ok().body("Clone complete!"));
     }
-    return Ok(deauth(&req));
+    Ok(deauth(&req))
 }
 
-async fn restore(
+/// A backup of the data of the logged in user, see [`UserBackup`]
+#[get("/mine.json")]
+async fn export_mine(
     data: web::Data<AppState>,
-    user: &User,
-    body: Backup,
-) -> Result<bool, sqlx::Error> {
-    tracing::info!(user.id, user.email, user.admin, "User requested a restore");
-    if !user.admin {
-        return Ok(false);
-    }
-    tracing::info!("=== Backup Metadata ===");
-    tracing::info!("Created at: {}", body.created_at);
-    tracing::info!("Site URL: {}", body.site_url);
-    tracing::info!("Version: {}", body.version);
-    tracing::info!("Build info: {:?}", body.build_info);
-    tracing::info!("=== Restoring backup ===");
-    let mut txn = data
-        .conn
-        .begin()
-        .await
-        .expect("Failed to start transaction");
-    tracing::info!("Removing all existing users");
-    sqlx::query!("DELETE FROM users")
-        .execute(&mut *txn)
-        .await
-        .expect("Failed to delete users");
-    tracing::info!("Removing all existing unverified users");
-    sqlx::query!("DELETE FROM unverified_users")
-        .execute(&mut *txn)
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    tracing::info!(user.id, user.email, "User requested a backup of their data");
+    let backup = export_user_backup(&data, &user)
         .await
-        .expect("Failed to delete unverified users");
-
-    tracing::info!("Restoring users");
-    for user in &body.users {
-        sqlx::query!(
-                "INSERT INTO users (id, email, password_hash, admin, created_at, interface_timezone) VALUES ($1, $2, $3, $4, $5, $6)",
-                user.id,
-                user.email,
-                user.password_hash,
-                user.admin,
-                user.created_at,
-                user.interface_timezone,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert user");
-    }
-
-    tracing::info!("Restoring public links");
-    for link in &body.public_links {
-        sqlx::query!(
-                "INSERT INTO public_calendar_links (id, user_id, created_at, min_priority, max_priority) VALUES ($1, $2, $3, $4, $5)",
-                link.id,
-                link.user_id,
-                link.created_at,
-                link.min_priority,
-                link.max_priority,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert public link");
-    }
-
-    tracing::info!("Restoring local events");
-    for event in &body.local_events {
-        sqlx::query!(
-                "INSERT INTO local_events (id, user_id, created_at, updated_at, starts_at, duration, summary, description, location, uid, all_day, priority) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
-                event.id,
-                event.user_id,
-                event.created_at,
-                event.updated_at,
-                event.starts_at,
-                event.duration,
-                event.summary,
-                event.description,
-                event.location,
-                event.uid,
-                event.all_day,
-                event.priority,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert local event");
-    }
-
-    tracing::info!("Restoring sources");
-    for source in &body.sources {
-        // Restoring file or object hashes would block updates to the source until the file changes
-        let file_hash: Option<String> = None;
-        let object_hash: Option<String> = None;
-
-        sqlx::query!(
-                "INSERT INTO ics_sources (id, user_id, is_public, name, url, created_at, last_fetched_at, file_hash, object_hash, updated_at, persist_events, all_as_allday, import_template) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
-                source.id,
-                source.user_id,
-                source.is_public,
-                source.name,
-                source.url,
-                source.created_at,
-                source.last_fetched_at,
-                file_hash,
-                object_hash,
-                source.updated_at,
-                source.persist_events,
-                source.all_as_allday,
-                source.import_template,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert source");
-    }
-
-    tracing::info!("Restoring source priorities");
-    for (user_id, ics_source_id, priority) in &body.source_priorities {
-        sqlx::query!(
-                "INSERT INTO ics_source_priorities (user_id, ics_source_id, priority) VALUES ($1, $2, $3)",
-                user_id,
-                ics_source_id,
-                priority,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert source priority");
-    }
-
-    tracing::info!("Restoring remote events");
-    for event in &body.persisted_remote_events {
-        sqlx::query!(
-                "INSERT INTO events (id, event_source_id, priority_override, rrule, dt_stamp, all_day, duration, summary, description, location, uid) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
-                event.id,
-                event.event_source_id,
-                event.priority_override,
-                event.rrule,
-                event.dt_stamp,
-                event.all_day,
-                event.duration,
-                event.summary,
-                event.description,
-                event.location,
-                event.uid,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert remote event");
-    }
-
-    tracing::info!("Restoring remote event occurrences");
-    for occurrence in &body.persisted_remote_event_occurrences {
-        sqlx::query!(
-                "INSERT INTO event_occurrences (id, event_id, starts_at, from_rrule) VALUES ($1, $2, $3, $4)",
-                occurrence.id,
-                occurrence.event_id,
-                occurrence.starts_at,
-                occurrence.from_rrule,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert remote event occurrence");
-    }
-
-    tracing::info!("Restoring event tags");
-    for (created_at, local_event_id, remote_event_id, tag) in &body.tags {
-        sqlx::query!(
-                "INSERT INTO event_tags (created_at, local_event_id, remote_event_id, tag) VALUES ($1, $2, $3, $4)",
-                created_at,
-                *local_event_id,
-                *remote_event_id,
-                tag,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert local event tag");
-    }
-
-    tracing::info!("Restoring attendance");
-    for attendance in &body.attendance {
-        sqlx::query!(
-                "INSERT INTO attendance (user_id, local_event_id, remote_event_id, planned, actual, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
-                attendance.user_id,
-                attendance.local_event_id,
-                attendance.remote_event_id,
-                attendance.planned,
-                attendance.actual,
-                attendance.created_at,
-                attendance.updated_at,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert attendance");
-    }
-
-    tracing::info!("Restoring bills");
-    for bill in &body.bills {
-        sqlx::query!(
-                "INSERT INTO bills (id, local_event_id, remote_event_id, payee_account_number, amount, reference, payee_name, payee_email, payee_address, payee_phone, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
-                bill.id,
-                bill.local_event_id,
-                bill.remote_event_id,
-                bill.payee_account_number,
-                bill.amount,
-                bill.reference,
-                bill.payee_name,
-                bill.payee_email,
-                bill.payee_address,
-                bill.payee_phone,
-                bill.created_at,
-                bill.updated_at,
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert bill");
-    }
+        .or_any_internal_server_error("Failed to export user data")?;
+    let date = chrono::Utc::now().format("%Y-%m-%d");
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"olmonoko-{date}.json\""),
+        ))
+        .json(backup))
+}
 
-    // NOTE See https://wiki.postgresql.org/wiki/Fixing_Sequences
-    tracing::info!("Resyncing Sequences");
-    let statements: Vec<String> = sqlx::query_scalar(r#"SELECT
-    'SELECT SETVAL(' ||
-       quote_literal(quote_ident(sequence_namespace.nspname) || '.' || quote_ident(class_sequence.relname)) ||
-       ', COALESCE(MAX(' ||quote_ident(pg_attribute.attname)|| '), 1) ) FROM ' ||
-       quote_ident(table_namespace.nspname)|| '.'||quote_ident(class_table.relname)|| ';'
-FROM pg_depend
-    INNER JOIN pg_class AS class_sequence
-        ON class_sequence.oid = pg_depend.objid
-            AND class_sequence.relkind = 'S'
-    INNER JOIN pg_class AS class_table
-        ON class_table.oid = pg_depend.refobjid
-    INNER JOIN pg_attribute
-        ON pg_attribute.attrelid = class_table.oid
-            AND pg_depend.refobjsubid = pg_attribute.attnum
-    INNER JOIN pg_namespace as table_namespace
-        ON table_namespace.oid = class_table.relnamespace
-    INNER JOIN pg_namespace AS sequence_namespace
-        ON sequence_namespace.oid = class_sequence.relnamespace
-ORDER BY sequence_namespace.nspname, class_sequence.relname;"#)
-            .fetch_all(&mut *txn)
-            .await
-            .expect("Failed to query sequences to resync");
-    for statement in statements {
-        println!("executing statement: {}", statement);
-        sqlx::query(&statement)
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to resync sequences");
+async fn import_mine(
+    data: &AppState,
+    req: &HttpRequest,
+    user: &User,
+    backup: UserBackup,
+    mode: UserBackupImportMode,
+) -> Result<HttpResponse, AnyInternalServerError> {
+    tracing::info!(
+        user.id,
+        user.email,
+        backup.email,
+        backup.site_url,
+        ?mode,
+        "User requested an import of their data"
+    );
+    let message = match import_user_backup(&data.conn, user.id, &backup, mode).await {
+        Ok(summary) => {
+            // sources without their hashes are fetched again with the next sync
+            if !backup.sources.is_empty() {
+                crate::calendar_io::scheduler::schedule_sync_oneoff(&data.scheduler)
+                    .await
+                    .or_any_internal_server_error("Failed to schedule post-import sync")?;
+            }
+            if req.is_frontend_request() {
+                return Ok(reload(req, false)
+                    .with_flash_message(FlashMessage::info(&format!(
+                        "Imported {} items, skipped {}",
+                        summary.imported, summary.skipped
+                    )))
+                    .finish());
+            }
+            return Ok(HttpResponse::Ok().json(summary));
+        }
+        Err(UserBackupError::Database(sqlx::Error::Database(e))) => {
+            format!("Invalid backup: {e}")
+        }
+        Err(UserBackupError::Database(e)) => {
+            return Err(e.internal_server_error_any("Failed to import backup"))
+        }
+        Err(e) => format!("Invalid backup: {e}"),
+    };
+    if req.is_frontend_request() {
+        return Ok(reload(req, false)
+            .with_flash_message(FlashMessage::error(&message))
+            .finish());
     }
+    Ok(HttpResponse::BadRequest().body(message))
+}
 
-    tracing::info!("Scheduling post-restore sync");
-    crate::calendar_io::scheduler::schedule_sync_oneoff(&data.scheduler)
-        .await
-        .expect("Failed to schedule post-restore sync!");
-
-    tracing::info!("Committing transaction");
-    txn.commit().await.expect("Failed to commit transaction");
-    tracing::info!("Restore complete!");
-    return Ok(true);
+#[post("/mine/restore.json")]
+async fn import_mine_json(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<UserBackupImportQuery>,
+    body: web::Json<UserBackup>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    import_mine(&data, &req, &user, body.into_inner(), query.mode).await
 }
 
-#[post("/restore.json")]
-async fn restore_json(
+#[post("/mine/restore")]
+async fn import_mine_form(
     data: web::Data<AppState>,
     req: HttpRequest,
-    body: web::Json<Backup>,
-) -> impl Responder {
-    if let Some(user) = req.get_session_user(&data).await {
-        let allowed = restore(data, &user, body.into_inner())
-            .await
-            .expect("Failed to restore backup");
-        if !allowed {
-            return deauth(&req);
+    form: web::Form<UserBackupImportForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(user) = session_user(&data, &req).await else {
+        return Ok(deauth(&req));
+    };
+    let form = form.into_inner();
+    let backup: UserBackup = match serde_json::from_str(&form.backup) {
+        Ok(backup) => backup,
+        Err(e) => {
+            return Ok(reload(&req, false)
+                .with_flash_message(FlashMessage::error(&format!("Invalid backup: {e}")))
+                .finish())
         }
-        return HttpResponse::Ok().body("Restore complete");
-    }
-    deauth(&req)
+    };
+    import_mine(&data, &req, &user, backup, form.mode).await
 }
 
 pub fn routes() -> Scope {
+    // for user backups, instance backups are streamed
     let json_cfg = web::JsonConfig::default()
         // raise max json payload size to 100 MB
         .limit(1024 * 1024 * 100);
+    // the import form carries the backup as JSON too
+    let form_cfg = web::FormConfig::default().limit(1024 * 1024 * 100);
     web::scope("/backup")
         .app_data(json_cfg)
+        .app_data(form_cfg)
         .service(export)
-        .service(restore_json)
+        .service(restore)
+        .service(snapshot_now)
+        .service(restore_snapshot)
         .service(clone_instance)
+        .service(export_mine)
+        .service(import_mine_json)
+        .service(import_mine_form)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        request::API_KEY_HEADER_NAME,
+        testing::{app_state, insert_user},
+    };
+    use actix_web::{cookie::Cookie, http::StatusCode, test, App};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn instance_backups_need_a_session(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let admin = insert_user(&conn, "admin@example.com").await;
+        sqlx::query!("UPDATE users SET admin = true WHERE id = $1", admin)
+            .execute(&conn)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, expires_at) VALUES ('session', $1, $2)",
+            admin,
+            chrono::Utc::now().timestamp() + 60 * 60
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let key = sqlx::query_scalar!(
+            "INSERT INTO api_keys (user_id, description, scopes) VALUES ($1, 'key', '{}') RETURNING id",
+            admin
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        let app = test::init_service(App::new().app_data(data).service(routes())).await;
+        let with_key = |request: test::TestRequest| {
+            request
+                .insert_header((API_KEY_HEADER_NAME, key.to_string()))
+                .to_request()
+        };
+
+        for request in [
+            test::TestRequest::get().uri("/backup/dump.ndjson"),
+            test::TestRequest::get().uri("/backup/dump.json"),
+            test::TestRequest::post().uri("/backup/restore?dry_run=true"),
+        ] {
+            let response = test::call_service(&app, with_key(request)).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let request = test::TestRequest::get()
+            .uri("/backup/dump.json")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, "session"))
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        let header = String::from_utf8(body.to_vec()).unwrap();
+        assert!(header.starts_with(r#"{"format":"#), "{header}");
+    }
 }
@@ -0,0 +1,503 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, Scope};
+
+use olmonoko_common::models::bills::from_epc_qr::{NewBillEpcQrForm, NewBillEpcQrFormWithUserId};
+use olmonoko_common::models::bills::from_finvoice::{
+    NewBillFinvoiceForm, NewBillFinvoiceFormWithUserId,
+};
+use olmonoko_common::models::bills::reminder::{ReminderSettings, ReminderSettingsForm};
+use olmonoko_common::models::bills::statement::StatementForm;
+use olmonoko_common::models::bills::validation::{
+    validate_currency, validate_reference_for, BillError,
+};
+use olmonoko_common::models::bills::{
+    parse_amount, Bill, BillFilter, BillId, BillPaidForm, BillUpdateForm, EventId, NewBill,
+    NewBillForm, NewBillWithEvent, RawBill,
+};
+use olmonoko_common::models::share::SharePermission;
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
+use olmonoko_common::utils::time::{from_date_form, timestamp};
+use olmonoko_common::AppState;
+
+use crate::db::bills::{
+    get_reminder_settings, get_user_bills, insert_bill_with_event, reconcile_statement,
+    set_reminder_settings,
+};
+use crate::db::events::is_remote_event_visible;
+use crate::db::request::{
+    deauth, reload, EnhancedRequest, InternalServerError, OrInternalServerError,
+};
+use crate::db::shares::get_local_event_permission;
+
+/// Responds to a change of a bill: reloads the page for the UI, returns the bill otherwise
+fn respond_with_bill(request: &HttpRequest, bill: Option<RawBill>, message: &str) -> HttpResponse {
+    let Some(bill) = bill else {
+        return HttpResponse::NotFound().body("bill not found");
+    };
+    crate::calendar_io::export_cache::invalidate_user(bill.user_id);
+    if request.is_frontend_request() {
+        return reload(request, true)
+            .with_flash_message(FlashMessage::info(message))
+            .finish();
+    }
+    HttpResponse::Ok().json(Bill::from(bill))
+}
+
+/// Tells why a bill wasn't changed: with a flash message for the UI, with `status` otherwise
+fn refuse(request: &HttpRequest, mut status: HttpResponseBuilder, message: &str) -> HttpResponse {
+    if request.is_frontend_request() {
+        return reload(request, true)
+            .with_flash_message(FlashMessage::error(message))
+            .finish();
+    }
+    status.body(message.to_string())
+}
+
+#[get("")]
+async fn bills(
+    data: web::Data<AppState>,
+    query: web::Query<BillFilter>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let (due_after, due_before) = query.due_range(user.interface_timezone_h);
+        let bills = get_user_bills(&data, user.id, &query, due_after, due_before).await?;
+        return Ok(HttpResponse::Ok().json(bills));
+    }
+    Ok(deauth(&request))
+}
+
+/// Attaches a bill to an existing local event the user can write to, or a remote event they can see
+#[post("")]
+async fn new_bill(
+    data: web::Data<AppState>,
+    form: web::Form<NewBillForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let new = match NewBill::try_from(form.into_inner()) {
+            Ok(new) => new,
+            Err(e) => return Ok(refuse(&request, HttpResponse::BadRequest(), &e.to_string())),
+        };
+        // bills on local events belong to the owner of the calendar
+        let (owner_id, local_event_id, remote_event_id) = match new.event_id {
+            EventId::Local(id) => match get_local_event_permission(&data, user.id, id).await? {
+                Some((owner_id, SharePermission::Write)) => (owner_id, Some(id), None),
+                _ => return Ok(HttpResponse::NotFound().body("event not found")),
+            },
+            EventId::Remote(id) => {
+                if !is_remote_event_visible(&data, user.id, id).await? {
+                    return Ok(HttpResponse::NotFound().body("event not found"));
+                }
+                (user.id, None, Some(id))
+            }
+        };
+        let inserted = sqlx::query_as!(
+            RawBill,
+            r#"
+                INSERT INTO bills (user_id, local_event_id, remote_event_id, payee_account_number, amount, currency, reference, payee_name, payee_email, payee_address, payee_phone, payee_bic, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $13)
+                ON CONFLICT DO NOTHING
+                RETURNING *
+            "#,
+            owner_id,
+            local_event_id,
+            remote_event_id,
+            new.payee_account_number,
+            new.amount,
+            new.currency,
+            new.reference,
+            new.payee_name,
+            new.payee_email,
+            new.payee_address,
+            new.payee_phone,
+            new.payee_bic,
+            timestamp(),
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to insert bill")?;
+        if inserted.is_none() {
+            return Ok(refuse(
+                &request,
+                HttpResponse::Conflict(),
+                "The event already has a bill",
+            ));
+        }
+        return Ok(respond_with_bill(&request, inserted, "Bill added"));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/paid")]
+async fn mark_paid(
+    data: web::Data<AppState>,
+    path: web::Path<BillId>,
+    form: Option<web::Form<BillPaidForm>>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let form = form.map(|form| form.into_inner()).unwrap_or_default();
+        let paid_at = match form.paid_at {
+            Some(date) => match from_date_form(&date, user.interface_timezone_h) {
+                Some(paid_at) => paid_at.timestamp(),
+                None => return Ok(HttpResponse::BadRequest().body("invalid paid_at date")),
+            },
+            None => timestamp(),
+        };
+        let updated = sqlx::query_as!(
+            RawBill,
+            r#"
+                UPDATE bills
+                SET paid_at = $3, updated_at = $4
+                WHERE id = $1 AND user_id = $2
+                RETURNING *
+            "#,
+            path.into_inner(),
+            user.id,
+            paid_at,
+            timestamp()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to mark bill as paid")?;
+        return Ok(respond_with_bill(&request, updated, "Bill marked as paid"));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/unpaid")]
+async fn mark_unpaid(
+    data: web::Data<AppState>,
+    path: web::Path<BillId>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let updated = sqlx::query_as!(
+            RawBill,
+            r#"
+                UPDATE bills
+                SET paid_at = NULL, updated_at = $3
+                WHERE id = $1 AND user_id = $2
+                RETURNING *
+            "#,
+            path.into_inner(),
+            user.id,
+            timestamp()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to mark bill as unpaid")?;
+        return Ok(respond_with_bill(
+            &request,
+            updated,
+            "Bill marked as unpaid",
+        ));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/update")]
+async fn update_bill(
+    data: web::Data<AppState>,
+    path: web::Path<BillId>,
+    form: web::Form<BillUpdateForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let form = form.into_inner();
+        let Some(amount) = parse_amount(&form.amount) else {
+            return Ok(refuse(
+                &request,
+                HttpResponse::BadRequest(),
+                "Invalid amount",
+            ));
+        };
+        let id = path.into_inner();
+        let Some(payee_account_number) = sqlx::query_scalar!(
+            "SELECT payee_account_number FROM bills WHERE id = $1 AND user_id = $2",
+            id,
+            user.id
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to query bill")?
+        else {
+            return Ok(respond_with_bill(&request, None, ""));
+        };
+        let currency = form.currency.as_deref().map(validate_currency).transpose();
+        let validated =
+            validate_reference_for(&payee_account_number, &form.reference).and(currency);
+        let currency = match validated {
+            Ok(currency) => currency,
+            Err(e) => return Ok(refuse(&request, HttpResponse::BadRequest(), &e.to_string())),
+        };
+        let updated = sqlx::query_as!(
+            RawBill,
+            r#"
+                UPDATE bills
+                SET amount = $3, reference = $4, payee_name = COALESCE($5, payee_name), currency = COALESCE($6, currency), updated_at = $7
+                WHERE id = $1 AND user_id = $2
+                RETURNING *
+            "#,
+            id,
+            user.id,
+            amount,
+            form.reference.trim(),
+            form.payee_name,
+            currency,
+            timestamp()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to update bill")?;
+        return Ok(respond_with_bill(&request, updated, "Bill updated"));
+    }
+    Ok(deauth(&request))
+}
+
+/// Detaches the bill from its event, the event itself is kept
+#[post("/{id}/delete")]
+async fn delete_bill(
+    data: web::Data<AppState>,
+    path: web::Path<BillId>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let deleted = sqlx::query_as!(
+            RawBill,
+            "DELETE FROM bills WHERE id = $1 AND user_id = $2 RETURNING *",
+            path.into_inner(),
+            user.id
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to delete bill")?;
+        return Ok(respond_with_bill(&request, deleted, "Bill removed"));
+    }
+    Ok(deauth(&request))
+}
+
+/// Creates a bill and the event it's due on from a decoded QR code or e-invoice
+async fn import_bill(
+    data: &web::Data<AppState>,
+    request: &HttpRequest,
+    decoded: Result<NewBillWithEvent, BillError>,
+) -> Result<HttpResponse, InternalServerError<sqlx::Error>> {
+    let decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) if request.is_frontend_request() => {
+            return Ok(reload(request, true)
+                .with_flash_message(FlashMessage::error(&e.to_string()))
+                .finish())
+        }
+        Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+    };
+    let (inserted_event, inserted_bill) = insert_bill_with_event(data, decoded).await?;
+    Ok(respond_with_bill(
+        request,
+        Some(inserted_bill),
+        &format!("Bill created on {}", inserted_event.summary),
+    ))
+}
+
+/// Imports a bill from the text payload of an EPC QR code
+#[post("/import/epc")]
+async fn import_epc_qr(
+    data: web::Data<AppState>,
+    form: web::Form<NewBillEpcQrForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let decoded = NewBillWithEvent::try_from(NewBillEpcQrFormWithUserId {
+            user_id: user.id,
+            form: form.into_inner(),
+        });
+        return import_bill(&data, &request, decoded).await;
+    }
+    Ok(deauth(&request))
+}
+
+/// Imports a bill from a Finvoice e-invoice
+#[post("/import/finvoice")]
+async fn import_finvoice(
+    data: web::Data<AppState>,
+    form: web::Form<NewBillFinvoiceForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let decoded = NewBillWithEvent::try_from(NewBillFinvoiceFormWithUserId {
+            user_id: user.id,
+            form: form.into_inner(),
+        });
+        return import_bill(&data, &request, decoded).await;
+    }
+    Ok(deauth(&request))
+}
+
+/// Imports a bank statement, marking the bills paid by its transactions as paid
+#[post("/reconcile")]
+async fn reconcile_statement_form(
+    data: web::Data<AppState>,
+    form: web::Form<StatementForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let transactions = match form.parse() {
+            Ok(transactions) => transactions,
+            Err(e) if request.is_frontend_request() => {
+                return Ok(reload(&request, true)
+                    .with_flash_message(FlashMessage::error(&e.to_string()))
+                    .finish())
+            }
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        };
+        let reconciliation = reconcile_statement(
+            &data,
+            user.id,
+            user.interface_timezone_h,
+            &transactions,
+            form.create_events,
+        )
+        .await?;
+        if request.is_frontend_request() {
+            return Ok(reload(&request, true)
+                .with_flash_message(FlashMessage::info(&reconciliation.to_string()))
+                .finish());
+        }
+        return Ok(HttpResponse::Ok().json(reconciliation));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/reminders")]
+async fn reminder_settings(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let settings = get_reminder_settings(&data, user.id).await?;
+        return Ok(HttpResponse::Ok().json(settings));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/reminders")]
+async fn update_reminder_settings(
+    data: web::Data<AppState>,
+    form: web::Form<ReminderSettingsForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let settings = match ReminderSettings::try_from(form.into_inner()) {
+            Ok(settings) => settings,
+            Err(e) if request.is_frontend_request() => {
+                return Ok(reload(&request, true)
+                    .with_flash_message(FlashMessage::error(e))
+                    .finish())
+            }
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e)),
+        };
+        set_reminder_settings(&data, user.id, &settings).await?;
+        if request.is_frontend_request() {
+            return Ok(reload(&request, true)
+                .with_flash_message(FlashMessage::info("Reminder settings saved"))
+                .finish());
+        }
+        return Ok(HttpResponse::Ok().json(settings));
+    }
+    Ok(deauth(&request))
+}
+
+pub fn routes() -> Scope {
+    web::scope("/bill")
+        .service(bills)
+        .service(new_bill)
+        .service(import_epc_qr)
+        .service(import_finvoice)
+        .service(reconcile_statement_form)
+        .service(reminder_settings)
+        .service(update_reminder_settings)
+        .service(mark_paid)
+        .service(mark_unpaid)
+        .service(update_bill)
+        .service(delete_bill)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        request::SESSION_COOKIE_NAME,
+        testing::{app_state, insert_local_event, insert_user},
+    };
+    use actix_web::{cookie::Cookie, http::StatusCode, test, App};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn api_clients_get_errors_and_updates_keep_the_payee(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let event_id = insert_local_event(&conn, user_id, "event").await;
+        sqlx::query!(
+            "INSERT INTO sessions (id, user_id, expires_at) VALUES ('session', $1, $2)",
+            user_id,
+            chrono::Utc::now().timestamp() + 60 * 60
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let app = test::init_service(App::new().app_data(data).service(routes())).await;
+        let post = |uri: &str, form: &[(&str, String)]| {
+            test::TestRequest::post()
+                .uri(uri)
+                .cookie(Cookie::new(SESSION_COOKIE_NAME, "session"))
+                .set_form(form.to_vec())
+                .to_request()
+        };
+        let add_bill = |amount: &str| {
+            post(
+                "/bill",
+                &[
+                    ("local_event_id", event_id.to_string()),
+                    ("payee_account_number", "FI2112345600000785".to_string()),
+                    ("amount", amount.to_string()),
+                    ("reference", "1232".to_string()),
+                    ("payee_name", "Payee".to_string()),
+                ],
+            )
+        };
+
+        let invalid = test::call_service(&app, add_bill("lots")).await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        let bill: Bill = test::call_and_read_body_json(&app, add_bill("12.30")).await;
+        let duplicate = test::call_service(&app, add_bill("12.30")).await;
+        assert_eq!(duplicate.status(), StatusCode::CONFLICT);
+
+        let update_uri = format!("/bill/{}/update", bill.id);
+        let invalid = test::call_service(
+            &app,
+            post(
+                &update_uri,
+                &[
+                    ("amount", "lots".to_string()),
+                    ("reference", "1232".to_string()),
+                ],
+            ),
+        )
+        .await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        let updated: Bill = test::call_and_read_body_json(
+            &app,
+            post(
+                &update_uri,
+                &[
+                    ("amount", "20".to_string()),
+                    ("reference", "1232".to_string()),
+                ],
+            ),
+        )
+        .await;
+        assert_eq!(updated.amount, 2000);
+        assert_eq!(updated.payee_name.as_deref(), Some("Payee"));
+    }
+}
@@ -9,13 +9,14 @@ use olmonoko_common::{
         attendance::{AttendanceEvent, AttendanceForm, NewAttendance},
         bills::{
             from_barcode::{NewBillBarcodeForm, NewBillBarcodeFormWithUserId},
-            EventId, NewBillWithEvent,
+            NewBillWithEvent,
         },
         event::{
             local::{LocalEvent, LocalEventForm, LocalEventId, NewLocalEvent, RawLocalEvent},
             remote::RemoteEventId,
             EventOccurrenceHuman, DEFAULT_PRIORITY,
         },
+        share::SharePermission,
         user::UserPublic,
     },
     utils::{
@@ -25,10 +26,14 @@ use olmonoko_common::{
     AppState,
 };
 
+use itertools::Itertools;
+
 use crate::db::{
     attendance::DBWrite,
-    events::{get_visible_event_occurrences, parse_priority},
-    request::{reload, EnhancedRequest},
+    bills::insert_bill_with_event,
+    events::{get_visible_event_occurrences, parse_priority, EventScope},
+    request::{reload, EnhancedRequest, InternalServerError},
+    shares::{get_local_calendar_permission, get_local_event_permission},
 };
 
 #[post("/local")]
@@ -36,7 +41,7 @@ async fn new_local_event(
     data: web::Data<AppState>,
     form: web::Form<LocalEventForm>,
     request: HttpRequest,
-) -> impl Responder {
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     tracing::info!("Creating new local event: {:?}", form);
     let user_opt = request.get_session_user(&data).await.map(UserPublic::from);
     if let Some(user) = user_opt {
@@ -44,6 +49,15 @@ async fn new_local_event(
         let attendance_form = form.attendance.clone();
 
         let new = NewLocalEvent::from((form, &user));
+        let owner_id = new.user_id;
+        let permission = get_local_calendar_permission(&data, user.id, owner_id).await?;
+        if permission != Some(SharePermission::Write) {
+            return Ok(reload(&request, false)
+                .with_flash_message(FlashMessage::error(
+                    "You don't have write access to that calendar",
+                ))
+                .finish());
+        }
 
         // begin transaction
         let mut txn = data
@@ -97,15 +111,16 @@ async fn new_local_event(
             .expect("Failed to insert attendance");
         // commit transaction
         txn.commit().await.expect("Failed to commit transaction");
+        crate::calendar_io::export_cache::invalidate_user(owner_id);
 
-        return reload(&request, false)
+        return Ok(reload(&request, false)
             .with_flash_message(FlashMessage::info(&format!(
                 "Event {} created",
                 inserted.id
             )))
-            .finish();
+            .finish());
     }
-    HttpResponse::Unauthorized().finish()
+    Ok(HttpResponse::Unauthorized().finish())
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -131,7 +146,12 @@ async fn delete_local_event(
             RawLocalEvent,
             r#"
                 DELETE FROM local_events
-                WHERE user_id = $2::integer
+                -- events in shared calendars can only be deleted one at a time
+                WHERE (user_id = $2::integer OR ($1::integer IS NOT NULL AND user_id IN (
+                        SELECT owner_id
+                        FROM calendar_shares
+                        WHERE grantee_id = $2 AND ics_source_id IS NULL AND permission = 'write'
+                    )))
                     AND ($1::integer IS NULL OR id = $1) 
                     AND ($3::bigint IS NULL OR starts_at > $3) 
                     AND ($4::bigint IS NULL OR starts_at < $4) 
@@ -169,6 +189,9 @@ async fn delete_local_event(
         .into_iter()
         .map(LocalEvent::from)
         .collect::<Vec<_>>();
+        for owner_id in deleted.iter().map(|event| event.user_id).unique() {
+            crate::calendar_io::export_cache::invalidate_user(owner_id);
+        }
 
         let message = if deleted.is_empty() {
             FlashMessage::warning("No events deleted")
@@ -188,12 +211,23 @@ async fn update_local_event(
     request: HttpRequest,
     id: Path<LocalEventId>,
     form: web::Form<LocalEventForm>,
-) -> impl Responder {
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     let user_opt = request.get_session_user(&data).await.map(UserPublic::from);
     if let Some(user) = user_opt {
         let id = id.into_inner();
         let form = form.into_inner();
         let attendance_form = form.attendance.clone();
+        let owner_id = match get_local_event_permission(&data, user.id, id).await? {
+            Some((owner_id, SharePermission::Write)) => owner_id,
+            Some((_, SharePermission::Read)) => {
+                return Ok(reload(&request, true)
+                    .with_flash_message(FlashMessage::error(
+                        "You don't have write access to that calendar",
+                    ))
+                    .finish());
+            }
+            None => return Ok(HttpResponse::NotFound().body("event not found")),
+        };
 
         // begin transaction
         let mut txn = data
@@ -206,7 +240,8 @@ async fn update_local_event(
         sqlx::query!(
             r#"
                 UPDATE local_events
-                SET starts_at = $1, all_day = $2, duration = $3, summary = $4, description = $5, location = $6, priority = $7
+                SET starts_at = $1, all_day = $2, duration = $3, summary = $4, description = $5, location = $6, priority = $7,
+                    updated_at = EXTRACT(EPOCH FROM NOW())*1000
                 WHERE id = $8 AND user_id = $9
             "#,
             new.starts_at,
@@ -217,7 +252,7 @@ async fn update_local_event(
             new.location,
             new.priority,
             id,
-            user.id
+            owner_id
         )
         .execute(&mut *txn)
         .await
@@ -248,12 +283,14 @@ async fn update_local_event(
 
         // commit transaction
         txn.commit().await.expect("Failed to commit transaction");
+        crate::calendar_io::export_cache::invalidate_user(owner_id);
+        crate::calendar_io::export_cache::invalidate_user(user.id);
 
-        return reload(&request, true)
+        return Ok(reload(&request, true)
             .with_flash_message(FlashMessage::info(&format!("Event {} updated", id)))
-            .finish();
+            .finish());
     }
-    HttpResponse::Unauthorized().finish()
+    Ok(HttpResponse::Unauthorized().finish())
 }
 
 #[put("/local/{id}/attendance")]
@@ -262,11 +299,17 @@ async fn update_local_attendance(
     request: HttpRequest,
     id: Path<LocalEventId>,
     form: web::Form<AttendanceForm>,
-) -> impl Responder {
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     let (mut context, user_opt, _key, _timer) = request.get_session_context(&data).await;
     if let Some(user) = user_opt {
         let id = id.into_inner();
         let form = form.into_inner();
+        if get_local_event_permission(&data, user.id, id)
+            .await?
+            .is_none()
+        {
+            return Ok(HttpResponse::NotFound().body("event not found"));
+        }
         let mut txn = data
             .conn
             .begin()
@@ -290,9 +333,10 @@ async fn update_local_attendance(
             .unwrap();
 
         txn.commit().await.expect("Failed to commit transaction");
-        return HttpResponse::Ok().body(content);
+        crate::calendar_io::export_cache::invalidate_user(user.id);
+        return Ok(HttpResponse::Ok().body(content));
     }
-    HttpResponse::Unauthorized().finish()
+    Ok(HttpResponse::Unauthorized().finish())
 }
 
 #[put("/remote/{id}/attendance")]
@@ -330,6 +374,7 @@ async fn update_remote_attendance(
             .unwrap();
 
         txn.commit().await.expect("Failed to commit transaction");
+        crate::calendar_io::export_cache::invalidate_user(user.id);
         return HttpResponse::Ok().body(content);
     }
     HttpResponse::Unauthorized().finish()
@@ -340,7 +385,7 @@ async fn new_bill_from_barcode(
     data: web::Data<AppState>,
     form: web::Form<NewBillBarcodeForm>,
     request: HttpRequest,
-) -> impl Responder {
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     let user_opt = request.get_session_user(&data).await;
     if let Some(user) = user_opt {
         let form = form.into_inner();
@@ -348,60 +393,25 @@ async fn new_bill_from_barcode(
             user_id: user.id,
             form,
         };
-        let (new_event, mut new_bill) =
-            NewBillWithEvent::try_from(with_user_id).expect("Failed to decode barcode");
-        let mut txn = data
-            .conn
-            .begin()
-            .await
-            .expect("Failed to begin transaction");
-        let inserted_event = sqlx::query_as!(
-            RawLocalEvent,
-            r#"
-                INSERT INTO local_events (user_id, priority, starts_at, all_day, duration, summary, description, location, uid)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-                RETURNING *
-            "#,
-            new_event.user_id,
-            new_event.priority,
-            new_event.starts_at,
-            new_event.all_day,
-            new_event.duration,
-            new_event.summary,
-            new_event.description,
-            new_event.location,
-            new_event.uid
-        )
-            .fetch_one(&mut *txn)
-            .await
-            .map(LocalEvent::from)
-            .expect("Failed to insert new local event");
-
-        // insert tags
-        for tag in new_event.tags {
-            sqlx::query!(
-                "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, $2)",
-                inserted_event.id,
-                tag
-            )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert tag");
-        }
-
-        new_bill.event_id = EventId::Local(inserted_event.id);
-        sqlx::query!("INSERT INTO bills (local_event_id, payee_account_number, amount, reference, payee_name, payee_email, payee_address, payee_phone) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)", inserted_event.id, new_bill.payee_account_number, new_bill.amount, new_bill.reference, new_bill.payee_name, new_bill.payee_email, new_bill.payee_address, new_bill.payee_phone).execute(&mut *txn).await.expect("Failed to insert new bill");
-
-        txn.commit().await.expect("Failed to commit transaction");
+        let decoded = match NewBillWithEvent::try_from(with_user_id) {
+            Ok(decoded) => decoded,
+            Err(e) if request.is_frontend_request() => {
+                return Ok(reload(&request, false)
+                    .with_flash_message(FlashMessage::error(&e.to_string()))
+                    .finish())
+            }
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        };
+        let (inserted_event, _) = insert_bill_with_event(&data, decoded).await?;
 
-        return reload(&request, false)
+        return Ok(reload(&request, false)
             .with_flash_message(FlashMessage::info(&format!(
                 "Event {} created",
                 inserted_event.id
             )))
-            .finish();
+            .finish());
     }
-    HttpResponse::Unauthorized().finish()
+    Ok(HttpResponse::Unauthorized().finish())
 }
 
 #[get("/occurrences/planning_to_attend")]
@@ -413,7 +423,9 @@ async fn planning_to_attend(data: web::Data<AppState>, request: HttpRequest) ->
 
             ..Default::default()
         };
-        let events = get_visible_event_occurrences(&data, Some(user.id), true, &filter).await;
+        let events =
+            get_visible_event_occurrences(&data, Some(user.id), EventScope::Shared, true, &filter)
+                .await;
         let events = events
             .into_iter()
             .map(|e| EventOccurrenceHuman::from((e, &user.interface_timezone_parsed)))
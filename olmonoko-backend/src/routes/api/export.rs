@@ -1,49 +1,275 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch,
+    LastModified,
+};
 use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder, Scope};
+use sha2::Digest;
 use uuid::Uuid;
 
+use crate::calendar_io::export_cache::{self, CachedExport};
+use crate::calendar_io::IcsOptions;
+
 use crate::db::errors::TemplateOrDatabaseError;
-use crate::db::events::{get_user_local_events, get_visible_event_occurrences};
+use crate::db::events::{get_user_local_events, get_visible_event_occurrences, EventScope};
+use crate::db::groups::{
+    get_group_as_member, get_group_event_occurrences, get_group_export_links, get_group_members,
+};
 use crate::db::request::{
     deauth, EnhancedRequest, InternalServerError, IntoInternalServerError, OrInternalServerError,
 };
+use crate::db::sources::get_visible_sources;
 use crate::db::user::get_user_export_links;
 use olmonoko_common::models::event::{Event, EventOccurrence, Priority};
+use olmonoko_common::models::group::{GroupId, GroupMember};
 use olmonoko_common::models::public_link::{PublicLink, RawPublicLink};
+use olmonoko_common::models::user::UserId;
 use olmonoko_common::utils::event_filters::EventFilter;
+use olmonoko_common::utils::time::{from_date_form, from_timestamp, timestamp};
 use olmonoko_common::AppState;
 
+/// How long calendar clients may reuse an export before revalidating it, in seconds.
+const EXPORT_MAX_AGE: u32 = 5 * 60;
+
+/// Fetches an export link for a subscriber, recording the access.
+/// Expired links are treated as if they didn't exist.
+async fn access_link(
+    data: &web::Data<AppState>,
+    id: &str,
+) -> Result<Option<PublicLink>, InternalServerError<sqlx::Error>> {
+    let now = timestamp();
+    sqlx::query_as!(
+        RawPublicLink,
+        r#"
+            UPDATE public_calendar_links
+            SET last_accessed_at = $2, fetch_count = fetch_count + 1
+            WHERE id = $1 AND (expires_at IS NULL OR expires_at > $2)
+            RETURNING *
+        "#,
+        id,
+        now
+    )
+    .fetch_optional(&data.conn)
+    .await
+    .or_internal_server_error("Failed to fetch public calendar link from the database")
+    .map(|link| link.map(PublicLink::from))
+}
+
+/// The users whose events end up in a link's export: the opted-in members for group links,
+/// the owner otherwise.
+async fn get_link_members(
+    data: &web::Data<AppState>,
+    link: &PublicLink,
+) -> Result<(Vec<UserId>, Option<Vec<GroupMember>>), InternalServerError<sqlx::Error>> {
+    match link.group_id {
+        Some(group_id) => {
+            let members = get_group_members(data, group_id).await?;
+            let user_ids = members
+                .iter()
+                .filter(|m| m.share_events)
+                .map(|m| m.user_id)
+                .collect();
+            Ok((user_ids, Some(members)))
+        }
+        None => Ok((vec![link.user_id], None)),
+    }
+}
+
+/// Computes a strong ETag from everything that ends up in a link's export:
+/// the link's own settings, group membership, the sources visible to the exported users
+/// and the state of their local events and attendance.
+async fn export_etag(
+    data: &web::Data<AppState>,
+    link: &PublicLink,
+    user_ids: &[UserId],
+    members: Option<&[GroupMember]>,
+) -> Result<String, InternalServerError<sqlx::Error>> {
+    let state = sqlx::query!(
+        r#"
+            SELECT
+                (
+                    SELECT string_agg(p.user_id || ':' || s.id || ':' || COALESCE(s.object_hash, '') || ':' || COALESCE(p.priority, 0) || ':' || s.name, ',' ORDER BY p.user_id, s.id)
+                    FROM ics_sources AS s
+                    INNER JOIN ics_source_priorities AS p
+                        ON p.ics_source_id = s.id
+                        AND p.user_id = ANY($1)
+                    WHERE s.user_id = p.user_id OR s.is_public
+                ) AS sources,
+                (
+                    SELECT COUNT(*) || ':' || COALESCE(MAX(id), 0) || ':' || COALESCE(MAX(updated_at), 0)
+                    FROM local_events
+                    WHERE user_id = ANY($1)
+                ) AS local_events,
+                (
+                    SELECT COUNT(*) || ':' || COALESCE(MAX(updated_at), 0)
+                    FROM attendance
+                    WHERE user_id = ANY($1)
+                ) AS attendance,
+                (
+                    SELECT COUNT(*) || ':' || COALESCE(MAX(updated_at), 0)
+                    FROM bills
+                    WHERE user_id = ANY($1)
+                ) AS bills,
+                (
+                    -- tags can't be edited, only added and removed
+                    SELECT COUNT(*) || ':' || COALESCE(MAX(t.created_at), 0)
+                    FROM event_tags AS t
+                    LEFT JOIN local_events AS l ON l.id = t.local_event_id
+                    LEFT JOIN events AS e ON e.id = t.remote_event_id
+                    WHERE l.user_id = ANY($1)
+                        OR EXISTS (
+                            SELECT 1 FROM ics_source_priorities AS p
+                            WHERE p.ics_source_id = e.event_source_id AND p.user_id = ANY($1)
+                        )
+                ) AS tags
+        "#,
+        user_ids
+    )
+    .fetch_one(&data.conn)
+    .await
+    .or_internal_server_error("Failed to fetch export state from the database")?;
+
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(crate::get_version());
+    hasher.update(link.id.as_bytes());
+    hasher.update(format!(
+        "{:?}:{:?}:{}:{}:{}:{:?}",
+        link.min_priority,
+        link.max_priority,
+        link.export_categories,
+        link.export_source,
+        link.export_attendance,
+        link.alarm_priority
+    ));
+    if let Some(members) = members {
+        for m in members {
+            hasher.update(format!(
+                "{}:{}:{:?}:{:?}",
+                m.user_id, m.share_events, m.min_priority, m.max_priority
+            ));
+        }
+    }
+    hasher.update(state.sources.unwrap_or_default());
+    hasher.update(state.local_events.unwrap_or_default());
+    hasher.update(state.attendance.unwrap_or_default());
+    hasher.update(state.bills.unwrap_or_default());
+    hasher.update(state.tags.unwrap_or_default());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether the client already has the export identified by `etag` (or, lacking
+/// an `If-None-Match`, a copy at least as new as `last_modified`).
+fn is_fresh(request: &HttpRequest, etag: &EntityTag, last_modified: Option<HttpDate>) -> bool {
+    if let Ok(if_none_match) = IfNoneMatch::parse(request) {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(items) => items.iter().any(|item| item.weak_eq(etag)),
+        };
+    }
+    match (IfModifiedSince::parse(request), last_modified) {
+        (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+/// Events exported by a link: the merged group calendar for group links, the owner's own otherwise.
+/// Calendars shared with the owner are never exported, the link could be passed on to anyone.
+async fn get_link_event_occurrences(
+    data: &web::Data<AppState>,
+    link: &PublicLink,
+    members: Option<&[GroupMember]>,
+) -> Vec<EventOccurrence> {
+    let filter = EventFilter {
+        min_priority: link.min_priority,
+        max_priority: link.max_priority,
+        ..Default::default()
+    };
+    match members {
+        Some(members) => get_group_event_occurrences(data, members, true, &filter).await,
+        None => {
+            get_visible_event_occurrences(
+                data,
+                Some(link.user_id),
+                EventScope::Followed,
+                true,
+                &filter,
+            )
+            .await
+        }
+    }
+}
+
 #[get("/{id}.ics")]
 async fn get_calendar(
     data: web::Data<AppState>,
     path: web::Path<Uuid>,
+    request: HttpRequest,
 ) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     let id = path.into_inner().to_string();
     tracing::info!("Fetching calendar for id {id}");
-    let opt = sqlx::query_as!(
-        RawPublicLink,
-        "SELECT * FROM public_calendar_links WHERE id = $1",
-        id
-    )
-    .fetch_optional(&data.conn)
-    .await
-    .or_internal_server_error("Failed to fetch public calendar link from the database")?
-    .map(PublicLink::from);
+    let opt = access_link(&data, &id).await?;
     if let Some(public_link) = opt {
-        let events = get_visible_event_occurrences(
-            &data,
-            Some(public_link.user_id),
-            true,
-            &EventFilter {
-                min_priority: public_link.min_priority,
-                max_priority: public_link.max_priority,
-                ..Default::default()
-            },
-        )
-        .await;
-        let ics = crate::calendar_io::compose_ics(events)
-            .await
-            .expect("Failed to compose ics");
-        return Ok(HttpResponse::Ok().content_type("text/calendar").body(ics));
+        let (user_ids, members) = get_link_members(&data, &public_link).await?;
+        let etag = export_etag(&data, &public_link, &user_ids, members.as_deref()).await?;
+        let cached = export_cache::get(&id, &etag).await;
+        let entity_tag = EntityTag::new_strong(etag.clone());
+        let cache_control = CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(EXPORT_MAX_AGE),
+        ]);
+        let last_modified = cached
+            .as_ref()
+            .map(|cached| HttpDate::from(std::time::SystemTime::from(cached.last_modified)));
+        if is_fresh(&request, &entity_tag, last_modified) {
+            let mut response = HttpResponse::NotModified();
+            response
+                .insert_header(ETag(entity_tag))
+                .insert_header(cache_control);
+            if let Some(last_modified) = last_modified {
+                response.insert_header(LastModified(last_modified));
+            }
+            return Ok(response.finish());
+        }
+
+        let cached = match cached {
+            Some(cached) => cached,
+            None => {
+                let events =
+                    get_link_event_occurrences(&data, &public_link, members.as_deref()).await;
+                let mut source_names = HashMap::new();
+                for user_id in &user_ids {
+                    source_names.extend(
+                        get_visible_sources(&data, Some(*user_id))
+                            .await
+                            .into_iter()
+                            .map(|source| (source.id, source.name)),
+                    );
+                }
+                let options = IcsOptions::from_link(&public_link, source_names);
+                let ics = crate::calendar_io::compose_ics(events, &options)
+                    .await
+                    .expect("Failed to compose ics");
+                let rendered = CachedExport {
+                    user_ids,
+                    etag,
+                    // HTTP dates only have a resolution of one second
+                    last_modified: from_timestamp(timestamp()),
+                    body: Arc::new(ics),
+                };
+                export_cache::insert(&id, rendered.clone()).await;
+                rendered
+            }
+        };
+        Ok(HttpResponse::Ok()
+            .content_type("text/calendar")
+            .insert_header(ETag(entity_tag))
+            .insert_header(LastModified(HttpDate::from(std::time::SystemTime::from(
+                cached.last_modified,
+            ))))
+            .insert_header(cache_control)
+            .body(cached.body.as_ref().clone()))
     } else {
         Ok(HttpResponse::NotFound().body("link not found"))
     }
@@ -55,29 +281,12 @@ async fn get_calendar_rss(
 ) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
     let id = path.into_inner().to_string();
     tracing::info!("Fetching calendar for id {id}");
-    let opt = sqlx::query_as!(
-        RawPublicLink,
-        "SELECT * FROM public_calendar_links WHERE id = $1",
-        id
-    )
-    .fetch_optional(&data.conn)
-    .await
-    .or_internal_server_error("Failed to fetch public calendar link from the database")?
-    .map(PublicLink::from);
+    let opt = access_link(&data, &id).await?;
     if let Some(public_link) = opt {
-        let events = get_visible_event_occurrences(
-            &data,
-            Some(public_link.user_id),
-            true,
-            &EventFilter {
-                min_priority: public_link.min_priority,
-                max_priority: public_link.max_priority,
-                ..Default::default()
-            },
-        )
-        .await;
+        let (_, members) = get_link_members(&data, &public_link).await?;
+        let events = get_link_event_occurrences(&data, &public_link, members.as_deref()).await;
         let ics = crate::calendar_io::compose_rss(events).expect("Failed to compose rss");
-        return Ok(HttpResponse::Ok().content_type("application/xml").body(ics));
+        Ok(HttpResponse::Ok().content_type("application/xml").body(ics))
     } else {
         Ok(HttpResponse::NotFound().body("link not found"))
     }
@@ -107,7 +316,7 @@ async fn get_local_calendar(
             o
         })
         .collect();
-        let ics = crate::calendar_io::compose_ics(events)
+        let ics = crate::calendar_io::compose_ics(events, &IcsOptions::default())
             .await
             .expect("Failed to compose ics");
         return Ok(HttpResponse::Ok().content_type("text/calendar").body(ics));
@@ -138,82 +347,165 @@ async fn delete_link(
     }
 }
 
+use olmonoko_common::models::ics_source::deserialize_checkbox;
+/// Renders the list of links the edited link belongs to, either the user's own or a group's
+async fn render_export_links(
+    data: &web::Data<AppState>,
+    context: &mut tera::Context,
+    user_id: UserId,
+    group_id: Option<GroupId>,
+) -> Result<String, InternalServerError<TemplateOrDatabaseError>> {
+    let links = match group_id {
+        Some(group_id) => get_group_export_links(data, group_id).await,
+        None => get_user_export_links(data, user_id).await,
+    }
+    .map_err(|e| TemplateOrDatabaseError::from(e.cause).internal_server_error(&e.context))?;
+    context.insert("export_links", &links);
+    context.insert("export_group_id", &group_id);
+    data.templates
+        .render("components/export_link.html", context)
+        .map_err(TemplateOrDatabaseError::from)
+        .or_internal_server_error("Failed to render template")
+}
+
 use serde_with::As;
 use serde_with::NoneAsEmptyString;
-#[derive(Debug, Clone, Copy, serde::Deserialize)]
-struct ChangePriorityFilterForm {
+#[derive(Debug, Clone, serde::Deserialize)]
+struct ChangeLinkSettingsForm {
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     min_priority: Option<Priority>,
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     max_priority: Option<Priority>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    name: Option<String>,
+    /// Date (YYYY-MM-DD) in the user's interface timezone, the link stops working at the start of it
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    expires_at: Option<String>,
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    export_categories: bool,
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    export_source: bool,
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    export_attendance: bool,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    alarm_priority: Option<Priority>,
 }
 #[patch("/{id}.ics")]
 async fn change_filters(
     data: web::Data<AppState>,
     path: web::Path<Uuid>,
-    form: web::Form<ChangePriorityFilterForm>,
+    form: web::Form<ChangeLinkSettingsForm>,
     request: HttpRequest,
 ) -> Result<impl Responder, InternalServerError<TemplateOrDatabaseError>> {
     let id = path.into_inner().to_string();
     let (mut context, user_opt, _key, _timer) = request.get_session_context(&data).await;
     if let Some(user) = user_opt {
-        sqlx::query!(
-            "UPDATE public_calendar_links SET min_priority = $1, max_priority = $2 WHERE id = $3 AND user_id = $4",
+        let form = form.into_inner();
+        let expires_at = match form.expires_at.as_deref() {
+            Some(date) => match from_date_form(date, user.interface_timezone_h) {
+                Some(expires_at) => Some(expires_at.timestamp()),
+                None => return Ok(HttpResponse::BadRequest().body("Invalid expiry date")),
+            },
+            None => None,
+        };
+        let group_id = sqlx::query_scalar!(
+            r#"
+                UPDATE public_calendar_links
+                SET min_priority = $1, max_priority = $2, name = $3, expires_at = $4,
+                    export_categories = $5, export_source = $6, export_attendance = $7, alarm_priority = $8
+                WHERE id = $9 AND user_id = $10
+                RETURNING group_id
+            "#,
             form.min_priority,
             form.max_priority,
+            form.name,
+            expires_at,
+            form.export_categories,
+            form.export_source,
+            form.export_attendance,
+            form.alarm_priority,
             id,
             user.id
         )
-        .execute(&data.conn)
+        .fetch_optional(&data.conn)
         .await
         .map_err(TemplateOrDatabaseError::from)
-        .or_internal_server_error("Failed to update min priority")?;
-
-        context.insert(
-            "export_links",
-            &get_user_export_links(&data, user.id).await.map_err(|e| {
-                TemplateOrDatabaseError::from(e.cause).internal_server_error(&e.context)
-            })?,
-        );
-        let content = data
-            .templates
-            .render("components/export_link.html", &context)
-            .map_err(TemplateOrDatabaseError::from)
-            .or_internal_server_error("Failed to render template")?;
+        .or_internal_server_error("Failed to update export link settings")?
+        .flatten();
+
+        let content = render_export_links(&data, &mut context, user.id, group_id).await?;
         return Ok(HttpResponse::Ok().body(content));
     }
     Ok(deauth(&request))
 }
 
+/// Replaces the id of a link while keeping its settings, invalidating the old url
+#[post("/{id}/rotate")]
+async fn rotate_link(
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<TemplateOrDatabaseError>> {
+    let id = path.into_inner().to_string();
+    let (mut context, user_opt, _key, _timer) = request.get_session_context(&data).await;
+    if let Some(user) = user_opt {
+        let new_id = Uuid::new_v4().to_string();
+        let group_id = sqlx::query_scalar!(
+            "UPDATE public_calendar_links SET id = $1, last_accessed_at = NULL, fetch_count = 0 WHERE id = $2 AND user_id = $3 RETURNING group_id",
+            new_id,
+            id,
+            user.id
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .map_err(TemplateOrDatabaseError::from)
+        .or_internal_server_error("Failed to rotate public link")?
+        .flatten();
+        tracing::info!(user.id, "Rotated export link {id} to {new_id}");
+
+        let content = render_export_links(&data, &mut context, user.id, group_id).await?;
+        return Ok(HttpResponse::Ok().body(content));
+    }
+    Ok(deauth(&request))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct NewLinkQuery {
+    /// Create a link to a group calendar instead of the user's own
+    group_id: Option<GroupId>,
+}
 #[post("")]
 async fn new_link(
     data: web::Data<AppState>,
+    query: web::Query<NewLinkQuery>,
     request: HttpRequest,
 ) -> Result<impl Responder, InternalServerError<TemplateOrDatabaseError>> {
     let (mut context, user_opt, _key, _timer) = request.get_session_context(&data).await;
     if let Some(user) = user_opt {
+        let group_id = query.into_inner().group_id;
+        if let Some(group_id) = group_id {
+            let group = get_group_as_member(&data, group_id, user.id)
+                .await
+                .map_err(|e| {
+                    TemplateOrDatabaseError::from(e.cause).internal_server_error(&e.context)
+                })?;
+            if group.is_none() {
+                return Ok(HttpResponse::NotFound().body("group not found"));
+            }
+        }
         let new_id = Uuid::new_v4().to_string();
         sqlx::query!(
-            "INSERT INTO public_calendar_links (id, user_id) VALUES ($1, $2)",
+            "INSERT INTO public_calendar_links (id, user_id, group_id) VALUES ($1, $2, $3)",
             new_id,
-            user.id
+            user.id,
+            group_id
         )
         .execute(&data.conn)
         .await
         .map_err(TemplateOrDatabaseError::from)
         .or_internal_server_error("Failed to insert new public link")?;
 
-        context.insert(
-            "export_links",
-            &get_user_export_links(&data, user.id).await.map_err(|e| {
-                TemplateOrDatabaseError::from(e.cause).internal_server_error(&e.context)
-            })?,
-        );
-        let content = data
-            .templates
-            .render("components/export_link.html", &context)
-            .map_err(TemplateOrDatabaseError::from)
-            .or_internal_server_error("Failed to render template")?;
+        let content = render_export_links(&data, &mut context, user.id, group_id).await?;
         return Ok(HttpResponse::Ok().body(content));
     }
     Ok(HttpResponse::Unauthorized().finish())
@@ -236,8 +528,126 @@ pub fn routes() -> Scope {
         .service(new_link)
         .service(delete_link)
         .service(change_filters)
+        .service(rotate_link)
         .service(get_mine)
         .service(get_local_calendar)
         .service(get_calendar)
         .service(get_calendar_rss)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::testing::{
+        add_source_to_calendar, app_state, insert_local_event, insert_source, insert_user, share,
+    };
+    use actix_web::{test, App};
+    use itertools::Itertools;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn calendars_shared_with_a_member_stay_out_of_group_exports(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let member = insert_user(&conn, "member@example.com").await;
+        let other_member = insert_user(&conn, "other@example.com").await;
+        let outsider = insert_user(&conn, "outsider@example.com").await;
+        insert_local_event(&conn, member, "member local").await;
+        insert_source(&conn, member, false, "member source").await;
+        insert_local_event(&conn, other_member, "other local").await;
+        insert_local_event(&conn, outsider, "outsider local").await;
+        let outsider_source = insert_source(&conn, outsider, false, "outsider private").await;
+        let public_source = insert_source(&conn, outsider, true, "outsider public").await;
+        share(&conn, outsider, member, None, "write").await;
+        share(&conn, outsider, member, Some(outsider_source), "read").await;
+        add_source_to_calendar(&conn, member, outsider_source).await;
+        add_source_to_calendar(&conn, member, public_source).await;
+        let group_id = sqlx::query_scalar!(
+            "INSERT INTO calendar_groups (owner_id, name) VALUES ($1, 'group') RETURNING id",
+            member
+        )
+        .fetch_one(&conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO calendar_group_members (group_id, user_id, share_events) VALUES ($1, $2, true), ($1, $3, true)",
+            group_id,
+            member,
+            other_member
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let link_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO public_calendar_links (id, user_id, group_id) VALUES ($1, $2, $3)",
+            link_id.to_string(),
+            member,
+            group_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+
+        let app = test::init_service(App::new().app_data(data).service(get_calendar)).await;
+        let request = test::TestRequest::get()
+            .uri(&format!("/{link_id}.ics"))
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        let ics = String::from_utf8(body.to_vec()).unwrap();
+        let summaries = ics
+            .lines()
+            .filter_map(|line| line.strip_prefix("SUMMARY:"))
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(summaries, ["member local", "member source", "other local"]);
+    }
+
+    #[sqlx::test]
+    async fn tags_and_bills_change_the_etag(conn: PgPool) {
+        let data = app_state(conn.clone()).await;
+        let user_id = insert_user(&conn, "user@example.com").await;
+        let event_id = insert_local_event(&conn, user_id, "event").await;
+        let link_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO public_calendar_links (id, user_id) VALUES ($1, $2)",
+            link_id.to_string(),
+            user_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let app = test::init_service(App::new().app_data(data).service(get_calendar)).await;
+        let etag = || async {
+            let request = test::TestRequest::get()
+                .uri(&format!("/{link_id}.ics"))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            response
+                .headers()
+                .get(actix_web::http::header::ETAG)
+                .unwrap()
+                .clone()
+        };
+
+        let untouched = etag().await;
+        assert_eq!(etag().await, untouched);
+        sqlx::query!(
+            "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, 'tagged')",
+            event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        let tagged = etag().await;
+        assert_ne!(tagged, untouched);
+        sqlx::query!(
+            "INSERT INTO bills (user_id, local_event_id, payee_account_number, amount, reference) VALUES ($1, $2, 'FI2112345600000785', 100, '13')",
+            user_id,
+            event_id
+        )
+        .execute(&conn)
+        .await
+        .unwrap();
+        assert_ne!(etag().await, tagged);
+    }
+}
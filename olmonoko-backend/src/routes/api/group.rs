@@ -0,0 +1,280 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Scope};
+
+use olmonoko_common::models::group::{
+    Group, GroupForm, GroupId, GroupMemberInviteForm, GroupMembershipForm, RawGroup,
+};
+use olmonoko_common::models::user::UserId;
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
+use olmonoko_common::utils::time::timestamp;
+use olmonoko_common::AppState;
+
+use crate::db::groups::{get_group_as_member, get_group_members, get_user_groups};
+use crate::db::request::{
+    deauth, redirect, reload, EnhancedRequest, InternalServerError, OrInternalServerError,
+};
+
+const MIN_NAME_LENGTH: usize = 3;
+
+#[get("")]
+async fn groups(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let groups = get_user_groups(&data, user.id).await?;
+        return Ok(HttpResponse::Ok().json(groups));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/{id}")]
+async fn group_by_id(
+    data: web::Data<AppState>,
+    path: web::Path<GroupId>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let id = path.into_inner();
+        if let Some(group) = get_group_as_member(&data, id, user.id).await? {
+            let members = get_group_members(&data, id).await?;
+            return Ok(HttpResponse::Ok().json(serde_json::json!({
+                "group": group,
+                "members": members,
+            })));
+        }
+        return Ok(HttpResponse::NotFound().body("group not found"));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("")]
+async fn create_group(
+    data: web::Data<AppState>,
+    form: web::Form<GroupForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if form.name.len() < MIN_NAME_LENGTH {
+        return Ok(reload(&request, true)
+            .with_flash_message(FlashMessage::error(&format!(
+                "Name must be at least {} characters",
+                MIN_NAME_LENGTH
+            )))
+            .finish());
+    }
+    if let Some(user) = request.get_session_user(&data).await {
+        let now = timestamp();
+        let mut txn = data
+            .conn
+            .begin()
+            .await
+            .or_internal_server_error("Failed to begin transaction")?;
+        let group = sqlx::query_as!(
+            RawGroup,
+            "INSERT INTO calendar_groups (owner_id, name, created_at) VALUES ($1, $2, $3) RETURNING *",
+            user.id,
+            form.name,
+            now
+        )
+        .fetch_one(&mut *txn)
+        .await
+        .map(Group::from)
+        .or_internal_server_error("Failed to insert new group")?;
+        sqlx::query!(
+            "INSERT INTO calendar_group_members (group_id, user_id, created_at) VALUES ($1, $2, $3)",
+            group.id,
+            user.id,
+            now
+        )
+        .execute(&mut *txn)
+        .await
+        .or_internal_server_error("Failed to add owner to new group")?;
+        txn.commit()
+            .await
+            .or_internal_server_error("Failed to commit transaction")?;
+
+        if request.is_frontend_request() {
+            return Ok(redirect(&format!("/groups/{}", group.id))
+                .with_flash_message(FlashMessage::info("Group created"))
+                .finish());
+        }
+        return Ok(HttpResponse::Ok().json(group));
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/delete")]
+async fn delete_group(
+    data: web::Data<AppState>,
+    path: web::Path<GroupId>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let id = path.into_inner();
+        let deleted = sqlx::query!(
+            "DELETE FROM calendar_groups WHERE id = $1 AND owner_id = $2",
+            id,
+            user.id
+        )
+        .execute(&data.conn)
+        .await
+        .or_internal_server_error("Failed to delete group")?
+        .rows_affected();
+        if deleted == 0 {
+            return Ok(reload(&request, false)
+                .with_flash_message(FlashMessage::error("Only the owner can delete a group"))
+                .finish());
+        }
+        return Ok(redirect("/groups")
+            .with_flash_message(FlashMessage::info("Group deleted"))
+            .finish());
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/members")]
+async fn invite_member(
+    data: web::Data<AppState>,
+    path: web::Path<GroupId>,
+    form: web::Form<GroupMemberInviteForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let id = path.into_inner();
+        let group = get_group_as_member(&data, id, user.id).await?;
+        if group.is_none_or(|group| group.owner_id != user.id) {
+            return Ok(reload(&request, false)
+                .with_flash_message(FlashMessage::error("Only the owner can add members"))
+                .finish());
+        }
+        let invited = sqlx::query_scalar!(
+            r#"
+                INSERT INTO calendar_group_members (group_id, user_id, created_at)
+                SELECT $1, id, $3 FROM users WHERE email = $2
+                ON CONFLICT DO NOTHING
+                RETURNING user_id
+            "#,
+            id,
+            form.email.trim(),
+            timestamp()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to add group member")?;
+        let message = match invited {
+            Some(_) => FlashMessage::info(&format!("Added {} to the group", form.email)),
+            None => FlashMessage::warning(&format!(
+                "No user {} found, or they are already a member",
+                form.email
+            )),
+        };
+        return Ok(reload(&request, false).with_flash_message(message).finish());
+    }
+    Ok(deauth(&request))
+}
+
+/// Removes a member from a group. The owner can remove anyone but themselves, other members can only leave.
+#[post("/{id}/members/{user_id}/remove")]
+async fn remove_member(
+    data: web::Data<AppState>,
+    path: web::Path<(GroupId, UserId)>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let (id, member_id) = path.into_inner();
+        let Some(group) = get_group_as_member(&data, id, user.id).await? else {
+            return Ok(HttpResponse::NotFound().body("group not found"));
+        };
+        let leaving = member_id == user.id;
+        if leaving && group.owner_id == user.id {
+            return Ok(reload(&request, false)
+                .with_flash_message(FlashMessage::error(
+                    "The owner can't leave their group, but they can delete it",
+                ))
+                .finish());
+        }
+        if !leaving && group.owner_id != user.id {
+            return Ok(reload(&request, false)
+                .with_flash_message(FlashMessage::error("Only the owner can remove members"))
+                .finish());
+        }
+        let mut txn = data
+            .conn
+            .begin()
+            .await
+            .or_internal_server_error("Failed to begin transaction")?;
+        sqlx::query!(
+            "DELETE FROM calendar_group_members WHERE group_id = $1 AND user_id = $2",
+            id,
+            member_id
+        )
+        .execute(&mut *txn)
+        .await
+        .or_internal_server_error("Failed to remove group member")?;
+        // links created by the member shouldn't keep exporting the group
+        sqlx::query!(
+            "DELETE FROM public_calendar_links WHERE group_id = $1 AND user_id = $2",
+            id,
+            member_id
+        )
+        .execute(&mut *txn)
+        .await
+        .or_internal_server_error("Failed to remove group member's export links")?;
+        txn.commit()
+            .await
+            .or_internal_server_error("Failed to commit transaction")?;
+
+        if leaving {
+            return Ok(redirect("/groups")
+                .with_flash_message(FlashMessage::info(&format!("Left {}", group.name)))
+                .finish());
+        }
+        return Ok(reload(&request, false)
+            .with_flash_message(FlashMessage::info("Member removed"))
+            .finish());
+    }
+    Ok(deauth(&request))
+}
+
+#[post("/{id}/membership")]
+async fn update_membership(
+    data: web::Data<AppState>,
+    path: web::Path<GroupId>,
+    form: web::Form<GroupMembershipForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let id = path.into_inner();
+        let form = form.into_inner();
+        sqlx::query!(
+            r#"
+                UPDATE calendar_group_members
+                SET colour = $1, share_events = $2, min_priority = $3, max_priority = $4
+                WHERE group_id = $5 AND user_id = $6
+            "#,
+            form.colour,
+            form.share_events,
+            form.min_priority,
+            form.max_priority,
+            id,
+            user.id
+        )
+        .execute(&data.conn)
+        .await
+        .or_internal_server_error("Failed to update group membership")?;
+        return Ok(reload(&request, false)
+            .with_flash_message(FlashMessage::info("Membership updated"))
+            .finish());
+    }
+    Ok(deauth(&request))
+}
+
+pub fn routes() -> Scope {
+    web::scope("/group")
+        .service(groups)
+        .service(group_by_id)
+        .service(create_group)
+        .service(delete_group)
+        .service(invite_member)
+        .service(remove_member)
+        .service(update_membership)
+}
@@ -1,24 +1,36 @@
 use actix_web::{web, Scope};
 
 pub(crate) mod backup;
+pub(crate) mod bill;
 pub(crate) mod data_source;
 pub(crate) mod event;
 pub(crate) mod export;
+pub(crate) mod group;
 pub(crate) mod key;
 pub(crate) mod meta;
-pub(crate) mod user;
+pub(crate) mod oidc;
+pub(crate) mod report;
+pub(crate) mod share;
 pub(crate) mod timer;
+pub(crate) mod two_factor;
 pub(crate) mod ui_utils;
+pub(crate) mod user;
 
 pub fn routes() -> Scope {
     web::scope("/api")
         .service(data_source::routes())
         .service(meta::routes())
         .service(user::routes())
+        .service(oidc::routes())
         .service(export::routes())
+        .service(group::routes())
+        .service(share::routes())
         .service(event::routes())
+        .service(bill::routes())
+        .service(report::routes())
         .service(backup::routes())
         .service(key::routes())
         .service(timer::routes())
+        .service(two_factor::routes())
         .service(ui_utils::routes())
 }
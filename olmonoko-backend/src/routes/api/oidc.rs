@@ -0,0 +1,170 @@
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Scope};
+
+use olmonoko_common::models::oidc::{OidcCallbackQuery, OidcLoginQuery, OIDC_LOGIN_LIFETIME};
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
+use olmonoko_common::AppState;
+
+use crate::auth::oidc::OIDC_PROVIDER;
+use crate::db::login_attempts::record_login_attempt;
+use crate::db::oidc::{
+    create_oidc_login, get_or_provision_oidc_user, take_oidc_login, OidcUserError,
+};
+use crate::db::request::{
+    redirect, AnyInternalServerError, EnhancedRequest, OrInternalServerError,
+};
+use crate::db::two_factor::{create_login_challenge, get_user_totp};
+
+/// Holds the state of the login in the browser that started it. Without it, anyone could start
+/// a login and send the callback URL to someone else, logging them in as the sender.
+const STATE_COOKIE_NAME: &str = "oidc_state";
+
+fn state_cookie(state: String) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE_NAME, state)
+        .path("/api/oidc")
+        .secure(true)
+        .http_only(true)
+        // sent along when the identity provider redirects back
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(OIDC_LOGIN_LIFETIME))
+        .finish()
+}
+
+fn state_removal_cookie() -> Cookie<'static> {
+    let mut cookie = state_cookie(String::new());
+    cookie.make_removal();
+    cookie
+}
+
+fn login_failed(message: &str) -> HttpResponse {
+    let mut builder = redirect("/me");
+    builder.cookie(state_removal_cookie());
+    builder
+        .with_flash_message(FlashMessage::error(message))
+        .finish()
+}
+
+/// Sends the browser to the identity provider
+#[get("/login")]
+async fn login(
+    data: web::Data<AppState>,
+    query: web::Query<OidcLoginQuery>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(provider) = OIDC_PROVIDER.as_ref() else {
+        return Ok(HttpResponse::NotFound().body("Single sign-on is not configured"));
+    };
+    let authorization = match provider.authorize().await {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            tracing::error!("Failed to start single sign-on: {e}");
+            return Ok(login_failed("Failed to reach the identity provider"));
+        }
+    };
+    create_oidc_login(&data.conn, &authorization, query.remember)
+        .await
+        .or_any_internal_server_error("Failed to save single sign-on login")?;
+    Ok(redirect(&authorization.url)
+        .cookie(state_cookie(authorization.state))
+        .finish())
+}
+
+/// Where the identity provider sends the browser back to. The provider is trusted with any
+/// second factor, so this starts a session directly.
+#[get("/callback")]
+async fn callback(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<OidcCallbackQuery>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let Some(provider) = OIDC_PROVIDER.as_ref() else {
+        return Ok(HttpResponse::NotFound().body("Single sign-on is not configured"));
+    };
+    let query = query.into_inner();
+    let started_here = req
+        .cookie(STATE_COOKIE_NAME)
+        .is_some_and(|cookie| cookie.value() == query.state);
+    if !started_here {
+        tracing::warn!("Single sign-on callback from a browser that didn't start the login");
+        return Ok(login_failed("Your login expired, log in again"));
+    }
+    let pending = take_oidc_login(&data.conn, &query.state)
+        .await
+        .or_any_internal_server_error("Failed to fetch single sign-on login")?;
+    let Some(pending) = pending else {
+        return Ok(login_failed("Your login expired, log in again"));
+    };
+    let Some(code) = query.code else {
+        tracing::warn!(
+            "Identity provider refused the login: {:?} {:?}",
+            query.error,
+            query.error_description
+        );
+        // the description is chosen by the provider, so it's only logged
+        return Ok(login_failed("The identity provider refused the login"));
+    };
+    let identity = match provider
+        .identify(code, pending.pkce_verifier, pending.nonce)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("Single sign-on failed: {e}");
+            return Ok(login_failed("Single sign-on failed"));
+        }
+    };
+    let email = &identity.email;
+    let user_id =
+        match get_or_provision_oidc_user(&data.conn, &identity, provider.auto_provision).await {
+            Err(e @ OidcUserError::UnverifiedEmail(_)) => {
+                tracing::warn!("Single sign-on refused: {e}");
+                return Ok(login_failed(
+                    "The identity provider hasn't verified your email",
+                ));
+            }
+            result => result.or_any_internal_server_error("Failed to fetch single sign-on user")?,
+        };
+    if let Some(user_id) = user_id {
+        let two_factor = get_user_totp(&data.conn, user_id)
+            .await
+            .or_any_internal_server_error("Failed to fetch two-factor settings for login")?
+            .is_some_and(|totp| totp.enabled);
+        if two_factor {
+            // the provider only stands in for the password, the attempt is recorded once the
+            // second factor is checked
+            let challenge = create_login_challenge(&data.conn, user_id, pending.remember)
+                .await
+                .or_any_internal_server_error("Failed to create login challenge")?;
+            return Ok(redirect("/login/verify")
+                .cookie(crate::auth::login_challenge_cookie(challenge))
+                .cookie(state_removal_cookie())
+                .finish());
+        }
+    }
+    let ip_address = req.get_client_ip();
+    let user_agent = req.get_user_agent();
+    record_login_attempt(
+        &data.conn,
+        email,
+        user_id,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        user_id.is_some(),
+    )
+    .await
+    .or_any_internal_server_error("Failed to record login attempt")?;
+    let Some(user_id) = user_id else {
+        tracing::warn!("Single sign-on for {email}, who has no account");
+        return Ok(login_failed(&format!("There is no account for {email}")));
+    };
+    let (_session, cookie) = crate::auth::create_session(&data, &req, user_id, pending.remember)
+        .await
+        .or_any_internal_server_error("Failed to create session")?;
+    Ok(redirect("/")
+        .cookie(cookie)
+        .cookie(state_removal_cookie())
+        .finish())
+}
+
+pub fn routes() -> Scope {
+    web::scope("/oidc").service(login).service(callback)
+}
@@ -0,0 +1,206 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Scope};
+
+use olmonoko_common::models::report::{bill_report_csv, time_report_csv, InvalidDate, ReportQuery};
+use olmonoko_common::models::timesheet::{teamwork_csv, timesheet_csv, TimesheetQuery};
+use olmonoko_common::AppState;
+
+use crate::db::reports::{get_reports, get_timesheet};
+use crate::db::request::{deauth, EnhancedRequest, InternalServerError};
+
+fn invalid_date(e: InvalidDate) -> HttpResponse {
+    HttpResponse::BadRequest().body(e.to_string())
+}
+
+fn csv_response(filename: &str, body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{filename}\""),
+        ))
+        .body(body)
+}
+
+#[get("/bills")]
+async fn bills(
+    data: web::Data<AppState>,
+    query: web::Query<ReportQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return Ok(invalid_date(e)),
+        };
+        let (rows, _) = get_reports(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().json(rows));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/bills.csv")]
+async fn bills_csv(
+    data: web::Data<AppState>,
+    query: web::Query<ReportQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return Ok(invalid_date(e)),
+        };
+        let (rows, _) = get_reports(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await?;
+        return Ok(csv_response("bills.csv", bill_report_csv(&rows)));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/time")]
+async fn time(
+    data: web::Data<AppState>,
+    query: web::Query<ReportQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return Ok(invalid_date(e)),
+        };
+        let (_, rows) = get_reports(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().json(rows));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/time.csv")]
+async fn time_csv(
+    data: web::Data<AppState>,
+    query: web::Query<ReportQuery>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return Ok(invalid_date(e)),
+        };
+        let (_, rows) = get_reports(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await?;
+        return Ok(csv_response("time.csv", time_report_csv(&rows)));
+    }
+    Ok(deauth(&request))
+}
+
+#[get("/timesheet")]
+async fn timesheet(
+    data: web::Data<AppState>,
+    query: web::Query<TimesheetQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return invalid_date(e),
+        };
+        let rows = get_timesheet(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await;
+        return HttpResponse::Ok().json(rows);
+    }
+    deauth(&request)
+}
+
+#[get("/timesheet.csv")]
+async fn timesheet_download(
+    data: web::Data<AppState>,
+    query: web::Query<TimesheetQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Some(user) = request.get_session_user(&data).await {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return invalid_date(e),
+        };
+        let rows = get_timesheet(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await;
+        return csv_response("timesheet.csv", timesheet_csv(&rows, query.by_tag));
+    }
+    deauth(&request)
+}
+
+/// Grouping by tag would repeat the entries, so it's ignored
+#[get("/teamwork.csv")]
+async fn teamwork_download(
+    data: web::Data<AppState>,
+    query: web::Query<TimesheetQuery>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Some(user) = request.get_session_user(&data).await {
+        let query = TimesheetQuery {
+            by_tag: false,
+            ..query.into_inner()
+        };
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return invalid_date(e),
+        };
+        let rows = get_timesheet(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await;
+        return csv_response("teamwork.csv", teamwork_csv(&rows, &user.email));
+    }
+    deauth(&request)
+}
+
+pub fn routes() -> Scope {
+    web::scope("/report")
+        .service(bills)
+        .service(bills_csv)
+        .service(time)
+        .service(time_csv)
+        .service(timesheet)
+        .service(timesheet_download)
+        .service(teamwork_download)
+}
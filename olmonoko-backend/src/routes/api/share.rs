@@ -0,0 +1,121 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Scope};
+
+use olmonoko_common::models::share::{ShareForm, ShareId, SharePermission};
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
+use olmonoko_common::utils::time::timestamp;
+use olmonoko_common::AppState;
+
+use crate::db::request::{
+    deauth, reload, EnhancedRequest, InternalServerError, OrInternalServerError,
+};
+use crate::db::shares::get_user_shares;
+
+#[get("")]
+async fn shares(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let granted = get_user_shares(&data, user.id, true).await?;
+        let received = get_user_shares(&data, user.id, false).await?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "granted": granted,
+            "received": received,
+        })));
+    }
+    Ok(deauth(&request))
+}
+
+/// Grants another user access to the local calendar or one of the user's own sources.
+/// Granting something that's already shared with the user changes the permission.
+#[post("")]
+async fn new_share(
+    data: web::Data<AppState>,
+    form: web::Form<ShareForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let form = form.into_inner();
+        if let Some(source_id) = form.ics_source_id {
+            if form.permission != SharePermission::Read {
+                return Ok(reload(&request, false)
+                    .with_flash_message(FlashMessage::error("Sources can only be shared read-only"))
+                    .finish());
+            }
+            let owned = sqlx::query_scalar!(
+                "SELECT id FROM ics_sources WHERE id = $1 AND user_id = $2",
+                source_id,
+                user.id
+            )
+            .fetch_optional(&data.conn)
+            .await
+            .or_internal_server_error("Failed to query source to share")?;
+            if owned.is_none() {
+                return Ok(reload(&request, false)
+                    .with_flash_message(FlashMessage::error("You can only share your own sources"))
+                    .finish());
+            }
+        }
+        let grantee_id = sqlx::query_scalar!(
+            r#"
+                INSERT INTO calendar_shares (owner_id, grantee_id, ics_source_id, permission, created_at)
+                SELECT $1, id, $3, $4, $5 FROM users WHERE email = $2 AND id <> $1
+                ON CONFLICT (owner_id, grantee_id, ics_source_id) DO UPDATE SET permission = $4
+                RETURNING grantee_id
+            "#,
+            user.id,
+            form.email.trim(),
+            form.ics_source_id,
+            form.permission.to_string(),
+            timestamp()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to insert share")?;
+        let message = match grantee_id {
+            Some(grantee_id) => {
+                crate::calendar_io::export_cache::invalidate_user(grantee_id);
+                FlashMessage::info(&format!("Shared with {}", form.email))
+            }
+            None => FlashMessage::warning(&format!("No user {} found", form.email)),
+        };
+        return Ok(reload(&request, false).with_flash_message(message).finish());
+    }
+    Ok(deauth(&request))
+}
+
+/// Revokes a share. Both the owner and the grantee can remove it.
+#[post("/{id}/delete")]
+async fn delete_share(
+    data: web::Data<AppState>,
+    path: web::Path<ShareId>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let id = path.into_inner();
+        let grantee_id = sqlx::query_scalar!(
+            "DELETE FROM calendar_shares WHERE id = $1 AND (owner_id = $2 OR grantee_id = $2) RETURNING grantee_id",
+            id,
+            user.id
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_internal_server_error("Failed to delete share")?;
+        let message = match grantee_id {
+            Some(grantee_id) => {
+                crate::calendar_io::export_cache::invalidate_user(grantee_id);
+                FlashMessage::info("Share removed")
+            }
+            None => FlashMessage::warning("Share not found"),
+        };
+        return Ok(reload(&request, false).with_flash_message(message).finish());
+    }
+    Ok(deauth(&request))
+}
+
+pub fn routes() -> Scope {
+    web::scope("/share")
+        .service(shares)
+        .service(new_share)
+        .service(delete_share)
+}
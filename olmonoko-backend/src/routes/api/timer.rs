@@ -1,32 +1,75 @@
 use actix_web::HttpRequest;
-use actix_web::{post, web, HttpResponse, Responder, Scope};
+use actix_web::{get, post, web, HttpResponse, Responder, Scope};
 use olmonoko_common::models::attendance::NewAttendance;
 use olmonoko_common::models::event::local::{LocalEvent, NewLocalEvent, RawLocalEvent};
 use olmonoko_common::models::event::Priority;
-use olmonoko_common::models::timer::{NewTimer, RawTimer, Timer, TimerForm, TimerId};
+use olmonoko_common::models::timer::{
+    NewTimer, RawTimer, Timer, TimerForm, TimerId, TimerStartForm, TimerStopForm,
+};
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
 use olmonoko_common::utils::time::timestamp;
 
 use crate::db::attendance::DBWrite;
 use crate::db::errors::TemplateOrDatabaseError;
 use crate::db::request::{reload, AnyInternalServerError, EnhancedRequest, OrInternalServerError};
+use crate::db::timers::{get_user_timer, get_user_timers};
 use olmonoko_common::AppState;
 
+const FORBIDDEN_TEMPLATE: &str = "olmonoko.timer.forbidden-template";
+
+#[get("")]
+async fn list(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let timers = get_user_timers(&data.conn, user.id)
+            .await
+            .or_any_internal_server_error("Failed to fetch timers")?;
+        return Ok(HttpResponse::Ok().json(timers));
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
+#[get("/{id}")]
+async fn get_timer(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let timer = get_user_timer(&data.conn, user.id, path.into_inner())
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?;
+        return Ok(match timer {
+            Some(timer) => HttpResponse::Ok().json(timer),
+            None => HttpResponse::NotFound().body("timer not found"),
+        });
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
 #[post("")]
 async fn start(
     form: web::Form<TimerForm>,
     data: web::Data<AppState>,
     request: HttpRequest,
 ) -> Result<impl Responder, AnyInternalServerError> {
-    let (mut context, user_opt, _key, _timer) = request.get_session_context(&data).await;
+    let (mut context, user_opt, _key, mut timers) = request.get_session_context(&data).await;
 
     if let Some(user) = user_opt {
         let details = NewTimer::from(form.into_inner());
-        let result_raw = sqlx::query_as!(RawTimer, r#"INSERT INTO timers (user_id, template, summary, details, location, created_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#, user.id, details.template, details.summary, details.details, details.location, details.created_at).fetch_one(&data.conn).await;
-        let result = match result_raw {
-            Ok(result_raw) => Timer::from(result_raw),
+        let mut txn = data
+            .conn
+            .begin()
+            .await
+            .or_any_internal_server_error("Failed to begin transaction")?;
+        let result_raw = sqlx::query_as!(RawTimer, r#"INSERT INTO timers (user_id, template, name, summary, details, location, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#, user.id, details.template, details.name, details.summary, details.details, details.location, details.created_at).fetch_one(&mut *txn).await;
+        let raw = match result_raw {
+            Ok(result_raw) => result_raw,
             Err(sqlx::Error::Database(e)) => {
                 let msg = e.message();
-                if msg == "olmonoko.timer.forbidden-template" {
+                if msg == FORBIDDEN_TEMPLATE {
                     return Ok(HttpResponse::Forbidden()
                         .body("You are not authorized to use that event as a timer template"));
                 }
@@ -35,8 +78,24 @@ async fn start(
             }
             Err(e) => Err(e).or_any_internal_server_error("Failed to insert new timer into db")?,
         };
+        sqlx::query!(
+            "INSERT INTO timer_segments (timer_id, started_at) VALUES ($1, $2)",
+            raw.id,
+            raw.created_at
+        )
+        .execute(&mut *txn)
+        .await
+        .or_any_internal_server_error("Failed to start timer segment")?;
+        txn.commit()
+            .await
+            .or_any_internal_server_error("Failed to commit transaction")?;
+        let result = get_user_timer(&data.conn, user.id, raw.id)
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+            .ok_or("Timer disappeared")?;
         if request.is_frontend_request() {
-            context.insert("timer_active", &result);
+            timers.push(result);
+            context.insert("timers", &timers);
 
             let content = data
                 .templates
@@ -51,6 +110,227 @@ async fn start(
     Ok(HttpResponse::Unauthorized().finish())
 }
 
+/// Responds to a pause or resume: reloads the page for the UI, returns the timer otherwise
+async fn respond_with_timer(
+    data: &web::Data<AppState>,
+    request: &HttpRequest,
+    timer: &Timer,
+) -> Result<HttpResponse, AnyInternalServerError> {
+    if request.is_frontend_request() {
+        return Ok(reload(request, true).finish());
+    }
+    let timer = get_user_timer(&data.conn, timer.user_id, timer.id)
+        .await
+        .or_any_internal_server_error("Failed to fetch timer")?;
+    Ok(HttpResponse::Ok().json(timer))
+}
+
+fn conflict(request: &HttpRequest, message: &str) -> HttpResponse {
+    if request.is_frontend_request() {
+        return reload(request, true)
+            .with_flash_message(FlashMessage::error(message))
+            .finish();
+    }
+    HttpResponse::Conflict().body(message.to_owned())
+}
+
+#[post("/{id}/pause")]
+async fn pause(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let timer_id = path.into_inner();
+        let Some(timer) = get_user_timer(&data.conn, user.id, timer_id)
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+        else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        if !timer.running {
+            return Ok(conflict(&request, "The timer is already paused"));
+        }
+        sqlx::query!(
+            "UPDATE timer_segments SET ended_at = GREATEST($2, started_at) WHERE timer_id = $1 AND ended_at IS NULL",
+            timer.id,
+            timestamp()
+        )
+        .execute(&data.conn)
+        .await
+        .or_any_internal_server_error("Failed to pause timer")?;
+        return respond_with_timer(&data, &request, &timer).await;
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
+#[post("/{id}/resume")]
+async fn resume(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let timer_id = path.into_inner();
+        let Some(timer) = get_user_timer(&data.conn, user.id, timer_id)
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+        else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        if timer.running {
+            return Ok(conflict(&request, "The timer is already running"));
+        }
+        let result = sqlx::query!(
+            "INSERT INTO timer_segments (timer_id, started_at) VALUES ($1, $2)",
+            timer.id,
+            timestamp()
+        )
+        .execute(&data.conn)
+        .await;
+        match result {
+            Ok(_) => {}
+            // resumed by another request since it was fetched
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Ok(conflict(&request, "The timer is already running"));
+            }
+            Err(e) => Err(e).or_any_internal_server_error("Failed to resume timer")?,
+        }
+        return respond_with_timer(&data, &request, &timer).await;
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
+/// Changes the details or the template of a running timer, all fields are replaced
+#[post("/{id}/update")]
+async fn update(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    form: web::Form<TimerForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let Some(timer) = get_user_timer(&data.conn, user.id, path.into_inner())
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+        else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        let form = form.into_inner();
+        let result = sqlx::query!(
+            "UPDATE timers SET template = $2, name = $3, summary = $4, details = $5, location = $6 WHERE id = $1",
+            timer.id,
+            form.template,
+            form.name,
+            form.summary,
+            form.details,
+            form.location
+        )
+        .execute(&data.conn)
+        .await;
+        match result {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(e)) if e.message() == FORBIDDEN_TEMPLATE => {
+                return Ok(HttpResponse::Forbidden()
+                    .body("You are not authorized to use that event as a timer template"));
+            }
+            Err(e) => Err(e).or_any_internal_server_error("Failed to update timer")?,
+        }
+        return respond_with_timer(&data, &request, &timer).await;
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
+/// Moves the start of the timer, e.g. when it was started late
+#[post("/{id}/start")]
+async fn adjust_start(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    form: web::Form<TimerStartForm>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let Some(timer) = get_user_timer(&data.conn, user.id, path.into_inner())
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+        else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        let Some(starts_at) = form.parse(user.interface_timezone_h) else {
+            return Ok(HttpResponse::BadRequest().body("Invalid start time"));
+        };
+        // the first segment can't end before it starts
+        let latest = timer
+            .segments
+            .first()
+            .and_then(|segment| segment.ended_at)
+            .map_or(timestamp(), |ended_at| ended_at.timestamp());
+        if starts_at > latest {
+            return Ok(conflict(
+                &request,
+                "The timer can't start after its first segment ends",
+            ));
+        }
+        let mut txn = data
+            .conn
+            .begin()
+            .await
+            .or_any_internal_server_error("Failed to begin transaction")?;
+        sqlx::query!(
+            "UPDATE timers SET created_at = $2 WHERE id = $1",
+            timer.id,
+            starts_at
+        )
+        .execute(&mut *txn)
+        .await
+        .or_any_internal_server_error("Failed to update timer")?;
+        sqlx::query!(
+            r#"
+                UPDATE timer_segments SET started_at = $2
+                WHERE id = (SELECT id FROM timer_segments WHERE timer_id = $1 ORDER BY started_at, id LIMIT 1)
+            "#,
+            timer.id,
+            starts_at
+        )
+        .execute(&mut *txn)
+        .await
+        .or_any_internal_server_error("Failed to update timer segment")?;
+        txn.commit()
+            .await
+            .or_any_internal_server_error("Failed to commit transaction")?;
+        return respond_with_timer(&data, &request, &timer).await;
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
+/// Deletes the timer without creating an event
+#[post("/{id}/discard")]
+async fn discard(
+    data: web::Data<AppState>,
+    path: web::Path<TimerId>,
+    request: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some(user) = request.get_session_user(&data).await {
+        let discarded = sqlx::query_as!(
+            RawTimer,
+            "DELETE FROM timers WHERE user_id = $1 AND id = $2 RETURNING *",
+            user.id,
+            path.into_inner()
+        )
+        .fetch_optional(&data.conn)
+        .await
+        .or_any_internal_server_error("Failed to discard timer")?;
+        let Some(discarded) = discarded else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        if request.is_frontend_request() {
+            return Ok(reload(&request, true).finish());
+        }
+        return Ok(HttpResponse::Ok().json(discarded));
+    }
+    Ok(HttpResponse::Unauthorized().finish())
+}
+
 // Always in the past, not that important overall
 const DEFAULT_TIMER_PRIORITY: Priority = 9;
 
@@ -58,26 +338,23 @@ const DEFAULT_TIMER_PRIORITY: Priority = 9;
 async fn stop(
     data: web::Data<AppState>,
     path: web::Path<TimerId>,
+    form: Option<web::Form<TimerStopForm>>,
     request: HttpRequest,
 ) -> Result<impl Responder, AnyInternalServerError> {
-    let (_context, user_opt, _key, _timer) = request.get_session_context(&data).await;
+    let (_context, user_opt, _key, _timers) = request.get_session_context(&data).await;
 
     if let Some(user) = &user_opt {
         let timer_id = path.into_inner();
+        let split = form.is_some_and(|form| form.split);
         let ends_at = timestamp();
 
-        let timer = sqlx::query_as!(
-            RawTimer,
-            "SELECT * FROM timers WHERE user_id = $1 AND id = $2",
-            user.id,
-            timer_id
-        )
-        .fetch_one(&data.conn)
-        .await
-        .or_any_internal_server_error("Failed to fetch timer")
-        .map(Timer::from)?;
-        let duration = ends_at - timer.created_at.timestamp();
-        let (summary, details, location, priority, tags) = {
+        let Some(timer) = get_user_timer(&data.conn, user.id, timer_id)
+            .await
+            .or_any_internal_server_error("Failed to fetch timer")?
+        else {
+            return Ok(HttpResponse::NotFound().body("timer not found"));
+        };
+        let (summary, details, location, priority, mut tags) = {
             let template = sqlx::query_as!(
                 RawLocalEvent,
                 "SELECT * FROM local_events WHERE user_id = $1 AND id = $2",
@@ -88,26 +365,45 @@ async fn stop(
             .await
             .or_any_internal_server_error("Failed to fetch timer template")?;
             (
-                timer.summary.unwrap_or(template.summary),
-                timer.details.or(template.description),
-                timer.location.or(template.location),
+                timer.summary.clone().unwrap_or(template.summary),
+                timer.details.clone().or(template.description),
+                timer.location.clone().or(template.location),
                 template.priority.or(Some(DEFAULT_TIMER_PRIORITY)),
                 vec!["olmonoko::timer".to_owned()],
             )
         };
 
-        let new = NewLocalEvent {
-            user_id: user.id,
-            uid: format!("olmonoko::timer::{timer_id}"),
-            summary,
-            all_day: false,
-            location,
-            description: details,
-            tags,
-            priority,
-            duration: Some(duration as i32),
-            starts_at: timer.created_at.timestamp(),
-        };
+        // the template's own tags carry over, so time can be reported by tag
+        let template_tags = sqlx::query_scalar!(
+            "SELECT tag FROM event_tags WHERE local_event_id = $1 AND tag NOT LIKE 'olmonoko::%' ORDER BY tag",
+            timer.template
+        )
+        .fetch_all(&data.conn)
+        .await
+        .or_any_internal_server_error("Failed to fetch timer template tags")?;
+        tags.extend(template_tags);
+
+        let entries = timer.entries(split, ends_at);
+        let single = entries.len() == 1;
+        let new_events = entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, (starts_at, duration))| NewLocalEvent {
+                user_id: user.id,
+                uid: if single {
+                    format!("olmonoko::timer::{timer_id}")
+                } else {
+                    format!("olmonoko::timer::{timer_id}::{}", index + 1)
+                },
+                summary: summary.clone(),
+                all_day: false,
+                location: location.clone(),
+                description: details.clone(),
+                tags: tags.clone(),
+                priority,
+                duration: Some(duration as i32),
+                starts_at,
+            });
 
         // begin transaction
         let mut txn = data
@@ -115,50 +411,55 @@ async fn stop(
             .begin()
             .await
             .or_any_internal_server_error("Failed to begin transaction")?;
-        // insert event
-        let inserted = sqlx::query_as!(
-            RawLocalEvent,
-            r#"
-                INSERT INTO local_events (user_id, priority, starts_at, all_day, duration, summary, description, location, uid)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-                RETURNING *
-            "#,
-            new.user_id,
-            new.priority,
-            new.starts_at,
-            new.all_day,
-            new.duration,
-            new.summary,
-            new.description,
-            new.location,
-            new.uid
-        )
-            .fetch_one(&mut *txn)
-            .await
-            .map(LocalEvent::from)
-            .expect("Failed to insert new local event");
-        // insert tags
-        for tag in new.tags {
-            sqlx::query!(
-                "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, $2)",
-                inserted.id,
-                tag
+        let mut inserted_events = vec![];
+        for new in new_events {
+            // insert event
+            let inserted = sqlx::query_as!(
+                RawLocalEvent,
+                r#"
+                    INSERT INTO local_events (user_id, priority, starts_at, all_day, duration, summary, description, location, uid, timer_template_id)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                    RETURNING *
+                "#,
+                new.user_id,
+                new.priority,
+                new.starts_at,
+                new.all_day,
+                new.duration,
+                new.summary,
+                new.description,
+                new.location,
+                new.uid,
+                timer.template
             )
-            .execute(&mut *txn)
-            .await
-            .expect("Failed to insert tag");
+                .fetch_one(&mut *txn)
+                .await
+                .map(LocalEvent::from)
+                .or_any_internal_server_error("Failed to insert new local event")?;
+            // insert tags
+            for tag in new.tags {
+                sqlx::query!(
+                    "INSERT INTO event_tags (local_event_id, tag) VALUES ($1, $2)",
+                    inserted.id,
+                    tag
+                )
+                .execute(&mut *txn)
+                .await
+                .or_any_internal_server_error("Failed to insert tag")?;
+            }
+            // insert attendance
+            let attendance = NewAttendance {
+                user_id: user.id,
+                actual: true,
+                planned: true,
+                event_id: olmonoko_common::models::attendance::AttendanceEvent::Local(inserted.id),
+            };
+            attendance
+                .write(&mut *txn)
+                .await
+                .or_any_internal_server_error("Failed to insert attendance")?;
+            inserted_events.push(inserted);
         }
-        // insert attendance
-        let attendance = NewAttendance {
-            user_id: user.id,
-            actual: true,
-            planned: true,
-            event_id: olmonoko_common::models::attendance::AttendanceEvent::Local(inserted.id),
-        };
-        attendance
-            .write(&mut *txn)
-            .await
-            .expect("Failed to insert attendance");
 
         sqlx::query!(
             "DELETE FROM timers WHERE user_id = $1 AND id = $2",
@@ -170,17 +471,29 @@ async fn stop(
         .or_any_internal_server_error("Failed to delete timer")?;
 
         // commit transaction
-        txn.commit().await.expect("Failed to commit transaction");
+        txn.commit()
+            .await
+            .or_any_internal_server_error("Failed to commit transaction")?;
+        crate::calendar_io::export_cache::invalidate_user(user.id);
 
         if request.is_frontend_request() {
             return Ok(reload(&request, true).finish());
         } else {
-            return Ok(HttpResponse::Ok().json(inserted));
+            return Ok(HttpResponse::Ok().json(inserted_events));
         }
     }
     Ok(HttpResponse::Unauthorized().finish())
 }
 
 pub fn routes() -> Scope {
-    web::scope("/timer").service(start).service(stop)
+    web::scope("/timer")
+        .service(list)
+        .service(get_timer)
+        .service(start)
+        .service(update)
+        .service(adjust_start)
+        .service(discard)
+        .service(pause)
+        .service(resume)
+        .service(stop)
 }
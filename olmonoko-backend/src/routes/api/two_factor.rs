@@ -0,0 +1,260 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Scope};
+
+use olmonoko_common::models::two_factor::{TotpProvisioning, TwoFactorCodeForm};
+use olmonoko_common::models::user::{UserId, UserPublic};
+use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
+use olmonoko_common::utils::time::timestamp;
+use olmonoko_common::AppState;
+
+use crate::auth::two_factor::{generate_recovery_codes, new_secret, provisioning, verify_code};
+use crate::db::errors::TemplateOrDatabaseError;
+use crate::db::request::{
+    deauth, reload, AnyInternalServerError, EnhancedRequest, OrInternalServerError,
+};
+use crate::db::two_factor::{
+    disable_totp, enable_totp, get_two_factor_status, get_user_totp, replace_recovery_codes,
+    set_pending_totp, verify_second_factor,
+};
+
+/// Renders the two-factor panel of `/me`, along with what's only shown once
+async fn two_factor_component(
+    data: &web::Data<AppState>,
+    mut context: tera::Context,
+    user_id: UserId,
+    provisioning: Option<TotpProvisioning>,
+    recovery_codes: Option<Vec<String>>,
+    error: Option<&str>,
+) -> Result<HttpResponse, AnyInternalServerError> {
+    let status = get_two_factor_status(&data.conn, user_id)
+        .await
+        .or_any_internal_server_error("Failed to fetch two-factor status")?;
+    context.insert("two_factor", &status);
+    context.insert("provisioning", &provisioning);
+    context.insert("recovery_codes", &recovery_codes);
+    context.insert("two_factor_error", &error);
+    let content = data
+        .templates
+        .render("components/auth/two_factor.html", &context)
+        .map_err(TemplateOrDatabaseError::from)
+        .or_any_internal_server_error("Failed to render template")?;
+    Ok(HttpResponse::Ok().body(content))
+}
+
+/// Two-factor settings can only be changed with a session, not with an API key
+async fn session_user(
+    data: &web::Data<AppState>,
+    req: &HttpRequest,
+) -> Option<(tera::Context, UserPublic)> {
+    let (context, user, key, _timers) = req.get_session_context(data).await;
+    match (user, key) {
+        (Some(user), None) => Some((context, user)),
+        _ => None,
+    }
+}
+
+#[get("")]
+async fn get_status(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((_context, user)) = session_user(&data, &req).await {
+        let status = get_two_factor_status(&data.conn, user.id)
+            .await
+            .or_any_internal_server_error("Failed to fetch two-factor status")?;
+        return Ok(HttpResponse::Ok().json(status));
+    }
+    Ok(deauth(&req))
+}
+
+/// Generates a new secret to be confirmed with [`enable`]
+#[post("/setup")]
+async fn setup(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((context, user)) = session_user(&data, &req).await {
+        let enabled = get_user_totp(&data.conn, user.id)
+            .await
+            .or_any_internal_server_error("Failed to fetch two-factor settings")?
+            .is_some_and(|totp| totp.enabled);
+        if enabled {
+            return Ok(
+                HttpResponse::Conflict().body("Two-factor authentication is already enabled")
+            );
+        }
+        let secret = new_secret();
+        set_pending_totp(&data.conn, user.id, &secret)
+            .await
+            .or_any_internal_server_error("Failed to save two-factor secret")?;
+        let mut provisioning = provisioning(&secret, &user.email)
+            .or_any_internal_server_error("Failed to build two-factor provisioning")?;
+        if req.is_frontend_request() {
+            return two_factor_component(&data, context, user.id, Some(provisioning), None, None)
+                .await;
+        }
+        provisioning.qr_svg = None;
+        return Ok(HttpResponse::Ok().json(provisioning));
+    }
+    Ok(deauth(&req))
+}
+
+/// Confirms the secret from [`setup`] with a code from it and hands out recovery codes
+#[post("/enable")]
+async fn enable(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<TwoFactorCodeForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((context, user)) = session_user(&data, &req).await {
+        let totp = get_user_totp(&data.conn, user.id)
+            .await
+            .or_any_internal_server_error("Failed to fetch two-factor settings")?;
+        let Some(totp) = totp.filter(|totp| !totp.enabled) else {
+            return Ok(HttpResponse::Conflict().body("Set up two-factor authentication first"));
+        };
+        let step = verify_code(&totp.secret, &user.email, &form.code, None, timestamp())
+            .or_any_internal_server_error("Failed to verify two-factor code")?;
+        let Some(step) = step else {
+            if req.is_frontend_request() {
+                let provisioning = provisioning(&totp.secret, &user.email)
+                    .or_any_internal_server_error("Failed to build two-factor provisioning")?;
+                return two_factor_component(
+                    &data,
+                    context,
+                    user.id,
+                    Some(provisioning),
+                    None,
+                    Some("Invalid code, try again"),
+                )
+                .await;
+            }
+            return Ok(HttpResponse::Forbidden().body("Invalid code"));
+        };
+        enable_totp(&data.conn, user.id, step)
+            .await
+            .or_any_internal_server_error("Failed to enable two-factor authentication")?;
+        let codes = generate_recovery_codes();
+        replace_recovery_codes(&data.conn, user.id, &codes)
+            .await
+            .or_any_internal_server_error("Failed to save recovery codes")?;
+        if req.is_frontend_request() {
+            return two_factor_component(&data, context, user.id, None, Some(codes), None).await;
+        }
+        return Ok(HttpResponse::Ok().json(codes));
+    }
+    Ok(deauth(&req))
+}
+
+#[post("/disable")]
+async fn disable(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<TwoFactorCodeForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((context, user)) = session_user(&data, &req).await {
+        let valid =
+            verify_second_factor(&data.conn, user.id, &user.email, &form.code, form.is_totp())
+                .await
+                .or_any_internal_server_error("Failed to verify two-factor code")?;
+        if !valid {
+            if req.is_frontend_request() {
+                return two_factor_component(
+                    &data,
+                    context,
+                    user.id,
+                    None,
+                    None,
+                    Some("Invalid code"),
+                )
+                .await;
+            }
+            return Ok(HttpResponse::Forbidden().body("Invalid code"));
+        }
+        disable_totp(&data.conn, user.id)
+            .await
+            .or_any_internal_server_error("Failed to disable two-factor authentication")?;
+        if req.is_frontend_request() {
+            return two_factor_component(&data, context, user.id, None, None, None).await;
+        }
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    Ok(deauth(&req))
+}
+
+/// Replaces the recovery codes, used or not
+#[post("/recovery-codes")]
+async fn regenerate_recovery_codes(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<TwoFactorCodeForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((context, user)) = session_user(&data, &req).await {
+        let valid =
+            verify_second_factor(&data.conn, user.id, &user.email, &form.code, form.is_totp())
+                .await
+                .or_any_internal_server_error("Failed to verify two-factor code")?;
+        if !valid {
+            if req.is_frontend_request() {
+                return two_factor_component(
+                    &data,
+                    context,
+                    user.id,
+                    None,
+                    None,
+                    Some("Invalid code"),
+                )
+                .await;
+            }
+            return Ok(HttpResponse::Forbidden().body("Invalid code"));
+        }
+        let codes = generate_recovery_codes();
+        replace_recovery_codes(&data.conn, user.id, &codes)
+            .await
+            .or_any_internal_server_error("Failed to save recovery codes")?;
+        if req.is_frontend_request() {
+            return two_factor_component(&data, context, user.id, None, Some(codes), None).await;
+        }
+        return Ok(HttpResponse::Ok().json(codes));
+    }
+    Ok(deauth(&req))
+}
+
+/// Lets an admin remove the two-factor authentication of a user who lost access to it
+#[post("/user/{id}/reset")]
+async fn reset(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    id: web::Path<UserId>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    if let Some((_context, user)) = session_user(&data, &req).await {
+        if !user.admin {
+            return Ok(HttpResponse::Forbidden().body("You are not an admin"));
+        }
+        let id = id.into_inner();
+        disable_totp(&data.conn, id)
+            .await
+            .or_any_internal_server_error("Failed to reset two-factor authentication")?;
+        tracing::info!(
+            admin = user.id,
+            user = id,
+            "Two-factor authentication reset"
+        );
+        if req.is_frontend_request() {
+            return Ok(reload(&req, true)
+                .with_flash_message(FlashMessage::info("Two-factor authentication reset"))
+                .finish());
+        }
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    Ok(deauth(&req))
+}
+
+pub fn routes() -> Scope {
+    web::scope("/2fa")
+        .service(get_status)
+        .service(setup)
+        .service(enable)
+        .service(disable)
+        .service(regenerate_recovery_codes)
+        .service(reset)
+}
@@ -1,11 +1,29 @@
 use actix_web::cookie::Cookie;
 use actix_web::HttpRequest;
 use actix_web::{delete, get, patch, post, web, HttpResponse, Responder, Scope};
-use uuid::Uuid;
 
-use crate::db::request::{deauth, redirect, reload, EnhancedRequest, SESSION_COOKIE_NAME};
-use olmonoko_common::models::session::{NewSession, SessionRaw};
-use olmonoko_common::models::user::{NewUser, RawUser, UserForm, UserId, UserPublic};
+use crate::db::errors::TemplateOrDatabaseError;
+use crate::db::login_attempts::{
+    begin_login_attempt, cancel_login_attempt, finish_login_attempt, LoginPermit,
+};
+use crate::db::request::{
+    deauth, redirect, reload, AnyInternalServerError, EnhancedRequest, OrInternalServerError,
+    LOGIN_CHALLENGE_COOKIE_NAME, SESSION_COOKIE_NAME,
+};
+use crate::db::sessions::{
+    get_user_sessions, revoke_other_sessions as db_revoke_other_sessions,
+    revoke_session as db_revoke_session,
+};
+use crate::db::two_factor::{
+    create_login_challenge, delete_login_challenge, get_login_challenge, get_user_totp,
+    verify_second_factor,
+};
+use olmonoko_common::models::session::SessionHandle;
+use olmonoko_common::models::two_factor::TwoFactorCodeForm;
+use olmonoko_common::models::user::{
+    NewUser, PasswordChangeForm, PasswordResetForm, PasswordResetRequestForm, RawUser, UserForm,
+    UserId, UserPublic,
+};
 use olmonoko_common::utils::flash::{FlashMessage, WithFlashMessage};
 use olmonoko_common::AppState;
 
@@ -28,7 +46,26 @@ async fn users(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     deauth(&req)
 }
 
-const MIN_PASSWORD_LENGTH: usize = 9; // We set this high to prevent brute force attacks as we don't have rate limiting yet
+const MIN_PASSWORD_LENGTH: usize = 9; // We set this high to make guessing slow on top of the login throttling
+
+fn password_too_short(req: &HttpRequest, password: &str) -> Option<HttpResponse> {
+    if password.len() >= MIN_PASSWORD_LENGTH {
+        return None;
+    }
+    let message = format!(
+        "Password must be at least {} characters",
+        MIN_PASSWORD_LENGTH
+    );
+    if req.is_frontend_request() {
+        return Some(
+            reload(req, false)
+                .with_flash_message(FlashMessage::error(&message))
+                .finish(),
+        );
+    }
+    Some(HttpResponse::BadRequest().body(message))
+}
+
 #[post("")]
 async fn register(
     data: web::Data<AppState>,
@@ -36,16 +73,8 @@ async fn register(
     req: HttpRequest,
 ) -> impl Responder {
     let user = user.into_inner();
-    if user.password.len() < MIN_PASSWORD_LENGTH {
-        return reload(&req, false)
-            .with_flash_message(FlashMessage::error(
-                format!(
-                    "Password must be at least {} characters",
-                    MIN_PASSWORD_LENGTH
-                )
-                .as_str(),
-            ))
-            .finish();
+    if let Some(response) = password_too_short(&req, &user.password) {
+        return response;
     }
     let mut active_user: NewUser = NewUser {
         email: user.email.clone(),
@@ -78,37 +107,22 @@ async fn register(
 }
 
 #[get("/verify/{secret}")]
-async fn verify_user(data: web::Data<AppState>, secret: web::Path<String>) -> impl Responder {
+async fn verify_user(
+    data: web::Data<AppState>,
+    secret: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
     let secret = secret.into_inner();
     let result = crate::auth::verify_user(&data, &secret).await;
     match result {
         Ok(user) => {
-            let five_days_from_now = chrono::Utc::now() + chrono::Duration::days(5);
-            let new_session = NewSession {
-                id: Uuid::new_v4().to_string(),
-                user_id: user.id,
-                expires_at: five_days_from_now.timestamp(),
-            };
-            let created = sqlx::query_as!(
-                SessionRaw,
-                "INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, $3) RETURNING *",
-                new_session.id,
-                new_session.user_id,
-                new_session.expires_at
-            )
-            .fetch_one(&data.conn)
-            .await
-            .unwrap();
-            let cookie = Cookie::build(SESSION_COOKIE_NAME, created.id.clone())
-                .path("/")
-                .secure(true)
-                .http_only(true)
-                .expires(None) // Change later
-                .finish();
-            return redirect("/")
+            let (_session, cookie) = crate::auth::create_session(&data, &req, user.id, false)
+                .await
+                .expect("Failed to create session");
+            redirect("/")
                 .with_flash_message(FlashMessage::info("Your account has been verified"))
                 .cookie(cookie)
-                .finish();
+                .finish()
         }
         Err(e) => {
             tracing::error!("Failed to verify user: {:?}", e);
@@ -162,6 +176,18 @@ async fn remove_user(
     deauth(&req)
 }
 
+fn throttled(req: &HttpRequest, wait: i64) -> HttpResponse {
+    let message = format!("Too many failed login attempts, try again in {wait} seconds");
+    if req.is_frontend_request() {
+        return reload(req, false)
+            .with_flash_message(FlashMessage::error(&message))
+            .finish();
+    }
+    HttpResponse::TooManyRequests()
+        .insert_header((actix_web::http::header::RETRY_AFTER, wait.to_string()))
+        .body(message)
+}
+
 #[post("/login")]
 async fn login(
     data: web::Data<AppState>,
@@ -169,63 +195,163 @@ async fn login(
     req: HttpRequest,
 ) -> impl Responder {
     let user_input = user.into_inner();
-    if let Some(user) = sqlx::query!("SELECT * FROM users WHERE email = $1", user_input.email)
-        .fetch_optional(&data.conn)
-        .await
-        .expect("Failed to fetch user for login")
-    {
-        if !bcrypt::verify(&user_input.password, &user.password_hash).unwrap() {
-            tracing::warn!("Failed login attempt for {}", user.email);
+    let ip_address = req.get_client_ip();
+    let user_agent = req.get_user_agent();
+    let permit = begin_login_attempt(
+        &data.conn,
+        &user_input.email,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+    )
+    .await
+    .expect("Failed to check login attempts");
+    let attempt = match permit {
+        LoginPermit::Granted(attempt) => attempt,
+        LoginPermit::Throttled(wait) => {
+            tracing::warn!(
+                "Throttled login attempt for {} from {:?}",
+                user_input.email,
+                ip_address
+            );
+            return throttled(&req, wait);
+        }
+    };
+    let user = sqlx::query!(
+        "SELECT id, password_hash FROM users WHERE email = $1",
+        user_input.email
+    )
+    .fetch_optional(&data.conn)
+    .await
+    .expect("Failed to fetch user for login");
+    let valid = crate::auth::verify_password(
+        &user_input.password,
+        user.as_ref().map(|user| user.password_hash.as_str()),
+    );
+    let user_id = user.map(|user| user.id);
+    if let (true, Some(user_id)) = (valid, user_id) {
+        let two_factor = get_user_totp(&data.conn, user_id)
+            .await
+            .expect("Failed to fetch two-factor settings for login")
+            .is_some_and(|totp| totp.enabled);
+        if two_factor {
+            // the attempt is recorded once the second factor is checked
+            cancel_login_attempt(&data.conn, attempt)
+                .await
+                .expect("Failed to cancel login attempt");
+            let challenge = create_login_challenge(&data.conn, user_id, user_input.remember)
+                .await
+                .expect("Failed to create login challenge");
+            let cookie = crate::auth::login_challenge_cookie(challenge);
             if req.is_frontend_request() {
-                return reload(&req, false)
-                    .with_flash_message(FlashMessage::error("Invalid email or password"))
-                    .finish();
+                return redirect("/login/verify").cookie(cookie).finish();
             }
-            return HttpResponse::Forbidden().body("Invalid email or password");
+            return HttpResponse::Accepted()
+                .cookie(cookie)
+                .body("Two-factor code required");
         }
-        let five_days_from_now = chrono::Utc::now() + chrono::Duration::days(5);
-        let new_session = NewSession {
-            id: Uuid::new_v4().to_string(),
-            user_id: user.id,
-            expires_at: five_days_from_now.timestamp(),
-        };
-        let created = sqlx::query_as!(
-            SessionRaw,
-            "INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, $3) RETURNING *",
-            new_session.id,
-            new_session.user_id,
-            new_session.expires_at
-        )
-        .fetch_one(&data.conn)
+    }
+    finish_login_attempt(&data.conn, attempt, user_id, valid)
         .await
-        .expect("Failed to create session");
-        let cookie = Cookie::build(SESSION_COOKIE_NAME, created.id.clone())
-            .path("/")
-            .secure(true)
-            .http_only(true)
-            .expires(None) // Change later
-            .finish();
+        .expect("Failed to record login attempt");
+    if let (true, Some(user_id)) = (valid, user_id) {
+        let (created, cookie) =
+            crate::auth::create_session(&data, &req, user_id, user_input.remember)
+                .await
+                .expect("Failed to create session");
         if req.is_frontend_request() {
             return redirect("/").cookie(cookie).finish();
         }
         return HttpResponse::Ok().cookie(cookie).body(created.id.clone());
     }
+    tracing::warn!("Failed login attempt for {}", user_input.email);
     if req.is_frontend_request() {
         return reload(&req, false)
             .with_flash_message(FlashMessage::error("Invalid email or password"))
             .finish();
     }
-    return HttpResponse::Forbidden().body("Invalid email or password");
+    HttpResponse::Forbidden().body("Invalid email or password")
+}
+
+/// The second step of logging in with two-factor authentication
+#[post("/login/verify")]
+async fn login_verify(
+    data: web::Data<AppState>,
+    form: web::Form<TwoFactorCodeForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    let challenge_id = req
+        .cookie(LOGIN_CHALLENGE_COOKIE_NAME)
+        .map(|cookie| cookie.value().to_string());
+    let challenge = match &challenge_id {
+        Some(id) => get_login_challenge(&data.conn, id)
+            .await
+            .expect("Failed to fetch login challenge"),
+        None => None,
+    };
+    let (Some(challenge_id), Some((user_id, email, remember))) = (challenge_id, challenge) else {
+        if req.is_frontend_request() {
+            return redirect("/me")
+                .with_flash_message(FlashMessage::error("Your login expired, log in again"))
+                .finish();
+        }
+        return HttpResponse::Unauthorized().body("Your login expired, log in again");
+    };
+    let ip_address = req.get_client_ip();
+    let user_agent = req.get_user_agent();
+    let permit = begin_login_attempt(
+        &data.conn,
+        &email,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+    )
+    .await
+    .expect("Failed to check login attempts");
+    let attempt = match permit {
+        LoginPermit::Granted(attempt) => attempt,
+        LoginPermit::Throttled(wait) => return throttled(&req, wait),
+    };
+    let valid = verify_second_factor(&data.conn, user_id, &email, &form.code, form.is_totp())
+        .await
+        .expect("Failed to verify the second factor");
+    finish_login_attempt(&data.conn, attempt, Some(user_id), valid)
+        .await
+        .expect("Failed to record login attempt");
+    if !valid {
+        tracing::warn!("Failed two-factor login attempt for {email}");
+        if req.is_frontend_request() {
+            return reload(&req, false)
+                .with_flash_message(FlashMessage::error("Invalid code"))
+                .finish();
+        }
+        return HttpResponse::Forbidden().body("Invalid code");
+    }
+    delete_login_challenge(&data.conn, &challenge_id)
+        .await
+        .expect("Failed to delete login challenge");
+    let (created, cookie) = crate::auth::create_session(&data, &req, user_id, remember)
+        .await
+        .expect("Failed to create session");
+    let mut removal_cookie = Cookie::build(LOGIN_CHALLENGE_COOKIE_NAME, "")
+        .path("/")
+        .finish();
+    removal_cookie.make_removal();
+    if req.is_frontend_request() {
+        return redirect("/").cookie(cookie).cookie(removal_cookie).finish();
+    }
+    HttpResponse::Ok()
+        .cookie(cookie)
+        .cookie(removal_cookie)
+        .body(created.id)
 }
 
 #[post("/logout")]
 async fn logout(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    let session_cookie = req.cookie(SESSION_COOKIE_NAME).unwrap();
-    let session_id = session_cookie.value();
-    sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
-        .execute(&data.conn)
-        .await
-        .unwrap();
+    if let Some(session_id) = req.get_session_id() {
+        sqlx::query!("DELETE FROM sessions WHERE id = $1", session_id)
+            .execute(&data.conn)
+            .await
+            .unwrap();
+    }
     let mut removal_cookie = Cookie::build(SESSION_COOKIE_NAME, "").finish();
     removal_cookie.make_removal();
     if req.is_frontend_request() {
@@ -234,7 +360,125 @@ async fn logout(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
             .cookie(removal_cookie)
             .finish();
     }
-    return HttpResponse::Ok().cookie(removal_cookie).body("Goodbye!");
+    HttpResponse::Ok().cookie(removal_cookie).body("Goodbye!")
+}
+
+/// Changes the password of the logged in user and logs out their other sessions
+#[post("/password")]
+async fn change_password(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<PasswordChangeForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let (_context, user, key, _timers) = req.get_session_context(&data).await;
+    let (Some(user), None, Some(session_id)) = (user, key, req.get_session_id()) else {
+        return Ok(deauth(&req));
+    };
+    let form = form.into_inner();
+    let current_hash =
+        sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
+            .fetch_one(&data.conn)
+            .await
+            .or_any_internal_server_error("Failed to fetch password")?;
+    if !crate::auth::verify_password(&form.current_password, Some(&current_hash)) {
+        if req.is_frontend_request() {
+            return Ok(reload(&req, false)
+                .with_flash_message(FlashMessage::error("Current password is wrong"))
+                .finish());
+        }
+        return Ok(HttpResponse::Forbidden().body("Current password is wrong"));
+    }
+    if let Some(response) = password_too_short(&req, &form.new_password) {
+        return Ok(response);
+    }
+    let password_hash = bcrypt::hash(&form.new_password, bcrypt::DEFAULT_COST)
+        .or_any_internal_server_error("Failed to hash password")?;
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE id = $2",
+        password_hash,
+        user.id
+    )
+    .execute(&data.conn)
+    .await
+    .or_any_internal_server_error("Failed to update password")?;
+    db_revoke_other_sessions(&data.conn, user.id, &session_id)
+        .await
+        .or_any_internal_server_error("Failed to revoke sessions")?;
+    tracing::info!(user = user.id, "Password changed");
+    if req.is_frontend_request() {
+        return Ok(reload(&req, false)
+            .with_flash_message(FlashMessage::info(
+                "Password changed, other devices have been logged out",
+            ))
+            .finish());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Emails a reset link. Answers the same, and equally fast, whether or not the account exists,
+/// so the link is sent in the background and any errors only end up in the logs.
+#[post("/password/forgot")]
+async fn forgot_password(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<PasswordResetRequestForm>,
+) -> impl Responder {
+    let email = form.into_inner().email.trim().to_string();
+    let ip_address = req.get_client_ip();
+    let data = data.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            crate::auth::request_password_reset(&data, &email, ip_address.as_deref()).await
+        {
+            tracing::error!("Failed to request password reset for {email}: {e}");
+        }
+    });
+    let message = "If the email has an account, a link to reset its password has been sent to it";
+    if req.is_frontend_request() {
+        return reload(&req, false)
+            .with_flash_message(FlashMessage::info(message))
+            .finish();
+    }
+    HttpResponse::Accepted().body(message)
+}
+
+/// Sets a new password with a link from [`forgot_password`] and logs out every session
+#[post("/password/reset")]
+async fn reset_password(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    form: web::Form<PasswordResetForm>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let form = form.into_inner();
+    if let Some(response) = password_too_short(&req, &form.new_password) {
+        return Ok(response);
+    }
+    let password_hash = bcrypt::hash(&form.new_password, bcrypt::DEFAULT_COST)
+        .or_any_internal_server_error("Failed to hash password")?;
+    let user_id = crate::auth::reset_password(&data, &form.secret, &password_hash)
+        .await
+        .or_any_internal_server_error("Failed to reset password")?;
+    let Some(user_id) = user_id else {
+        let message = "The reset link is invalid or has expired";
+        if req.is_frontend_request() {
+            return Ok(redirect("/me")
+                .with_flash_message(FlashMessage::error(message))
+                .finish());
+        }
+        return Ok(HttpResponse::NotFound().body(message));
+    };
+    tracing::info!(user = user_id, "Password reset");
+    let mut removal_cookie = Cookie::build(SESSION_COOKIE_NAME, "").finish();
+    removal_cookie.make_removal();
+    if req.is_frontend_request() {
+        return Ok(redirect("/me")
+            .with_flash_message(FlashMessage::info(
+                "Your password has been reset, log in with it",
+            ))
+            .cookie(removal_cookie)
+            .finish());
+    }
+    Ok(HttpResponse::NoContent().cookie(removal_cookie).finish())
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -288,14 +532,96 @@ async fn me(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     deauth(&req)
 }
 
+async fn sessions_component(
+    data: &web::Data<AppState>,
+    mut context: tera::Context,
+    user_id: UserId,
+    current_id: &str,
+) -> Result<HttpResponse, AnyInternalServerError> {
+    let sessions = get_user_sessions(&data.conn, user_id, current_id)
+        .await
+        .or_any_internal_server_error("Failed to fetch sessions")?;
+    context.insert("sessions", &sessions);
+    let content = data
+        .templates
+        .render("components/auth/sessions.html", &context)
+        .map_err(TemplateOrDatabaseError::from)
+        .or_any_internal_server_error("Failed to render template")?;
+    Ok(HttpResponse::Ok().body(content))
+}
+
+#[get("/sessions")]
+async fn list_sessions(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let (_context, user, key, _timers) = req.get_session_context(&data).await;
+    if let (Some(user), None, Some(session_id)) = (user, key, req.get_session_id()) {
+        let sessions = get_user_sessions(&data.conn, user.id, &session_id)
+            .await
+            .or_any_internal_server_error("Failed to fetch sessions")?;
+        return Ok(HttpResponse::Ok().json(sessions));
+    }
+    Ok(deauth(&req))
+}
+
+#[delete("/sessions/{handle}")]
+async fn revoke_session(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    handle: web::Path<SessionHandle>,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let (context, user, key, _timers) = req.get_session_context(&data).await;
+    if let (Some(user), None, Some(session_id)) = (user, key, req.get_session_id()) {
+        let revoked = db_revoke_session(&data.conn, user.id, handle.into_inner())
+            .await
+            .or_any_internal_server_error("Failed to revoke session")?;
+        if !revoked {
+            return Ok(HttpResponse::NotFound().body("No such session"));
+        }
+        if req.is_frontend_request() {
+            return sessions_component(&data, context, user.id, &session_id).await;
+        }
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    Ok(deauth(&req))
+}
+
+/// Revokes every session except the one making the request
+#[delete("/sessions")]
+async fn revoke_other_sessions(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+) -> Result<impl Responder, AnyInternalServerError> {
+    let (context, user, key, _timers) = req.get_session_context(&data).await;
+    if let (Some(user), None, Some(session_id)) = (user, key, req.get_session_id()) {
+        let revoked = db_revoke_other_sessions(&data.conn, user.id, &session_id)
+            .await
+            .or_any_internal_server_error("Failed to revoke sessions")?;
+        if req.is_frontend_request() {
+            return sessions_component(&data, context, user.id, &session_id).await;
+        }
+        return Ok(HttpResponse::Ok().json(revoked));
+    }
+    Ok(deauth(&req))
+}
+
 pub fn routes() -> Scope {
     web::scope("/user")
         .service(users)
+        // before remove_user, whose path would shadow them
+        .service(list_sessions)
+        .service(revoke_session)
+        .service(revoke_other_sessions)
         .service(remove_user)
         .service(register)
         .service(verify_user)
         .service(login)
+        .service(login_verify)
         .service(logout)
+        .service(change_password)
+        .service(forgot_password)
+        .service(reset_password)
         .service(me)
         .service(change_user_interface_timezone)
 }
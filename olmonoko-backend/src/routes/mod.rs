@@ -1,7 +1,9 @@
 use actix_cors::Cors;
 use actix_files::Files;
 use actix_web::{web, App, HttpServer};
+use olmonoko_common::models::bills::{format_amount, DEFAULT_CURRENCY};
 use olmonoko_common::{get_site_url, AppState, BuildInformation, DatabaseConnection};
+use std::collections::HashMap;
 use tokio_cron_scheduler::JobScheduler;
 use tracing::info;
 use tracing_actix_web::TracingLogger;
@@ -15,8 +17,21 @@ use crate::middleware::AutoCacher;
 use crate::{get_source_commit, get_version};
 use actix_web::middleware::{from_fn, DefaultHeaders};
 
+/// Formats an amount in cents, e.g. `{{ bill.amount | money(currency=bill.currency) }}`
+fn money(value: &tera::Value, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let amount = value
+        .as_i64()
+        .ok_or_else(|| tera::Error::msg("money: value must be an integer"))?;
+    let currency = args
+        .get("currency")
+        .and_then(|currency| currency.as_str())
+        .unwrap_or(DEFAULT_CURRENCY);
+    Ok(tera::Value::String(format_amount(amount, currency)))
+}
+
 pub async fn run_server(conn: DatabaseConnection, scheduler: JobScheduler) -> std::io::Result<()> {
-    let templates = tera::Tera::new("templates/**/*").unwrap();
+    let mut templates = tera::Tera::new("templates/**/*").unwrap();
+    templates.register_filter("money", money);
     let site_url = get_site_url();
     let commit = get_source_commit();
     let commit_short = commit.as_ref().map(|s| s.chars().take(7).collect());
@@ -38,6 +53,9 @@ pub async fn run_server(conn: DatabaseConnection, scheduler: JobScheduler) -> st
         scheduler,
         templates,
     };
+    crate::calendar_io::scheduler::schedule_snapshots(&state)
+        .await
+        .expect("Failed to schedule backups");
     if PREDICTIVE_CACHE_ENABLED {
         info!("Predictive Caching enabled")
     }
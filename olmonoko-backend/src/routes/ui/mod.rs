@@ -10,10 +10,14 @@ use olmonoko_common::{
     models::{
         api_key::{ApiKey, ApiKeyForm, RawApiKey},
         attendance::{Attendance, AttendanceForm, RawAttendance},
+        bills::{BillFilter, BillStatus},
         event::{
             local::{LocalEventForm, LocalEventId},
-            EventOccurrenceHuman, Priority,
+            EventOccurrenceHuman, EventSource, Priority,
         },
+        group::{GroupId, GroupMember},
+        report::ReportQuery,
+        share::SharePermission,
         user::{RawUser, UnverifiedUser, UserPublic},
     },
     utils::{
@@ -24,6 +28,7 @@ use olmonoko_common::{
     },
     AppState,
 };
+use std::collections::BTreeMap;
 
 fn remove_flash_cookie(mut builder: HttpResponseBuilder) -> HttpResponseBuilder {
     let mut removal_cookie = actix_web::cookie::Cookie::build(FLASH_COOKIE_NAME, "")
@@ -78,7 +83,17 @@ async fn local(
         context.insert("filter", &query.filter);
         context.insert("filter_set", &query.filter.is_defined());
         let filter = EventFilter::from(query.filter.clone());
-        let events = get_user_local_events(&data, user.id, false, &filter).await;
+        let mut events = get_user_local_events(&data, user.id, false, &filter).await;
+        events.extend(get_shared_local_events(&data, user.id, false, &filter).await);
+        let writable_calendars = get_user_shares(&data, user.id, false)
+            .await
+            .expect("Failed to query calendars shared with the user")
+            .into_iter()
+            .filter(|share| {
+                share.ics_source_id.is_none() && share.permission == SharePermission::Write
+            })
+            .collect::<Vec<_>>();
+        context.insert("writable_calendars", &writable_calendars);
         let available_tags = events
             .iter()
             .flat_map(|event| event.tags.iter())
@@ -150,6 +165,7 @@ async fn local(
                         duration_h: None,
                         duration_m: None,
                         duration_s: None,
+                        calendar_owner_id: None,
                         attendance: AttendanceForm {
                             attend_plan: true,
                             attend_actual: false,
@@ -185,6 +201,9 @@ async fn source(
     remove_flash_cookie(HttpResponse::Ok()).body(content)
 }
 
+/// How many of the latest login attempts are shown on the admin page
+const ADMIN_LOGIN_ATTEMPT_COUNT: i64 = 100;
+
 #[get("/admin")]
 async fn admin(data: web::Data<AppState>, request: HttpRequest) -> impl Responder {
     let (mut context, user, _key, _timer) = request.get_session_context(&data).await;
@@ -202,6 +221,18 @@ async fn admin(data: web::Data<AppState>, request: HttpRequest) -> impl Responde
         .await
         .expect("Failed to get unverified users");
     context.insert("unverified_users", &unverified_users);
+    let login_attempts = get_recent_login_attempts(&data.conn, ADMIN_LOGIN_ATTEMPT_COUNT)
+        .await
+        .expect("Failed to get login attempts");
+    context.insert("login_attempts", &login_attempts);
+    if let Some(config) = SNAPSHOTS.as_ref() {
+        let snapshots = list_snapshots(config).await.unwrap_or_else(|e| {
+            tracing::error!("Failed to list backups: {e:?}");
+            Vec::new()
+        });
+        context.insert("snapshots", &snapshots);
+        context.insert("snapshot_dir", &config.dir.display().to_string());
+    }
     let content = data.templates.render("pages/admin.html", &context).unwrap();
     remove_flash_cookie(HttpResponse::Ok()).body(content)
 }
@@ -227,11 +258,39 @@ async fn me(
         .await
         .or_internal_server_error("Failed to fetch api keys for /me")?
         .into_iter()
-        .map(|raw| ApiKey::try_from(raw).map(|key| ApiKeyForm::from(key)))
+        .map(|raw| ApiKey::try_from(raw).map(ApiKeyForm::from))
         .collect::<Result<Vec<_>, _>>()
         .expect("invalid api keys returned from db for /me");
         context.insert("api_keys", &api_keys);
 
+        context.insert(
+            "shares_granted",
+            &get_user_shares(&data, user.id, true).await?,
+        );
+        context.insert(
+            "shares_received",
+            &get_user_shares(&data, user.id, false).await?,
+        );
+        let own_sources = get_visible_sources(&data, Some(user.id))
+            .await
+            .into_iter()
+            .filter(|s| s.user_id == user.id)
+            .collect::<Vec<_>>();
+        context.insert("own_sources", &own_sources);
+
+        if let Some(session_id) = request.get_session_id() {
+            let sessions = get_user_sessions(&data.conn, user.id, &session_id)
+                .await
+                .or_internal_server_error("Failed to fetch sessions for /me")?;
+            context.insert("sessions", &sessions);
+        }
+        context.insert(
+            "two_factor",
+            &get_two_factor_status(&data.conn, user.id)
+                .await
+                .or_internal_server_error("Failed to fetch two-factor status for /me")?,
+        );
+
         let all_timezones = chrono_tz::TZ_VARIANTS
             .iter()
             .map(|tz| tz.name())
@@ -243,6 +302,208 @@ async fn me(
     Ok(remove_flash_cookie(HttpResponse::Ok()).body(content))
 }
 
+/// The second step of logging in, after [`crate::routes::api::user`] has checked the password
+#[get("/login/verify")]
+async fn login_verify(data: web::Data<AppState>, request: HttpRequest) -> impl Responder {
+    let (context, _user, _key, _timers) = request.get_session_context(&data).await;
+    let content = data
+        .templates
+        .render("pages/login_verify.html", &context)
+        .unwrap();
+    remove_flash_cookie(HttpResponse::Ok()).body(content)
+}
+
+/// Where the link of a password reset email leads
+#[get("/password/reset/{secret}")]
+async fn password_reset(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    secret: web::Path<String>,
+) -> impl Responder {
+    let (mut context, _user, _key, _timers) = request.get_session_context(&data).await;
+    context.insert("secret", &secret.into_inner());
+    let content = data
+        .templates
+        .render("pages/password_reset.html", &context)
+        .unwrap();
+    remove_flash_cookie(HttpResponse::Ok()).body(content)
+}
+
+#[get("/groups")]
+async fn groups(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    let (mut context, user, _key, _timer) = request.get_session_context(&data).await;
+    if let Some(user) = user {
+        context.insert("groups", &get_user_groups(&data, user.id).await?);
+    }
+    let content = data
+        .templates
+        .render("pages/groups.html", &context)
+        .unwrap();
+    Ok(remove_flash_cookie(HttpResponse::Ok()).body(content))
+}
+
+/// An event in a group calendar, along with the member it's attributed to
+#[derive(Debug, serde::Serialize)]
+struct GroupEvent<'a> {
+    event: EventOccurrenceHuman,
+    member: Option<&'a GroupMember>,
+}
+#[get("/groups/{id}")]
+async fn group_calendar(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    path: web::Path<GroupId>,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    let (mut context, user, _key, _timer) = request.get_session_context(&data).await;
+    if let Some(user) = user {
+        let id = path.into_inner();
+        let Some(group) = get_group_as_member(&data, id, user.id).await? else {
+            return Ok(HttpResponse::NotFound().body("group not found"));
+        };
+        let members = get_group_members(&data, id).await?;
+        let now = chrono::Utc::now();
+        let events = get_group_event_occurrences(
+            &data,
+            &members,
+            true,
+            &EventFilter {
+                after: Some((now - chrono::Duration::days(1)).timestamp()),
+                before: Some((now + chrono::Duration::days(30)).timestamp()),
+                ..Default::default()
+            },
+        )
+        .await;
+        let events = events
+            .into_iter()
+            .map(|e| {
+                let member = match &e.source {
+                    EventSource::Group(group_source) => {
+                        members.iter().find(|m| m.user_id == group_source.user_id)
+                    }
+                    _ => None,
+                };
+                GroupEvent {
+                    event: EventOccurrenceHuman::from((e, &user.interface_timezone_parsed)),
+                    member,
+                }
+            })
+            .collect::<Vec<_>>();
+        context.insert("events", &events);
+        context.insert("membership", &members.iter().find(|m| m.user_id == user.id));
+        context.insert("members", &members);
+        context.insert("group", &group);
+        context.insert("export_links", &get_group_export_links(&data, id).await?);
+        context.insert("export_group_id", &id);
+    }
+    let content = data.templates.render("pages/group.html", &context).unwrap();
+    Ok(remove_flash_cookie(HttpResponse::Ok()).body(content))
+}
+
+/// Sums of the amounts of the listed bills of one currency, in cents
+#[derive(Debug, Default, serde::Serialize)]
+struct BillTotals {
+    unpaid: i64,
+    overdue: i64,
+    paid: i64,
+}
+#[get("/bills")]
+async fn bills(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    query: Query<BillFilter>,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    let (mut context, user, _key, _timer) = request.get_session_context(&data).await;
+    if let Some(user) = user {
+        let (due_after, due_before) = query.due_range(user.interface_timezone_h);
+        let bills = get_user_bills(&data, user.id, &query, due_after, due_before).await?;
+        let mut totals: BTreeMap<String, BillTotals> = BTreeMap::new();
+        for bill in &bills {
+            let amount = i64::from(bill.bill.amount);
+            let totals = totals.entry(bill.bill.currency.clone()).or_default();
+            match bill.status {
+                BillStatus::Unpaid => totals.unpaid += amount,
+                BillStatus::Overdue => {
+                    totals.unpaid += amount;
+                    totals.overdue += amount;
+                }
+                BillStatus::Paid => totals.paid += amount,
+            }
+        }
+        let bills = bills
+            .into_iter()
+            .map(|bill| {
+                let due_at = bill.due_at.map(|due_at| {
+                    due_at
+                        .with_timezone(&user.interface_timezone_parsed)
+                        .format("%Y-%m-%d")
+                        .to_string()
+                });
+                (bill, due_at)
+            })
+            .collect::<Vec<_>>();
+        // events a bill can be attached to
+        let now = chrono::Utc::now();
+        let billable_events = get_user_local_events(
+            &data,
+            user.id,
+            false,
+            &EventFilter {
+                after: Some((now - chrono::Duration::days(30)).timestamp()),
+                before: Some((now + chrono::Duration::days(365)).timestamp()),
+                ..Default::default()
+            },
+        )
+        .await
+        .into_iter()
+        .filter(|event| event.bill.is_none())
+        .collect::<Vec<_>>();
+        context.insert("bills", &bills);
+        context.insert("totals", &totals);
+        context.insert("filter", &query.into_inner());
+        context.insert("billable_events", &billable_events);
+        let reminder_settings = get_reminder_settings(&data, user.id).await?;
+        context.insert("reminder_settings", &reminder_settings);
+    }
+    let content = data.templates.render("pages/bills.html", &context).unwrap();
+    Ok(remove_flash_cookie(HttpResponse::Ok()).body(content))
+}
+
+#[get("/reports")]
+async fn reports(
+    data: web::Data<AppState>,
+    request: HttpRequest,
+    query: Query<ReportQuery>,
+) -> Result<impl Responder, InternalServerError<sqlx::Error>> {
+    let (mut context, user, _key, _timer) = request.get_session_context(&data).await;
+    if let Some(user) = user {
+        let range = match query.range(user.interface_timezone_h) {
+            Ok(range) => range,
+            Err(e) => return Ok(HttpResponse::BadRequest().body(e.to_string())),
+        };
+        let (bill_rows, time_rows) = get_reports(
+            &data,
+            user.id,
+            &user.interface_timezone_parsed,
+            range,
+            &query,
+        )
+        .await?;
+        let query_string = request.query_string();
+        context.insert("bill_rows", &bill_rows);
+        context.insert("time_rows", &time_rows);
+        context.insert("query", &query.into_inner());
+        context.insert("query_string", query_string);
+    }
+    let content = data
+        .templates
+        .render("pages/reports.html", &context)
+        .unwrap();
+    Ok(remove_flash_cookie(HttpResponse::Ok()).body(content))
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct IndexQuery {
     year: Option<i32>,
@@ -281,6 +542,7 @@ async fn list(
         let events = get_visible_event_occurrences(
             &data,
             Some(user.id),
+            EventScope::Shared,
             true,
             &EventFilter {
                 after: Some(yesterday),
@@ -339,12 +601,28 @@ struct CalendarPositionGoto {
 use serde_with::rust::deserialize_ignore_any;
 
 use crate::db::{
-    events::{get_user_local_events, get_visible_event_occurrences},
+    bills::{get_reminder_settings, get_user_bills},
+    events::{
+        get_shared_local_events, get_user_local_events, get_visible_event_occurrences, EventScope,
+    },
+    groups::{
+        get_group_as_member, get_group_event_occurrences, get_group_export_links,
+        get_group_members, get_user_groups,
+    },
+    login_attempts::get_recent_login_attempts,
+    reports::get_reports,
     request::{deauth, redirect, EnhancedRequest, InternalServerError, OrInternalServerError},
-    sources::{get_source_as_user_with_event_count, get_visible_sources_with_event_count},
+    sessions::get_user_sessions,
+    shares::get_user_shares,
+    sources::{
+        get_source_as_user_with_event_count, get_visible_sources,
+        get_visible_sources_with_event_count,
+    },
     timeline::compile_timeline,
+    two_factor::get_two_factor_status,
     user::get_user_export_links,
 };
+use crate::snapshots::{list_snapshots, SNAPSHOTS};
 #[derive(Debug, serde::Deserialize, PartialEq)]
 #[serde(untagged)]
 enum CalendarQueryPosition {
@@ -477,7 +755,9 @@ async fn calendar(
         let to = (pivot + chrono::Duration::days(7)).timestamp();
         filter.after = Some(from);
         filter.before = Some(to);
-        let events = get_visible_event_occurrences(&data, Some(user.id), true, &filter).await;
+        let events =
+            get_visible_event_occurrences(&data, Some(user.id), EventScope::Shared, true, &filter)
+                .await;
         // humanize dates etc
         let events = events
             .into_iter()
@@ -617,20 +897,16 @@ struct TimelineQuery {
     #[serde(default)]
     granularity: TimelineGranularity,
 }
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, serde::Deserialize, Default)]
 pub enum TimelineGranularity {
     Year,
     Month,
+    #[default]
     Week,
     Day,
     Hour,
     Second,
 }
-impl Default for TimelineGranularity {
-    fn default() -> Self {
-        Self::Week
-    }
-}
 #[get("/timeline")]
 pub async fn timeline(
     data: web::Data<AppState>,
@@ -696,6 +972,12 @@ pub fn routes() -> Scope {
         .service(local)
         .service(source)
         .service(me)
+        .service(login_verify)
+        .service(password_reset)
+        .service(groups)
+        .service(group_calendar)
+        .service(bills)
+        .service(reports)
         .service(list)
         .service(calendar)
         .service(timeline)
@@ -0,0 +1,426 @@
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use olmonoko_common::{models::backup::BackupSnapshot, utils::time::timestamp, AppState};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::db::backup::{backup_header, write_backup, BackupError};
+
+/// Every night at 3 AM UTC
+const DEFAULT_SCHEDULE: &str = "0 0 3 * * *";
+const DEFAULT_KEEP: usize = 7;
+
+const SNAPSHOT_PREFIX: &str = "olmonoko-";
+const SNAPSHOT_EXTENSION: &str = ".ndjson.gz";
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Written next to each snapshot in the format of `sha256sum`, so that `sha256sum -c` works too
+const CHECKSUM_EXTENSION: &str = ".sha256";
+/// Snapshots are renamed once they're complete, so a crash never leaves a truncated one behind
+const PARTIAL_EXTENSION: &str = ".partial";
+/// Partial files left behind by a crash are removed once they haven't been written to for this long
+const STALE_PARTIAL_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Invalid backup configuration: {0}")]
+    Config(String),
+    #[error("Failed to write backup: {0}")]
+    Backup(#[from] BackupError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No backup named {0}")]
+    NotFound(String),
+    #[error("There's already a backup named {0}, try again in a second")]
+    AlreadyExists(String),
+    #[error("{0} has no checksum file")]
+    MissingChecksum(String),
+    #[error("The checksum of {0} doesn't match, the file is damaged")]
+    ChecksumMismatch(String),
+}
+
+pub struct SnapshotConfig {
+    pub dir: PathBuf,
+    /// A cron expression with seconds, like the other jobs
+    pub schedule: String,
+    /// How many of the latest snapshots are kept
+    pub keep: usize,
+    /// Snapshots older than this are removed even when there are fewer than `keep`
+    pub max_age_days: Option<i64>,
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+impl SnapshotConfig {
+    /// Configured with `BACKUP_DIR`, and optionally `BACKUP_SCHEDULE`, `BACKUP_KEEP` and
+    /// `BACKUP_MAX_AGE_DAYS`. None when scheduled backups aren't configured.
+    pub fn from_env() -> Result<Option<Self>, SnapshotError> {
+        let Some(dir) = env("BACKUP_DIR") else {
+            return Ok(None);
+        };
+        let keep = match env("BACKUP_KEEP") {
+            Some(keep) => keep.parse().ok().filter(|keep| *keep > 0).ok_or_else(|| {
+                SnapshotError::Config(format!("BACKUP_KEEP must be at least 1, not {keep}"))
+            })?,
+            None => DEFAULT_KEEP,
+        };
+        let max_age_days = match env("BACKUP_MAX_AGE_DAYS") {
+            Some(days) => Some(days.parse().ok().filter(|days| *days > 0).ok_or_else(|| {
+                SnapshotError::Config(format!(
+                    "BACKUP_MAX_AGE_DAYS must be a number of days, not {days}"
+                ))
+            })?),
+            None => None,
+        };
+        Ok(Some(Self {
+            dir: PathBuf::from(dir),
+            schedule: env("BACKUP_SCHEDULE").unwrap_or_else(|| DEFAULT_SCHEDULE.to_string()),
+            keep,
+            max_age_days,
+        }))
+    }
+}
+
+pub static SNAPSHOTS: LazyLock<Option<SnapshotConfig>> =
+    LazyLock::new(|| SnapshotConfig::from_env().expect("Invalid backup configuration"));
+
+/// The creation time of a snapshot file, None for other files
+fn parse_snapshot_name(name: &str) -> Option<i64> {
+    let time = name
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_EXTENSION)?;
+    chrono::NaiveDateTime::parse_from_str(time, SNAPSHOT_TIME_FORMAT)
+        .ok()
+        .map(|time| time.and_utc().timestamp())
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(CHECKSUM_EXTENSION);
+    PathBuf::from(name)
+}
+
+async fn file_checksum(path: &Path) -> Result<String, SnapshotError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The checksum recorded for the snapshot, None if the checksum file is missing
+async fn read_checksum(path: &Path) -> Result<Option<String>, SnapshotError> {
+    match tokio::fs::read_to_string(checksum_path(path)).await {
+        Ok(content) => Ok(content.split_whitespace().next().map(str::to_string)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A new file only the server can read, backups have every password hash and session in them
+async fn create_private(path: &Path) -> std::io::Result<tokio::fs::File> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path).await
+}
+
+/// A partial file that no other snapshot in progress could be writing to
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}{PARTIAL_EXTENSION}", uuid::Uuid::new_v4()));
+    PathBuf::from(name)
+}
+
+/// Moves the complete file into place, unless there's already a file with the name
+async fn publish(partial_path: &Path, path: &Path, name: &str) -> Result<(), SnapshotError> {
+    let linked = tokio::fs::hard_link(partial_path, path).await;
+    tokio::fs::remove_file(partial_path).await?;
+    match linked {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            Err(SnapshotError::AlreadyExists(name.to_string()))
+        }
+        linked => Ok(linked?),
+    }
+}
+
+/// Backs up the whole instance into a new compressed snapshot
+pub async fn take_snapshot(
+    data: &AppState,
+    config: &SnapshotConfig,
+) -> Result<BackupSnapshot, SnapshotError> {
+    let mut dir = tokio::fs::DirBuilder::new();
+    dir.recursive(true);
+    #[cfg(unix)]
+    dir.mode(0o700);
+    dir.create(&config.dir).await?;
+    let created_at = chrono::Utc::now();
+    let name = format!(
+        "{SNAPSHOT_PREFIX}{}{SNAPSHOT_EXTENSION}",
+        created_at.format(SNAPSHOT_TIME_FORMAT)
+    );
+    let path = config.dir.join(&name);
+    let partial = partial_path(&path);
+    let written = async {
+        let mut encoder = GzipEncoder::new(create_private(&partial).await?);
+        write_backup(&data.conn, &backup_header(data), &mut encoder).await?;
+        encoder.into_inner().sync_all().await?;
+        Ok::<_, SnapshotError>(())
+    };
+    if let Err(e) = written.await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(e);
+    }
+    let checksum = file_checksum(&partial).await?;
+    // claimed before the checksum is written, so that it can't replace the checksum of another
+    publish(&partial, &path, &name).await?;
+    let checksum_partial = partial_path(&checksum_path(&path));
+    let mut checksum_file = create_private(&checksum_partial).await?;
+    checksum_file
+        .write_all(format!("{checksum}  {name}\n").as_bytes())
+        .await?;
+    checksum_file.sync_all().await?;
+    tokio::fs::rename(&checksum_partial, checksum_path(&path)).await?;
+    let size = tokio::fs::metadata(&path).await?.len();
+    Ok(BackupSnapshot {
+        name,
+        created_at: created_at.timestamp(),
+        size,
+        checksum: Some(checksum),
+    })
+}
+
+/// The snapshots in the backup directory, newest first
+pub async fn list_snapshots(config: &SnapshotConfig) -> Result<Vec<BackupSnapshot>, SnapshotError> {
+    let mut entries = match tokio::fs::read_dir(&config.dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut snapshots = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let Some(created_at) = parse_snapshot_name(&name) else {
+            continue;
+        };
+        snapshots.push(BackupSnapshot {
+            size: entry.metadata().await?.len(),
+            checksum: read_checksum(&entry.path()).await?,
+            name,
+            created_at,
+        });
+    }
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created_at));
+    Ok(snapshots)
+}
+
+/// The snapshots past the retention limits, from snapshots sorted newest first.
+/// The newest one is always kept, so that there's something to restore.
+fn expired_snapshots<'a>(
+    snapshots: &'a [BackupSnapshot],
+    config: &SnapshotConfig,
+    now: i64,
+) -> impl Iterator<Item = &'a BackupSnapshot> {
+    let keep = config.keep;
+    let max_age = config.max_age_days.map(|days| days * 24 * 60 * 60);
+    snapshots
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(move |(index, snapshot)| {
+            *index >= keep || max_age.is_some_and(|age| now - snapshot.created_at > age)
+        })
+        .map(|(_, snapshot)| snapshot)
+}
+
+/// Whether the file is what's left of a snapshot that was never completed
+async fn is_stale_partial(entry: &tokio::fs::DirEntry) -> Result<bool, SnapshotError> {
+    let name = entry.file_name();
+    let Some(name) = name.to_str() else {
+        return Ok(false);
+    };
+    if !name.starts_with(SNAPSHOT_PREFIX) || !name.ends_with(PARTIAL_EXTENSION) {
+        return Ok(false);
+    }
+    let modified = entry.metadata().await?.modified()?;
+    Ok(modified.elapsed().is_ok_and(|age| age > STALE_PARTIAL_AGE))
+}
+
+/// Removes the snapshots past the retention limits along with their checksum files,
+/// and whatever was left of the snapshots that failed halfway
+pub async fn prune_snapshots(config: &SnapshotConfig) -> Result<usize, SnapshotError> {
+    let snapshots = list_snapshots(config).await?;
+    let mut removed = 0;
+    if let Ok(mut entries) = tokio::fs::read_dir(&config.dir).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if is_stale_partial(&entry).await? {
+                tokio::fs::remove_file(entry.path()).await?;
+                removed += 1;
+            }
+        }
+    }
+    for snapshot in expired_snapshots(&snapshots, config, timestamp()) {
+        let path = config.dir.join(&snapshot.name);
+        tokio::fs::remove_file(&path).await?;
+        match tokio::fs::remove_file(checksum_path(&path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Checks the snapshot against its checksum and opens it for [`crate::db::backup::import_backup`]
+pub async fn open_snapshot(
+    config: &SnapshotConfig,
+    name: &str,
+) -> Result<impl AsyncBufRead + Unpin, SnapshotError> {
+    // also keeps the name from pointing outside of the directory
+    if parse_snapshot_name(name).is_none() {
+        return Err(SnapshotError::NotFound(name.to_string()));
+    }
+    let path = config.dir.join(name);
+    if !tokio::fs::try_exists(&path).await? {
+        return Err(SnapshotError::NotFound(name.to_string()));
+    }
+    let Some(expected) = read_checksum(&path).await? else {
+        return Err(SnapshotError::MissingChecksum(name.to_string()));
+    };
+    if file_checksum(&path).await? != expected {
+        return Err(SnapshotError::ChecksumMismatch(name.to_string()));
+    }
+    let file = tokio::fs::File::open(&path).await?;
+    Ok(BufReader::new(GzipDecoder::new(BufReader::new(file))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(created_at: i64) -> BackupSnapshot {
+        BackupSnapshot {
+            name: String::new(),
+            created_at,
+            size: 0,
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn snapshot_names_are_parsed() {
+        assert_eq!(
+            parse_snapshot_name("olmonoko-20240102T030405Z.ndjson.gz"),
+            Some(1704164645)
+        );
+        assert_eq!(
+            parse_snapshot_name("olmonoko-20240102T030405Z.ndjson.gz.sha256"),
+            None
+        );
+        assert_eq!(
+            parse_snapshot_name("olmonoko-20240102T030405Z.ndjson.gz.partial"),
+            None
+        );
+        assert_eq!(parse_snapshot_name("olmonoko-../../etc.ndjson.gz"), None);
+    }
+
+    #[test]
+    fn retention_keeps_the_newest() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        let snapshots: Vec<_> = (0..5).map(|age| snapshot(now - age * day)).collect();
+        let config = |keep, max_age_days| SnapshotConfig {
+            dir: PathBuf::new(),
+            schedule: DEFAULT_SCHEDULE.to_string(),
+            keep,
+            max_age_days,
+        };
+        let expired = |config: &SnapshotConfig| {
+            expired_snapshots(&snapshots, config, now)
+                .map(|snapshot| (now - snapshot.created_at) / day)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(expired(&config(3, None)), [3, 4]);
+        assert_eq!(expired(&config(10, Some(2))), [3, 4]);
+        assert_eq!(expired(&config(2, Some(3))), [2, 3, 4]);
+        // even when all of them are too old
+        let old: Vec<_> = (10..13).map(|age| snapshot(now - age * day)).collect();
+        assert_eq!(
+            expired_snapshots(&old, &config(10, Some(1)), now).count(),
+            2
+        );
+    }
+
+    /// A fresh directory under the system's temporary one
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("olmonoko-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_private_and_never_replaced() {
+        let dir = temp_dir();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let name = "olmonoko-20240102T030405Z.ndjson.gz";
+        let path = dir.join(name);
+        let mut published = vec![];
+        for _ in 0..2 {
+            let partial = partial_path(&path);
+            create_private(&partial).await.unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = tokio::fs::metadata(&partial)
+                    .await
+                    .unwrap()
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+            published.push(publish(&partial, &path, name).await);
+            assert!(!tokio::fs::try_exists(&partial).await.unwrap());
+        }
+        assert!(published[0].is_ok());
+        assert!(matches!(published[1], Err(SnapshotError::AlreadyExists(_))));
+        assert!(tokio::fs::try_exists(&path).await.unwrap());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stale_partial_files_are_removed() {
+        let dir = temp_dir();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("olmonoko-20240102T030405Z.ndjson.gz");
+        let stale = partial_path(&path);
+        let fresh = partial_path(&path);
+        create_private(&fresh).await.unwrap();
+        create_private(&stale)
+            .await
+            .unwrap()
+            .into_std()
+            .await
+            .set_modified(std::time::SystemTime::now() - 2 * STALE_PARTIAL_AGE)
+            .unwrap();
+        let config = SnapshotConfig {
+            dir: dir.clone(),
+            schedule: DEFAULT_SCHEDULE.to_string(),
+            keep: DEFAULT_KEEP,
+            max_age_days: None,
+        };
+        assert_eq!(prune_snapshots(&config).await.unwrap(), 1);
+        assert!(!tokio::fs::try_exists(&stale).await.unwrap());
+        assert!(tokio::fs::try_exists(&fresh).await.unwrap());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
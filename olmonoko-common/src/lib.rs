@@ -48,7 +48,7 @@ pub const APP_NAVIGATION_ENTRIES_ADMIN: [NavigationEntry; 1] = [NavigationEntry
     active: None,
     position: 10, // Last
 }];
-pub const APP_NAVIGATION_ENTRIES_LOGGEDIN: [NavigationEntry; 2] = [
+pub const APP_NAVIGATION_ENTRIES_LOGGEDIN: [NavigationEntry; 5] = [
     NavigationEntry {
         name: "Profile",
         path: "/me",
@@ -61,6 +61,24 @@ pub const APP_NAVIGATION_ENTRIES_LOGGEDIN: [NavigationEntry; 2] = [
         active: None,
         position: 3, // Second
     },
+    NavigationEntry {
+        name: "Groups",
+        path: "/groups",
+        active: None,
+        position: 6,
+    },
+    NavigationEntry {
+        name: "Bills",
+        path: "/bills",
+        active: None,
+        position: 7,
+    },
+    NavigationEntry {
+        name: "Reports",
+        path: "/reports",
+        active: None,
+        position: 7,
+    },
 ];
 pub const APP_NAVIGATION_ENTRIES_LOGGEDOUT: [NavigationEntry; 1] = [NavigationEntry {
     name: "Sign in",
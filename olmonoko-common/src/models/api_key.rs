@@ -22,17 +22,16 @@ impl TryFrom<&str> for AuthScope {
     type Error = &'static str;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            AUTHSCOPE_RF_UPCOMING_EVENTS => return Ok(Self::ReadFeatureUpcomingEvents),
-            _ => {}
+        if value == AUTHSCOPE_RF_UPCOMING_EVENTS {
+            return Ok(Self::ReadFeatureUpcomingEvents);
         }
         Err("Not a valid AuthScope")
     }
 }
-impl ToString for AuthScope {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for AuthScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AuthScope::ReadFeatureUpcomingEvents => AUTHSCOPE_RF_UPCOMING_EVENTS.to_owned(),
+            AuthScope::ReadFeatureUpcomingEvents => f.write_str(AUTHSCOPE_RF_UPCOMING_EVENTS),
         }
     }
 }
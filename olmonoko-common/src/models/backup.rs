@@ -0,0 +1,363 @@
+use super::{
+    api_key::RawApiKey,
+    attendance::RawAttendance,
+    bills::{reminder::RawReminderSettings, BillId, RawBill},
+    event::{
+        local::{LocalEventId, RawLocalEvent},
+        remote::{RawRemoteEvent, RawRemoteEventOccurrence, RemoteEventId},
+        Priority,
+    },
+    group::{RawGroup, RawGroupMember},
+    ics_source::{IcsSourceId, RawIcsSource},
+    login_attempt::RawLoginAttempt,
+    public_link::RawPublicLink,
+    session::SessionRaw,
+    share::RawShare,
+    timer::{RawTimer, RawTimerSegment},
+    two_factor::RawUserTotp,
+    user::{RawUser, UnverifiedUser, UserId},
+};
+use crate::BuildInformation;
+
+/// Only this many problems are listed in an import report, the rest are just counted
+const MAX_REPORTED_PROBLEMS: usize = 100;
+
+/// Identifies the first line of an instance backup
+pub const BACKUP_FORMAT: &str = "olmonoko-backup";
+/// Version 1 was a single JSON document without a header, see [`BackupV1`]
+pub const BACKUP_FORMAT_VERSION: u32 = 2;
+
+/// The first line of an instance backup. The rest of the lines are [`BackupRecord`]s, one row
+/// each, with the parents before their children and a [`BackupRecord::End`] last.
+///
+/// Ephemeral rows, like pending logins and password resets, and the events of sources that
+/// aren't persisted, which are fetched again, are left out.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupHeader {
+    pub format: String,
+    pub format_version: u32,
+    pub created_at: i64,
+    pub site_url: String,
+    pub version: String,
+    pub build_info: BuildInformation,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "table", content = "row", rename_all = "snake_case")]
+pub enum BackupRecord {
+    Users(RawUser),
+    UnverifiedUsers(UnverifiedUser),
+    Sessions(SessionRaw),
+    ApiKeys(RawApiKey),
+    UserTotp(RawUserTotp),
+    UserRecoveryCodes(RawRecoveryCode),
+    LoginAttempts(RawLoginAttempt),
+    CalendarGroups(RawGroup),
+    CalendarGroupMembers(RawGroupMember),
+    IcsSources(RawIcsSource),
+    IcsSourcePriorities(RawSourcePriority),
+    CalendarShares(RawShare),
+    PublicCalendarLinks(RawPublicLink),
+    LocalEvents(RawLocalEvent),
+    Events(RawRemoteEvent),
+    EventOccurrences(RawRemoteEventOccurrence),
+    EventTags(RawEventTag),
+    Attendance(RawAttendance),
+    Bills(RawBill),
+    BillReminders(RawBillReminder),
+    BillReminderSettings(RawReminderSettings),
+    Timers(RawTimer),
+    TimerSegments(RawTimerSegment),
+    /// A backup without this was cut short
+    End(BackupTrailer),
+}
+impl BackupRecord {
+    pub fn table(&self) -> &'static str {
+        match self {
+            Self::Users(_) => "users",
+            Self::UnverifiedUsers(_) => "unverified_users",
+            Self::Sessions(_) => "sessions",
+            Self::ApiKeys(_) => "api_keys",
+            Self::UserTotp(_) => "user_totp",
+            Self::UserRecoveryCodes(_) => "user_recovery_codes",
+            Self::LoginAttempts(_) => "login_attempts",
+            Self::CalendarGroups(_) => "calendar_groups",
+            Self::CalendarGroupMembers(_) => "calendar_group_members",
+            Self::IcsSources(_) => "ics_sources",
+            Self::IcsSourcePriorities(_) => "ics_source_priorities",
+            Self::CalendarShares(_) => "calendar_shares",
+            Self::PublicCalendarLinks(_) => "public_calendar_links",
+            Self::LocalEvents(_) => "local_events",
+            Self::Events(_) => "events",
+            Self::EventOccurrences(_) => "event_occurrences",
+            Self::EventTags(_) => "event_tags",
+            Self::Attendance(_) => "attendance",
+            Self::Bills(_) => "bills",
+            Self::BillReminders(_) => "bill_reminders",
+            Self::BillReminderSettings(_) => "bill_reminder_settings",
+            Self::Timers(_) => "timers",
+            Self::TimerSegments(_) => "timer_segments",
+            Self::End(_) => "end",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupTrailer {
+    /// Rows in the backup, without the header and this
+    pub records: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawRecoveryCode {
+    pub id: i32,
+    pub user_id: UserId,
+    pub code_hash: String,
+    pub used_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawSourcePriority {
+    pub user_id: UserId,
+    pub ics_source_id: IcsSourceId,
+    pub priority: Priority,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawEventTag {
+    pub local_event_id: Option<LocalEventId>,
+    pub remote_event_id: Option<RemoteEventId>,
+    pub tag: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawBillReminder {
+    pub bill_id: BillId,
+    pub kind: String,
+    pub sent_at: i64,
+}
+
+/// The backup format before [`BackupHeader`], one JSON document of the whole instance
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct BackupV1 {
+    pub created_at: i64,
+    pub site_url: String,
+    pub version: String,
+    pub build_info: BuildInformation,
+    pub users: Vec<RawUser>,
+    pub public_links: Vec<RawPublicLink>,
+    pub local_events: Vec<RawLocalEvent>,
+    pub sources: Vec<RawIcsSource>,
+    pub source_priorities: Vec<(UserId, IcsSourceId, Priority)>, // user_id, ics_source_id, priority
+    pub attendance: Vec<RawAttendance>,
+    pub bills: Vec<RawBill>,
+    pub persisted_remote_events: Vec<RawRemoteEvent>,
+    pub persisted_remote_event_occurrences: Vec<RawRemoteEventOccurrence>,
+    pub tags: Vec<(i64, Option<LocalEventId>, Option<RemoteEventId>, String)>, // created_at, local_event_id, remote_event_id, tag
+    #[serde(default)]
+    pub groups: Vec<RawGroup>,
+    #[serde(default)]
+    pub group_members: Vec<RawGroupMember>,
+    #[serde(default)]
+    pub bill_reminder_settings: Vec<RawReminderSettings>,
+}
+impl BackupV1 {
+    /// The same backup in the current format
+    pub fn migrate(self) -> (BackupHeader, Vec<BackupRecord>) {
+        let header = BackupHeader {
+            format: BACKUP_FORMAT.to_string(),
+            format_version: BACKUP_FORMAT_VERSION,
+            created_at: self.created_at,
+            site_url: self.site_url,
+            version: self.version,
+            build_info: self.build_info,
+        };
+        let mut records: Vec<BackupRecord> = Vec::new();
+        records.extend(self.users.into_iter().map(BackupRecord::Users));
+        records.extend(self.groups.into_iter().map(BackupRecord::CalendarGroups));
+        records.extend(
+            self.group_members
+                .into_iter()
+                .map(BackupRecord::CalendarGroupMembers),
+        );
+        records.extend(self.sources.into_iter().map(BackupRecord::IcsSources));
+        records.extend(self.source_priorities.into_iter().map(
+            |(user_id, ics_source_id, priority)| {
+                BackupRecord::IcsSourcePriorities(RawSourcePriority {
+                    user_id,
+                    ics_source_id,
+                    priority,
+                })
+            },
+        ));
+        records.extend(
+            self.public_links
+                .into_iter()
+                .map(BackupRecord::PublicCalendarLinks),
+        );
+        records.extend(self.local_events.into_iter().map(BackupRecord::LocalEvents));
+        records.extend(
+            self.persisted_remote_events
+                .into_iter()
+                .map(BackupRecord::Events),
+        );
+        records.extend(
+            self.persisted_remote_event_occurrences
+                .into_iter()
+                .map(BackupRecord::EventOccurrences),
+        );
+        records.extend(self.tags.into_iter().map(
+            |(created_at, local_event_id, remote_event_id, tag)| {
+                BackupRecord::EventTags(RawEventTag {
+                    local_event_id,
+                    remote_event_id,
+                    tag,
+                    created_at,
+                })
+            },
+        ));
+        records.extend(self.attendance.into_iter().map(BackupRecord::Attendance));
+        records.extend(self.bills.into_iter().map(BackupRecord::Bills));
+        records.extend(
+            self.bill_reminder_settings
+                .into_iter()
+                .map(BackupRecord::BillReminderSettings),
+        );
+        records.push(BackupRecord::End(BackupTrailer {
+            records: records.len(),
+        }));
+        (header, records)
+    }
+}
+
+/// Everything a single user owns. Ids are those of the instance the backup was made on and
+/// are remapped on import, so the `user_id` fields of the rows are ignored.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct UserBackup {
+    pub created_at: i64,
+    pub site_url: String,
+    pub version: String,
+    /// The account the backup was made from
+    pub email: String,
+    pub interface_timezone: String,
+    pub local_events: Vec<RawLocalEvent>,
+    pub sources: Vec<RawIcsSource>,
+    pub source_priorities: Vec<(IcsSourceId, Priority)>, // ics_source_id, priority
+    pub persisted_remote_events: Vec<RawRemoteEvent>,
+    pub persisted_remote_event_occurrences: Vec<RawRemoteEventOccurrence>,
+    pub tags: Vec<(i64, Option<LocalEventId>, Option<RemoteEventId>, String)>, // created_at, local_event_id, remote_event_id, tag
+    pub attendance: Vec<RawAttendance>,
+    pub bills: Vec<RawBill>,
+    pub bill_reminder_settings: Option<RawReminderSettings>,
+    pub timers: Vec<RawTimer>,
+    pub timer_segments: Vec<RawTimerSegment>,
+    pub public_links: Vec<RawPublicLink>,
+    pub api_keys: Vec<ApiKeyMetadata>,
+}
+
+/// An API key without its id, which is the secret. Imported keys get new ids.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ApiKeyMetadata {
+    pub description: String,
+    pub scopes: Vec<String>,
+    pub revoked: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserBackupImportMode {
+    /// Keeps the existing data and skips what's already there
+    #[default]
+    Merge,
+    /// Removes the existing data the backup covers first
+    Replace,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct UserBackupImportQuery {
+    #[serde(default)]
+    pub mode: UserBackupImportMode,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct UserBackupImportForm {
+    #[serde(default)]
+    pub mode: UserBackupImportMode,
+    /// The backup as JSON
+    pub backup: String,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct UserBackupImportSummary {
+    pub imported: usize,
+    /// Rows that already existed, or that refer to events missing from the backup
+    pub skipped: usize,
+}
+impl UserBackupImportSummary {
+    pub fn count(&mut self, imported: bool) {
+        if imported {
+            self.imported += 1;
+        } else {
+            self.skipped += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct BackupImportQuery {
+    /// Checks the backup against an empty instance and rolls everything back
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A line that couldn't be restored, and why
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupProblem {
+    pub line: usize,
+    pub table: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupImportReport {
+    pub format_version: u32,
+    pub created_at: i64,
+    pub site_url: String,
+    pub dry_run: bool,
+    /// False on dry runs, and when there were problems
+    pub applied: bool,
+    /// Existing users removed to make room for the backup, along with all of their data
+    pub replaced_users: u64,
+    /// Rows restored per table
+    pub tables: std::collections::BTreeMap<String, usize>,
+    /// The first problems found. Restoring stops at the first one unless it's a dry run.
+    pub problems: Vec<BackupProblem>,
+    pub problem_count: usize,
+}
+impl BackupImportReport {
+    pub fn problem(&mut self, line: usize, table: Option<&str>, message: String) {
+        self.problem_count += 1;
+        if self.problems.len() < MAX_REPORTED_PROBLEMS {
+            self.problems.push(BackupProblem {
+                line,
+                table: table.map(str::to_string),
+                message,
+            });
+        }
+    }
+}
+
+/// An instance backup written to the backup directory on a schedule
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupSnapshot {
+    /// The file name, which is also how the snapshot is referred to
+    pub name: String,
+    pub created_at: i64,
+    /// Compressed size in bytes
+    pub size: u64,
+    /// SHA-256 of the file, from the checksum file next to it
+    pub checksum: Option<String>,
+}
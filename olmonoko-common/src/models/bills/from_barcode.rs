@@ -1,11 +1,11 @@
-use crate::models::event::local::NewLocalEvent;
-use crate::models::event::Priority;
 use crate::models::user::UserId;
 
+use super::validation::{validate_iban, validate_reference, validate_rf_reference, BillError};
 use super::EventId;
 use super::NewBill;
 use super::NewBillWithEvent;
-use chrono::NaiveTime;
+use super::DEFAULT_CURRENCY;
+use chrono::Datelike;
 use serde_with::As;
 use serde_with::NoneAsEmptyString;
 
@@ -32,47 +32,26 @@ pub struct NewBillBarcodeFormWithUserId {
     pub form: NewBillBarcodeForm,
 }
 
-const BILL_DEFAULT_PRIORITY: Priority = 1; // Highest possible priority
 impl TryFrom<NewBillBarcodeFormWithUserId> for NewBillWithEvent {
-    type Error = &'static str;
+    type Error = BillError;
 
     fn try_from(data: NewBillBarcodeFormWithUserId) -> Result<Self, Self::Error> {
         let NewBillBarcodeFormWithUserId { user_id, form } = data;
-        let barcode = decode_barcode(&form.barcode).ok_or("invalid barcode")?;
-        let due = barcode
-            .due
-            .ok_or("invoice is missing a due date")?
-            .and_time(NaiveTime::MIN)
-            .and_utc()
-            .timestamp();
+        let barcode = decode_barcode(&form.barcode)?;
+        let due = barcode.due.ok_or(BillError::MissingDueDate)?;
         let new_bill = NewBill {
             event_id: EventId::Local(-1), // placeholder
-            payee_account_number: barcode.payee_account_number.clone(),
+            payee_account_number: barcode.payee_account_number,
             amount: barcode.amount_cents,
-            reference: barcode.reference.clone(),
+            currency: DEFAULT_CURRENCY.to_string(),
+            reference: barcode.reference,
             payee_name: form.payee_name,
             payee_email: form.payee_email,
             payee_address: form.payee_address,
             payee_phone: form.payee_phone,
+            payee_bic: None,
         };
-        let description = None;
-        let uid = format!(
-            "bill-{}-{}@olmonoko",
-            barcode.payee_account_number, barcode.reference
-        );
-        let new_event = NewLocalEvent {
-            user_id,
-            tags: vec!["olmonoko::bill".to_string()],
-            priority: Some(BILL_DEFAULT_PRIORITY),
-            starts_at: due,
-            all_day: true,
-            duration: None,
-            summary: form.summary,
-            description,
-            location: None,
-            uid,
-        };
-        Ok((new_event, new_bill))
+        Ok(new_bill.with_event(user_id, form.summary, due, None))
     }
 }
 
@@ -82,13 +61,13 @@ pub enum SupportedBarcodeVersion {
     V5,
 }
 impl TryFrom<char> for SupportedBarcodeVersion {
-    type Error = &'static str;
+    type Error = BillError;
 
     fn try_from(value: char) -> Result<Self, Self::Error> {
         match value {
             '4' => Ok(Self::V4),
             '5' => Ok(Self::V5),
-            _ => Err("unsupported barcode version"),
+            _ => Err(BillError::UnsupportedBarcodeVersion(value)),
         }
     }
 }
@@ -108,47 +87,62 @@ pub struct Barcode {
     pub reference: String,
     pub due: Option<chrono::NaiveDate>,
 }
-pub fn decode_barcode(barcode: &str) -> Option<Barcode> {
-    let mut chars = barcode.chars();
-    let input = chars.by_ref();
-    let version = SupportedBarcodeVersion::try_from(input.next()?).ok()?;
+const BARCODE_LENGTH: usize = 54;
+/// The largest amount that fits in a barcode, larger ones are encoded as zero
+const BARCODE_MAX_AMOUNT_CENTS: i32 = 99_999_999;
+
+/// Decodes and validates the IBAN and reference checksums of a Finnish bank barcode
+pub fn decode_barcode(barcode: &str) -> Result<Barcode, BillError> {
+    let barcode = barcode.trim();
+    if barcode.len() != BARCODE_LENGTH {
+        return Err(BillError::InvalidBarcodeLength(barcode.chars().count()));
+    }
+    if !barcode.chars().all(|c| c.is_ascii_digit()) {
+        return Err(BillError::NonNumericBarcode);
+    }
+    let version = SupportedBarcodeVersion::try_from(barcode.as_bytes()[0] as char)?;
     // only Finnish IBANs are supported by the spec for now
-    let payee_account_number = format!("FI{}", input.take(16).collect::<String>());
-    let amount_cents = input.take(6 + 2).collect::<String>().parse().ok()?;
+    let payee_account_number = format!("FI{}", &barcode[1..17]);
+    validate_iban(&payee_account_number)?;
+    let amount_cents = barcode[17..25]
+        .parse()
+        .map_err(|_| BillError::InvalidAmount)?;
     let reference = match version {
         SupportedBarcodeVersion::V5 => {
             // 2 checksum chars, 21 data chars
-            let checksum = input.take(2).collect::<String>();
-            let data_raw = input.take(21).collect::<String>();
+            let checksum = &barcode[25..27];
             // remove leading zeros from data
-            let data = data_raw.trim_start_matches('0');
-            format!("RF{}{}", checksum, data)
+            let data = barcode[27..48].trim_start_matches('0');
+            let reference = format!("RF{}{}", checksum, data);
+            validate_rf_reference(&reference)?;
+            reference
         }
         SupportedBarcodeVersion::V4 => {
-            // skip 3 reserved chars
-            let raw = input.skip(3).take(20).collect::<String>();
-            // remove leading zeros
-            raw.trim_start_matches('0').to_string()
+            // skip 3 reserved chars, remove leading zeros
+            let reference = barcode[28..48].trim_start_matches('0').to_string();
+            validate_reference(&reference)?;
+            reference
         }
     };
-    let due_year: u32 = input.take(2).collect::<String>().parse().ok()?;
-    let due_month: u32 = input.take(2).collect::<String>().parse().ok()?;
-    let due_day: u32 = input.take(2).collect::<String>().parse().ok()?;
+    let due_year: u32 = barcode[48..50]
+        .parse()
+        .map_err(|_| BillError::InvalidDueDate)?;
+    let due_month: u32 = barcode[50..52]
+        .parse()
+        .map_err(|_| BillError::InvalidDueDate)?;
+    let due_day: u32 = barcode[52..54]
+        .parse()
+        .map_err(|_| BillError::InvalidDueDate)?;
     let due = if due_year + due_month + due_day == 0 {
         None
     } else {
-        Some(chrono::NaiveDate::from_ymd_opt(
-            2000 + due_year as i32,
-            due_month,
-            due_day,
-        )?)
+        Some(
+            chrono::NaiveDate::from_ymd_opt(2000 + due_year as i32, due_month, due_day)
+                .ok_or(BillError::InvalidDueDate)?,
+        )
     };
-    if input.next().is_some() {
-        // the spec doesn't allow extra characters
-        return None;
-    }
 
-    Some(Barcode {
+    Ok(Barcode {
         version,
         payee_account_number,
         amount_cents,
@@ -157,6 +151,60 @@ pub fn decode_barcode(barcode: &str) -> Option<Barcode> {
     })
 }
 
+/// Encodes a bank barcode, the inverse of [`decode_barcode`].
+/// The version is picked based on whether the reference is an RF reference.
+pub fn encode_barcode(
+    payee_account_number: &str,
+    amount_cents: i32,
+    reference: &str,
+    due: Option<chrono::NaiveDate>,
+) -> Result<String, BillError> {
+    let iban: String = payee_account_number
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    validate_iban(&iban)?;
+    let account = iban
+        .strip_prefix("FI")
+        .filter(|account| account.len() == 16)
+        .ok_or_else(|| BillError::UnencodableIban(payee_account_number.to_string()))?;
+    if amount_cents < 0 {
+        return Err(BillError::InvalidAmount);
+    }
+    let amount_cents = if amount_cents > BARCODE_MAX_AMOUNT_CENTS {
+        0
+    } else {
+        amount_cents
+    };
+    let compact_reference: String = reference
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    let unencodable = || BillError::UnencodableReference(reference.to_string());
+    let (version, reference_field) = if let Some(rf) = compact_reference.strip_prefix("RF") {
+        validate_rf_reference(&compact_reference)?;
+        let (checksum, data) = rf.split_at(2);
+        if data.len() > 21 || !data.chars().all(|c| c.is_ascii_digit()) {
+            return Err(unencodable());
+        }
+        (5, format!("{checksum}{data:0>21}"))
+    } else {
+        validate_reference(&compact_reference)?;
+        let data = compact_reference.trim_start_matches('0');
+        (4, format!("000{data:0>20}"))
+    };
+    let due = match due {
+        Some(due) if (2000..2100).contains(&due.year()) => due.format("%y%m%d").to_string(),
+        Some(_) => return Err(BillError::InvalidDueDate),
+        None => "000000".to_string(),
+    };
+    Ok(format!(
+        "{version}{account}{amount_cents:08}{reference_field}{due}"
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::NaiveDate;
@@ -167,6 +215,16 @@ mod tests {
     fn test_decode_barcode_v4_example_1() {
         let barcode = "479440520200360820048831500000000868516259619897100612";
         let decoded = decode_barcode(barcode).unwrap();
+        assert_eq!(
+            encode_barcode(
+                &decoded.payee_account_number,
+                decoded.amount_cents,
+                &decoded.reference,
+                decoded.due
+            )
+            .unwrap(),
+            barcode
+        );
         assert_eq!(decoded.version, SupportedBarcodeVersion::V4);
         assert_eq!(decoded.payee_account_number, "FI7944052020036082");
         assert_eq!(decoded.amount_cents, 488315);
@@ -195,7 +253,6 @@ mod tests {
         assert_eq!(decoded.version, SupportedBarcodeVersion::V4);
         assert_eq!(decoded.payee_account_number, "FI9239390001003391");
         assert_eq!(decoded.amount_cents, 2);
-        // disabled until we implement the reference spec
         assert_eq!(decoded.reference, "1357914");
         assert_eq!(
             decoded.due,
@@ -221,6 +278,16 @@ mod tests {
     fn test_decode_barcode_v5_example_9() {
         let barcode = "592393900010033910000000295000000000000001357914991224";
         let decoded = decode_barcode(barcode).unwrap();
+        assert_eq!(
+            encode_barcode(
+                &decoded.payee_account_number,
+                decoded.amount_cents,
+                &decoded.reference,
+                decoded.due
+            )
+            .unwrap(),
+            barcode
+        );
         assert_eq!(decoded.version, SupportedBarcodeVersion::V5);
         assert_eq!(decoded.payee_account_number, "FI9239390001003391");
         assert_eq!(decoded.amount_cents, 2);
@@ -231,6 +298,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_decode_barcode_invalid() {
+        // last digit of the IBAN changed
+        assert_eq!(
+            decode_barcode("479440520200360830048831500000000868516259619897100612"),
+            Err(BillError::InvalidIban("FI7944052020036083".to_string()))
+        );
+        // check digit of the reference changed
+        assert_eq!(
+            decode_barcode("479440520200360820048831500000000868516259619898100612"),
+            Err(BillError::InvalidReference("868516259619898".to_string()))
+        );
+        // RF checksum changed
+        assert_eq!(
+            decode_barcode("579440520200360820048831508000000868516259619897100612"),
+            Err(BillError::InvalidRfReference(
+                "RF08868516259619897".to_string()
+            ))
+        );
+        assert_eq!(
+            decode_barcode("3794405202003608200488315"),
+            Err(BillError::InvalidBarcodeLength(25))
+        );
+        assert_eq!(
+            decode_barcode("379440520200360820048831500000000868516259619897100612"),
+            Err(BillError::UnsupportedBarcodeVersion('3'))
+        );
+    }
+
+    #[test]
+    fn test_encode_barcode_large_amount() {
+        let encoded =
+            encode_barcode("FI79 4405 2020 0360 82", 100_000_000, "1357914", None).unwrap();
+        assert_eq!(
+            encoded,
+            "479440520200360820000000000000000000000001357914000000"
+        );
+        assert!(encode_barcode("GB82WEST12345698765432", 100, "1357914", None).is_err());
+    }
+
     #[test]
     fn from_real_bill_1() {
         let form = NewBillBarcodeForm {
@@ -250,7 +357,7 @@ mod tests {
             event.starts_at,
             NaiveDate::from_ymd_opt(2024, 6, 3)
                 .unwrap()
-                .and_time(NaiveTime::MIN)
+                .and_time(chrono::NaiveTime::MIN)
                 .and_utc()
                 .timestamp()
         );
@@ -0,0 +1,211 @@
+use crate::models::user::UserId;
+
+use super::validation::{validate_any_reference, validate_iban, BillError};
+use super::{parse_amount, EventId, NewBill, NewBillWithEvent, DEFAULT_CURRENCY};
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+/// See <https://www.europeanpaymentscouncil.eu/document-library/guidance-documents/quick-response-code-guidelines-enable-data-capture-initiation>
+/// (EPC069-12) for the spec
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NewBillEpcQrForm {
+    /// Defaults to the name of the payee
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub summary: Option<String>,
+    /// The text content of the QR code
+    pub payload: String,
+    /// The QR code doesn't carry a due date, YYYY-MM-DD
+    pub due_date: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NewBillEpcQrFormWithUserId {
+    pub user_id: UserId,
+    pub form: NewBillEpcQrForm,
+}
+
+impl TryFrom<NewBillEpcQrFormWithUserId> for NewBillWithEvent {
+    type Error = BillError;
+
+    fn try_from(data: NewBillEpcQrFormWithUserId) -> Result<Self, Self::Error> {
+        let NewBillEpcQrFormWithUserId { user_id, form } = data;
+        let qr = decode_epc_qr(&form.payload)?;
+        let due = chrono::NaiveDate::parse_from_str(form.due_date.trim(), "%Y-%m-%d")
+            .map_err(|_| BillError::InvalidDueDate)?;
+        let summary = form
+            .summary
+            .unwrap_or_else(|| format!("Bill from {}", qr.payee_name));
+        let description = qr.remittance_info.filter(|info| *info != qr.reference);
+        let new_bill = NewBill {
+            event_id: EventId::Local(-1), // placeholder
+            payee_account_number: qr.payee_account_number,
+            amount: qr.amount_cents,
+            currency: DEFAULT_CURRENCY.to_string(),
+            reference: qr.reference,
+            payee_name: Some(qr.payee_name),
+            payee_email: None,
+            payee_address: None,
+            payee_phone: None,
+            payee_bic: qr.bic,
+        };
+        Ok(new_bill.with_event(user_id, summary, due, description))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EpcQr {
+    /// Either 1 or 2, the BIC is optional since version 2
+    pub version: u8,
+    pub bic: Option<String>,
+    pub payee_name: String,
+    pub payee_account_number: String,
+    pub amount_cents: i32,
+    pub purpose: Option<String>,
+    /// The structured creditor reference, or the unstructured remittance info when missing
+    pub reference: String,
+    pub remittance_info: Option<String>,
+}
+
+const EPC_QR_MAX_LENGTH: usize = 331;
+
+/// Decodes and validates the text payload of an EPC QR code ("GiroCode")
+pub fn decode_epc_qr(payload: &str) -> Result<EpcQr, BillError> {
+    let invalid = |reason: &str| BillError::InvalidEpcQr(reason.to_string());
+    let payload = payload.trim();
+    if payload.len() > EPC_QR_MAX_LENGTH {
+        return Err(invalid("payload is too long"));
+    }
+    let mut lines = payload.lines().map(str::trim);
+    let mut next = || lines.next().filter(|line| !line.is_empty());
+
+    if next() != Some("BCD") {
+        return Err(invalid("missing service tag BCD"));
+    }
+    let version = match next() {
+        Some("001") => 1,
+        Some("002") => 2,
+        _ => return Err(invalid("unsupported version")),
+    };
+    // the payload has already been decoded, so the character set doesn't matter
+    match next().and_then(|charset| charset.parse::<u8>().ok()) {
+        Some(1..=8) => {}
+        _ => return Err(invalid("invalid character set")),
+    }
+    if next() != Some("SCT") {
+        return Err(invalid("missing identification code SCT"));
+    }
+    let bic = next().map(str::to_string);
+    if version == 1 && bic.is_none() {
+        return Err(invalid("version 1 requires a BIC"));
+    }
+    let payee_name = next()
+        .ok_or_else(|| invalid("missing payee name"))?
+        .to_string();
+    let payee_account_number: String = next()
+        .ok_or_else(|| invalid("missing IBAN"))?
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    validate_iban(&payee_account_number)?;
+    let amount = next().ok_or(BillError::InvalidAmount)?;
+    let amount_cents = match amount.get(..3) {
+        Some("EUR") => parse_amount(&amount[3..]).ok_or(BillError::InvalidAmount)?,
+        Some(currency) if currency.chars().all(|c| c.is_ascii_uppercase()) => {
+            return Err(BillError::UnsupportedCurrency(currency.to_string()))
+        }
+        _ => return Err(BillError::InvalidAmount),
+    };
+    let purpose = next().map(str::to_string);
+    let structured_reference = next().map(str::to_string);
+    let remittance_info = next().map(str::to_string);
+    let reference = match (structured_reference, &remittance_info) {
+        (Some(_), Some(_)) => {
+            return Err(invalid(
+                "only one of the structured and unstructured remittance info is allowed",
+            ))
+        }
+        (Some(reference), None) => {
+            validate_any_reference(&reference)?;
+            reference
+        }
+        (None, Some(info)) => info.clone(),
+        (None, None) => String::new(),
+    };
+
+    Ok(EpcQr {
+        version,
+        bic,
+        payee_name,
+        payee_account_number,
+        amount_cents,
+        purpose,
+        reference,
+        remittance_info,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_epc_qr_structured_reference() {
+        let payload = "BCD\n002\n1\nSCT\nBHBLDEHHXXX\nFranz Mustermänn\nDE71110220330123456789\nEUR12.3\nGDDS\nRF18539007547034\n\n";
+        let decoded = decode_epc_qr(payload).unwrap();
+        assert_eq!(decoded.version, 2);
+        assert_eq!(decoded.bic.as_deref(), Some("BHBLDEHHXXX"));
+        assert_eq!(decoded.payee_name, "Franz Mustermänn");
+        assert_eq!(decoded.payee_account_number, "DE71110220330123456789");
+        assert_eq!(decoded.amount_cents, 1230);
+        assert_eq!(decoded.purpose.as_deref(), Some("GDDS"));
+        assert_eq!(decoded.reference, "RF18539007547034");
+        assert_eq!(decoded.remittance_info, None);
+    }
+
+    #[test]
+    fn test_decode_epc_qr_unstructured_remittance() {
+        let payload = "BCD\r\n001\r\n1\r\nSCT\r\nBPOTBEB1\r\nRed Cross of Belgium\r\nBE72000000001616\r\nEUR1\r\nCHAR\r\n\r\nUrgency fund\r\nSample EPC QR code";
+        let decoded = decode_epc_qr(payload).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.payee_account_number, "BE72000000001616");
+        assert_eq!(decoded.amount_cents, 100);
+        assert_eq!(decoded.reference, "Urgency fund");
+    }
+
+    #[test]
+    fn test_decode_epc_qr_invalid() {
+        let without_bic = "BCD\n001\n1\nSCT\n\nRed Cross of Belgium\nBE72000000001616\nEUR1";
+        assert!(matches!(
+            decode_epc_qr(without_bic),
+            Err(BillError::InvalidEpcQr(_))
+        ));
+        let bad_iban = "BCD\n002\n1\nSCT\n\nRed Cross of Belgium\nBE72000000001617\nEUR1";
+        assert_eq!(
+            decode_epc_qr(bad_iban),
+            Err(BillError::InvalidIban("BE72000000001617".to_string()))
+        );
+        let dollars = "BCD\n002\n1\nSCT\n\nRed Cross of Belgium\nBE72000000001616\nUSD1";
+        assert_eq!(
+            decode_epc_qr(dollars),
+            Err(BillError::UnsupportedCurrency("USD".to_string()))
+        );
+    }
+
+    #[test]
+    fn from_form() {
+        let form = NewBillEpcQrForm {
+            summary: None,
+            payload: "BCD\n002\n1\nSCT\n\nOy Yritys Ab\nFI7944052020036082\nEUR45.60\n\nRF09868516259619897".to_string(),
+            due_date: "2024-12-01".to_string(),
+        };
+        let (event, bill) =
+            NewBillWithEvent::try_from(NewBillEpcQrFormWithUserId { form, user_id: -1 }).unwrap();
+        assert_eq!(event.summary, "Bill from Oy Yritys Ab");
+        assert_eq!(event.starts_at, 1733011200);
+        assert_eq!(bill.amount, 4560);
+        assert_eq!(bill.reference, "RF09868516259619897");
+        assert_eq!(bill.payee_bic, None);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::models::user::UserId;
+use crate::utils::xml::{find, text};
+
+use super::validation::{validate_currency, validate_iban, validate_reference_for, BillError};
+use super::{parse_amount, EventId, NewBill, NewBillWithEvent, DEFAULT_CURRENCY};
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+/// See <https://www.finanssiala.fi/en/topics/finvoice-standard/> for the spec.
+/// Only the fields needed for paying the invoice are read.
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NewBillFinvoiceForm {
+    /// Defaults to the invoice number and the name of the seller
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub summary: Option<String>,
+    /// The XML document, optionally wrapped in its SOAP transmission frame
+    pub finvoice: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NewBillFinvoiceFormWithUserId {
+    pub user_id: UserId,
+    pub form: NewBillFinvoiceForm,
+}
+
+impl TryFrom<NewBillFinvoiceFormWithUserId> for NewBillWithEvent {
+    type Error = BillError;
+
+    fn try_from(data: NewBillFinvoiceFormWithUserId) -> Result<Self, Self::Error> {
+        let NewBillFinvoiceFormWithUserId { user_id, form } = data;
+        let invoice = decode_finvoice(&form.finvoice)?;
+        let summary =
+            form.summary
+                .unwrap_or_else(|| match (&invoice.invoice_number, &invoice.payee_name) {
+                    (Some(number), Some(name)) => format!("Invoice {number} from {name}"),
+                    (None, Some(name)) => format!("Invoice from {name}"),
+                    (Some(number), None) => format!("Invoice {number}"),
+                    (None, None) => "Invoice".to_string(),
+                });
+        let new_bill = NewBill {
+            event_id: EventId::Local(-1), // placeholder
+            payee_account_number: invoice.payee_account_number,
+            amount: invoice.amount_cents,
+            currency: invoice.currency,
+            reference: invoice.reference,
+            payee_name: invoice.payee_name,
+            payee_email: invoice.payee_email,
+            payee_address: invoice.payee_address,
+            payee_phone: invoice.payee_phone,
+            payee_bic: invoice.payee_bic,
+        };
+        Ok(new_bill.with_event(user_id, summary, invoice.due, None))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Finvoice {
+    pub invoice_number: Option<String>,
+    pub payee_name: Option<String>,
+    pub payee_account_number: String,
+    pub payee_bic: Option<String>,
+    pub amount_cents: i32,
+    pub currency: String,
+    pub reference: String,
+    pub due: chrono::NaiveDate,
+    pub payee_email: Option<String>,
+    pub payee_address: Option<String>,
+    pub payee_phone: Option<String>,
+}
+
+/// Finvoice dates are either CCYYMMDD or, in older documents, DD.MM.CCYY
+fn parse_finvoice_date(date: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(date, "%Y%m%d")
+        .or_else(|_| chrono::NaiveDate::parse_from_str(date, "%d.%m.%Y"))
+        .ok()
+}
+
+/// Decodes the payment details of a Finvoice document.
+/// The EPI (payment) details take precedence over the seller and invoice details.
+pub fn decode_finvoice(xml: &str) -> Result<Finvoice, BillError> {
+    let invalid = |reason: &str| BillError::InvalidFinvoice(reason.to_string());
+    // skips the SOAP frame (a separate document) and the XML declaration, whose encoding
+    // would be wrong anyway as the text has already been decoded
+    let start = xml
+        .match_indices("<Finvoice")
+        .map(|(start, _)| start)
+        .find(|start| {
+            xml[start + "<Finvoice".len()..].starts_with(|c: char| c.is_whitespace() || c == '>')
+        })
+        .ok_or_else(|| invalid("missing Finvoice element"))?;
+    let document = roxmltree::Document::parse(xml[start..].trim_end())
+        .map_err(|e| BillError::InvalidFinvoice(e.to_string()))?;
+    let root = document.root_element();
+
+    let payee_account_number: String = text(
+        root,
+        &[
+            "EpiDetails",
+            "EpiPartyDetails",
+            "EpiBeneficiaryPartyDetails",
+            "EpiAccountID",
+        ],
+    )
+    .or_else(|| {
+        text(
+            root,
+            &[
+                "SellerInformationDetails",
+                "SellerAccountDetails",
+                "SellerAccountID",
+            ],
+        )
+    })
+    .ok_or_else(|| invalid("missing the payee's account"))?
+    .chars()
+    .filter(|c| !c.is_whitespace())
+    .collect::<String>()
+    .to_uppercase();
+    validate_iban(&payee_account_number)?;
+    let payee_bic = text(
+        root,
+        &[
+            "EpiDetails",
+            "EpiPartyDetails",
+            "EpiBfiPartyDetails",
+            "EpiBfiIdentifier",
+        ],
+    )
+    .or_else(|| {
+        text(
+            root,
+            &[
+                "SellerInformationDetails",
+                "SellerAccountDetails",
+                "SellerBic",
+            ],
+        )
+    });
+    let payee_name = text(
+        root,
+        &[
+            "EpiDetails",
+            "EpiPartyDetails",
+            "EpiBeneficiaryPartyDetails",
+            "EpiNameAddressDetails",
+        ],
+    )
+    .or_else(|| text(root, &["SellerPartyDetails", "SellerOrganisationName"]));
+
+    let payment = find(root, &["EpiDetails", "EpiPaymentInstructionDetails"]);
+    let amount = payment
+        .and_then(|payment| find(payment, &["EpiInstructedAmount"]))
+        .or_else(|| find(root, &["InvoiceDetails", "InvoiceTotalVatIncludedAmount"]))
+        .ok_or(BillError::InvalidAmount)?;
+    let currency = validate_currency(
+        amount
+            .attribute("AmountCurrencyIdentifier")
+            .unwrap_or(DEFAULT_CURRENCY),
+    )?;
+    let amount_cents = amount
+        .text()
+        .and_then(parse_amount)
+        .ok_or(BillError::InvalidAmount)?;
+
+    let reference = payment
+        .and_then(|payment| text(payment, &["EpiRemittanceInfoIdentifier"]))
+        .ok_or_else(|| invalid("missing the payment reference"))?;
+    validate_reference_for(&payee_account_number, &reference)?;
+
+    let due = payment
+        .and_then(|payment| text(payment, &["EpiDateOptionDate"]))
+        .or_else(|| {
+            text(
+                root,
+                &["InvoiceDetails", "PaymentTermsDetails", "InvoiceDueDate"],
+            )
+        })
+        .ok_or(BillError::MissingDueDate)?;
+    let due = parse_finvoice_date(&due).ok_or(BillError::InvalidDueDate)?;
+
+    let payee_address = find(root, &["SellerPartyDetails", "SellerPostalAddressDetails"])
+        .map(|address| {
+            [
+                "SellerStreetName",
+                "SellerPostCodeIdentifier",
+                "SellerTownName",
+            ]
+            .iter()
+            .filter_map(|name| text(address, &[name]))
+            .collect::<Vec<_>>()
+            .join(" ")
+        })
+        .filter(|address| !address.is_empty());
+
+    Ok(Finvoice {
+        invoice_number: text(root, &["InvoiceDetails", "InvoiceNumber"]),
+        payee_name,
+        payee_account_number,
+        payee_bic,
+        amount_cents,
+        currency,
+        reference,
+        due,
+        payee_email: text(
+            root,
+            &[
+                "SellerInformationDetails",
+                "SellerCommonEmailaddressIdentifier",
+            ],
+        ),
+        payee_address,
+        payee_phone: text(root, &["SellerInformationDetails", "SellerPhoneNumber"]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINVOICE: &str = r#"<?xml version="1.0" encoding="ISO-8859-15"?>
+<Finvoice Version="3.0">
+  <SellerPartyDetails>
+    <SellerPartyIdentifier>0123456-7</SellerPartyIdentifier>
+    <SellerOrganisationName>Myyjä Oy</SellerOrganisationName>
+    <SellerPostalAddressDetails>
+      <SellerStreetName>Katu 1</SellerStreetName>
+      <SellerTownName>Helsinki</SellerTownName>
+      <SellerPostCodeIdentifier>00100</SellerPostCodeIdentifier>
+    </SellerPostalAddressDetails>
+  </SellerPartyDetails>
+  <SellerInformationDetails>
+    <SellerPhoneNumber>+358401234567</SellerPhoneNumber>
+    <SellerCommonEmailaddressIdentifier>laskutus@example.com</SellerCommonEmailaddressIdentifier>
+    <SellerAccountDetails>
+      <SellerAccountID IdentificationSchemeName="IBAN">FI7944052020036082</SellerAccountID>
+      <SellerBic IdentificationSchemeName="BIC">NDEAFIHH</SellerBic>
+    </SellerAccountDetails>
+  </SellerInformationDetails>
+  <InvoiceDetails>
+    <InvoiceNumber>1234</InvoiceNumber>
+    <InvoiceTotalVatIncludedAmount AmountCurrencyIdentifier="EUR">123,45</InvoiceTotalVatIncludedAmount>
+    <PaymentTermsDetails>
+      <InvoiceDueDate Format="CCYYMMDD">20241201</InvoiceDueDate>
+    </PaymentTermsDetails>
+  </InvoiceDetails>
+  <EpiDetails>
+    <EpiPartyDetails>
+      <EpiBfiPartyDetails>
+        <EpiBfiIdentifier IdentificationSchemeName="BIC">NDEAFIHH</EpiBfiIdentifier>
+      </EpiBfiPartyDetails>
+      <EpiBeneficiaryPartyDetails>
+        <EpiNameAddressDetails>Myyjä Oy</EpiNameAddressDetails>
+        <EpiAccountID IdentificationSchemeName="IBAN">FI7944052020036082</EpiAccountID>
+      </EpiBeneficiaryPartyDetails>
+    </EpiPartyDetails>
+    <EpiPaymentInstructionDetails>
+      <EpiRemittanceInfoIdentifier IdentificationSchemeName="SPY">1357914</EpiRemittanceInfoIdentifier>
+      <EpiInstructedAmount AmountCurrencyIdentifier="EUR">123,45</EpiInstructedAmount>
+      <EpiDateOptionDate Format="CCYYMMDD">20241201</EpiDateOptionDate>
+    </EpiPaymentInstructionDetails>
+  </EpiDetails>
+</Finvoice>"#;
+
+    #[test]
+    fn test_decode_finvoice() {
+        let decoded = decode_finvoice(FINVOICE).unwrap();
+        assert_eq!(decoded.invoice_number.as_deref(), Some("1234"));
+        assert_eq!(decoded.payee_name.as_deref(), Some("Myyjä Oy"));
+        assert_eq!(decoded.payee_account_number, "FI7944052020036082");
+        assert_eq!(decoded.payee_bic.as_deref(), Some("NDEAFIHH"));
+        assert_eq!(decoded.amount_cents, 12345);
+        assert_eq!(decoded.reference, "1357914");
+        assert_eq!(
+            decoded.due,
+            chrono::NaiveDate::from_ymd_opt(2024, 12, 1).unwrap()
+        );
+        assert_eq!(
+            decoded.payee_address.as_deref(),
+            Some("Katu 1 00100 Helsinki")
+        );
+        assert_eq!(decoded.payee_email.as_deref(), Some("laskutus@example.com"));
+        assert_eq!(decoded.payee_phone.as_deref(), Some("+358401234567"));
+    }
+
+    #[test]
+    fn test_decode_finvoice_soap_frame() {
+        let framed = format!(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/"><SOAP-ENV:Header/><SOAP-ENV:Body/></SOAP-ENV:Envelope>{}"#,
+            FINVOICE.split_once("?>").unwrap().1
+        );
+        assert_eq!(decode_finvoice(&framed).unwrap().amount_cents, 12345);
+    }
+
+    #[test]
+    fn test_decode_finvoice_invalid() {
+        assert!(matches!(
+            decode_finvoice("<Finvoice>"),
+            Err(BillError::InvalidFinvoice(_))
+        ));
+        let wrong_reference = FINVOICE.replace(">1357914<", ">1357915<");
+        assert_eq!(
+            decode_finvoice(&wrong_reference),
+            Err(BillError::InvalidReference("1357915".to_string()))
+        );
+        let crowns = FINVOICE
+            .replace("\"EUR\"", "\"SEK\"")
+            .replace("FI7944052020036082", "SE4550000000058398257466")
+            .replace(">1357914<", ">123456<");
+        let decoded = decode_finvoice(&crowns).unwrap();
+        assert_eq!(decoded.currency, "SEK");
+        assert_eq!(decoded.reference, "123456");
+        let euros = FINVOICE.replace("\"EUR\"", "\"euro\"");
+        assert_eq!(
+            decode_finvoice(&euros),
+            Err(BillError::UnsupportedCurrency("EURO".to_string()))
+        );
+    }
+
+    #[test]
+    fn from_form() {
+        let form = NewBillFinvoiceForm {
+            summary: None,
+            finvoice: FINVOICE.to_string(),
+        };
+        let (event, bill) =
+            NewBillWithEvent::try_from(NewBillFinvoiceFormWithUserId { form, user_id: -1 })
+                .unwrap();
+        assert_eq!(event.summary, "Invoice 1234 from Myyjä Oy");
+        assert_eq!(event.starts_at, 1733011200);
+        assert_eq!(bill.payee_bic.as_deref(), Some("NDEAFIHH"));
+    }
+}
@@ -1,31 +1,48 @@
 pub mod from_barcode;
+pub mod from_epc_qr;
+pub mod from_finvoice;
+pub mod reminder;
+pub mod statement;
+pub mod validation;
 
 use chrono::Utc;
 
-use crate::utils::time::from_timestamp;
+use crate::utils::time::{from_date_form, from_timestamp};
+use from_barcode::encode_barcode;
 use serde_with::As;
 use serde_with::NoneAsEmptyString;
+use validation::{validate_currency, validate_iban, validate_reference_for, BillError};
 
-use super::event::local::NewLocalEvent;
-use super::event::EventLike;
+use super::event::local::{LocalEventId, NewLocalEvent};
+use super::event::remote::RemoteEventId;
+use super::event::Priority;
+use super::user::UserId;
+
+pub type BillId = i32;
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
 pub struct RawBill {
-    pub id: i32,
+    pub id: BillId,
+    pub user_id: UserId,
 
     // either but not both
     pub local_event_id: Option<i32>,
     pub remote_event_id: Option<i32>,
 
     pub payee_account_number: String,
+    /// In the minor unit (cents) of the currency
     pub amount: i32,
+    /// ISO 4217 code
+    pub currency: String,
     pub reference: String,
 
     pub payee_name: Option<String>,
     pub payee_email: Option<String>,
     pub payee_address: Option<String>,
     pub payee_phone: Option<String>,
+    pub payee_bic: Option<String>,
 
+    pub paid_at: Option<i64>,
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -38,18 +55,21 @@ pub enum EventId {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Bill {
-    pub id: i32,
+    pub id: BillId,
+    pub user_id: UserId,
     pub event_id: EventId,
 
     pub payee_account_number: String,
     pub amount: i32,
+    pub currency: String,
     pub reference: String,
-    // due and paid_at are stored in the event
-    // as starts_at and duration (paid_at - due)
+    // the due date is the start of the event
+    pub paid_at: Option<chrono::DateTime<Utc>>,
     pub payee_name: Option<String>,
     pub payee_email: Option<String>,
     pub payee_address: Option<String>,
     pub payee_phone: Option<String>,
+    pub payee_bic: Option<String>,
 
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
@@ -58,6 +78,7 @@ impl From<RawBill> for Bill {
     fn from(raw: RawBill) -> Self {
         Self {
             id: raw.id,
+            user_id: raw.user_id,
             event_id: if let Some(local_event_id) = raw.local_event_id {
                 EventId::Local(local_event_id)
             } else {
@@ -68,16 +89,33 @@ impl From<RawBill> for Bill {
             },
             payee_account_number: raw.payee_account_number,
             amount: raw.amount,
+            currency: raw.currency,
             reference: raw.reference,
+            paid_at: raw.paid_at.map(from_timestamp),
             payee_name: raw.payee_name,
             payee_email: raw.payee_email,
             payee_address: raw.payee_address,
             payee_phone: raw.payee_phone,
+            payee_bic: raw.payee_bic,
             created_at: from_timestamp(raw.created_at),
             updated_at: from_timestamp(raw.updated_at),
         }
     }
 }
+impl Bill {
+    /// The bank barcode for paying this bill, due on the given date
+    pub fn barcode(&self, due: Option<chrono::NaiveDate>) -> Result<String, BillError> {
+        if self.currency != DEFAULT_CURRENCY {
+            return Err(BillError::UnsupportedCurrency(self.currency.clone()));
+        }
+        encode_barcode(
+            &self.payee_account_number,
+            self.amount,
+            &self.reference,
+            due,
+        )
+    }
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct NewBill {
@@ -85,22 +123,102 @@ pub struct NewBill {
 
     pub payee_account_number: String,
     pub amount: i32,
+    pub currency: String,
     pub reference: String,
 
     pub payee_name: Option<String>,
     pub payee_email: Option<String>,
     pub payee_address: Option<String>,
     pub payee_phone: Option<String>,
+    pub payee_bic: Option<String>,
 }
 
 pub type NewBillWithEvent = (NewLocalEvent, NewBill);
 
+const BILL_DEFAULT_PRIORITY: Priority = 1; // Highest possible priority
+impl NewBill {
+    /// Pairs a decoded bill with a new all-day event on its due date.
+    /// The event id of the bill is a placeholder until the event has been inserted.
+    pub fn with_event(
+        self,
+        user_id: UserId,
+        summary: String,
+        due: chrono::NaiveDate,
+        description: Option<String>,
+    ) -> NewBillWithEvent {
+        let uid = format!(
+            "bill-{}-{}@olmonoko",
+            self.payee_account_number, self.reference
+        );
+        let new_event = NewLocalEvent {
+            user_id,
+            tags: vec!["olmonoko::bill".to_string()],
+            priority: Some(BILL_DEFAULT_PRIORITY),
+            starts_at: due.and_time(chrono::NaiveTime::MIN).and_utc().timestamp(),
+            all_day: true,
+            duration: None,
+            summary,
+            description,
+            location: None,
+            uid,
+        };
+        (new_event, self)
+    }
+}
+
+/// Bank barcodes and EPC QR codes are always in euros
+pub const DEFAULT_CURRENCY: &str = "EUR";
+
+/// Formats an amount in the minor unit of the currency the way it's written where the currency
+/// is mostly used, e.g. `1 234,50 €`, `1 234,50 kr` or `$1,234.50`
+pub fn format_amount(amount: i64, currency: &str) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let cents = amount.unsigned_abs() % 100;
+    let group = |separator: &str| {
+        let units = (amount.unsigned_abs() / 100).to_string();
+        let digits: Vec<char> = units.chars().collect();
+        digits
+            .rchunks(3)
+            .rev()
+            .map(|chunk| chunk.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+    match currency {
+        "EUR" => format!("{sign}{},{cents:02} €", group(" ")),
+        "SEK" | "NOK" | "DKK" => format!("{sign}{},{cents:02} kr", group(" ")),
+        "USD" => format!("{sign}${}.{cents:02}", group(",")),
+        "GBP" => format!("{sign}£{}.{cents:02}", group(",")),
+        _ => format!("{sign}{}.{cents:02} {currency}", group(",")),
+    }
+}
+
+/// Parses an amount, e.g. `12,34` or `12.3`, into cents
+pub fn parse_amount(amount: &str) -> Option<i32> {
+    let amount = amount.trim().replace(',', ".");
+    let (euros, cents) = amount.split_once('.').unwrap_or((&amount, "0"));
+    if cents.is_empty() || cents.len() > 2 || !cents.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let euros: i32 = euros.parse().ok().filter(|euros: &i32| *euros >= 0)?;
+    let cents: i32 = format!("{cents:0<2}").parse().ok()?;
+    euros.checked_mul(100)?.checked_add(cents)
+}
+
+/// Attaches a bill to an existing event
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct NewBillForm {
-    pub event_id: EventId,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub local_event_id: Option<LocalEventId>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub remote_event_id: Option<RemoteEventId>,
 
     pub payee_account_number: String,
-    pub amount: i32,
+    /// In the major unit of the currency, e.g. euros
+    pub amount: String,
+    /// Defaults to euros
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub currency: Option<String>,
     pub reference: String,
 
     #[serde(default, with = "As::<NoneAsEmptyString>")]
@@ -111,19 +229,146 @@ pub struct NewBillForm {
     pub payee_address: Option<String>,
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     pub payee_phone: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub payee_bic: Option<String>,
 }
 
-impl From<NewBillForm> for NewBill {
-    fn from(form: NewBillForm) -> Self {
-        Self {
-            event_id: form.event_id,
-            payee_account_number: form.payee_account_number,
-            amount: form.amount,
-            reference: form.reference,
+impl TryFrom<NewBillForm> for NewBill {
+    type Error = BillError;
+
+    fn try_from(form: NewBillForm) -> Result<Self, Self::Error> {
+        let event_id = match (form.local_event_id, form.remote_event_id) {
+            (Some(id), None) => EventId::Local(id),
+            (None, Some(id)) => EventId::Remote(id),
+            _ => return Err(BillError::MissingEvent),
+        };
+        let payee_account_number = form.payee_account_number.trim().to_string();
+        validate_iban(&payee_account_number)?;
+        let reference = form.reference.trim().to_string();
+        validate_reference_for(&payee_account_number, &reference)?;
+        let currency = match form.currency {
+            Some(currency) => validate_currency(&currency)?,
+            None => DEFAULT_CURRENCY.to_string(),
+        };
+        Ok(Self {
+            event_id,
+            payee_account_number,
+            amount: parse_amount(&form.amount).ok_or(BillError::InvalidAmount)?,
+            currency,
+            reference,
             payee_name: form.payee_name,
             payee_email: form.payee_email,
             payee_address: form.payee_address,
             payee_phone: form.payee_phone,
+            payee_bic: form.payee_bic,
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BillUpdateForm {
+    /// In the major unit of the bill's currency
+    pub amount: String,
+    /// Keeps the current currency when missing
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub currency: Option<String>,
+    pub reference: String,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub payee_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BillPaidForm {
+    /// A date in the user's timezone, defaults to now
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub paid_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillStatus {
+    Unpaid,
+    /// Unpaid past the due date
+    Overdue,
+    Paid,
+}
+impl std::str::FromStr for BillStatus {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unpaid" => Ok(Self::Unpaid),
+            "overdue" => Ok(Self::Overdue),
+            "paid" => Ok(Self::Paid),
+            _ => Err("Not a valid BillStatus"),
+        }
+    }
+}
+impl std::fmt::Display for BillStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BillStatus::Unpaid => f.write_str("unpaid"),
+            BillStatus::Overdue => f.write_str("overdue"),
+            BillStatus::Paid => f.write_str("paid"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BillFilter {
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub status: Option<BillStatus>,
+    /// Dates in the user's timezone
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub due_after: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub due_before: Option<String>,
+    /// Matches the payee's name or account number
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub payee: Option<String>,
+}
+
+impl BillFilter {
+    /// Parses the due date bounds as timestamps in the given timezone.
+    /// The upper bound is inclusive, so it's moved to the start of the next day.
+    pub fn due_range(&self, tz_offset: i8) -> (Option<i64>, Option<i64>) {
+        let parse = |date: &Option<String>| {
+            date.as_deref()
+                .and_then(|date| from_date_form(date, tz_offset))
+                .map(|dt| dt.timestamp())
+        };
+        let due_after = parse(&self.due_after);
+        let due_before = parse(&self.due_before).map(|ts| ts + 24 * 60 * 60);
+        (due_after, due_before)
+    }
+}
+
+/// A bill along with the event it's due on
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BillWithEvent {
+    pub bill: Bill,
+    pub summary: String,
+    pub due_at: Option<chrono::DateTime<Utc>>,
+    pub status: BillStatus,
+    /// None if the bill can't be paid with a barcode, e.g. for foreign IBANs
+    pub barcode: Option<String>,
+}
+impl From<(RawBill, String, Option<i64>)> for BillWithEvent {
+    fn from((raw, summary, due_at): (RawBill, String, Option<i64>)) -> Self {
+        let status = match (raw.paid_at, due_at) {
+            (Some(_), _) => BillStatus::Paid,
+            (None, Some(due_at)) if due_at < crate::utils::time::timestamp() => BillStatus::Overdue,
+            (None, _) => BillStatus::Unpaid,
+        };
+        let bill = Bill::from(raw);
+        let due_at = due_at.map(from_timestamp);
+        let barcode = bill.barcode(due_at.map(|due| due.date_naive())).ok();
+        Self {
+            bill,
+            summary,
+            due_at,
+            status,
+            barcode,
         }
     }
 }
@@ -132,11 +377,13 @@ impl From<NewBillForm> for NewBill {
 pub trait BillLike {
     fn payee_account_number(&self) -> &str;
     fn amount(&self) -> i32;
+    fn currency(&self) -> &str;
     fn reference(&self) -> &str;
     fn payee_name(&self) -> Option<&str>;
     fn payee_email(&self) -> Option<&str>;
     fn payee_address(&self) -> Option<&str>;
     fn payee_phone(&self) -> Option<&str>;
+    fn is_paid(&self) -> bool;
 }
 
 impl BillLike for Bill {
@@ -146,6 +393,9 @@ impl BillLike for Bill {
     fn amount(&self) -> i32 {
         self.amount
     }
+    fn currency(&self) -> &str {
+        &self.currency
+    }
     fn reference(&self) -> &str {
         &self.reference
     }
@@ -161,6 +411,9 @@ impl BillLike for Bill {
     fn payee_phone(&self) -> Option<&str> {
         self.payee_phone.as_deref()
     }
+    fn is_paid(&self) -> bool {
+        self.paid_at.is_some()
+    }
 }
 impl BillLike for NewBill {
     fn payee_account_number(&self) -> &str {
@@ -169,6 +422,9 @@ impl BillLike for NewBill {
     fn amount(&self) -> i32 {
         self.amount
     }
+    fn currency(&self) -> &str {
+        &self.currency
+    }
     fn reference(&self) -> &str {
         &self.reference
     }
@@ -184,26 +440,26 @@ impl BillLike for NewBill {
     fn payee_phone(&self) -> Option<&str> {
         self.payee_phone.as_deref()
     }
+    fn is_paid(&self) -> bool {
+        false
+    }
 }
 
 pub trait AutoDescription {
-    fn generate_description(&self, event: Option<&impl EventLike>) -> String;
+    fn generate_description(&self) -> String;
 }
 
 impl<T: BillLike> AutoDescription for T {
-    fn generate_description(&self, event: Option<&impl EventLike>) -> String {
-        let paid_info = event
-            .and_then(|e| e.duration())
-            .map(|_| " (paid)")
-            .unwrap_or_default();
+    fn generate_description(&self) -> String {
+        let paid_info = if self.is_paid() { " (paid)" } else { "" };
         format!(
             r#"=== BILL{paid_info} ===
 Payee bank account: {}
 Reference: {}
-Amount: {}€"#,
+Amount: {}"#,
             self.payee_account_number(),
             self.reference(),
-            (self.amount() as f64) / 100.0,
+            format_amount(self.amount().into(), self.currency()),
             // self.payee_name().unwrap_or("Unknown"),
             // self.payee_email().unwrap_or("Unknown"),
             // self.payee_address().unwrap_or("Unknown"),
@@ -211,3 +467,29 @@ Amount: {}€"#,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_amount("12"), Some(1200));
+        assert_eq!(parse_amount("12.3"), Some(1230));
+        assert_eq!(parse_amount(" 12,34 "), Some(1234));
+        assert_eq!(parse_amount("0.05"), Some(5));
+        assert_eq!(parse_amount("12.345"), None);
+        assert_eq!(parse_amount("12."), None);
+        assert_eq!(parse_amount("-1"), None);
+        assert_eq!(parse_amount("abc"), None);
+    }
+
+    #[test]
+    fn formatted_amounts() {
+        assert_eq!(format_amount(123450, "EUR"), "1 234,50 €");
+        assert_eq!(format_amount(5, "EUR"), "0,05 €");
+        assert_eq!(format_amount(-123456789, "SEK"), "-1 234 567,89 kr");
+        assert_eq!(format_amount(123450, "USD"), "$1,234.50");
+        assert_eq!(format_amount(100000, "CHF"), "1,000.00 CHF");
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::Utc;
+
+use crate::models::ics_source::deserialize_checkbox;
+use crate::models::user::UserId;
+
+use super::{format_amount, Bill};
+
+const CHANNEL_EMAIL: &str = "email";
+const CHANNEL_WEBHOOK: &str = "webhook";
+/// Where bill reminders are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationChannelKind {
+    /// To the user's own email address
+    Email,
+    /// As JSON in a POST to the user's URL
+    Webhook,
+}
+impl TryFrom<&str> for NotificationChannelKind {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            CHANNEL_EMAIL => Ok(Self::Email),
+            CHANNEL_WEBHOOK => Ok(Self::Webhook),
+            _ => Err("Not a valid NotificationChannelKind"),
+        }
+    }
+}
+impl std::fmt::Display for NotificationChannelKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotificationChannelKind::Email => f.write_str(CHANNEL_EMAIL),
+            NotificationChannelKind::Webhook => f.write_str(CHANNEL_WEBHOOK),
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct RawReminderSettings {
+    pub user_id: UserId,
+    pub enabled: bool,
+    pub lead_days: i32,
+    pub channel: String,
+    pub webhook_url: Option<String>,
+    pub escalate_overdue: bool,
+    pub updated_at: i64,
+}
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReminderSettings {
+    pub enabled: bool,
+    /// How many days before the due date the first reminder is sent
+    pub lead_days: i32,
+    pub channel: NotificationChannelKind,
+    pub webhook_url: Option<String>,
+    /// Raises the events of overdue bills to the highest priority
+    pub escalate_overdue: bool,
+}
+impl Default for ReminderSettings {
+    fn default() -> Self {
+        Self {
+            // opt-in, so that reminders don't start arriving for every existing bill at once
+            enabled: false,
+            lead_days: 3,
+            channel: NotificationChannelKind::Email,
+            webhook_url: None,
+            escalate_overdue: true,
+        }
+    }
+}
+impl From<RawReminderSettings> for ReminderSettings {
+    fn from(raw: RawReminderSettings) -> Self {
+        Self {
+            enabled: raw.enabled,
+            lead_days: raw.lead_days,
+            channel: NotificationChannelKind::try_from(raw.channel.as_str())
+                .expect("Invalid notification channel in database"),
+            webhook_url: raw.webhook_url,
+            escalate_overdue: raw.escalate_overdue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReminderSettingsForm {
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub enabled: bool,
+    pub lead_days: i32,
+    pub channel: NotificationChannelKind,
+    #[serde(default, with = "serde_with::As::<serde_with::NoneAsEmptyString>")]
+    pub webhook_url: Option<String>,
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub escalate_overdue: bool,
+}
+impl TryFrom<ReminderSettingsForm> for ReminderSettings {
+    type Error = &'static str;
+
+    fn try_from(form: ReminderSettingsForm) -> Result<Self, Self::Error> {
+        if !(0..=60).contains(&form.lead_days) {
+            return Err("The lead time must be between 0 and 60 days");
+        }
+        let webhook_url = form.webhook_url.map(|url| url.trim().to_string());
+        let valid_url = webhook_url
+            .as_deref()
+            .is_some_and(|url| url.starts_with("https://") || url.starts_with("http://"));
+        if form.channel == NotificationChannelKind::Webhook && !valid_url {
+            return Err("Webhook reminders need an http(s) URL");
+        }
+        Ok(Self {
+            enabled: form.enabled,
+            lead_days: form.lead_days,
+            channel: form.channel,
+            webhook_url,
+            escalate_overdue: form.escalate_overdue,
+        })
+    }
+}
+
+const REMINDER_UPCOMING: &str = "upcoming";
+const REMINDER_OVERDUE: &str = "overdue";
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReminderKind {
+    /// Sent once the bill is due within the lead time
+    Upcoming,
+    /// Sent once the due date has passed
+    Overdue,
+}
+impl TryFrom<&str> for ReminderKind {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            REMINDER_UPCOMING => Ok(Self::Upcoming),
+            REMINDER_OVERDUE => Ok(Self::Overdue),
+            _ => Err("Not a valid ReminderKind"),
+        }
+    }
+}
+impl std::fmt::Display for ReminderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReminderKind::Upcoming => f.write_str(REMINDER_UPCOMING),
+            ReminderKind::Overdue => f.write_str(REMINDER_OVERDUE),
+        }
+    }
+}
+
+/// A reminder of an unpaid bill, also the JSON body of webhook reminders
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BillReminder {
+    pub kind: ReminderKind,
+    pub bill: Bill,
+    /// Of the event the bill is due on
+    pub summary: String,
+    pub due_at: chrono::DateTime<Utc>,
+}
+impl BillReminder {
+    /// The reminder due for an unpaid bill, if any, given the kinds already sent
+    pub fn due_kind(
+        due_at: i64,
+        now: i64,
+        lead_days: i32,
+        sent: &[ReminderKind],
+    ) -> Option<ReminderKind> {
+        let kind = if due_at < now {
+            ReminderKind::Overdue
+        } else if due_at < now + i64::from(lead_days) * 24 * 60 * 60 {
+            ReminderKind::Upcoming
+        } else {
+            return None;
+        };
+        (!sent.contains(&kind)).then_some(kind)
+    }
+
+    pub fn subject(&self) -> String {
+        match self.kind {
+            ReminderKind::Upcoming => format!("Bill due soon: {}", self.summary),
+            ReminderKind::Overdue => format!("Bill overdue: {}", self.summary),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let due = self.due_at.format("%Y-%m-%d");
+        let when = match self.kind {
+            ReminderKind::Upcoming => format!("is due on {due}"),
+            ReminderKind::Overdue => format!("was due on {due} and hasn't been marked as paid"),
+        };
+        let payee = self
+            .bill
+            .payee_name
+            .as_deref()
+            .unwrap_or(&self.bill.payee_account_number);
+        format!(
+            "Your bill \"{}\" of {} to {payee} {when}.\n\nAccount: {}\nReference: {}",
+            self.summary,
+            format_amount(self.bill.amount.into(), &self.bill.currency),
+            self.bill.payee_account_number,
+            self.bill.reference,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn due_kinds() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        assert_eq!(BillReminder::due_kind(now + 5 * day, now, 3, &[]), None);
+        assert_eq!(
+            BillReminder::due_kind(now + 2 * day, now, 3, &[]),
+            Some(ReminderKind::Upcoming)
+        );
+        assert_eq!(
+            BillReminder::due_kind(now + 2 * day, now, 3, &[ReminderKind::Upcoming]),
+            None
+        );
+        // skipping the upcoming reminder when the bill was added late
+        assert_eq!(
+            BillReminder::due_kind(now - day, now, 3, &[]),
+            Some(ReminderKind::Overdue)
+        );
+        assert_eq!(
+            BillReminder::due_kind(now - day, now, 3, &[ReminderKind::Overdue]),
+            None
+        );
+    }
+}
@@ -0,0 +1,575 @@
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+use crate::models::ics_source::deserialize_checkbox;
+use crate::utils::xml::{children, find, text};
+
+use super::{format_amount, Bill, BillId, DEFAULT_CURRENCY};
+
+/// A transaction on a bank statement
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Transaction {
+    pub booked_on: chrono::NaiveDate,
+    /// Negative for payments out of the account
+    pub amount_cents: i64,
+    pub currency: String,
+    pub counterparty_name: Option<String>,
+    pub counterparty_account: Option<String>,
+    pub reference: Option<String>,
+    pub message: Option<String>,
+    /// The bank's own identifier for the transaction, if any
+    pub archive_id: Option<String>,
+}
+impl Transaction {
+    /// Stays the same when the same statement is imported again
+    pub fn uid(&self) -> String {
+        let id = match &self.archive_id {
+            Some(archive_id) => archive_id.clone(),
+            None => format!(
+                "{}-{}-{}-{}",
+                self.booked_on.format("%Y%m%d"),
+                self.amount_cents,
+                self.counterparty_account.as_deref().unwrap_or(""),
+                self.reference.as_deref().unwrap_or("")
+            ),
+        };
+        let id: String = id.chars().filter(|c| !c.is_whitespace()).collect();
+        format!("transaction-{id}@olmonoko")
+    }
+
+    pub fn summary(&self) -> String {
+        let direction = if self.amount_cents < 0 { "to" } else { "from" };
+        let amount = format_amount(self.amount_cents.abs(), &self.currency);
+        match &self.counterparty_name {
+            Some(name) => format!("{amount} {direction} {name}"),
+            None => amount,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        let mut lines = vec!["=== TRANSACTION ===".to_string()];
+        if let Some(account) = &self.counterparty_account {
+            lines.push(format!("Account: {account}"));
+        }
+        if let Some(reference) = &self.reference {
+            lines.push(format!("Reference: {reference}"));
+        }
+        if let Some(message) = &self.message {
+            lines.push(format!("Message: {message}"));
+        }
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum StatementError {
+    InvalidXml(String),
+    /// Not a camt.053 statement, with the reason
+    InvalidCamt(String),
+    MissingColumn(String),
+    /// A CSV statement without the date and amount columns
+    MissingMapping,
+    /// A CSV row that couldn't be parsed, with its 1-based line number and the reason
+    InvalidRow(usize, String),
+}
+impl std::fmt::Display for StatementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatementError::InvalidXml(e) => write!(f, "invalid XML: {e}"),
+            StatementError::InvalidCamt(reason) => {
+                write!(f, "invalid camt.053 statement: {reason}")
+            }
+            StatementError::MissingColumn(column) => {
+                write!(f, "the CSV header has no column named {column}")
+            }
+            StatementError::MissingMapping => {
+                f.write_str("CSV statements need the names of the date and amount columns")
+            }
+            StatementError::InvalidRow(line, reason) => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+impl std::error::Error for StatementError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    /// ISO 20022 camt.053 XML
+    Camt053,
+    Csv,
+}
+
+/// Which columns of a CSV statement hold which fields, by their header
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CsvMapping {
+    #[serde(default = "CsvMapping::default_delimiter")]
+    pub delimiter: char,
+    pub date_column: String,
+    /// A chrono format string, e.g. `%d.%m.%Y`
+    #[serde(default = "CsvMapping::default_date_format")]
+    pub date_format: String,
+    /// Signed, with either a decimal point or comma
+    pub amount_column: String,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub name_column: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub account_column: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub reference_column: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub message_column: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub archive_id_column: Option<String>,
+    /// Defaults to euros when missing
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub currency_column: Option<String>,
+}
+impl CsvMapping {
+    fn default_delimiter() -> char {
+        ';'
+    }
+    fn default_date_format() -> String {
+        "%d.%m.%Y".to_string()
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StatementForm {
+    pub format: StatementFormat,
+    pub statement: String,
+    /// Creates local events for transactions that don't pay any bill
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub create_events: bool,
+    /// Required for CSV statements
+    #[serde(flatten)]
+    pub mapping: Option<CsvMapping>,
+}
+impl StatementForm {
+    pub fn parse(&self) -> Result<Vec<Transaction>, StatementError> {
+        match (self.format, &self.mapping) {
+            (StatementFormat::Camt053, _) => parse_camt053(&self.statement),
+            (StatementFormat::Csv, Some(mapping)) => parse_csv(&self.statement, mapping),
+            (StatementFormat::Csv, None) => Err(StatementError::MissingMapping),
+        }
+    }
+}
+
+/// The outcome of importing a statement
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Reconciliation {
+    /// Bills marked as paid
+    pub paid: Vec<BillId>,
+    /// Transactions that didn't pay any bill
+    pub unmatched: usize,
+    /// Events created for the unmatched transactions, skipping ones imported before
+    pub events_created: usize,
+}
+impl std::fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bills marked as paid, {} transactions unmatched, {} events created",
+            self.paid.len(),
+            self.unmatched,
+            self.events_created
+        )
+    }
+}
+
+/// Parses an amount like `-1 234,56` into cents
+fn parse_signed_amount(amount: &str) -> Option<i64> {
+    let amount: String = amount
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\'')
+        .collect();
+    let (negative, amount) = match amount.strip_prefix('-') {
+        Some(amount) => (true, amount),
+        None => (false, amount.strip_prefix('+').unwrap_or(&amount)),
+    };
+    let amount = amount.replace(',', ".");
+    let (units, cents) = amount.split_once('.').unwrap_or((&amount, "0"));
+    if units.is_empty() || cents.is_empty() || cents.len() > 2 {
+        return None;
+    }
+    if !units
+        .chars()
+        .chain(cents.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let cents = units.parse::<i64>().ok()? * 100 + format!("{cents:0<2}").parse::<i64>().ok()?;
+    Some(if negative { -cents } else { cents })
+}
+
+/// The counterparty of a transaction, the creditor of payments out and the debtor of payments in.
+/// Newer versions of camt.053 wrap the name in a `Pty` element.
+fn counterparty(details: roxmltree::Node, debit: bool) -> (Option<String>, Option<String>) {
+    let (party, account) = if debit {
+        ("Cdtr", "CdtrAcct")
+    } else {
+        ("Dbtr", "DbtrAcct")
+    };
+    let name = text(details, &["RltdPties", party, "Nm"])
+        .or_else(|| text(details, &["RltdPties", party, "Pty", "Nm"]));
+    let account = text(details, &["RltdPties", account, "Id", "IBAN"]);
+    (name, account)
+}
+
+/// Parses the entries of an ISO 20022 camt.053 bank statement.
+/// Batch entries are split into their transaction details.
+pub fn parse_camt053(xml: &str) -> Result<Vec<Transaction>, StatementError> {
+    let invalid = |reason: &str| StatementError::InvalidCamt(reason.to_string());
+    let document = roxmltree::Document::parse(xml.trim())
+        .map_err(|e| StatementError::InvalidXml(e.to_string()))?;
+    let statement = find(document.root_element(), &["BkToCstmrStmt"])
+        .ok_or_else(|| invalid("missing BkToCstmrStmt"))?;
+
+    let mut transactions = vec![];
+    for entry in children(statement, "Stmt").flat_map(|stmt| children(stmt, "Ntry")) {
+        let debit = match text(entry, &["CdtDbtInd"]).as_deref() {
+            Some("DBIT") => true,
+            Some("CRDT") => false,
+            _ => return Err(invalid("entry without a valid CdtDbtInd")),
+        };
+        let booked_on = text(entry, &["BookgDt", "Dt"])
+            .or_else(|| text(entry, &["BookgDt", "DtTm"]))
+            .and_then(|date| chrono::NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok())
+            .ok_or_else(|| invalid("entry without a valid booking date"))?;
+        let entry_amount = find(entry, &["Amt"]).ok_or_else(|| invalid("entry without Amt"))?;
+        let entry_reference = text(entry, &["AcctSvcrRef"]);
+        let details: Vec<_> = children(entry, "NtryDtls")
+            .flat_map(|details| children(details, "TxDtls"))
+            .collect();
+
+        let transaction = |details: Option<roxmltree::Node>, index: usize| {
+            let amount = details
+                .and_then(|details| {
+                    find(details, &["AmtDtls", "TxAmt", "Amt"]).or_else(|| find(details, &["Amt"]))
+                })
+                .unwrap_or(entry_amount);
+            let amount_cents = amount
+                .text()
+                .and_then(parse_signed_amount)
+                .ok_or_else(|| invalid("invalid amount"))?;
+            let (counterparty_name, counterparty_account) = details
+                .map(|details| counterparty(details, debit))
+                .unwrap_or_default();
+            let archive_id = details
+                .and_then(|details| text(details, &["Refs", "AcctSvcrRef"]))
+                .or_else(|| entry_reference.clone().map(|id| format!("{id}-{index}")));
+            Ok(Transaction {
+                booked_on,
+                amount_cents: if debit { -amount_cents } else { amount_cents },
+                currency: amount
+                    .attribute("Ccy")
+                    .unwrap_or(DEFAULT_CURRENCY)
+                    .to_string(),
+                counterparty_name,
+                counterparty_account,
+                reference: details
+                    .and_then(|details| text(details, &["RmtInf", "Strd", "CdtrRefInf", "Ref"])),
+                message: details.and_then(|details| text(details, &["RmtInf", "Ustrd"])),
+                archive_id,
+            })
+        };
+        if details.is_empty() {
+            transactions.push(transaction(None, 0)?);
+        } else {
+            for (index, details) in details.into_iter().enumerate() {
+                transactions.push(transaction(Some(details), index)?);
+            }
+        }
+    }
+    Ok(transactions)
+}
+
+/// Splits a CSV line, supporting quoted fields with doubled quotes inside
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Parses a CSV statement with a header row according to the mapping
+pub fn parse_csv(csv: &str, mapping: &CsvMapping) -> Result<Vec<Transaction>, StatementError> {
+    let mut lines = csv
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(vec![]);
+    };
+    let header: Vec<String> = split_csv_line(header, mapping.delimiter)
+        .into_iter()
+        .map(|column| column.trim().to_string())
+        .collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name.trim())
+            .ok_or_else(|| StatementError::MissingColumn(name.to_string()))
+    };
+    let optional_column = |name: &Option<String>| name.as_deref().map(column).transpose();
+    let date = column(&mapping.date_column)?;
+    let amount = column(&mapping.amount_column)?;
+    let name = optional_column(&mapping.name_column)?;
+    let account = optional_column(&mapping.account_column)?;
+    let reference = optional_column(&mapping.reference_column)?;
+    let message = optional_column(&mapping.message_column)?;
+    let archive_id = optional_column(&mapping.archive_id_column)?;
+    let currency = optional_column(&mapping.currency_column)?;
+
+    lines
+        .map(|(index, line)| {
+            let line_number = index + 1;
+            let invalid =
+                |reason: &str| StatementError::InvalidRow(line_number, reason.to_string());
+            let fields = split_csv_line(line, mapping.delimiter);
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| fields.get(index))
+                    .map(|field| field.trim())
+                    .filter(|field| !field.is_empty())
+                    .map(str::to_string)
+            };
+            let booked_on = field(Some(date))
+                .and_then(|date| {
+                    chrono::NaiveDate::parse_from_str(&date, &mapping.date_format).ok()
+                })
+                .ok_or_else(|| invalid("invalid date"))?;
+            let amount_cents = field(Some(amount))
+                .and_then(|amount| parse_signed_amount(&amount))
+                .ok_or_else(|| invalid("invalid amount"))?;
+            Ok(Transaction {
+                booked_on,
+                amount_cents,
+                currency: field(currency)
+                    .map(|currency| currency.to_uppercase())
+                    .unwrap_or_else(|| DEFAULT_CURRENCY.to_string()),
+                counterparty_name: field(name),
+                counterparty_account: field(account),
+                reference: field(reference),
+                message: field(message),
+                archive_id: field(archive_id),
+            })
+        })
+        .collect()
+}
+
+/// Strips the formatting that doesn't change the meaning of a reference or an IBAN
+fn normalize(value: &str) -> String {
+    let compact: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+    compact.trim_start_matches('0').to_string()
+}
+
+impl Transaction {
+    /// Whether this is a payment of the bill: a payment out with the same amount, reference
+    /// and, if the statement has it, IBAN
+    pub fn pays(&self, bill: &Bill) -> bool {
+        let Some(reference) = self.reference.as_deref() else {
+            return false;
+        };
+        self.amount_cents < 0
+            && self.currency == bill.currency
+            && i64::from(bill.amount) == -self.amount_cents
+            && normalize(&bill.reference) == normalize(reference)
+            && self
+                .counterparty_account
+                .as_deref()
+                .is_none_or(|account| normalize(account) == normalize(&bill.payee_account_number))
+    }
+}
+
+/// Pairs payments out with the unpaid bills they pay.
+/// Every bill and transaction is matched at most once.
+pub fn reconcile<'a>(
+    transactions: &'a [Transaction],
+    bills: &[Bill],
+) -> Vec<(BillId, &'a Transaction)> {
+    let mut open: Vec<&Bill> = bills.iter().filter(|bill| bill.paid_at.is_none()).collect();
+    let mut matches = vec![];
+    for transaction in transactions {
+        if let Some(position) = open.iter().position(|bill| transaction.pays(bill)) {
+            matches.push((open.remove(position).id, transaction));
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMT053: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>1</MsgId><CreDtTm>2024-12-02T06:00:00</CreDtTm></GrpHdr>
+    <Stmt>
+      <Id>1</Id>
+      <Ntry>
+        <Amt Ccy="EUR">45.60</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-12-01</Dt></BookgDt>
+        <AcctSvcrRef>20241201ABC</AcctSvcrRef>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties>
+              <Cdtr><Nm>Oy Yritys Ab</Nm></Cdtr>
+              <CdtrAcct><Id><IBAN>FI7944052020036082</IBAN></Id></CdtrAcct>
+            </RltdPties>
+            <RmtInf>
+              <Strd><CdtrRefInf><Ref>RF09868516259619897</Ref></CdtrRefInf></Strd>
+            </RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">1000.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-12-01</Dt></BookgDt>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties><Dbtr><Nm>Työnantaja Oy</Nm></Dbtr></RltdPties>
+            <RmtInf><Ustrd>Palkka</Ustrd></RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    fn bill(id: BillId, amount: i32, reference: &str) -> Bill {
+        Bill {
+            id,
+            user_id: 1,
+            event_id: super::super::EventId::Local(id),
+            payee_account_number: "FI79 4405 2020 0360 82".to_string(),
+            amount,
+            currency: "EUR".to_string(),
+            reference: reference.to_string(),
+            paid_at: None,
+            payee_name: None,
+            payee_email: None,
+            payee_address: None,
+            payee_phone: None,
+            payee_bic: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(parse_signed_amount("-1 234,56"), Some(-123456));
+        assert_eq!(parse_signed_amount("+12.3"), Some(1230));
+        assert_eq!(parse_signed_amount("45"), Some(4500));
+        assert_eq!(parse_signed_amount("1.234"), None);
+        assert_eq!(parse_signed_amount("-"), None);
+    }
+
+    #[test]
+    fn camt053() {
+        let transactions = parse_camt053(CAMT053).unwrap();
+        assert_eq!(transactions.len(), 2);
+        let payment = &transactions[0];
+        assert_eq!(payment.amount_cents, -4560);
+        assert_eq!(
+            payment.booked_on,
+            chrono::NaiveDate::from_ymd_opt(2024, 12, 1).unwrap()
+        );
+        assert_eq!(payment.counterparty_name.as_deref(), Some("Oy Yritys Ab"));
+        assert_eq!(
+            payment.counterparty_account.as_deref(),
+            Some("FI7944052020036082")
+        );
+        assert_eq!(payment.reference.as_deref(), Some("RF09868516259619897"));
+        assert_eq!(payment.uid(), "transaction-20241201ABC-0@olmonoko");
+        let salary = &transactions[1];
+        assert_eq!(salary.amount_cents, 100000);
+        assert_eq!(salary.counterparty_name.as_deref(), Some("Työnantaja Oy"));
+        assert_eq!(salary.message.as_deref(), Some("Palkka"));
+    }
+
+    #[test]
+    fn csv() {
+        let csv = "Kirjauspäivä;Määrä;Saaja/Maksaja;Tilinumero;Viite;Viesti\n\
+            01.12.2024;-45,60;Oy Yritys Ab;FI79 4405 2020 0360 82;00000001357914;\n\
+            02.12.2024;\"1 000,00\";\"Työnantaja; Oy\";;;Palkka\n";
+        let mapping = CsvMapping {
+            delimiter: ';',
+            date_column: "Kirjauspäivä".to_string(),
+            date_format: "%d.%m.%Y".to_string(),
+            amount_column: "Määrä".to_string(),
+            name_column: Some("Saaja/Maksaja".to_string()),
+            account_column: Some("Tilinumero".to_string()),
+            reference_column: Some("Viite".to_string()),
+            message_column: Some("Viesti".to_string()),
+            archive_id_column: None,
+            currency_column: None,
+        };
+        let transactions = parse_csv(csv, &mapping).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].amount_cents, -4560);
+        assert_eq!(transactions[0].message, None);
+        assert_eq!(transactions[1].amount_cents, 100000);
+        assert_eq!(
+            transactions[1].counterparty_name.as_deref(),
+            Some("Työnantaja; Oy")
+        );
+
+        let bad_date = csv.replace("02.12.2024", "2024-12-02");
+        assert_eq!(
+            parse_csv(&bad_date, &mapping),
+            Err(StatementError::InvalidRow(3, "invalid date".to_string()))
+        );
+        let missing_column = CsvMapping {
+            amount_column: "Summa".to_string(),
+            ..mapping
+        };
+        assert_eq!(
+            parse_csv(csv, &missing_column),
+            Err(StatementError::MissingColumn("Summa".to_string()))
+        );
+    }
+
+    #[test]
+    fn reconciliation() {
+        let transactions = parse_camt053(CAMT053).unwrap();
+        let bills = vec![
+            bill(1, 4560, "1357914"),
+            bill(2, 4500, "RF09868516259619897"),
+            bill(3, 4560, "RF09 8685 1625 9619 897"),
+            bill(4, 4560, "RF09868516259619897"),
+        ];
+        let matches = reconcile(&transactions, &bills);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, 3);
+
+        let mut paid = bills.clone();
+        paid[2].paid_at = Some(chrono::Utc::now());
+        let matches = reconcile(&transactions, &paid);
+        assert_eq!(matches[0].0, 4);
+    }
+}
@@ -0,0 +1,223 @@
+/// Why a bill, or the bank barcode it was read from, was rejected
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum BillError {
+    UnsupportedBarcodeVersion(char),
+    /// Barcodes are always 54 digits long
+    InvalidBarcodeLength(usize),
+    NonNumericBarcode,
+    InvalidIban(String),
+    /// A Finnish national reference whose 7-3-1 check digit doesn't match
+    InvalidReference(String),
+    /// An international (ISO 11649) reference whose mod 97 checksum doesn't match
+    InvalidRfReference(String),
+    /// V5 barcodes can only hold numeric RF references of up to 21 digits
+    UnencodableReference(String),
+    InvalidDueDate,
+    MissingDueDate,
+    InvalidAmount,
+    MissingEvent,
+    /// Barcodes can only hold Finnish IBANs
+    UnencodableIban(String),
+    /// A malformed EPC QR code payload, with the reason
+    InvalidEpcQr(String),
+    /// A malformed or incomplete Finvoice document, with the reason
+    InvalidFinvoice(String),
+    UnsupportedCurrency(String),
+}
+impl std::fmt::Display for BillError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BillError::UnsupportedBarcodeVersion(version) => {
+                write!(
+                    f,
+                    "unsupported barcode version '{version}', only 4 and 5 are supported"
+                )
+            }
+            BillError::InvalidBarcodeLength(length) => {
+                write!(f, "a barcode has 54 digits, got {length}")
+            }
+            BillError::NonNumericBarcode => f.write_str("a barcode can only contain digits"),
+            BillError::InvalidIban(iban) => write!(f, "invalid IBAN {iban}: checksum mismatch"),
+            BillError::InvalidReference(reference) => {
+                write!(f, "invalid reference {reference}: check digit mismatch")
+            }
+            BillError::InvalidRfReference(reference) => {
+                write!(f, "invalid RF reference {reference}: checksum mismatch")
+            }
+            BillError::UnencodableReference(reference) => {
+                write!(f, "reference {reference} can't be encoded in a barcode")
+            }
+            BillError::InvalidDueDate => f.write_str("invalid due date"),
+            BillError::MissingDueDate => f.write_str("invoice is missing a due date"),
+            BillError::InvalidAmount => f.write_str("invalid amount"),
+            BillError::MissingEvent => f.write_str("a bill needs either a local or a remote event"),
+            BillError::UnencodableIban(iban) => {
+                write!(
+                    f,
+                    "only Finnish IBANs can be encoded in a barcode, got {iban}"
+                )
+            }
+            BillError::InvalidEpcQr(reason) => write!(f, "invalid EPC QR code: {reason}"),
+            BillError::InvalidFinvoice(reason) => write!(f, "invalid Finvoice: {reason}"),
+            BillError::UnsupportedCurrency(currency) => {
+                write!(f, "unsupported currency {currency}")
+            }
+        }
+    }
+}
+impl std::error::Error for BillError {}
+
+/// Remainder of a number written out as (alphanumeric) digits, letters counting as 10-35.
+/// Used by both IBANs and RF references.
+fn mod97(digits: &str) -> Option<u32> {
+    digits.chars().try_fold(0, |acc, c| {
+        let value = c.to_digit(36)?;
+        Some(if value < 10 {
+            (acc * 10 + value) % 97
+        } else {
+            (acc * 100 + value) % 97
+        })
+    })
+}
+
+/// Validates the mod 97 checksum of an IBAN of any country, e.g. `FI7944052020036082`
+pub fn validate_iban(iban: &str) -> Result<(), BillError> {
+    let invalid = || BillError::InvalidIban(iban.to_string());
+    let compact: String = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    let compact = compact.to_uppercase();
+    // checked first, as the rest slices the string by bytes
+    if !compact.chars().all(|c| c.is_ascii_alphanumeric())
+        || !(15..=34).contains(&compact.len())
+        || !compact[..2].chars().all(|c| c.is_ascii_alphabetic())
+        || !compact[2..4].chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let rearranged = format!("{}{}", &compact[4..], &compact[..4]);
+    match mod97(&rearranged) {
+        Some(1) => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// The 7-3-1 check digit of the base of a Finnish national reference
+pub fn reference_check_digit(base: &str) -> Option<u32> {
+    let sum = base
+        .chars()
+        .rev()
+        .zip([7, 3, 1].into_iter().cycle())
+        .try_fold(0, |sum, (c, weight)| Some(sum + c.to_digit(10)? * weight))?;
+    Some((10 - sum % 10) % 10)
+}
+
+/// Validates a Finnish national reference: 4-20 digits, the last of which is the 7-3-1 check digit
+pub fn validate_reference(reference: &str) -> Result<(), BillError> {
+    let invalid = || BillError::InvalidReference(reference.to_string());
+    let compact: String = reference.chars().filter(|c| !c.is_whitespace()).collect();
+    let compact = compact.trim_start_matches('0');
+    if !compact.chars().all(|c| c.is_ascii_digit()) || !(4..=20).contains(&compact.len()) {
+        return Err(invalid());
+    }
+    let (base, check) = compact.split_at(compact.len() - 1);
+    match (reference_check_digit(base), check.parse::<u32>()) {
+        (Some(expected), Ok(check)) if expected == check => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Validates an ISO 11649 creditor reference, e.g. `RF09868516259619897`
+pub fn validate_rf_reference(reference: &str) -> Result<(), BillError> {
+    let invalid = || BillError::InvalidRfReference(reference.to_string());
+    let compact: String = reference.chars().filter(|c| !c.is_whitespace()).collect();
+    let compact = compact.to_uppercase();
+    if !(5..=25).contains(&compact.len())
+        || !compact.starts_with("RF")
+        || !compact.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(invalid());
+    }
+    let rearranged = format!("{}{}", &compact[4..], &compact[..4]);
+    match mod97(&rearranged) {
+        Some(1) => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Validates the reference of a bill paid to the account.
+/// Other countries have their own reference formats, so only RF references and the
+/// references of Finnish accounts can be checked.
+pub fn validate_reference_for(iban: &str, reference: &str) -> Result<(), BillError> {
+    let is_finnish = iban.trim().to_uppercase().starts_with("FI");
+    if is_finnish || reference.trim().to_uppercase().starts_with("RF") {
+        return validate_any_reference(reference);
+    }
+    if reference.trim().is_empty() || reference.chars().count() > 35 {
+        return Err(BillError::InvalidReference(reference.to_string()));
+    }
+    Ok(())
+}
+
+/// Normalizes an ISO 4217 currency code, e.g. `sek` to `SEK`
+pub fn validate_currency(currency: &str) -> Result<String, BillError> {
+    let currency = currency.trim().to_uppercase();
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(BillError::UnsupportedCurrency(currency));
+    }
+    Ok(currency)
+}
+
+/// Validates either kind of reference accepted by Finnish banks
+pub fn validate_any_reference(reference: &str) -> Result<(), BillError> {
+    if reference.trim().to_uppercase().starts_with("RF") {
+        validate_rf_reference(reference)
+    } else {
+        validate_reference(reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ibans() {
+        assert_eq!(validate_iban("FI7944052020036082"), Ok(()));
+        assert_eq!(validate_iban("FI79 4405 2020 0360 82"), Ok(()));
+        assert_eq!(validate_iban("GB82WEST12345698765432"), Ok(()));
+        assert!(validate_iban("FI7944052020036083").is_err());
+        assert!(validate_iban("FI79").is_err());
+        assert!(validate_iban("Fé7944052020036082").is_err());
+        assert!(validate_iban("FI7é44052020036082").is_err());
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(reference_check_digit("135791"), Some(4));
+        assert_eq!(validate_reference("1357914"), Ok(()));
+        assert_eq!(validate_reference("00000000001357914"), Ok(()));
+        assert!(validate_reference("1357915").is_err());
+        assert!(validate_reference("12").is_err());
+        assert!(validate_reference("135791é").is_err());
+    }
+
+    #[test]
+    fn rf_references() {
+        assert_eq!(validate_rf_reference("RF09868516259619897"), Ok(()));
+        assert_eq!(validate_rf_reference("RF18 5390 0754 7034"), Ok(()));
+        assert!(validate_rf_reference("RF19539007547034").is_err());
+        assert!(validate_rf_reference("868516259619897").is_err());
+    }
+
+    #[test]
+    fn foreign_references() {
+        // Swedish OCR references aren't checked
+        assert_eq!(
+            validate_reference_for("SE4550000000058398257466", "123456"),
+            Ok(())
+        );
+        assert!(validate_reference_for("FI7944052020036082", "123456").is_err());
+        assert!(validate_reference_for("SE4550000000058398257466", "RF00123").is_err());
+        assert_eq!(validate_currency(" sek"), Ok("SEK".to_string()));
+        assert!(validate_currency("kr").is_err());
+    }
+}
@@ -32,6 +32,8 @@ pub struct RawLocalEvent {
     pub description: Option<String>,
     pub location: Option<String>,
     pub uid: String,
+    /// Set for events created by stopping a timer
+    pub timer_template_id: Option<LocalEventId>,
 }
 impl EventLike for RawLocalEvent {
     fn id(&self) -> EventId {
@@ -85,6 +87,7 @@ pub struct LocalEvent {
     pub description: Option<String>,
     pub location: Option<String>,
     pub uid: String,
+    pub timer_template_id: Option<LocalEventId>,
     // Attachments
     pub bill: Option<Bill>,
 }
@@ -106,6 +109,7 @@ impl From<RawLocalEvent> for LocalEvent {
             description: raw.description,
             location: raw.location,
             uid: raw.uid,
+            timer_template_id: raw.timer_template_id,
             bill: None,
         }
     }
@@ -128,6 +132,7 @@ impl From<(RawLocalEvent, Vec<String>, Option<Attendance>)> for LocalEvent {
             description: raw.description,
             location: raw.location,
             uid: raw.uid,
+            timer_template_id: raw.timer_template_id,
             bill: None,
         }
     }
@@ -170,7 +175,7 @@ impl
                     parts.push(desc.clone());
                 }
                 if let Some(bill) = bill.as_ref() {
-                    parts.push(bill.generate_description(Some(&raw)));
+                    parts.push(bill.generate_description());
                 }
                 Some(parts.join("\n\n"))
             } else {
@@ -195,7 +200,8 @@ impl
             description,
             location: raw.location,
             uid: raw.uid,
-            bill: bill.map(Bill::from),
+            timer_template_id: raw.timer_template_id,
+            bill,
         }
     }
 }
@@ -271,6 +277,9 @@ pub struct LocalEventForm {
     pub duration_s: Option<i32>,
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     pub location: Option<String>,
+    /// Creates the event in a local calendar shared with the user, instead of their own
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub calendar_owner_id: Option<UserId>,
 
     #[serde(flatten)]
     pub attendance: AttendanceForm,
@@ -308,7 +317,7 @@ impl<'a> From<FormWithUser<'a>> for NewLocalEvent {
         let uid = format!("{}:{}@olmonoko", uuid::Uuid::new_v4(), user.id);
 
         Self {
-            user_id: user.id,
+            user_id: form.calendar_owner_id.unwrap_or(user.id),
             starts_at,
             priority: form.priority,
             tags,
@@ -342,6 +351,7 @@ impl From<LocalEvent> for LocalEventForm {
             duration_m,
             duration_s,
             location: event.location,
+            calendar_owner_id: Some(event.user_id),
             attendance: AttendanceForm::default(),
         }
     }
@@ -387,6 +397,7 @@ pub mod tests {
             duration_m: Some(30),
             duration_s: Some(5),
             location: Some("Test".to_string()),
+            calendar_owner_id: None,
             attendance: AttendanceForm::default(),
         };
         let event = NewLocalEvent::from((form, &test_user()));
@@ -414,6 +425,7 @@ pub mod tests {
             duration_s: None,
             duration_m: None,
             location: Some("Test".to_string()),
+            calendar_owner_id: None,
             attendance: AttendanceForm::default(),
         };
         let event = NewLocalEvent::from((form, &test_user()));
@@ -439,6 +451,7 @@ pub mod tests {
             duration_m: None,
             duration_h: None,
             location: Some("Test".to_string()),
+            calendar_owner_id: None,
             attendance: AttendanceForm::default(),
         };
         let event = NewLocalEvent::from((form, &test_user()));
@@ -449,4 +462,26 @@ pub mod tests {
         assert_eq!(event.duration, Some(3600));
         assert_eq!(event.location, Some("Test".to_string()));
     }
+
+    #[test]
+    fn parse_form_shared_calendar() {
+        let form = LocalEventForm {
+            priority: None,
+            tags: None,
+            summary: "Test".to_string(),
+            description: None,
+            starts_at: "2021-01-01T00:00".to_string(),
+            starts_at_tz: Some(0),
+            all_day: false,
+            duration_s: None,
+            duration_m: None,
+            duration_h: None,
+            location: None,
+            calendar_owner_id: Some(2),
+            attendance: AttendanceForm::default(),
+        };
+        let event = NewLocalEvent::from((form, &test_user()));
+        assert_eq!(event.user_id, 2);
+        assert!(event.uid.ends_with(":1@olmonoko"));
+    }
 }
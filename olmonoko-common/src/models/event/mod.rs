@@ -11,6 +11,7 @@ use self::{local::LocalEvent, remote::RemoteEvent};
 
 use super::{
     attendance::{Attendance, AttendanceForm},
+    group::GroupId,
     user::UserId,
 };
 
@@ -24,11 +25,36 @@ pub struct SourceRemote {
     pub source_id: RemoteSourceId,
 }
 
+/// An event merged into a group calendar, attributed to the member who shared it
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct SourceGroup {
+    pub group_id: GroupId,
+    pub user_id: UserId,
+    /// Set if the member got the event from a remote source
+    pub source_id: Option<RemoteSourceId>,
+}
+
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "type")]
 pub enum EventSource {
     Local(SourceLocal),
     Remote(SourceRemote),
+    Group(SourceGroup),
+}
+impl EventSource {
+    /// Attributes an event shared by `user_id` to a group
+    pub fn into_group(self, group_id: GroupId, user_id: UserId) -> Self {
+        let source_id = match self {
+            EventSource::Local(_) => None,
+            EventSource::Remote(remote) => Some(remote.source_id),
+            EventSource::Group(group) => group.source_id,
+        };
+        EventSource::Group(SourceGroup {
+            group_id,
+            user_id,
+            source_id,
+        })
+    }
 }
 
 pub type EventId = i32;
@@ -0,0 +1,98 @@
+use chrono::Utc;
+
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+use crate::models::ics_source::deserialize_checkbox;
+use crate::utils::time::from_timestamp;
+
+use super::event::Priority;
+use super::user::UserId;
+
+pub type GroupId = i32;
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct RawGroup {
+    pub id: GroupId,
+    pub owner_id: UserId,
+    pub name: String,
+    pub created_at: i64,
+}
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Group {
+    pub id: GroupId,
+    pub owner_id: UserId,
+    pub name: String,
+    pub created_at: chrono::DateTime<Utc>,
+}
+impl From<RawGroup> for Group {
+    fn from(raw: RawGroup) -> Self {
+        Self {
+            id: raw.id,
+            owner_id: raw.owner_id,
+            name: raw.name,
+            created_at: from_timestamp(raw.created_at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GroupForm {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct RawGroupMember {
+    pub group_id: GroupId,
+    pub user_id: UserId,
+    pub colour: Option<String>,
+    pub share_events: bool,
+    pub min_priority: Option<Priority>,
+    pub max_priority: Option<Priority>,
+    pub created_at: i64,
+}
+/// A member of a group, with the email of the user for attribution
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GroupMember {
+    pub group_id: GroupId,
+    pub user_id: UserId,
+    pub email: String,
+    pub colour: Option<String>,
+    /// Only members who have opted in have their events merged into the group calendar
+    pub share_events: bool,
+    pub min_priority: Option<Priority>,
+    pub max_priority: Option<Priority>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+impl From<(RawGroupMember, String)> for GroupMember {
+    fn from((raw, email): (RawGroupMember, String)) -> Self {
+        Self {
+            group_id: raw.group_id,
+            user_id: raw.user_id,
+            email,
+            colour: raw.colour,
+            share_events: raw.share_events,
+            min_priority: raw.min_priority,
+            max_priority: raw.max_priority,
+            created_at: from_timestamp(raw.created_at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GroupMemberInviteForm {
+    pub email: String,
+}
+
+/// Settings a member controls about their own membership
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GroupMembershipForm {
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub colour: Option<String>,
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub share_events: bool,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub min_priority: Option<Priority>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub max_priority: Option<Priority>,
+}
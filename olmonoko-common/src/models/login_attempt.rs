@@ -0,0 +1,111 @@
+use chrono::Utc;
+
+use crate::utils::time::from_timestamp;
+
+use super::user::UserId;
+
+/// Failed attempts older than this, in seconds, no longer slow down logging in
+pub const FAILURE_WINDOW: i64 = 15 * 60;
+/// How long logging in is blocked after too many failures
+pub const LOCKOUT_DURATION: i64 = 15 * 60;
+/// The log of attempts is kept this long
+pub const LOGIN_ATTEMPT_RETENTION: i64 = 30 * 24 * 60 * 60;
+const BASE_DELAY: i64 = 1;
+const MAX_DELAY: i64 = 5 * 60;
+
+/// Throttling of one account
+pub const ACCOUNT_THROTTLE: ThrottlePolicy = ThrottlePolicy {
+    free_failures: 3,
+    lockout_failures: 10,
+};
+/// Throttling of one address, looser as people may share it
+pub const IP_THROTTLE: ThrottlePolicy = ThrottlePolicy {
+    free_failures: 10,
+    lockout_failures: 50,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    /// Failures allowed without any delay
+    pub free_failures: usize,
+    /// Failures after which logging in is locked for [`LOCKOUT_DURATION`]
+    pub lockout_failures: usize,
+}
+impl ThrottlePolicy {
+    /// The seconds to wait before the next attempt.
+    /// `failures` are the timestamps of the counted failures, oldest first.
+    /// The delay doubles with every failure past the free ones.
+    pub fn wait(&self, failures: &[i64], now: i64) -> i64 {
+        let Some(last) = failures.last() else {
+            return 0;
+        };
+        let count = failures.len();
+        let delay = if count >= self.lockout_failures {
+            LOCKOUT_DURATION
+        } else if count > self.free_failures {
+            let doublings = (count - self.free_failures - 1).min(16) as u32;
+            (BASE_DELAY << doublings).min(MAX_DELAY)
+        } else {
+            return 0;
+        };
+        (last + delay - now).max(0)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawLoginAttempt {
+    pub id: i64,
+    pub email: String,
+    pub user_id: Option<UserId>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LoginAttempt {
+    pub id: i64,
+    pub email: String,
+    /// None if no account has the email
+    pub user_id: Option<UserId>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub created_at: chrono::DateTime<Utc>,
+}
+impl From<RawLoginAttempt> for LoginAttempt {
+    fn from(raw: RawLoginAttempt) -> Self {
+        Self {
+            id: raw.id,
+            email: raw.email,
+            user_id: raw.user_id,
+            ip_address: raw.ip_address,
+            user_agent: raw.user_agent,
+            success: raw.success,
+            created_at: from_timestamp(raw.created_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_delay_and_lockout() {
+        let failures = |count: i64| (0..count).collect::<Vec<_>>();
+        let now = 100;
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(0), now), 0);
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(3), now), 0);
+        // the last failure was at 3, 4 and 6
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(4), 3), 1);
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(5), 4), 2);
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(7), 6), 8);
+        assert_eq!(ACCOUNT_THROTTLE.wait(&failures(7), now), 0);
+        assert_eq!(
+            ACCOUNT_THROTTLE.wait(&failures(10), now),
+            9 + LOCKOUT_DURATION - now
+        );
+    }
+}
@@ -1,9 +1,17 @@
 pub mod api_key;
 pub mod attendance;
+pub mod backup;
 pub mod bills;
 pub mod event;
+pub mod group;
 pub mod ics_source;
+pub mod login_attempt;
+pub mod oidc;
 pub mod public_link;
+pub mod report;
 pub mod session;
-pub mod user;
+pub mod share;
 pub mod timer;
+pub mod timesheet;
+pub mod two_factor;
+pub mod user;
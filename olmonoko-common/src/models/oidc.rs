@@ -0,0 +1,30 @@
+use crate::models::ics_source::deserialize_checkbox;
+
+/// The time the user has to log in at the identity provider, in seconds
+pub const OIDC_LOGIN_LIFETIME: i64 = 10 * 60;
+
+/// A login sent to the identity provider, looked up by the state it returns
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawOidcLogin {
+    pub state: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+    pub remember: bool,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OidcLoginQuery {
+    /// Keep the session across browser restarts
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub remember: bool,
+}
+
+/// What the identity provider redirects back with, either a code or an error
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OidcCallbackQuery {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
@@ -1,8 +1,11 @@
 use uuid::Uuid;
 
-use crate::{get_site_url, utils::time::from_timestamp};
+use crate::{
+    get_site_url,
+    utils::time::{from_timestamp, timestamp},
+};
 
-use super::{event::Priority, user::UserId};
+use super::{event::Priority, group::GroupId, user::UserId};
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
 pub struct RawPublicLink {
@@ -11,6 +14,21 @@ pub struct RawPublicLink {
     pub created_at: i64,
     pub min_priority: Option<Priority>,
     pub max_priority: Option<Priority>,
+    pub name: Option<String>,
+    pub expires_at: Option<i64>,
+    pub last_accessed_at: Option<i64>,
+    #[serde(default)]
+    pub fetch_count: i64,
+    #[serde(default)]
+    pub export_categories: bool,
+    #[serde(default)]
+    pub export_source: bool,
+    #[serde(default)]
+    pub export_attendance: bool,
+    #[serde(default)]
+    pub alarm_priority: Option<Priority>,
+    #[serde(default)]
+    pub group_id: Option<GroupId>,
 }
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PublicLink {
@@ -19,6 +37,21 @@ pub struct PublicLink {
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub min_priority: Option<Priority>,
     pub max_priority: Option<Priority>,
+    pub name: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expired: bool,
+    pub last_accessed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub fetch_count: i64,
+    /// Emit CATEGORIES from event tags
+    pub export_categories: bool,
+    /// Emit X-OLMONOKO-SOURCE with the name of the event's source
+    pub export_source: bool,
+    /// Emit STATUS and TRANSP based on planned attendance
+    pub export_attendance: bool,
+    /// Events with at least this priority get a VALARM
+    pub alarm_priority: Option<Priority>,
+    /// Set if the link exports a group calendar instead of the user's own
+    pub group_id: Option<GroupId>,
     pub url: String,
 }
 impl From<RawPublicLink> for PublicLink {
@@ -30,6 +63,18 @@ impl From<RawPublicLink> for PublicLink {
             created_at: from_timestamp(raw.created_at),
             min_priority: raw.min_priority,
             max_priority: raw.max_priority,
+            name: raw.name,
+            expires_at: raw.expires_at.map(from_timestamp),
+            expired: raw
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= timestamp()),
+            last_accessed_at: raw.last_accessed_at.map(from_timestamp),
+            fetch_count: raw.fetch_count,
+            export_categories: raw.export_categories,
+            export_source: raw.export_source,
+            export_attendance: raw.export_attendance,
+            alarm_priority: raw.alarm_priority,
+            group_id: raw.group_id,
             url: format!("{}/api/export/{}.ics", site_url, raw.id),
         }
     }
@@ -0,0 +1,376 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::Datelike;
+use chrono_tz::Tz;
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+use crate::models::bills::{BillId, BillWithEvent};
+use crate::models::event::local::{LocalEvent, LocalEventId};
+use crate::utils::time::from_date_form;
+
+/// Tags of events created by stopping a timer
+pub const TIMER_TAG: &str = "olmonoko::timer";
+/// Internal tags like [`TIMER_TAG`] are left out of reports
+pub(crate) const INTERNAL_TAG_PREFIX: &str = "olmonoko::";
+const UNTAGGED: &str = "(untagged)";
+const UNKNOWN_TEMPLATE: &str = "(unknown template)";
+const NO_DUE_DATE: &str = "(no due date)";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillGrouping {
+    /// By the month of the due date
+    #[default]
+    Month,
+    Payee,
+    /// By the tags of the event the bill is due on, a bill with many tags counts towards each
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeGrouping {
+    /// By the ISO week the timer was started on
+    #[default]
+    Week,
+    /// By the tags of the event, an event with many tags counts towards each
+    Tag,
+    /// By the event the timer was started from
+    Template,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ReportQuery {
+    /// Dates in the user's timezone, both inclusive
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub from: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub bills_by: BillGrouping,
+    #[serde(default)]
+    pub time_by: TimeGrouping,
+}
+impl ReportQuery {
+    /// Parses the date bounds as timestamps in the given timezone.
+    /// The upper bound is inclusive, so it's moved to the start of the next day.
+    pub fn range(&self, tz_offset: i8) -> Result<(Option<i64>, Option<i64>), InvalidDate> {
+        let parse = |date: &Option<String>| {
+            date.as_deref()
+                .map(|date| {
+                    from_date_form(date, tz_offset)
+                        .map(|dt| dt.timestamp())
+                        .ok_or_else(|| InvalidDate(date.to_string()))
+                })
+                .transpose()
+        };
+        Ok((
+            parse(&self.from)?,
+            parse(&self.to)?.map(|ts| ts + 24 * 60 * 60),
+        ))
+    }
+}
+
+/// A date of a query that isn't in the `YYYY-MM-DD` format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidDate(pub String);
+impl std::fmt::Display for InvalidDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid date: {}", self.0)
+    }
+}
+impl std::error::Error for InvalidDate {}
+
+/// Bill totals of a group in one currency
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BillReportRow {
+    pub key: String,
+    pub currency: String,
+    pub bills: usize,
+    /// In the minor unit of the currency
+    pub paid: i64,
+    pub unpaid: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TimeReportRow {
+    pub key: String,
+    pub entries: usize,
+    pub seconds: i64,
+    /// Rounded to two decimals
+    pub hours: f64,
+}
+
+pub(crate) fn user_tags(tags: &[String]) -> Vec<String> {
+    let tags: Vec<String> = tags
+        .iter()
+        .filter(|tag| !tag.is_empty() && !tag.starts_with(INTERNAL_TAG_PREFIX))
+        .cloned()
+        .collect();
+    if tags.is_empty() {
+        vec![UNTAGGED.to_string()]
+    } else {
+        tags
+    }
+}
+
+/// Totals of the bills, grouped by the key and currency in that order.
+/// `tags` maps the ids of the bills to the tags of the events they're due on.
+pub fn bill_report(
+    bills: &[BillWithEvent],
+    by: BillGrouping,
+    tz: &Tz,
+    tags: &HashMap<BillId, Vec<String>>,
+) -> Vec<BillReportRow> {
+    let mut rows: BTreeMap<(String, String), BillReportRow> = BTreeMap::new();
+    for BillWithEvent { bill, due_at, .. } in bills {
+        let keys = match by {
+            BillGrouping::Month => vec![due_at
+                .map(|due_at| due_at.with_timezone(tz).format("%Y-%m").to_string())
+                .unwrap_or_else(|| NO_DUE_DATE.to_string())],
+            BillGrouping::Payee => vec![bill
+                .payee_name
+                .clone()
+                .unwrap_or_else(|| bill.payee_account_number.clone())],
+            BillGrouping::Tag => user_tags(tags.get(&bill.id).map_or(&[], Vec::as_slice)),
+        };
+        for key in keys {
+            let row = rows
+                .entry((key.clone(), bill.currency.clone()))
+                .or_insert_with(|| BillReportRow {
+                    key,
+                    currency: bill.currency.clone(),
+                    bills: 0,
+                    paid: 0,
+                    unpaid: 0,
+                });
+            row.bills += 1;
+            if bill.paid_at.is_some() {
+                row.paid += i64::from(bill.amount);
+            } else {
+                row.unpaid += i64::from(bill.amount);
+            }
+        }
+    }
+    rows.into_values().collect()
+}
+
+/// Time tracked with timers, from the events created by stopping them.
+/// `template_names` maps the ids of the templates to their summaries.
+pub fn time_report(
+    events: &[LocalEvent],
+    by: TimeGrouping,
+    tz: &Tz,
+    template_names: &HashMap<LocalEventId, String>,
+) -> Vec<TimeReportRow> {
+    let mut rows: BTreeMap<String, (usize, i64)> = BTreeMap::new();
+    for event in events {
+        if !event.tags.iter().any(|tag| tag == TIMER_TAG) {
+            continue;
+        }
+        let Some(duration) = event.duration else {
+            continue;
+        };
+        let keys = match by {
+            TimeGrouping::Week => {
+                let week = event.starts_at.with_timezone(tz).iso_week();
+                vec![format!("{}-W{:02}", week.year(), week.week())]
+            }
+            TimeGrouping::Tag => user_tags(&event.tags),
+            TimeGrouping::Template => vec![event
+                .timer_template_id
+                .and_then(|id| template_names.get(&id).cloned())
+                .unwrap_or_else(|| UNKNOWN_TEMPLATE.to_string())],
+        };
+        for key in keys {
+            let row = rows.entry(key).or_default();
+            row.0 += 1;
+            row.1 += i64::from(duration);
+        }
+    }
+    rows.into_iter()
+        .map(|(key, (entries, seconds))| TimeReportRow {
+            key,
+            entries,
+            seconds,
+            hours: (seconds as f64 / 36.0).round() / 100.0,
+        })
+        .collect()
+}
+
+/// Quotes the field if needed. Text that a spreadsheet would run as a formula is prefixed with `'`,
+/// numbers like negative amounts are left as they are.
+fn csv_field(field: &str) -> String {
+    let field =
+        if field.starts_with(['=', '+', '-', '@', '\t', '\r']) && field.parse::<f64>().is_err() {
+            format!("'{field}")
+        } else {
+            field.to_string()
+        };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+pub(crate) fn to_csv(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut csv = header.join(",");
+    csv.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Amounts are in major units with a decimal point, e.g. `12.30`
+pub fn bill_report_csv(rows: &[BillReportRow]) -> String {
+    let major = |amount: i64| {
+        let sign = if amount < 0 { "-" } else { "" };
+        format!("{sign}{}.{:02}", amount.abs() / 100, amount.abs() % 100)
+    };
+    to_csv(
+        &["group", "currency", "bills", "paid", "unpaid", "total"],
+        rows.iter().map(|row| {
+            vec![
+                row.key.clone(),
+                row.currency.clone(),
+                row.bills.to_string(),
+                major(row.paid),
+                major(row.unpaid),
+                major(row.paid + row.unpaid),
+            ]
+        }),
+    )
+}
+
+pub fn time_report_csv(rows: &[TimeReportRow]) -> String {
+    to_csv(
+        &["group", "entries", "seconds", "hours"],
+        rows.iter().map(|row| {
+            vec![
+                row.key.clone(),
+                row.entries.to_string(),
+                row.seconds.to_string(),
+                format!("{:.2}", row.hours),
+            ]
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::event::local::RawLocalEvent;
+
+    fn timer_event(
+        starts_at: i64,
+        duration: i32,
+        tags: &str,
+        template: LocalEventId,
+    ) -> LocalEvent {
+        let raw = RawLocalEvent {
+            id: 1,
+            user_id: 1,
+            created_at: starts_at,
+            updated_at: starts_at,
+            priority: None,
+            rrule: None,
+            starts_at,
+            all_day: false,
+            duration: Some(duration),
+            summary: "Work".to_string(),
+            description: None,
+            location: None,
+            uid: "olmonoko::timer::1".to_string(),
+            timer_template_id: Some(template),
+        };
+        LocalEvent::from((raw, tags))
+    }
+
+    #[test]
+    fn time_by_week_tag_and_template() {
+        // Monday 2024-12-02 00:00 in Helsinki is still Sunday of week 48 in UTC
+        let events = vec![
+            timer_event(1733090400, 3600, "olmonoko::timer,client", 10),
+            timer_event(1733610600, 1800, "olmonoko::timer", 11),
+            timer_event(1733610600, 900, "not-a-timer", 10),
+        ];
+        let tz: Tz = "Europe/Helsinki".parse().unwrap();
+        let by_week = time_report(&events, TimeGrouping::Week, &tz, &HashMap::new());
+        assert_eq!(by_week.len(), 1);
+        assert_eq!(by_week[0].key, "2024-W49");
+        assert_eq!(by_week[0].seconds, 5400);
+        assert_eq!(by_week[0].hours, 1.5);
+        let by_week_utc = time_report(&events, TimeGrouping::Week, &Tz::UTC, &HashMap::new());
+        assert_eq!(by_week_utc.len(), 2);
+
+        let by_tag = time_report(&events, TimeGrouping::Tag, &tz, &HashMap::new());
+        let keys: Vec<_> = by_tag.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(keys, vec![UNTAGGED, "client"]);
+
+        let names = HashMap::from([(10, "Project".to_string())]);
+        let by_template = time_report(&events, TimeGrouping::Template, &tz, &names);
+        let keys: Vec<_> = by_template.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(keys, vec![UNKNOWN_TEMPLATE, "Project"]);
+    }
+
+    #[test]
+    fn invalid_dates_are_refused() {
+        let query = |from: &str, to: &str| ReportQuery {
+            from: Some(from.to_string()),
+            to: Some(to.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query("2024-12-01", "2024-12-01").range(2),
+            Ok((Some(1733004000), Some(1733090400)))
+        );
+        assert_eq!(
+            query("2024-12-01", "2024-13-01").range(2),
+            Err(InvalidDate("2024-13-01".to_string()))
+        );
+        assert_eq!(
+            query("1.12.2024", "2024-12-01").range(2),
+            Err(InvalidDate("1.12.2024".to_string()))
+        );
+        assert_eq!(ReportQuery::default().range(2), Ok((None, None)));
+    }
+
+    #[test]
+    fn csv_escaping() {
+        let rows = vec![BillReportRow {
+            key: "Oy \"Yritys\", Ab".to_string(),
+            currency: "EUR".to_string(),
+            bills: 2,
+            paid: 1230,
+            unpaid: 5,
+        }];
+        assert_eq!(
+            bill_report_csv(&rows),
+            "group,currency,bills,paid,unpaid,total\r\n\"Oy \"\"Yritys\"\", Ab\",EUR,2,12.30,0.05,12.35\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_formulas_are_defused() {
+        let rows = vec![BillReportRow {
+            key: "=HYPERLINK(\"http://example.com\")".to_string(),
+            currency: "@SUM(A1)".to_string(),
+            bills: 1,
+            paid: -1230,
+            unpaid: 0,
+        }];
+        assert_eq!(
+            bill_report_csv(&rows),
+            "group,currency,bills,paid,unpaid,total\r\n\"'=HYPERLINK(\"\"http://example.com\"\")\",'@SUM(A1),1,-12.30,0.00,-12.30\r\n"
+        );
+        assert_eq!(csv_field("+cmd"), "'+cmd");
+        assert_eq!(csv_field("-cmd"), "'-cmd");
+        assert_eq!(csv_field("\tcmd"), "'\tcmd");
+        assert_eq!(csv_field("a-b"), "a-b");
+    }
+}
@@ -4,27 +4,71 @@ use crate::utils::time::from_timestamp;
 
 use super::user::UserId;
 
+pub type SessionHandle = Uuid;
+
+/// Sessions expire after being unused for this long, in seconds
+pub const SESSION_IDLE_TIMEOUT: i64 = 24 * 60 * 60;
+/// ...or this long if the user asked to be remembered
+pub const REMEMBERED_SESSION_IDLE_TIMEOUT: i64 = 30 * 24 * 60 * 60;
+/// No session outlives this, however active
+pub const SESSION_MAX_AGE: i64 = 90 * 24 * 60 * 60;
+/// Activity is recorded at most this often to spare the database
+const SESSION_RENEW_INTERVAL: i64 = 60;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SessionRaw {
     pub id: String,
     pub user_id: UserId,
     pub expires_at: i64,
     pub created_at: i64,
+    pub handle: SessionHandle,
+    pub last_seen_at: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub remember: bool,
+}
+impl SessionRaw {
+    /// The expiry of a session used at `now`
+    pub fn expiry(created_at: i64, remember: bool, now: i64) -> i64 {
+        let idle_timeout = if remember {
+            REMEMBERED_SESSION_IDLE_TIMEOUT
+        } else {
+            SESSION_IDLE_TIMEOUT
+        };
+        (now + idle_timeout).min(created_at + SESSION_MAX_AGE)
+    }
+
+    pub fn needs_renewal(&self, now: i64) -> bool {
+        now - self.last_seen_at >= SESSION_RENEW_INTERVAL
+    }
 }
+
+/// A session as shown to its user, without the secret id
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Session {
-    pub id: Uuid,
+    pub handle: SessionHandle,
     pub user_id: UserId,
     pub expires_at: chrono::DateTime<chrono::Utc>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub remember: bool,
+    /// The session of the request listing the sessions
+    pub current: bool,
 }
-impl From<SessionRaw> for Session {
-    fn from(raw: SessionRaw) -> Self {
+impl From<(SessionRaw, &str)> for Session {
+    fn from((raw, current_id): (SessionRaw, &str)) -> Self {
         Self {
-            id: Uuid::parse_str(&raw.id).unwrap(),
+            handle: raw.handle,
             user_id: raw.user_id,
             expires_at: from_timestamp(raw.expires_at),
             created_at: from_timestamp(raw.created_at),
+            last_seen_at: from_timestamp(raw.last_seen_at),
+            user_agent: raw.user_agent,
+            ip_address: raw.ip_address,
+            remember: raw.remember,
+            current: raw.id == current_id,
         }
     }
 }
@@ -34,4 +78,22 @@ pub struct NewSession {
     pub id: String,
     pub user_id: UserId,
     pub expires_at: i64,
+    pub created_at: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub remember: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliding_expiry() {
+        let day = 24 * 60 * 60;
+        assert_eq!(SessionRaw::expiry(0, false, day), 2 * day);
+        assert_eq!(SessionRaw::expiry(0, true, day), 31 * day);
+        // renewal can't extend a session past its maximum age
+        assert_eq!(SessionRaw::expiry(0, true, 80 * day), SESSION_MAX_AGE);
+    }
 }
@@ -0,0 +1,96 @@
+use chrono::Utc;
+
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+use crate::utils::time::from_timestamp;
+
+use super::event::remote::RemoteSourceId;
+use super::user::UserId;
+
+pub type ShareId = i32;
+
+const SHARE_PERMISSION_READ: &str = "read";
+const SHARE_PERMISSION_WRITE: &str = "write";
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SharePermission {
+    Read,
+    /// Allows creating, editing and deleting events in a local calendar
+    Write,
+}
+impl TryFrom<&str> for SharePermission {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            SHARE_PERMISSION_READ => Ok(Self::Read),
+            SHARE_PERMISSION_WRITE => Ok(Self::Write),
+            _ => Err("Not a valid SharePermission"),
+        }
+    }
+}
+impl std::fmt::Display for SharePermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SharePermission::Read => f.write_str(SHARE_PERMISSION_READ),
+            SharePermission::Write => f.write_str(SHARE_PERMISSION_WRITE),
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct RawShare {
+    pub id: ShareId,
+    pub owner_id: UserId,
+    pub grantee_id: UserId,
+    pub ics_source_id: Option<RemoteSourceId>,
+    pub permission: String,
+    pub created_at: i64,
+}
+/// A grant from `owner_id` to `grantee_id`, either for a private source or for the owner's local calendar
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Share {
+    pub id: ShareId,
+    pub owner_id: UserId,
+    pub owner_email: String,
+    pub grantee_id: UserId,
+    pub grantee_email: String,
+    /// `None` for the owner's local calendar
+    pub ics_source_id: Option<RemoteSourceId>,
+    pub ics_source_name: Option<String>,
+    pub permission: SharePermission,
+    pub created_at: chrono::DateTime<Utc>,
+}
+/// The share along with the emails of both parties and the name of the shared source
+pub type RawShareWithNames = (RawShare, String, String, Option<String>);
+impl TryFrom<RawShareWithNames> for Share {
+    type Error = &'static str;
+
+    fn try_from(
+        (raw, owner_email, grantee_email, ics_source_name): RawShareWithNames,
+    ) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: raw.id,
+            owner_id: raw.owner_id,
+            owner_email,
+            grantee_id: raw.grantee_id,
+            grantee_email,
+            ics_source_id: raw.ics_source_id,
+            ics_source_name,
+            permission: SharePermission::try_from(raw.permission.as_str())?,
+            created_at: from_timestamp(raw.created_at),
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ShareForm {
+    pub email: String,
+    /// Empty for sharing the local calendar
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub ics_source_id: Option<RemoteSourceId>,
+    pub permission: SharePermission,
+}
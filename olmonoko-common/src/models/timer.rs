@@ -4,10 +4,12 @@ use uuid::Uuid;
 use serde_with::As;
 use serde_with::NoneAsEmptyString;
 
-use crate::utils::time::from_timestamp;
-use crate::utils::time::timestamp;
 use super::event::local::LocalEventId;
+use super::ics_source::deserialize_checkbox;
 use super::user::UserId;
+use crate::utils::time::from_timestamp;
+use crate::utils::time::timestamp;
+use crate::utils::time::try_from_form;
 
 pub type TimerId = Uuid;
 
@@ -22,20 +24,69 @@ pub struct RawTimer {
     pub template: LocalEventId,
 
     pub created_at: i64,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct RawTimerSegment {
+    pub id: i32,
+    pub timer_id: TimerId,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+}
+
+/// A stretch of time the timer was running for
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TimerSegment {
+    pub started_at: chrono::DateTime<Utc>,
+    /// None while the timer is running
+    pub ended_at: Option<chrono::DateTime<Utc>>,
+}
+impl From<RawTimerSegment> for TimerSegment {
+    fn from(raw: RawTimerSegment) -> Self {
+        Self {
+            started_at: from_timestamp(raw.started_at),
+            ended_at: raw.ended_at.map(from_timestamp),
+        }
+    }
+}
+impl TimerSegment {
+    /// In seconds, up to `now` for the running segment
+    pub fn duration(&self, now: i64) -> i64 {
+        let ends_at = self.ended_at.map_or(now, |ended_at| ended_at.timestamp());
+        (ends_at - self.started_at.timestamp()).max(0)
+    }
 }
 
-impl From<RawTimer> for Timer {
-    fn from(raw: RawTimer) -> Self {
+impl From<(RawTimer, Vec<RawTimerSegment>)> for Timer {
+    fn from((raw, segments): (RawTimer, Vec<RawTimerSegment>)) -> Self {
+        let segments: Vec<TimerSegment> = segments.into_iter().map(TimerSegment::from).collect();
+        let running = segments.iter().any(|segment| segment.ended_at.is_none());
+        let now = timestamp();
+        let finished: i64 = segments
+            .iter()
+            .filter(|segment| segment.ended_at.is_some())
+            .map(|segment| segment.duration(now))
+            .sum();
+        let running_since = segments
+            .iter()
+            .find(|segment| segment.ended_at.is_none())
+            .map(|segment| segment.started_at);
         Timer {
             id: raw.id,
             user_id: raw.user_id,
+            name: raw.name,
             summary: raw.summary,
             details: raw.details,
             location: raw.location,
 
             template: raw.template,
 
-            created_at: from_timestamp(raw.created_at)
+            created_at: from_timestamp(raw.created_at),
+            running,
+            running_since,
+            finished_seconds: finished,
+            segments,
         }
     }
 }
@@ -44,6 +95,7 @@ impl From<RawTimer> for Timer {
 pub struct Timer {
     pub id: TimerId,
     pub user_id: UserId,
+    pub name: Option<String>,
     pub summary: Option<String>,
     pub details: Option<String>,
     pub location: Option<String>,
@@ -51,28 +103,56 @@ pub struct Timer {
     pub template: LocalEventId,
 
     pub created_at: chrono::DateTime<Utc>,
+    /// False while paused
+    pub running: bool,
+    /// The start of the running segment
+    pub running_since: Option<chrono::DateTime<Utc>>,
+    /// Seconds in the segments before the running one
+    pub finished_seconds: i64,
+    pub segments: Vec<TimerSegment>,
+}
+impl Timer {
+    /// The start and duration in seconds of the events to create when the timer is stopped at `now`,
+    /// either one per segment or one spanning from the first start with the summed duration
+    pub fn entries(&self, split: bool, now: i64) -> Vec<(i64, i64)> {
+        let segments = self
+            .segments
+            .iter()
+            .map(|segment| (segment.started_at.timestamp(), segment.duration(now)));
+        if split {
+            return segments.collect();
+        }
+        let starts_at = self
+            .segments
+            .first()
+            .map_or(self.created_at.timestamp(), |segment| {
+                segment.started_at.timestamp()
+            });
+        vec![(starts_at, segments.map(|(_, duration)| duration).sum())]
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct NewTimer {
+    pub name: Option<String>,
     pub summary: Option<String>,
     pub details: Option<String>,
     pub location: Option<String>,
 
     pub template: LocalEventId,
-    pub created_at: i64
+    pub created_at: i64,
 }
 
 impl From<TimerForm> for NewTimer {
-
     fn from(form: TimerForm) -> Self {
         Self {
+            name: form.name,
             summary: form.summary,
             details: form.details,
             location: form.location,
 
             template: form.template,
-            created_at: timestamp()
+            created_at: timestamp(),
         }
     }
 }
@@ -83,13 +163,14 @@ impl From<Timer> for TimerForm {
         Self {
             id: Some(timer.id),
 
+            name: timer.name,
             summary: timer.summary,
             details: timer.details,
             location: timer.location,
 
             template: timer.template,
 
-            created_at
+            created_at,
         }
     }
 }
@@ -99,6 +180,8 @@ pub struct TimerForm {
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     pub id: Option<TimerId>,
 
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub name: Option<String>,
     #[serde(default, with = "As::<NoneAsEmptyString>")]
     pub summary: Option<String>,
     #[serde(default, with = "As::<NoneAsEmptyString>")]
@@ -111,3 +194,68 @@ pub struct TimerForm {
     #[serde(skip_deserializing)]
     pub created_at: Option<String>,
 }
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct TimerStopForm {
+    /// Create an event for every segment instead of one with the summed duration
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub split: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TimerStartForm {
+    /// A unix timestamp, or a `datetime-local` value in the user's timezone
+    pub starts_at: String,
+}
+impl TimerStartForm {
+    pub fn parse(&self, tz_offset: i8) -> Option<i64> {
+        let starts_at = self.starts_at.trim();
+        match starts_at.parse::<i64>() {
+            Ok(timestamp) => Some(timestamp),
+            Err(_) => try_from_form(starts_at, tz_offset).map(|dt| dt.timestamp()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let raw = RawTimer {
+            id: Uuid::nil(),
+            user_id: 1,
+            summary: None,
+            details: None,
+            location: None,
+            template: 1,
+            created_at: 1000,
+            name: None,
+        };
+        let segment = |id, started_at, ended_at| RawTimerSegment {
+            id,
+            timer_id: Uuid::nil(),
+            started_at,
+            ended_at,
+        };
+        let timer = Timer::from((
+            raw,
+            vec![segment(1, 1000, Some(1600)), segment(2, 2000, None)],
+        ));
+        assert!(timer.running);
+        assert_eq!(timer.finished_seconds, 600);
+        assert_eq!(timer.entries(false, 2300), vec![(1000, 900)]);
+        assert_eq!(timer.entries(true, 2300), vec![(1000, 600), (2000, 300)]);
+    }
+
+    #[test]
+    fn start_form() {
+        let form = |starts_at: &str| TimerStartForm {
+            starts_at: starts_at.to_string(),
+        };
+        assert_eq!(form("1733000000").parse(2), Some(1733000000));
+        assert_eq!(form("2024-12-01T12:00").parse(2), Some(1733047200));
+        assert_eq!(form("noon").parse(2), None);
+    }
+}
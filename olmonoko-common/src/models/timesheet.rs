@@ -0,0 +1,288 @@
+use chrono_tz::Tz;
+use serde_with::As;
+use serde_with::NoneAsEmptyString;
+
+use super::event::local::{LocalEvent, LocalEventId};
+use super::ics_source::deserialize_checkbox;
+use super::report::{to_csv, user_tags, InvalidDate, ReportQuery, INTERNAL_TAG_PREFIX, TIMER_TAG};
+
+/// Tags like `tw::project::Name` carry the Teamwork project of imported time logs
+const TEAMWORK_PROJECT_TAG_PREFIX: &str = "tw::project::";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct TimesheetQuery {
+    /// Dates in the user's timezone, both inclusive
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub from: Option<String>,
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub to: Option<String>,
+    /// Durations are rounded to a multiple of this many minutes, not at all if unset
+    #[serde(default, with = "As::<NoneAsEmptyString>")]
+    pub round_to: Option<u32>,
+    #[serde(default)]
+    pub rounding: Rounding,
+    /// Repeat the entries under each of their tags, sorted by tag
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub by_tag: bool,
+}
+impl TimesheetQuery {
+    pub fn range(&self, tz_offset: i8) -> Result<(Option<i64>, Option<i64>), InvalidDate> {
+        ReportQuery {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            ..Default::default()
+        }
+        .range(tz_offset)
+    }
+
+    /// Rounds a duration in seconds according to the query
+    pub fn round(&self, seconds: i64) -> i64 {
+        let step = match self.round_to {
+            Some(minutes) if minutes > 0 => i64::from(minutes) * 60,
+            _ => return seconds,
+        };
+        let steps = match self.rounding {
+            Rounding::Nearest => (seconds + step / 2) / step,
+            Rounding::Up => (seconds + step - 1) / step,
+            Rounding::Down => seconds / step,
+        };
+        steps * step
+    }
+}
+
+/// A timer-generated event, with its times in the user's timezone
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TimesheetRow {
+    /// The tag the row is listed under when grouping by tag
+    pub group: Option<String>,
+    pub event_id: LocalEventId,
+    pub starts_at: chrono::DateTime<Tz>,
+    /// The start moved forward by the rounded duration
+    pub ends_at: chrono::DateTime<Tz>,
+    /// Rounded, in seconds
+    pub seconds: i64,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    /// Without the internal tags
+    pub tags: Vec<String>,
+}
+impl TimesheetRow {
+    fn hours(&self) -> String {
+        format!("{:.2}", self.seconds as f64 / 3600.0)
+    }
+}
+
+/// The entries of the events created by stopping timers, in the order they were started
+pub fn timesheet(events: &[LocalEvent], query: &TimesheetQuery, tz: &Tz) -> Vec<TimesheetRow> {
+    let mut rows = Vec::new();
+    for event in events {
+        if !event.tags.iter().any(|tag| tag == TIMER_TAG) {
+            continue;
+        }
+        let Some(duration) = event.duration else {
+            continue;
+        };
+        let seconds = query.round(i64::from(duration));
+        let starts_at = event.starts_at.with_timezone(tz);
+        let row = TimesheetRow {
+            group: None,
+            event_id: event.id,
+            starts_at,
+            ends_at: starts_at + chrono::Duration::seconds(seconds),
+            seconds,
+            summary: event.summary.clone(),
+            description: event.description.clone(),
+            location: event.location.clone(),
+            tags: event
+                .tags
+                .iter()
+                .filter(|tag| !tag.is_empty() && !tag.starts_with(INTERNAL_TAG_PREFIX))
+                .cloned()
+                .collect(),
+        };
+        if query.by_tag {
+            for tag in user_tags(&event.tags) {
+                rows.push(TimesheetRow {
+                    group: Some(tag),
+                    ..row.clone()
+                });
+            }
+        } else {
+            rows.push(row);
+        }
+    }
+    rows.sort_by(|a, b| {
+        (&a.group, a.starts_at, a.event_id).cmp(&(&b.group, b.starts_at, b.event_id))
+    });
+    rows
+}
+
+pub fn timesheet_csv(rows: &[TimesheetRow], by_tag: bool) -> String {
+    let mut header = vec![
+        "date", "start", "end", "duration", "hours", "summary", "tags", "location",
+    ];
+    if by_tag {
+        header.insert(0, "tag");
+    }
+    to_csv(
+        &header,
+        rows.iter().map(|row| {
+            let mut fields = vec![
+                row.starts_at.format("%Y-%m-%d").to_string(),
+                row.starts_at.format("%H:%M").to_string(),
+                row.ends_at.format("%H:%M").to_string(),
+                format!("{}:{:02}", row.seconds / 3600, row.seconds % 3600 / 60),
+                row.hours(),
+                row.summary.clone(),
+                row.tags.join(","),
+                row.location.clone().unwrap_or_default(),
+            ];
+            if by_tag {
+                fields.insert(0, row.group.clone().unwrap_or_default());
+            }
+            fields
+        }),
+    )
+}
+
+/// In the format of Teamwork's time log export, which `wip_tools/teamwork_to_olmonoko.py` imports.
+/// The project comes from a `tw::project::` tag, or the summary when there's none.
+pub fn teamwork_csv(rows: &[TimesheetRow], who: &str) -> String {
+    to_csv(
+        &[
+            "Id",
+            "Date",
+            "Date/time",
+            "End Date/time",
+            "Project",
+            "Who",
+            "Description",
+            "Project Category",
+            "Company",
+            "Task list",
+            "Task",
+            "Parent task",
+            "Is sub-task",
+            "Is billable",
+            "Invoice number",
+            "Hours",
+            "Minutes",
+            "Decimal hours",
+        ],
+        rows.iter().map(|row| {
+            let project = row
+                .tags
+                .iter()
+                .find_map(|tag| tag.strip_prefix(TEAMWORK_PROJECT_TAG_PREFIX))
+                .unwrap_or(&row.summary);
+            vec![
+                row.event_id.to_string(),
+                row.starts_at.format("%d/%m/%Y").to_string(),
+                row.starts_at.format("%d/%m/%Y %H:%M").to_string(),
+                row.ends_at.format("%d/%m/%Y %H:%M").to_string(),
+                project.to_string(),
+                who.to_string(),
+                row.description.clone().unwrap_or_default(),
+                String::new(),
+                row.location.clone().unwrap_or_default(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                (row.seconds / 3600).to_string(),
+                (row.seconds % 3600 / 60).to_string(),
+                row.hours(),
+            ]
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::event::local::RawLocalEvent;
+
+    fn timer_event(id: LocalEventId, starts_at: i64, duration: i32, tags: &str) -> LocalEvent {
+        let raw = RawLocalEvent {
+            id,
+            user_id: 1,
+            created_at: starts_at,
+            updated_at: starts_at,
+            priority: None,
+            rrule: None,
+            starts_at,
+            all_day: false,
+            duration: Some(duration),
+            summary: "Work".to_string(),
+            description: Some("Fixing things".to_string()),
+            location: Some("Office".to_string()),
+            uid: format!("olmonoko::timer::{id}"),
+            timer_template_id: None,
+        };
+        LocalEvent::from((raw, tags))
+    }
+
+    #[test]
+    fn rounding() {
+        let query = |round_to, rounding| TimesheetQuery {
+            round_to,
+            rounding,
+            ..Default::default()
+        };
+        assert_eq!(query(None, Rounding::Up).round(100), 100);
+        assert_eq!(query(Some(15), Rounding::Nearest).round(7 * 60), 0);
+        assert_eq!(query(Some(15), Rounding::Nearest).round(8 * 60), 15 * 60);
+        assert_eq!(query(Some(15), Rounding::Up).round(60), 15 * 60);
+        assert_eq!(query(Some(15), Rounding::Down).round(29 * 60), 15 * 60);
+    }
+
+    #[test]
+    fn csv_by_tag_and_teamwork() {
+        // 2024-12-02 10:00 in Helsinki
+        let events = vec![
+            timer_event(
+                2,
+                1733126400,
+                5400,
+                "olmonoko::timer,client,tw::project::Site",
+            ),
+            timer_event(1, 1733040000, 600, "olmonoko::timer"),
+            timer_event(3, 1733040000, 600, "not-a-timer"),
+        ];
+        let tz: Tz = "Europe/Helsinki".parse().unwrap();
+        let query = TimesheetQuery {
+            round_to: Some(15),
+            rounding: Rounding::Up,
+            by_tag: true,
+            ..Default::default()
+        };
+        let rows = timesheet(&events, &query, &tz);
+        let groups: Vec<_> = rows.iter().filter_map(|row| row.group.as_deref()).collect();
+        assert_eq!(groups, vec!["(untagged)", "client", "tw::project::Site"]);
+        assert_eq!(
+            timesheet_csv(&rows[1..2], true),
+            "tag,date,start,end,duration,hours,summary,tags,location\r\n\
+             client,2024-12-02,10:00,11:30,1:30,1.50,Work,\"client,tw::project::Site\",Office\r\n"
+        );
+
+        let rows = timesheet(&events, &TimesheetQuery::default(), &tz);
+        assert_eq!(rows.len(), 2);
+        let teamwork = teamwork_csv(&rows, "user@example.com");
+        assert_eq!(
+            teamwork.lines().nth(2),
+            Some("2,02/12/2024,02/12/2024 10:00,02/12/2024 11:30,Site,user@example.com,Fixing things,,Office,,,,,,,1,30,1.50")
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use super::user::UserId;
+
+/// How many recovery codes are handed out at once
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// The time the user has to enter the second factor after the password, in seconds
+pub const LOGIN_CHALLENGE_LIFETIME: i64 = 5 * 60;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawUserTotp {
+    pub user_id: UserId,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_used_step: Option<i64>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// Secrets for adding the account to an authenticator app
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TotpProvisioning {
+    /// base32, for entering by hand
+    pub secret: String,
+    pub url: String,
+    /// The url as a QR code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_svg: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TwoFactorCodeForm {
+    /// A code from the authenticator app or a recovery code
+    pub code: String,
+}
+impl TwoFactorCodeForm {
+    /// Authenticator codes are all digits, recovery codes are not
+    pub fn is_totp(&self) -> bool {
+        let code = self.code.trim();
+        !code.is_empty() && code.chars().all(|c| c.is_ascii_digit() || c == ' ')
+    }
+}
+
+/// Recovery codes are shown grouped and in lower case, but accepted however they are typed
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_kinds() {
+        let form = |code: &str| TwoFactorCodeForm {
+            code: code.to_string(),
+        };
+        assert!(form("123 456").is_totp());
+        assert!(!form("abcde-12345").is_totp());
+        assert!(!form(" ").is_totp());
+        assert_eq!(normalize_recovery_code(" ABcde-12345 "), "abcde12345");
+    }
+}
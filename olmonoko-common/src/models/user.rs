@@ -1,3 +1,4 @@
+use super::ics_source::deserialize_checkbox;
 use crate::utils::time::from_timestamp;
 use chrono::{TimeZone, Utc};
 use chrono_tz::OffsetComponents;
@@ -58,6 +59,35 @@ pub struct NewUser {
 pub struct UserForm {
     pub email: String,
     pub password: String,
+    /// Keep the session across browser restarts, only used when logging in
+    #[serde(deserialize_with = "deserialize_checkbox", default)]
+    pub remember: bool,
+}
+
+/// How long a password reset link is valid for, in seconds
+pub const PASSWORD_RESET_LIFETIME: i64 = 60 * 60;
+/// Reset emails are throttled over this many seconds
+pub const PASSWORD_RESET_WINDOW: i64 = 60 * 60;
+/// Reset emails sent to one address per [`PASSWORD_RESET_WINDOW`]
+pub const PASSWORD_RESETS_PER_EMAIL: i64 = 3;
+/// Reset emails requested from one address per [`PASSWORD_RESET_WINDOW`]
+pub const PASSWORD_RESETS_PER_IP: i64 = 10;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordChangeForm {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordResetRequestForm {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PasswordResetForm {
+    pub secret: String,
+    pub new_password: String,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
@@ -2,3 +2,4 @@ pub mod event_filters;
 pub mod flash;
 pub mod time;
 pub mod ui;
+pub mod xml;
@@ -14,6 +14,11 @@ pub fn from_timestamp(timestamp: i64) -> DateTime<Utc> {
 }
 
 pub fn from_form(dt_form: &str, tz_offset: i8) -> DateTime<Utc> {
+    try_from_form(dt_form, tz_offset).expect("Failed to parse RFC3339 datetime")
+}
+
+/// Parses a `datetime-local` input in the given timezone, None if it's malformed
+pub fn try_from_form(dt_form: &str, tz_offset: i8) -> Option<DateTime<Utc>> {
     // FIX: This is stupid
     let dt = if dt_form.chars().filter(|c| *c == ':').count() == 2 {
         dt_form.to_string()
@@ -27,9 +32,15 @@ pub fn from_form(dt_form: &str, tz_offset: i8) -> DateTime<Utc> {
     };
     let rfc = format!("{dt}{tz}");
     tracing::debug!("Parsing RFC3339 datetime: {}", rfc);
-    let fixed =
-        chrono::DateTime::parse_from_rfc3339(&rfc).expect("Failed to parse RFC3339 datetime");
-    fixed.with_timezone(&Utc)
+    let fixed = chrono::DateTime::parse_from_rfc3339(&rfc).ok()?;
+    Some(fixed.with_timezone(&Utc))
+}
+
+/// Parses a `YYYY-MM-DD` date input as the start of that day in the given timezone
+pub fn from_date_form(date_form: &str, tz_offset: i8) -> Option<DateTime<Utc>> {
+    let date = chrono::NaiveDate::parse_from_str(date_form.trim(), "%Y-%m-%d").ok()?;
+    let start_of_day = date.and_time(chrono::NaiveTime::MIN).and_utc();
+    Some(start_of_day - chrono::Duration::hours(tz_offset.into()))
 }
 
 pub fn to_form(dt: DateTime<Utc>) -> Option<String> {
@@ -55,4 +66,13 @@ mod tests {
         let now = now.with_nanosecond(0).unwrap();
         assert_eq!(dt, now);
     }
+
+    #[test]
+    fn date_form() {
+        assert_eq!(
+            from_date_form("2024-11-23", 2).map(|dt| dt.timestamp()),
+            Some(1732312800)
+        );
+        assert_eq!(from_date_form("23.11.2024", 0), None);
+    }
 }
@@ -0,0 +1,31 @@
+//! Helpers for reading the XML formats of banks, which are matched by element names only,
+//! ignoring namespaces as they differ between versions of the same format
+
+/// Follows a path of child elements to the first match
+pub fn find<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    path: &[&str],
+) -> Option<roxmltree::Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| {
+        node.children()
+            .find(|child| child.is_element() && child.tag_name().name() == *name)
+    })
+}
+
+/// The trimmed, non-empty text of the element at the path
+pub fn text(node: roxmltree::Node, path: &[&str]) -> Option<String> {
+    find(node, path)
+        .and_then(|node| node.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+/// All child elements with the name
+pub fn children<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}